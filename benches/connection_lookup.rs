@@ -0,0 +1,48 @@
+//! Compares the hashers of the connection map, run this once with and once without the `fast-hash` feature:
+//!
+//! `cargo bench --bench connection_lookup` and `cargo bench --bench connection_lookup --features fast-hash`
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use laminar::{Packet, Socket};
+
+const CONNECTIONS: u16 = 1000;
+
+/// Binds a socket with a connection to each of `CONNECTIONS` addresses on the loopback interface.
+fn socket_with_connections() -> (Socket, Vec<SocketAddr>) {
+    let mut socket = Socket::bind("127.0.0.1:0").unwrap();
+    let addresses: Vec<SocketAddr> = (0..CONNECTIONS)
+        .map(|port| SocketAddr::from(([127, 0, 0, 1], 40_000 + port)))
+        .collect();
+
+    // sending creates the connection, nobody has to listen on the other end.
+    for address in &addresses {
+        socket.send(Packet::unreliable(*address, vec![1])).unwrap();
+    }
+    socket.manual_poll(Instant::now());
+    assert_eq!(socket.connection_count(), usize::from(CONNECTIONS));
+
+    (socket, addresses)
+}
+
+fn lookup_benchmark(c: &mut Criterion) {
+    let (socket, addresses) = socket_with_connections();
+    let name = if cfg!(feature = "fast-hash") {
+        "look up connections with the fast hasher"
+    } else {
+        "look up connections with the default hasher"
+    };
+
+    c.bench_function(name, move |b| {
+        b.iter(|| {
+            for address in &addresses {
+                criterion::black_box(socket.connection_state(address));
+            }
+        })
+    });
+}
+
+criterion_group!(benches, lookup_benchmark);
+criterion_main!(benches);
@@ -1,84 +1,122 @@
-use std::sync::Arc;
+use std::net::SocketAddr;
+use std::time::Instant;
 
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
 
-use laminar::{Config, DeliveryMethod, ProtocolVersion, VirtualConnection};
+use laminar::{Packet, Socket, SocketEvent};
 
-const SERVER_ADDR: &str = "127.0.0.1:12345";
-const CLIENT_ADDR: &str = "127.0.0.1:12346";
+/// Binds a sending and a receiving socket on the loopback interface.
+fn socket_pair() -> (Socket, Socket, SocketAddr) {
+    let sender = Socket::bind("127.0.0.1:0").unwrap();
+    let receiver = Socket::bind("127.0.0.1:0").unwrap();
+    let receiver_addr = receiver.local_addr().unwrap();
 
-fn process_packet_before_send(
-    connection: &mut VirtualConnection,
-    _config: &Config,
-    delivery_method: DeliveryMethod,
-) {
-    let payload = vec![1, 2, 3, 4, 5];
-
-    let _packet_data = connection
-        .process_outgoing(&payload, delivery_method)
-        .unwrap();
+    (sender, receiver, receiver_addr)
 }
 
-fn process_packet_when_received(connection: &mut VirtualConnection, data: &[u8]) {
-    connection.process_incoming(&data).unwrap().unwrap();
+/// Sends a single packet from `sender` and polls `receiver` until the packet came through.
+fn send_and_receive(sender: &mut Socket, receiver: &mut Socket, packet: Packet) {
+    sender.send(packet).unwrap();
+    sender.manual_poll(Instant::now());
+
+    loop {
+        receiver.manual_poll(Instant::now());
+        while let Some(event) = receiver.recv() {
+            if let SocketEvent::Packet(_) = event {
+                return;
+            }
+        }
+    }
 }
 
-/// This is mimicking the `HeaderParser for StandardHeader` implementation which is no longer
-/// visible externally
-fn standard_header_bytes(delivery_method: DeliveryMethod) -> Vec<u8> {
-    let mut buffer = Vec::new();
-    buffer.write_u16::<BigEndian>(ProtocolVersion::get_crc16());
-    // Represents a standard `Packet`
-    buffer.write_u8(0);
-    buffer.write_u8(delivery_method as u8);
-    buffer
+fn unreliable_benchmark(c: &mut Criterion) {
+    let (mut sender, mut receiver, receiver_addr) = socket_pair();
+
+    c.bench_function("process unreliable packet", move |b| {
+        b.iter(|| {
+            send_and_receive(
+                &mut sender,
+                &mut receiver,
+                Packet::unreliable(receiver_addr, vec![1; 500]),
+            )
+        })
+    });
 }
 
-/// This is mimicking the `HeaderParser for AckedPacketHeader` implementation which is no longer
-/// visible externally
-fn acked_header_bytes(
-    delivery_method: DeliveryMethod,
-    seq: u16,
-    ack_seq: u16,
-    ack_field: u32,
-) -> Vec<u8> {
-    let mut buffer = standard_header_bytes(delivery_method);
-    buffer.write_u16::<BigEndian>(seq);
-    buffer.write_u16::<BigEndian>(ack_seq);
-    buffer.write_u32::<BigEndian>(ack_field);
-    buffer
+fn reliable_benchmark(c: &mut Criterion) {
+    let (mut sender, mut receiver, receiver_addr) = socket_pair();
+
+    c.bench_function("process reliable packet", move |b| {
+        b.iter(|| {
+            send_and_receive(
+                &mut sender,
+                &mut receiver,
+                Packet::reliable_unordered(receiver_addr, vec![1; 500]),
+            )
+        })
+    });
 }
 
-fn receive_unreliable_benchmark(c: &mut Criterion) {
-    let mut connection =
-        VirtualConnection::new(SERVER_ADDR.parse().unwrap(), &Arc::new(Config::default()));
+/// Number of datagrams that are ready at once in the burst benchmark.
+const BURST_SIZE: usize = 64;
+
+fn burst_benchmark(c: &mut Criterion) {
+    let (mut sender, mut receiver, receiver_addr) = socket_pair();
 
-    // setup fake received bytes.
-    let mut buffer = standard_header_bytes(DeliveryMethod::UnreliableUnordered);
-    buffer.append(&mut vec![1; 500]);
+    // a single poll receives every datagram that is ready, each connection is looked up once per datagram.
+    c.bench_function("process burst of reliable packets", move |b| {
+        b.iter(|| {
+            for _ in 0..BURST_SIZE {
+                sender
+                    .send(Packet::reliable_unordered(receiver_addr, vec![1; 500]))
+                    .unwrap();
+            }
+            sender.manual_poll(Instant::now());
 
-    c.bench_function("process unreliable packet on receive", move |b| {
-        b.iter(|| process_packet_when_received(&mut connection, &buffer))
+            let mut received = 0;
+            while received < BURST_SIZE {
+                receiver.manual_poll(Instant::now());
+                while let Some(event) = receiver.recv() {
+                    if let SocketEvent::Packet(_) = event {
+                        received += 1;
+                    }
+                }
+            }
+        })
     });
 }
 
-fn receive_reliable_benchmark(c: &mut Criterion) {
-    let mut connection =
-        VirtualConnection::new(SERVER_ADDR.parse().unwrap(), &Arc::new(Config::default()));
+fn fragmented_benchmark(c: &mut Criterion) {
+    c.bench_function("process fragmented packet", move |b| {
+        b.iter_batched(
+            || {
+                let (sender, mut receiver, receiver_addr) = socket_pair();
 
-    // setup fake received bytes.
-    let mut buffer = acked_header_bytes(DeliveryMethod::ReliableUnordered, 0, 1, 2);
-    buffer.append(&mut vec![1; 500]);
+                // fragments are only reassembled for accepted connections, so the receiver has to send first.
+                receiver
+                    .send(Packet::unreliable(sender.local_addr().unwrap(), vec![]))
+                    .unwrap();
+                receiver.manual_poll(Instant::now());
 
-    c.bench_function("process reliable packet on receive", move |b| {
-        b.iter(|| process_packet_when_received(&mut connection, &buffer))
+                (sender, receiver, receiver_addr)
+            },
+            |(mut sender, mut receiver, receiver_addr)| {
+                send_and_receive(
+                    &mut sender,
+                    &mut receiver,
+                    Packet::reliable_unordered(receiver_addr, vec![1; 8 * 1024]),
+                )
+            },
+            BatchSize::PerIteration,
+        )
     });
 }
 
 criterion_group!(
     benches,
-    receive_unreliable_benchmark,
-    receive_reliable_benchmark
+    unreliable_benchmark,
+    reliable_benchmark,
+    burst_benchmark,
+    fragmented_benchmark
 );
 criterion_main!(benches);
@@ -0,0 +1,131 @@
+//! Measures sending packets and counts the heap allocations that are made per packet.
+//!
+//! Also counts the allocations and allocated bytes of receiving a fragmented packet, the
+//! reassembly must not copy the partially received buffer for every fragment.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use laminar::{Packet, Socket, SocketEvent};
+
+/// Counts the allocations made by the benchmark, on top of the system allocator.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const PACKETS: usize = 1000;
+
+/// Sends `PACKETS` unreliable packets in one poll, nobody has to listen on the other end.
+fn send_packets(socket: &mut Socket, address: SocketAddr, payload: &[u8]) {
+    for _ in 0..PACKETS {
+        socket
+            .send(Packet::unreliable(address, payload.to_vec()))
+            .unwrap();
+    }
+    socket.manual_poll(Instant::now());
+}
+
+fn send_benchmark(c: &mut Criterion) {
+    let mut socket = Socket::bind("127.0.0.1:0").unwrap();
+    let address = SocketAddr::from(([127, 0, 0, 1], 40_000));
+    let payload = vec![1; 500];
+
+    // the first poll creates the connection, which is not counted.
+    send_packets(&mut socket, address, &payload);
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    send_packets(&mut socket, address, &payload);
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    println!(
+        "{} allocations per sent packet",
+        allocations as f64 / PACKETS as f64
+    );
+
+    c.bench_function("send unreliable packets", move |b| {
+        b.iter(|| send_packets(&mut socket, address, &payload))
+    });
+}
+
+/// Size of the fragmented packet, with the default fragment size it is split into 8 fragments.
+const FRAGMENTED_PAYLOAD_SIZE: usize = 8 * 1024;
+
+/// Polls `receiver` until the fragmented packet was reassembled.
+fn receive_packet(receiver: &mut Socket) {
+    loop {
+        receiver.manual_poll(Instant::now());
+        while let Some(event) = receiver.recv() {
+            if let SocketEvent::Packet(_) = event {
+                return;
+            }
+        }
+    }
+}
+
+fn receive_fragmented_benchmark(c: &mut Criterion) {
+    let mut sender = Socket::bind("127.0.0.1:0").unwrap();
+    let mut receiver = Socket::bind("127.0.0.1:0").unwrap();
+    let receiver_addr = receiver.local_addr().unwrap();
+
+    // fragments are only reassembled for accepted connections, so the receiver has to send first.
+    receiver
+        .send(Packet::unreliable(sender.local_addr().unwrap(), vec![]))
+        .unwrap();
+    receiver.manual_poll(Instant::now());
+
+    let payload = vec![1; FRAGMENTED_PAYLOAD_SIZE];
+    let mut send_fragmented = move || {
+        sender
+            .send(Packet::reliable_unordered(receiver_addr, payload.clone()))
+            .unwrap();
+        sender.manual_poll(Instant::now());
+    };
+
+    // the first packet creates the connection, which is not counted.
+    send_fragmented();
+    receive_packet(&mut receiver);
+
+    send_fragmented();
+    let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+    let bytes_before = ALLOCATED_BYTES.load(Ordering::Relaxed);
+    receive_packet(&mut receiver);
+    println!(
+        "{} allocations and {} allocated bytes to receive a packet of {} bytes",
+        ALLOCATIONS.load(Ordering::Relaxed) - allocations_before,
+        ALLOCATED_BYTES.load(Ordering::Relaxed) - bytes_before,
+        FRAGMENTED_PAYLOAD_SIZE
+    );
+
+    c.bench_function("receive fragmented packet", move |b| {
+        b.iter(|| {
+            send_fragmented();
+            receive_packet(&mut receiver);
+        })
+    });
+}
+
+criterion_group!(benches, send_benchmark, receive_fragmented_benchmark);
+criterion_main!(benches);
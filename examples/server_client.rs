@@ -62,7 +62,7 @@ fn client() -> Result<(), ErrorKind> {
     loop {
         s_buffer.clear();
         stdin.read_line(&mut s_buffer)?;
-        let line = s_buffer.replace(|x| x == '\n' || x == '\r', "");
+        let line = s_buffer.replace(['\n', '\r'], "");
 
         socket.send(Packet::reliable_unordered(
             server,
@@ -7,9 +7,9 @@ use std::net::SocketAddr;
 use laminar::{Packet, Result, Socket, SocketEvent};
 
 /// The socket address of where the server is located.
-const SERVER_ADDR: &'static str = "127.0.0.1:12345";
+const SERVER_ADDR: &str = "127.0.0.1:12345";
 // The client address from where the data is sent.
-const CLIENT_ADDR: &'static str = "127.0.0.1:12346";
+const CLIENT_ADDR: &str = "127.0.0.1:12346";
 
 fn client_address() -> SocketAddr {
     CLIENT_ADDR.parse().unwrap()
@@ -38,20 +38,17 @@ pub fn receive_data() {
     // Next start receiving.
     loop {
         if let Some(result) = socket.recv() {
-            match result {
-                SocketEvent::Packet(packet) => {
-                    let endpoint: SocketAddr = packet.addr();
-                    let received_data: &[u8] = packet.payload();
-
-                    // you can here deserialize your bytes into the data you have passed it when sending.
-
-                    println!(
-                        "Received packet from: {:?} with length {}",
-                        endpoint,
-                        received_data.len()
-                    );
-                }
-                _ => {}
+            if let SocketEvent::Packet(packet) = result {
+                let endpoint: SocketAddr = packet.addr();
+                let received_data: &[u8] = packet.payload();
+
+                // you can here deserialize your bytes into the data you have passed it when sending.
+
+                println!(
+                    "Received packet from: {:?} with length {}",
+                    endpoint,
+                    received_data.len()
+                );
             }
             break;
         }
@@ -168,14 +168,14 @@ fn test_steady_stream(config: ClientConfiguration, mut socket: Socket) {
 
     let test_packet = Packet::reliable_unordered(config.destination, config.test_name.into_bytes());
 
-    let time_quantum = 1000 / config.packet_ps as u64;
+    let time_quantum = 1000 / config.packet_ps;
     let start_time = Instant::now();
     let mut packets_sent = 0;
 
     loop {
         socket.send(test_packet.clone()).unwrap();
         socket.manual_poll(Instant::now());
-        while let Some(_) = socket.recv() {}
+        while socket.recv().is_some() {}
 
         packets_sent += 1;
 
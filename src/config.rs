@@ -1,14 +1,37 @@
 use std::{default::Default, time::Duration};
+#[cfg(feature = "json")]
+use std::{fs::File, io::Read, path::Path};
 
-use crate::net::constants::{DEFAULT_MTU, FRAGMENT_SIZE_DEFAULT, MAX_FRAGMENTS_DEFAULT};
+use crate::error::{ConfigErrorKind, ErrorKind, Result};
+use crate::net::constants::{
+    CHECKSUM_SIZE, DEFAULT_MTU, FRAGMENT_HEADER_SIZE, FRAGMENT_SIZE_DEFAULT, MAX_FRAGMENTS_DEFAULT,
+    STANDARD_HEADER_SIZE, TIMESTAMP_SIZE,
+};
+#[cfg(feature = "encryption")]
+use crate::net::constants::{ENCRYPTION_HEADER_SIZE, ENCRYPTION_TAG_SIZE};
+use crate::net::QualityThresholds;
+#[cfg(feature = "encryption")]
+use crate::packet::EncryptionKey;
+use crate::packet::{AckFieldWidth, Compression, DeliveryGuarantee};
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+// options that are missing from a configuration file keep their default value.
+#[cfg_attr(feature = "serde", serde(default))]
 /// Contains the configuration options to configure laminar for special use-cases.
+///
+/// With the `serde` feature it can be serialized, for example to load it from a configuration file.
+/// With the `json` feature a configuration file can be loaded with `Config::from_path`.
 pub struct Config {
     /// Make the underlying UDP socket block when true, otherwise non-blocking.
     pub blocking_mode: bool,
     /// Value which can specify the amount of time that can pass without hearing from a client before considering them disconnected.
     pub idle_connection_timeout: Duration,
+    /// Value which specifies how often connections are checked for having timed out.
+    ///
+    /// A connection is dropped at most this long after `idle_connection_timeout` has passed, as long as the socket is polled.
+    /// If None, connections are checked on every poll (the default).
+    pub timeout_check_interval: Option<Duration>,
     /// Value which specifies at which interval (if at all) a heartbeat should be sent, if no other packet was sent in the meantime.
     /// If None, no heartbeats will be sent (the default).
     pub heartbeat_interval: Option<Duration>,
@@ -30,7 +53,15 @@ pub struct Config {
     pub fragment_size: u16,
     /// Value which can specify the size of the buffer that queues up fragments ready to be reassembled once all fragments have arrived.
     pub fragment_reassembly_buffer_size: u16,
+    /// Value which specifies how long the fragments of a packet are kept while the others are missing.
+    ///
+    /// The packet is counted as dropped once its first fragment arrived longer ago than this. Defaults to 5 seconds.
+    pub fragment_timeout: Duration,
     /// Value that specifies the size of the buffer the UDP data will be read into. Defaults to `1450` bytes.
+    ///
+    /// Datagrams that are bigger are cut off, so it can not be smaller than `fragment_size` plus the headers
+    /// of the first fragment: the standard, fragment and acknowledgment headers, the checksum, and the send time
+    /// and encryption overhead when those are enabled.
     pub receive_buffer_max_size: usize,
     /// Value which can specify the factor which will smooth out network jitter.
     ///
@@ -41,6 +72,31 @@ pub struct Config {
     ///
     /// Value which specifies the maximum round trip time before we consider it a problem. This is expressed in milliseconds.
     pub rtt_max_value: u16,
+    /// Value which specifies how many packets per second we recommend to send while the network quality is good.
+    ///
+    /// The network quality is good as long as the round trip time stays below `rtt_max_value`. Defaults to 30.
+    pub good_network_send_rate: u32,
+    /// Value which specifies how many packets per second we recommend to send while the network quality is bad.
+    ///
+    /// The network quality becomes bad as soon as the round trip time exceeds `rtt_max_value`. Defaults to 10.
+    pub bad_network_send_rate: u32,
+    /// Value which specifies how long the round trip time has to stay below `rtt_max_value` before a bad network is considered good again.
+    ///
+    /// This prevents flapping between the good and bad send rate. Defaults to `10s`.
+    pub network_recovery_time: Duration,
+    /// Value which specifies the thresholds a connection has to stay within for `ConnectionQuality::Excellent`.
+    ///
+    /// Defaults to a round trip time of 50ms, a jitter of 10ms and a loss rate of 1%.
+    pub excellent_quality: QualityThresholds,
+    /// Value which specifies the thresholds a connection has to stay within for `ConnectionQuality::Good`.
+    ///
+    /// Defaults to a round trip time of 100ms, a jitter of 25ms and a loss rate of 3%.
+    pub good_quality: QualityThresholds,
+    /// Value which specifies the thresholds a connection has to stay within for `ConnectionQuality::Poor`,
+    /// connections that exceed them are `ConnectionQuality::Bad`.
+    ///
+    /// Defaults to a round trip time of 250ms, a jitter of 50ms and a loss rate of 10%.
+    pub poor_quality: QualityThresholds,
     /// Value which can specify the event buffer we read socket events into.
     ///
     /// Value that specifies the size of the event buffer into which we receive socket events, in bytes. Defaults to 1024.
@@ -49,12 +105,151 @@ pub struct Config {
     ///
     /// Value that specifies how long we should block polling for socket events, in milliseconds. Defaults to `1ms`.
     pub socket_polling_timeout: Option<Duration>,
+    /// Value which specifies how often sending a datagram is retried when the socket reports a transient error.
+    ///
+    /// Only `WouldBlock`, `Interrupted` and out of buffer space errors are retried, right away, others fail.
+    /// Datagrams that still can not be sent are tried once more in the next poll, instead of blocking this one.
+    /// If that fails as well, they are counted in `NetworkStats::send_failures` and reported with
+    /// `SocketEvent::SendFailed`. Defaults to 0, no retries.
+    pub send_retries: u8,
     /// The maximum amount of reliable packets in flight on this connection before we drop the
     /// connection.
     ///
     /// When we send a reliable packet, it is stored locally until an acknowledgement comes back to
     /// us, if that store grows to a size.
+    ///
+    /// Sequence numbers are 16 bits wide and wrap around, so at most half of them can be told apart:
+    /// values above 32767 are rejected.
     pub max_packets_in_flight: u16,
+    /// Value which specifies how many reliable packets may be waiting for their acknowledgment before
+    /// sending another reliable packet to the same connection fails.
+    ///
+    /// `Socket::send` returns `ErrorKind::SendQueueFull` then, so the sender can back off before
+    /// `max_packets_in_flight` drops the connection. If None, sending is never refused (the default).
+    pub max_pending_packets: Option<u16>,
+    /// Value which specifies how many bytes per second may be sent to a single connection.
+    ///
+    /// User packets that would exceed it are handled as `defer_rate_limited_packets` specifies.
+    /// Resent packets and heartbeats are never held back, but they take from the same budget.
+    /// If None, sending is not limited (the default).
+    pub max_bytes_per_second: Option<u32>,
+    /// Value which specifies how many bytes a connection may send at once after it was quiet for a while.
+    ///
+    /// Only used with `max_bytes_per_second`. Defaults to 16 KiB.
+    pub send_burst_size: u32,
+    /// Value which specifies what happens to user packets that exceed `max_bytes_per_second`.
+    ///
+    /// If true, they are queued and sent in order once enough time passed (the default).
+    /// Otherwise they are not sent, and reported with `SocketEvent::DeliveryFailed`.
+    pub defer_rate_limited_packets: bool,
+    /// Value which specifies whether small user messages sent to a connection in one poll are packed into one datagram.
+    ///
+    /// Messages with the same guarantees are batched until the fragment size is reached, and sent at the end of the poll.
+    /// Both endpoints have to support batches, so this should only be enabled if the peer runs this version. Defaults to false.
+    pub batch_messages: bool,
+    /// Value which specifies how many connections a socket keeps at most, challenged connections included.
+    ///
+    /// Packets from and to new addresses are rejected with `ErrorKind::ConnectionLimitReached` until a
+    /// connection is dropped. Connections that are already kept are never evicted for new ones.
+    pub max_connections: usize,
+    /// Value which specifies how many connections may wait for the answer to their challenge at the same time.
+    ///
+    /// Further handshake requests are rejected with `ErrorKind::ConnectionLimitReached`, see `require_handshake`.
+    pub max_challenged_connections: usize,
+    /// Value which specifies whether packets that come from the address the socket is bound to are dropped.
+    ///
+    /// Such packets are only received when the network is misconfigured or spoofed, and would create a connection
+    /// of the socket with itself. They are rejected with `ErrorKind::SelfPacket` then. Defaults to false.
+    pub reject_self_packets: bool,
+    /// Value which specifies how often a dropped reliable packet is resent before it is given up on.
+    ///
+    /// Packets that are given up on are reported with `SocketEvent::DeliveryFailed`.
+    /// If None, dropped packets are resent until the connection is dropped (the default).
+    pub max_resends: Option<u16>,
+    /// Value which specifies whether the connection fails once a packet was given up on after `max_resends` resends.
+    ///
+    /// The remote host is considered gone then, even before `idle_connection_timeout` passed. The connection is
+    /// removed within the same poll, which emits `SocketEvent::ConnectionFailed`. Defaults to false.
+    pub disconnect_on_max_resends: bool,
+    /// Value which specifies how many `SocketEvent::PacketsDropped` events are held back at most,
+    /// while the application did not take all earlier events yet.
+    ///
+    /// The reports carry the payloads of the dropped packets, an application that stops taking events would
+    /// keep all of them in memory otherwise. With a cap, the reports are handed out at the end of a poll
+    /// that started with no events left to take, after the other events of that poll.
+    /// `dropped_reports_overflow` decides what happens to reports that do not fit.
+    /// If None, the reports are handed out right away (the default).
+    pub max_dropped_reports: Option<usize>,
+    /// Value which specifies what happens to a report of dropped packets once `max_dropped_reports` are held back.
+    ///
+    /// Defaults to `OverflowPolicy::DropOldest`.
+    pub dropped_reports_overflow: OverflowPolicy,
+    /// Value which specifies how many ordered packets of a stream are buffered at most while an older one is missing.
+    ///
+    /// Once more arrive, the missing packets are declared lost with `SocketEvent::SequenceSkipped`,
+    /// and the buffered packets are delivered in order. The skipped packets are dropped if they arrive later.
+    ///
+    /// This makes reliable ordered delivery lossy: a skipped packet is still acknowledged when its resend
+    /// arrives, so the sender does not send it again, but it is then dropped as a duplicate and never delivered.
+    /// Only set this when late packets are worth less than a delivery that keeps going.
+    /// If None, ordered packets wait for the missing one until the connection is dropped (the default).
+    pub max_reorder_window: Option<u16>,
+    /// Value which specifies the codec used to compress the payload of outgoing packets.
+    ///
+    /// Payloads that would not get smaller are sent uncompressed. Defaults to `Compression::None`.
+    pub compression: Compression,
+    /// Value which specifies how many previous packets are acknowledged with each acknowledgment header.
+    ///
+    /// Both endpoints have to use the same width, packets of a peer with another width are rejected.
+    /// Defaults to `AckFieldWidth::Bits32`.
+    pub ack_field_width: AckFieldWidth,
+    /// Value which specifies whether received packets that are too old for the acknowledgment bitfield are acknowledged as well.
+    ///
+    /// Reliable packets and heartbeats then carry ranges of the packets received further back, so that packets which
+    /// arrived late are not resent by the remote host. The remote host understands these regardless of its config.
+    /// Defaults to false.
+    pub selective_acks: bool,
+    /// Value which specifies whether missing reliable packets are reported to the remote host right away.
+    ///
+    /// A negative acknowledgment is sent as soon as a packet arrives while older ones are still missing,
+    /// the remote host resends those without waiting until they fall out of the acknowledgment bitfield.
+    /// Packets that were merely reordered are resent as well then. The remote host understands these
+    /// regardless of its config. Defaults to false.
+    pub negative_acks: bool,
+    /// Value which specifies whether user packets carry the time they were sent at, see `Packet::sent_at`.
+    ///
+    /// This adds 4 bytes to every datagram with user data. The remote host reads the time regardless of its config.
+    /// Defaults to false.
+    pub packet_timestamps: bool,
+    /// Value which specifies whether connections have to complete a handshake before they exchange packets.
+    ///
+    /// The side that starts a connection sends a handshake request, user packets are queued until it is accepted.
+    /// Other packets that arrive before the handshake completed are rejected, so both endpoints should enable this.
+    /// If false, connections are connected from the moment they are created (the default).
+    pub require_handshake: bool,
+    /// Value which specifies how long a remote host that asked for a connection has to answer its challenge.
+    ///
+    /// Hosts that do not answer in time are forgotten, this is separate from `idle_connection_timeout`.
+    /// Only used when `require_handshake` is set. Defaults to 1 second.
+    pub handshake_timeout: Duration,
+    /// Value which specifies the shared key used to encrypt packets, requires the `encryption` feature.
+    ///
+    /// Both endpoints have to use the same key. If None, packets are not encrypted (the default).
+    #[cfg(feature = "encryption")]
+    pub encryption_key: Option<EncryptionKey>,
+}
+
+/// What happens to a report that does not fit anymore, see `Config::dropped_reports_overflow`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OverflowPolicy {
+    /// The oldest report that is held back is discarded to make room for the new one.
+    DropOldest,
+    /// The new report is discarded.
+    DropNewest,
+    /// The new report is discarded, and `Socket::send` fails with `ErrorKind::DroppedBufferFull`
+    /// for reliable packets until there is room again, because each of them could be dropped as well.
+    Error,
 }
 
 impl Default for Config {
@@ -62,17 +257,783 @@ impl Default for Config {
         Self {
             blocking_mode: false,
             idle_connection_timeout: Duration::from_secs(5),
+            timeout_check_interval: None,
             heartbeat_interval: None,
             max_packet_size: (MAX_FRAGMENTS_DEFAULT * FRAGMENT_SIZE_DEFAULT) as usize,
             max_fragments: MAX_FRAGMENTS_DEFAULT as u8,
             fragment_size: FRAGMENT_SIZE_DEFAULT,
             fragment_reassembly_buffer_size: 64,
+            fragment_timeout: Duration::from_secs(5),
             receive_buffer_max_size: DEFAULT_MTU as usize,
             rtt_smoothing_factor: 0.10,
             rtt_max_value: 250,
+            good_network_send_rate: 30,
+            bad_network_send_rate: 10,
+            network_recovery_time: Duration::from_secs(10),
+            excellent_quality: QualityThresholds::new(
+                Duration::from_millis(50),
+                Duration::from_millis(10),
+                0.01,
+            ),
+            good_quality: QualityThresholds::new(
+                Duration::from_millis(100),
+                Duration::from_millis(25),
+                0.03,
+            ),
+            poor_quality: QualityThresholds::new(
+                Duration::from_millis(250),
+                Duration::from_millis(50),
+                0.10,
+            ),
             socket_event_buffer_size: 1024,
             socket_polling_timeout: Some(Duration::from_millis(1)),
+            send_retries: 0,
             max_packets_in_flight: 512,
+            max_pending_packets: None,
+            max_bytes_per_second: None,
+            send_burst_size: 16 * 1024,
+            defer_rate_limited_packets: true,
+            batch_messages: false,
+            max_connections: 4096,
+            max_challenged_connections: 512,
+            reject_self_packets: false,
+            max_resends: None,
+            disconnect_on_max_resends: false,
+            max_dropped_reports: None,
+            dropped_reports_overflow: OverflowPolicy::DropOldest,
+            max_reorder_window: None,
+            compression: Compression::None,
+            ack_field_width: AckFieldWidth::Bits32,
+            selective_acks: false,
+            negative_acks: false,
+            packet_timestamps: false,
+            require_handshake: false,
+            handshake_timeout: Duration::from_secs(1),
+            #[cfg(feature = "encryption")]
+            encryption_key: None,
+        }
+    }
+}
+
+impl Config {
+    /// Returns a `ConfigBuilder` that starts out with the default configuration.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::new()
+    }
+
+    /// Reads a configuration from the JSON file at the given path and validates it.
+    ///
+    /// Options that are missing from the file keep their default value, durations are written
+    /// as `{ "secs": 5, "nanos": 0 }`.
+    #[cfg(feature = "json")]
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Config> {
+        Config::from_reader(File::open(path)?)
+    }
+
+    /// Reads a configuration in JSON from the given reader and validates it, see `Config::from_path`.
+    #[cfg(feature = "json")]
+    pub fn from_reader(mut reader: impl Read) -> Result<Config> {
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents)?;
+
+        let config: Config = serde_json::from_str(&contents)
+            .map_err(|error| ConfigErrorKind::MalformedFile(error.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks that the options of this configuration can be used together.
+    ///
+    /// This is done by `ConfigBuilder::build` and whenever a socket is bound with a configuration.
+    pub fn validate(&self) -> Result<()> {
+        if self.fragment_size == 0 || self.max_fragments == 0 {
+            return Err(ConfigErrorKind::NoFragments.into());
+        }
+        if let Some(check_interval) = self.timeout_check_interval {
+            if check_interval > self.idle_connection_timeout {
+                return Err(ConfigErrorKind::TimeoutCheckIntervalExceedsTimeout.into());
+            }
+        }
+        if let Some(heartbeat_interval) = self.heartbeat_interval {
+            if heartbeat_interval >= self.idle_connection_timeout {
+                return Err(ConfigErrorKind::HeartbeatIntervalExceedsTimeout.into());
+            }
+        }
+        if !(0.0..=1.0).contains(&self.rtt_smoothing_factor) {
+            return Err(ConfigErrorKind::InvalidRttSmoothingFactor.into());
+        }
+        if self.bad_network_send_rate > self.good_network_send_rate {
+            return Err(ConfigErrorKind::BadSendRateExceedsGoodSendRate.into());
+        }
+        if !self.excellent_quality.is_within(&self.good_quality)
+            || !self.good_quality.is_within(&self.poor_quality)
+        {
+            return Err(ConfigErrorKind::UnorderedQualityThresholds.into());
+        }
+        if self.max_bytes_per_second == Some(0) || self.send_burst_size == 0 {
+            return Err(ConfigErrorKind::NoBandwidth.into());
+        }
+        if usize::from(self.fragment_size) + self.max_header_overhead()
+            > self.receive_buffer_max_size
+        {
+            return Err(ConfigErrorKind::FragmentExceedsReceiveBuffer.into());
+        }
+        if self.max_packets_in_flight > u16::MAX / 2 {
+            return Err(ConfigErrorKind::PacketsInFlightExceedSequenceSpace.into());
+        }
+        Ok(())
+    }
+
+    /// Returns how many bytes the first fragment of a packet carries on top of its payload.
+    ///
+    /// Unreliable packets carry less, they have no fragment and acknowledgment headers.
+    fn max_header_overhead(&self) -> usize {
+        let mut overhead = usize::from(STANDARD_HEADER_SIZE)
+            + usize::from(FRAGMENT_HEADER_SIZE)
+            + usize::from(self.ack_field_width.header_size())
+            + usize::from(CHECKSUM_SIZE);
+        if self.packet_timestamps {
+            overhead += usize::from(TIMESTAMP_SIZE);
+        }
+        #[cfg(feature = "encryption")]
+        {
+            if self.encryption_key.is_some() {
+                overhead += usize::from(ENCRYPTION_HEADER_SIZE) + usize::from(ENCRYPTION_TAG_SIZE);
+            }
+        }
+        overhead
+    }
+
+    /// Checks whether a payload of the given size can be sent with the given delivery guarantee.
+    ///
+    /// Unreliable packets are never fragmented, so they have to fit into `receive_buffer_max_size`
+    /// together with their headers. Reliable packets can use all `max_fragments`, as long as they
+    /// stay within `max_packet_size`.
+    pub fn check_payload_size(&self, delivery: DeliveryGuarantee, size: usize) -> Result<()> {
+        let max = self.max_payload_size(delivery);
+
+        if size > max {
+            Err(ErrorKind::PayloadTooLarge { size, max })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the biggest payload that can be sent with the given delivery guarantee, see `check_payload_size`.
+    pub(crate) fn max_payload_size(&self, delivery: DeliveryGuarantee) -> usize {
+        match delivery {
+            DeliveryGuarantee::Unreliable => self
+                .receive_buffer_max_size
+                .saturating_sub(self.max_header_overhead()),
+            DeliveryGuarantee::Reliable => {
+                let all_fragments =
+                    usize::from(self.fragment_size) * usize::from(self.max_fragments);
+                // the fragmenter counts the payload length in an u16.
+                all_fragments
+                    .min(self.max_packet_size)
+                    .min(usize::from(u16::MAX))
+            }
+        }
+    }
+}
+
+/// Builder that could be used to construct a validated `Config`.
+///
+/// Options that are not set keep their default value.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    /// Construct a new builder with the default configuration.
+    pub fn new() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Sets whether the underlying UDP socket blocks, see `Config::blocking_mode`.
+    pub fn blocking_mode(mut self, blocking_mode: bool) -> Self {
+        self.config.blocking_mode = blocking_mode;
+        self
+    }
+
+    /// Sets how long a remote host may be silent before it is disconnected, see `Config::idle_connection_timeout`.
+    pub fn idle_connection_timeout(mut self, timeout: Duration) -> Self {
+        self.config.idle_connection_timeout = timeout;
+        self
+    }
+
+    /// Sets how often connections are checked for having timed out, see `Config::timeout_check_interval`.
+    pub fn timeout_check_interval(mut self, interval: Duration) -> Self {
+        self.config.timeout_check_interval = Some(interval);
+        self
+    }
+
+    /// Enables heartbeats at the given interval, see `Config::heartbeat_interval`.
+    pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.config.heartbeat_interval = Some(interval);
+        self
+    }
+
+    /// Sets the maximal size of a packet, see `Config::max_packet_size`.
+    pub fn max_packet_size(mut self, max_packet_size: usize) -> Self {
+        self.config.max_packet_size = max_packet_size;
+        self
+    }
+
+    /// Sets the maximal number of fragments of a packet, see `Config::max_fragments`.
+    pub fn max_fragments(mut self, max_fragments: u8) -> Self {
+        self.config.max_fragments = max_fragments;
+        self
+    }
+
+    /// Sets the size of each fragment, see `Config::fragment_size`.
+    pub fn fragment_size(mut self, fragment_size: u16) -> Self {
+        self.config.fragment_size = fragment_size;
+        self
+    }
+
+    /// Sets how many packets can be reassembled at once, see `Config::fragment_reassembly_buffer_size`.
+    pub fn fragment_reassembly_buffer_size(mut self, size: u16) -> Self {
+        self.config.fragment_reassembly_buffer_size = size;
+        self
+    }
+
+    /// Sets how long incomplete fragmented packets are kept, see `Config::fragment_timeout`.
+    pub fn fragment_timeout(mut self, fragment_timeout: Duration) -> Self {
+        self.config.fragment_timeout = fragment_timeout;
+        self
+    }
+
+    /// Sets the size of the buffer received datagrams are read into, see `Config::receive_buffer_max_size`.
+    pub fn receive_buffer_max_size(mut self, size: usize) -> Self {
+        self.config.receive_buffer_max_size = size;
+        self
+    }
+
+    /// Sets the factor that smooths out network jitter, see `Config::rtt_smoothing_factor`.
+    pub fn rtt_smoothing_factor(mut self, factor: f32) -> Self {
+        self.config.rtt_smoothing_factor = factor;
+        self
+    }
+
+    /// Sets the round trip time above which the network is considered bad, see `Config::rtt_max_value`.
+    pub fn rtt_max_value(mut self, rtt_max_value: u16) -> Self {
+        self.config.rtt_max_value = rtt_max_value;
+        self
+    }
+
+    /// Sets the recommended send rate of a good network, see `Config::good_network_send_rate`.
+    pub fn good_network_send_rate(mut self, rate: u32) -> Self {
+        self.config.good_network_send_rate = rate;
+        self
+    }
+
+    /// Sets the recommended send rate of a bad network, see `Config::bad_network_send_rate`.
+    pub fn bad_network_send_rate(mut self, rate: u32) -> Self {
+        self.config.bad_network_send_rate = rate;
+        self
+    }
+
+    /// Sets how long a bad network has to be good before it is considered good again, see `Config::network_recovery_time`.
+    pub fn network_recovery_time(mut self, recovery_time: Duration) -> Self {
+        self.config.network_recovery_time = recovery_time;
+        self
+    }
+
+    /// Sets the thresholds of an excellent connection, see `Config::excellent_quality`.
+    pub fn excellent_quality(mut self, thresholds: QualityThresholds) -> Self {
+        self.config.excellent_quality = thresholds;
+        self
+    }
+
+    /// Sets the thresholds of a good connection, see `Config::good_quality`.
+    pub fn good_quality(mut self, thresholds: QualityThresholds) -> Self {
+        self.config.good_quality = thresholds;
+        self
+    }
+
+    /// Sets the thresholds of a poor connection, see `Config::poor_quality`.
+    pub fn poor_quality(mut self, thresholds: QualityThresholds) -> Self {
+        self.config.poor_quality = thresholds;
+        self
+    }
+
+    /// Sets the size of the socket event buffer, see `Config::socket_event_buffer_size`.
+    pub fn socket_event_buffer_size(mut self, size: usize) -> Self {
+        self.config.socket_event_buffer_size = size;
+        self
+    }
+
+    /// Sets how long polling blocks for socket events, see `Config::socket_polling_timeout`.
+    pub fn socket_polling_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.socket_polling_timeout = timeout;
+        self
+    }
+
+    /// Sets how often sending a datagram is retried after a transient error, see `Config::send_retries`.
+    pub fn send_retries(mut self, send_retries: u8) -> Self {
+        self.config.send_retries = send_retries;
+        self
+    }
+
+    /// Sets how many reliable packets may be in flight before the connection is dropped, see `Config::max_packets_in_flight`.
+    pub fn max_packets_in_flight(mut self, max_packets_in_flight: u16) -> Self {
+        self.config.max_packets_in_flight = max_packets_in_flight;
+        self
+    }
+
+    /// Sets how many reliable packets may wait for their acknowledgment, see `Config::max_pending_packets`.
+    pub fn max_pending_packets(mut self, max_pending_packets: u16) -> Self {
+        self.config.max_pending_packets = Some(max_pending_packets);
+        self
+    }
+
+    /// Sets how many bytes per second may be sent to a connection, see `Config::max_bytes_per_second`.
+    pub fn max_bytes_per_second(mut self, max_bytes_per_second: u32) -> Self {
+        self.config.max_bytes_per_second = Some(max_bytes_per_second);
+        self
+    }
+
+    /// Sets how many bytes may be sent at once, see `Config::send_burst_size`.
+    pub fn send_burst_size(mut self, send_burst_size: u32) -> Self {
+        self.config.send_burst_size = send_burst_size;
+        self
+    }
+
+    /// Sets whether rate limited packets are queued or not sent, see `Config::defer_rate_limited_packets`.
+    pub fn defer_rate_limited_packets(mut self, defer_rate_limited_packets: bool) -> Self {
+        self.config.defer_rate_limited_packets = defer_rate_limited_packets;
+        self
+    }
+
+    /// Sets whether small messages are packed into one datagram, see `Config::batch_messages`.
+    pub fn batch_messages(mut self, batch_messages: bool) -> Self {
+        self.config.batch_messages = batch_messages;
+        self
+    }
+
+    /// Sets how many connections are kept at most, see `Config::max_connections`.
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.config.max_connections = max_connections;
+        self
+    }
+
+    /// Sets how many connections may be challenged at the same time, see `Config::max_challenged_connections`.
+    pub fn max_challenged_connections(mut self, max_challenged_connections: usize) -> Self {
+        self.config.max_challenged_connections = max_challenged_connections;
+        self
+    }
+
+    /// Sets whether packets from the own address are dropped, see `Config::reject_self_packets`.
+    pub fn reject_self_packets(mut self, reject_self_packets: bool) -> Self {
+        self.config.reject_self_packets = reject_self_packets;
+        self
+    }
+
+    /// Sets how often a dropped reliable packet is resent before it is given up on, see `Config::max_resends`.
+    pub fn max_resends(mut self, max_resends: u16) -> Self {
+        self.config.max_resends = Some(max_resends);
+        self
+    }
+
+    /// Sets whether giving up on a packet fails the connection, see `Config::disconnect_on_max_resends`.
+    pub fn disconnect_on_max_resends(mut self, disconnect_on_max_resends: bool) -> Self {
+        self.config.disconnect_on_max_resends = disconnect_on_max_resends;
+        self
+    }
+
+    /// Sets how many reports of dropped packets are held back at most, see `Config::max_dropped_reports`.
+    pub fn max_dropped_reports(mut self, max_dropped_reports: usize) -> Self {
+        self.config.max_dropped_reports = Some(max_dropped_reports);
+        self
+    }
+
+    /// Sets what happens to reports that do not fit anymore, see `Config::dropped_reports_overflow`.
+    pub fn dropped_reports_overflow(mut self, dropped_reports_overflow: OverflowPolicy) -> Self {
+        self.config.dropped_reports_overflow = dropped_reports_overflow;
+        self
+    }
+
+    /// Sets how many ordered packets wait for a missing one at most, see `Config::max_reorder_window`.
+    pub fn max_reorder_window(mut self, max_reorder_window: u16) -> Self {
+        self.config.max_reorder_window = Some(max_reorder_window);
+        self
+    }
+
+    /// Sets the codec outgoing payloads are compressed with, see `Config::compression`.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.config.compression = compression;
+        self
+    }
+
+    /// Sets the width of the acknowledgment bitfield, see `Config::ack_field_width`.
+    pub fn ack_field_width(mut self, ack_field_width: AckFieldWidth) -> Self {
+        self.config.ack_field_width = ack_field_width;
+        self
+    }
+
+    /// Sets whether late packets are acknowledged selectively, see `Config::selective_acks`.
+    pub fn selective_acks(mut self, selective_acks: bool) -> Self {
+        self.config.selective_acks = selective_acks;
+        self
+    }
+
+    /// Sets whether missing packets are reported right away, see `Config::negative_acks`.
+    pub fn negative_acks(mut self, negative_acks: bool) -> Self {
+        self.config.negative_acks = negative_acks;
+        self
+    }
+
+    /// Sets whether user packets carry the time they were sent at, see `Config::packet_timestamps`.
+    pub fn packet_timestamps(mut self, packet_timestamps: bool) -> Self {
+        self.config.packet_timestamps = packet_timestamps;
+        self
+    }
+
+    /// Sets whether connections have to complete a handshake first, see `Config::require_handshake`.
+    pub fn require_handshake(mut self, require_handshake: bool) -> Self {
+        self.config.require_handshake = require_handshake;
+        self
+    }
+
+    /// Sets how long a remote host has to answer its challenge, see `Config::handshake_timeout`.
+    pub fn handshake_timeout(mut self, handshake_timeout: Duration) -> Self {
+        self.config.handshake_timeout = handshake_timeout;
+        self
+    }
+
+    /// Enables encryption with the given key, see `Config::encryption_key`.
+    #[cfg(feature = "encryption")]
+    pub fn encryption_key(mut self, key: EncryptionKey) -> Self {
+        self.config.encryption_key = Some(key);
+        self
+    }
+
+    /// Validates the configuration and returns it.
+    pub fn build(self) -> Result<Config> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Config;
+    use crate::error::{ConfigErrorKind, ErrorKind};
+    use crate::net::constants::{DEFAULT_MTU, FRAGMENT_SIZE_DEFAULT, MAX_FRAGMENTS_DEFAULT};
+    use crate::net::QualityThresholds;
+    use crate::packet::{AckFieldWidth, DeliveryGuarantee};
+
+    fn assert_invalid(config: crate::Result<Config>, expected: ConfigErrorKind) {
+        match config {
+            Err(ErrorKind::InvalidConfig(kind)) => assert_eq!(kind, expected),
+            _ => panic!["Supposed to get {:?}", expected],
+        }
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        assert!(Config::default().validate().is_ok());
+        assert!(Config::builder().build().is_ok());
+    }
+
+    #[test]
+    fn builder_sets_options() {
+        let config = Config::builder()
+            .idle_connection_timeout(Duration::from_secs(2))
+            .heartbeat_interval(Duration::from_secs(1))
+            .max_packets_in_flight(64)
+            .build()
+            .unwrap();
+
+        assert_eq!(config.idle_connection_timeout, Duration::from_secs(2));
+        assert_eq!(config.heartbeat_interval, Some(Duration::from_secs(1)));
+        assert_eq!(config.max_packets_in_flight, 64);
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        assert_invalid(
+            Config::builder().fragment_size(0).build(),
+            ConfigErrorKind::NoFragments,
+        );
+        assert_invalid(
+            Config::builder().max_fragments(0).build(),
+            ConfigErrorKind::NoFragments,
+        );
+        assert_invalid(
+            Config::builder()
+                .idle_connection_timeout(Duration::from_secs(1))
+                .timeout_check_interval(Duration::from_secs(2))
+                .build(),
+            ConfigErrorKind::TimeoutCheckIntervalExceedsTimeout,
+        );
+        assert_invalid(
+            Config::builder()
+                .idle_connection_timeout(Duration::from_secs(1))
+                .heartbeat_interval(Duration::from_secs(1))
+                .build(),
+            ConfigErrorKind::HeartbeatIntervalExceedsTimeout,
+        );
+        assert_invalid(
+            Config::builder().rtt_smoothing_factor(1.5).build(),
+            ConfigErrorKind::InvalidRttSmoothingFactor,
+        );
+        assert_invalid(
+            Config::builder().bad_network_send_rate(60).build(),
+            ConfigErrorKind::BadSendRateExceedsGoodSendRate,
+        );
+        assert_invalid(
+            Config::builder()
+                .excellent_quality(QualityThresholds::new(
+                    Duration::from_millis(150),
+                    Duration::from_millis(10),
+                    0.01,
+                ))
+                .build(),
+            ConfigErrorKind::UnorderedQualityThresholds,
+        );
+        assert_invalid(
+            Config::builder().max_bytes_per_second(0).build(),
+            ConfigErrorKind::NoBandwidth,
+        );
+        assert_invalid(
+            Config::builder().receive_buffer_max_size(1000).build(),
+            ConfigErrorKind::FragmentExceedsReceiveBuffer,
+        );
+        // the headers of the first fragment have to fit as well.
+        assert_invalid(
+            Config::builder()
+                .fragment_size(1000)
+                .receive_buffer_max_size(1020)
+                .build(),
+            ConfigErrorKind::FragmentExceedsReceiveBuffer,
+        );
+        assert!(Config::builder()
+            .fragment_size(1000)
+            .receive_buffer_max_size(1021)
+            .build()
+            .is_ok());
+        assert_invalid(
+            Config::builder()
+                .fragment_size(1000)
+                .receive_buffer_max_size(1021)
+                .ack_field_width(AckFieldWidth::Bits64)
+                .build(),
+            ConfigErrorKind::FragmentExceedsReceiveBuffer,
+        );
+        assert_invalid(
+            Config::builder().max_packets_in_flight(32768).build(),
+            ConfigErrorKind::PacketsInFlightExceedSequenceSpace,
+        );
+        assert!(Config::builder()
+            .max_packets_in_flight(32767)
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn encryption_overhead_has_to_fit_into_the_receive_buffer() {
+        let config = |receive_buffer_max_size| {
+            Config::builder()
+                .fragment_size(1000)
+                .receive_buffer_max_size(receive_buffer_max_size)
+                .encryption_key([1; 32].into())
+                .build()
+        };
+
+        assert_invalid(config(1060), ConfigErrorKind::FragmentExceedsReceiveBuffer);
+        assert!(config(1061).is_ok());
+    }
+
+    #[test]
+    fn unreliable_payloads_and_their_headers_have_to_fit_into_the_receive_buffer() {
+        let config = Config {
+            receive_buffer_max_size: 1000,
+            ..Config::default()
+        };
+        // the standard, fragment and acknowledgment headers and the checksum.
+        let max = 1000 - 21;
+
+        assert!(config
+            .check_payload_size(DeliveryGuarantee::Unreliable, max)
+            .is_ok());
+        match config.check_payload_size(DeliveryGuarantee::Unreliable, max + 1) {
+            Err(ErrorKind::PayloadTooLarge { size, max: limit }) => {
+                assert_eq!((size, limit), (max + 1, max))
+            }
+            _ => panic!["Supposed to get a payload too large error"],
         }
+
+        // the send time is appended as well.
+        let config = Config {
+            packet_timestamps: true,
+            ..config
+        };
+        assert!(config
+            .check_payload_size(DeliveryGuarantee::Unreliable, max - 4)
+            .is_ok());
+        assert!(config
+            .check_payload_size(DeliveryGuarantee::Unreliable, max - 3)
+            .is_err());
+    }
+
+    #[test]
+    fn default_config_uses_default_constants() {
+        let config = Config::default();
+
+        assert_eq!(config.max_fragments, MAX_FRAGMENTS_DEFAULT as u8);
+        assert_eq!(config.fragment_size, FRAGMENT_SIZE_DEFAULT);
+        assert_eq!(
+            config.max_packet_size,
+            (MAX_FRAGMENTS_DEFAULT * FRAGMENT_SIZE_DEFAULT) as usize
+        );
+        assert_eq!(config.receive_buffer_max_size, DEFAULT_MTU as usize);
+        assert_eq!(config.idle_connection_timeout, Duration::from_secs(5));
+        assert_eq!(config.heartbeat_interval, None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn config_round_trips_through_serde() {
+        let config = Config::builder()
+            .idle_connection_timeout(Duration::from_secs(2))
+            .heartbeat_interval(Duration::from_millis(500))
+            .ack_field_width(AckFieldWidth::Bits64)
+            .max_resends(3)
+            .build()
+            .unwrap();
+
+        let bytes = bincode::serialize(&config).unwrap();
+        let deserialized: Config = bincode::deserialize(&bytes).unwrap();
+
+        assert_eq!(format!("{:?}", deserialized), format!("{:?}", config));
+    }
+
+    #[cfg(feature = "json")]
+    fn assert_invalid_file(json: &str, expected: ConfigErrorKind) {
+        assert_invalid(Config::from_reader(json.as_bytes()), expected);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn config_is_loaded_from_a_file() {
+        let path = std::env::temp_dir().join(format!("laminar-config-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{
+                "idle_connection_timeout": { "secs": 2, "nanos": 0 },
+                "max_packets_in_flight": 64,
+                "packet_timestamps": true
+            }"#,
+        )
+        .unwrap();
+
+        let config = Config::from_path(&path);
+        std::fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+
+        assert_eq!(config.idle_connection_timeout, Duration::from_secs(2));
+        assert_eq!(config.max_packets_in_flight, 64);
+        assert!(config.packet_timestamps);
+        // the options that are not in the file keep their default.
+        assert_eq!(config.fragment_size, FRAGMENT_SIZE_DEFAULT);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn missing_file_is_an_io_error() {
+        match Config::from_path("/nonexistent/laminar.json") {
+            Err(ErrorKind::IOError(_)) => {}
+            _ => panic!["Supposed to get an io error"],
+        }
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn malformed_file_is_rejected() {
+        match Config::from_reader(r#"{ "fragment_size": "big" }"#.as_bytes()) {
+            Err(ErrorKind::InvalidConfig(ConfigErrorKind::MalformedFile(_))) => {}
+            _ => panic!["Supposed to get a malformed file error"],
+        }
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn file_without_fragments_is_rejected() {
+        assert_invalid_file(r#"{ "max_fragments": 0 }"#, ConfigErrorKind::NoFragments);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn file_with_a_timeout_check_interval_longer_than_the_timeout_is_rejected() {
+        assert_invalid_file(
+            r#"{ "timeout_check_interval": { "secs": 6, "nanos": 0 } }"#,
+            ConfigErrorKind::TimeoutCheckIntervalExceedsTimeout,
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn file_with_a_heartbeat_interval_longer_than_the_timeout_is_rejected() {
+        assert_invalid_file(
+            r#"{ "heartbeat_interval": { "secs": 5, "nanos": 0 } }"#,
+            ConfigErrorKind::HeartbeatIntervalExceedsTimeout,
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn file_with_an_invalid_rtt_smoothing_factor_is_rejected() {
+        assert_invalid_file(
+            r#"{ "rtt_smoothing_factor": 1.5 }"#,
+            ConfigErrorKind::InvalidRttSmoothingFactor,
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn file_with_a_bad_send_rate_above_the_good_send_rate_is_rejected() {
+        assert_invalid_file(
+            r#"{ "bad_network_send_rate": 60 }"#,
+            ConfigErrorKind::BadSendRateExceedsGoodSendRate,
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn file_with_unordered_quality_thresholds_is_rejected() {
+        assert_invalid_file(
+            r#"{ "excellent_quality": { "max_rtt": { "secs": 0, "nanos": 150000000 }, "max_jitter": { "secs": 0, "nanos": 10000000 }, "max_loss_rate": 0.01 } }"#,
+            ConfigErrorKind::UnorderedQualityThresholds,
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn file_without_bandwidth_is_rejected() {
+        assert_invalid_file(r#"{ "send_burst_size": 0 }"#, ConfigErrorKind::NoBandwidth);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn file_with_fragments_bigger_than_the_receive_buffer_is_rejected() {
+        assert_invalid_file(
+            r#"{ "receive_buffer_max_size": 1000 }"#,
+            ConfigErrorKind::FragmentExceedsReceiveBuffer,
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn file_with_more_packets_in_flight_than_sequence_numbers_is_rejected() {
+        assert_invalid_file(
+            r#"{ "max_packets_in_flight": 32768 }"#,
+            ConfigErrorKind::PacketsInFlightExceedSequenceSpace,
+        );
     }
 }
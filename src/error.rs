@@ -8,7 +8,7 @@ use std::{
 
 use crossbeam_channel::SendError;
 
-use crate::SocketEvent;
+use crate::{AckFieldWidth, SocketEvent};
 
 /// Wrapped result type for Laminar errors.
 pub type Result<T> = result::Result<T, ErrorKind>;
@@ -22,12 +22,60 @@ pub enum ErrorKind {
     FragmentError(FragmentErrorKind),
     /// Error relating to receiving or parsing a packet
     PacketError(PacketErrorKind),
+    /// The configuration contains options that can not be used together
+    InvalidConfig(ConfigErrorKind),
     /// Wrapper around a std io::Error
     IOError(io::Error),
     /// Did not receive enough data
     ReceivedDataToShort,
     /// Protocol versions did not match
-    ProtocolVersionMismatch,
+    ProtocolVersionMismatch {
+        /// The crc16 of our own protocol version
+        expected: u16,
+        /// The crc16 of the protocol version in the received packet
+        got: u16,
+    },
+    /// The acknowledgment bitfield of the received packet has another width than ours
+    AckFieldWidthMismatch {
+        /// The width we are configured with
+        expected: AckFieldWidth,
+        /// The width of the received packet
+        got: AckFieldWidth,
+    },
+    /// The checksum of the received data did not match
+    CorruptPacket,
+    /// The payload of the received packet could not be decompressed
+    DecompressionFailed,
+    /// The received packet could not be decrypted or authenticated
+    DecryptionFailed,
+    /// The received packet was received before or is too old to be accepted, see `Config::encryption_key`
+    ReplayedPacket,
+    /// A packet was received before the handshake of the connection completed
+    HandshakeNotCompleted,
+    /// A connection with a new address was refused, because `Config::max_connections` or
+    /// `Config::max_challenged_connections` was reached
+    ConnectionLimitReached,
+    /// A reliable packet was not sent, because `Config::max_pending_packets` are waiting for their acknowledgment
+    SendQueueFull,
+    /// A reliable packet was not sent, because `Config::max_dropped_reports` reports of dropped packets
+    /// are held back and `Config::dropped_reports_overflow` is `OverflowPolicy::Error`
+    DroppedBufferFull,
+    /// A packet was not sent, because its payload is bigger than a single datagram for unreliable
+    /// packets, or bigger than all allowed fragments for reliable packets
+    PayloadTooLarge {
+        /// The size of the payload in bytes
+        size: usize,
+        /// The maximal payload size in bytes for the delivery guarantee of the packet
+        max: usize,
+    },
+    /// A packet was not sent, because it exceeded `Config::max_bytes_per_second`
+    RateLimited,
+    /// A packet was received from the address the socket is bound to, see `Config::reject_self_packets`
+    SelfPacket,
+    /// A packet was received from an address that is not accepted, see `Socket::set_address_filter`
+    AddressRejected,
+    /// A reliable packet that was sent with `Socket::send_reliable_blocking` was not acknowledged in time
+    AcknowledgmentTimeout,
     /// Could not send on `SendChannel`.
     SendError(SendError<SocketEvent>),
     /// Expected header but could not be read from buffer.
@@ -39,26 +87,90 @@ impl Display for ErrorKind {
         match self {
             ErrorKind::DecodingError(e) => write!(
                 fmt,
-                "Something went wrong with parsing the header. Reason: {:?}.",
+                "Something went wrong with parsing the header. Reason: {}",
                 e
             ),
             ErrorKind::FragmentError(e) => write!(
                 fmt,
-                "Something went wrong with receiving/parsing fragments. Reason: {:?}.",
+                "Something went wrong with receiving/parsing fragments. Reason: {}",
                 e
             ),
             ErrorKind::PacketError(e) => write!(
                 fmt,
-                "Something went wrong with receiving/parsing packets. Reason: {:?}.",
+                "Something went wrong with receiving/parsing packets. Reason: {}",
                 e
             ),
-            ErrorKind::IOError(e) => write!(fmt, "An IO Error occurred. Reason: {:?}.", e),
+            ErrorKind::InvalidConfig(e) => {
+                write!(fmt, "The configuration is not valid. Reason: {}", e)
+            }
+            ErrorKind::IOError(e) => write!(fmt, "An IO Error occurred. Reason: {}.", e),
             ErrorKind::ReceivedDataToShort => {
                 write!(fmt, "The received data did not have any length.")
             }
-            ErrorKind::ProtocolVersionMismatch => {
-                write!(fmt, "The protocol versions do not match.")
+            ErrorKind::ProtocolVersionMismatch { expected, got } => write!(
+                fmt,
+                "The protocol versions do not match. Expected: {}, got: {}.",
+                expected, got
+            ),
+            ErrorKind::AckFieldWidthMismatch { expected, got } => write!(
+                fmt,
+                "The acknowledgment field widths do not match. Expected: {:?}, got: {:?}.",
+                expected, got
+            ),
+            ErrorKind::CorruptPacket => {
+                write!(fmt, "The checksum of the received data does not match.")
+            }
+            ErrorKind::DecompressionFailed => write!(
+                fmt,
+                "The payload of the received data could not be decompressed."
+            ),
+            ErrorKind::DecryptionFailed => write!(
+                fmt,
+                "The received data could not be decrypted or authenticated."
+            ),
+            ErrorKind::ReplayedPacket => write!(
+                fmt,
+                "The received data was received before or is too old to be accepted."
+            ),
+            ErrorKind::HandshakeNotCompleted => write!(
+                fmt,
+                "A packet was received before the handshake of the connection completed."
+            ),
+            ErrorKind::ConnectionLimitReached => {
+                write!(
+                    fmt,
+                    "No more connections can be kept, the limit was reached."
+                )
             }
+            ErrorKind::SendQueueFull => write!(
+                fmt,
+                "Too many reliable packets are waiting for their acknowledgment."
+            ),
+            ErrorKind::DroppedBufferFull => write!(
+                fmt,
+                "Too many reports of dropped packets are waiting to be taken."
+            ),
+            ErrorKind::PayloadTooLarge { size, max } => write!(
+                fmt,
+                "The payload of {} bytes is bigger than the max allowed size of {} bytes.",
+                size, max
+            ),
+            ErrorKind::RateLimited => write!(
+                fmt,
+                "The packet was not sent, the bandwidth limit of the connection was reached."
+            ),
+            ErrorKind::SelfPacket => write!(
+                fmt,
+                "The packet was received from the address of the socket itself."
+            ),
+            ErrorKind::AddressRejected => write!(
+                fmt,
+                "The packet was received from an address that is not accepted."
+            ),
+            ErrorKind::AcknowledgmentTimeout => write!(
+                fmt,
+                "The reliable packet was not acknowledged before the timeout elapsed."
+            ),
             ErrorKind::SendError(e) => write!(
                 fmt,
                 "Could not sent on channel because it was closed. Reason: {:?}",
@@ -73,7 +185,19 @@ impl Display for ErrorKind {
     }
 }
 
-impl Error for ErrorKind {}
+impl Error for ErrorKind {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ErrorKind::DecodingError(e) => Some(e),
+            ErrorKind::FragmentError(e) => Some(e),
+            ErrorKind::PacketError(e) => Some(e),
+            ErrorKind::InvalidConfig(e) => Some(e),
+            ErrorKind::IOError(e) => Some(e),
+            ErrorKind::SendError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
 /// Errors that could occur while parsing packet contents
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -84,6 +208,12 @@ pub enum DecodingErrorKind {
     OrderingGuarantee,
     /// The [DeliveryGuarantee] could not be read
     DeliveryGuarantee,
+    /// The [Compression] could not be read or is not enabled
+    Compression,
+    /// The messages of a batch could not be read
+    Batch,
+    /// The sequence numbers of a negative acknowledgment could not be read
+    NegativeAck,
 }
 
 impl Display for DecodingErrorKind {
@@ -96,10 +226,20 @@ impl Display for DecodingErrorKind {
             DecodingErrorKind::DeliveryGuarantee => {
                 write!(fmt, "The delivery guarantee could not be read.")
             }
+            DecodingErrorKind::Compression => {
+                write!(fmt, "The compression could not be read or is not enabled.")
+            }
+            DecodingErrorKind::Batch => write!(fmt, "The messages of a batch could not be read."),
+            DecodingErrorKind::NegativeAck => write!(
+                fmt,
+                "The sequence numbers of a negative acknowledgment could not be read."
+            ),
         }
     }
 }
 
+impl Error for DecodingErrorKind {}
+
 /// Errors that could occur while parsing packet contents
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum PacketErrorKind {
@@ -122,6 +262,8 @@ impl Display for PacketErrorKind {
     }
 }
 
+impl Error for PacketErrorKind {}
+
 /// Errors that could occur with constructing/parsing fragment contents
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum FragmentErrorKind {
@@ -129,6 +271,8 @@ pub enum FragmentErrorKind {
     PacketHeaderNotFound,
     /// Max number of allowed fragments has been exceeded
     ExceededMaxFragments,
+    /// The fragment is bigger than the configured fragment size
+    ExceededFragmentSize,
     /// This fragment was already processed
     AlreadyProcessedFragment,
     /// Attempted to fragment with an incorrect number of fragments
@@ -139,6 +283,8 @@ pub enum FragmentErrorKind {
     MultipleAckHeaders,
     /// Ack header is missing from a finished set of fragments
     MissingAckHeader,
+    /// A fragment other than the last one is smaller than the fragment size
+    IncompleteFragment,
 }
 
 impl Display for FragmentErrorKind {
@@ -151,6 +297,9 @@ impl Display for FragmentErrorKind {
                 fmt,
                 "The total numbers of fragments are bigger than the allowed fragments."
             ),
+            FragmentErrorKind::ExceededFragmentSize => {
+                write!(fmt, "The fragment is bigger than the allowed fragment size.")
+            }
             FragmentErrorKind::AlreadyProcessedFragment => {
                 write!(fmt, "The fragment received was already processed.")
             }
@@ -170,10 +319,87 @@ impl Display for FragmentErrorKind {
                 fmt,
                 "No ack headers were registered with any of the fragments."
             ),
+            FragmentErrorKind::IncompleteFragment => write!(
+                fmt,
+                "A fragment other than the last one is smaller than the fragment size."
+            ),
         }
     }
 }
 
+impl Error for FragmentErrorKind {}
+
+/// Errors that could occur while validating a configuration
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ConfigErrorKind {
+    /// The fragment size or the maximal number of fragments is zero
+    NoFragments,
+    /// The timeout check interval is longer than the idle connection timeout
+    TimeoutCheckIntervalExceedsTimeout,
+    /// The heartbeat interval is not shorter than the idle connection timeout
+    HeartbeatIntervalExceedsTimeout,
+    /// The rtt smoothing factor is not between 0 and 1
+    InvalidRttSmoothingFactor,
+    /// The send rate of a bad network is higher than the one of a good network
+    BadSendRateExceedsGoodSendRate,
+    /// The thresholds of a connection quality are looser than the ones of the next worse quality
+    UnorderedQualityThresholds,
+    /// The bandwidth limit or the burst size is zero
+    NoBandwidth,
+    /// The fragment size is bigger than the buffer datagrams are received into
+    FragmentExceedsReceiveBuffer,
+    /// More packets may be in flight than the 16 bit sequence numbers can tell apart after they wrap around
+    PacketsInFlightExceedSequenceSpace,
+    /// The configuration file could not be deserialized, with the reason of the deserializer
+    MalformedFile(String),
+}
+
+impl Display for ConfigErrorKind {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            ConfigErrorKind::NoFragments => {
+                write!(fmt, "The fragment size and max fragments can not be zero.")
+            }
+            ConfigErrorKind::TimeoutCheckIntervalExceedsTimeout => write!(
+                fmt,
+                "The timeout check interval can not be longer than the idle connection timeout."
+            ),
+            ConfigErrorKind::HeartbeatIntervalExceedsTimeout => write!(
+                fmt,
+                "The heartbeat interval has to be shorter than the idle connection timeout."
+            ),
+            ConfigErrorKind::InvalidRttSmoothingFactor => {
+                write!(fmt, "The rtt smoothing factor has to be between 0 and 1.")
+            }
+            ConfigErrorKind::BadSendRateExceedsGoodSendRate => write!(
+                fmt,
+                "The bad network send rate is higher than the good network send rate."
+            ),
+            ConfigErrorKind::UnorderedQualityThresholds => write!(
+                fmt,
+                "The thresholds of a connection quality can not be looser than the ones of a worse quality."
+            ),
+            ConfigErrorKind::NoBandwidth => write!(
+                fmt,
+                "The bandwidth limit and the send burst size can not be zero."
+            ),
+            ConfigErrorKind::FragmentExceedsReceiveBuffer => write!(
+                fmt,
+                "The fragment size and the fragment headers can not be bigger than the receive buffer."
+            ),
+            ConfigErrorKind::PacketsInFlightExceedSequenceSpace => write!(
+                fmt,
+                "At most 32767 packets can be in flight, half of the sequence numbers."
+            ),
+            ConfigErrorKind::MalformedFile(ref reason) => {
+                write!(fmt, "The configuration file can not be read: {}.", reason)
+            }
+        }
+    }
+}
+
+impl Error for ConfigErrorKind {}
+
 impl From<io::Error> for ErrorKind {
     fn from(inner: io::Error) -> ErrorKind {
         ErrorKind::IOError(inner)
@@ -186,6 +412,12 @@ impl From<PacketErrorKind> for ErrorKind {
     }
 }
 
+impl From<ConfigErrorKind> for ErrorKind {
+    fn from(inner: ConfigErrorKind) -> Self {
+        ErrorKind::InvalidConfig(inner)
+    }
+}
+
 impl From<FragmentErrorKind> for ErrorKind {
     fn from(inner: FragmentErrorKind) -> Self {
         ErrorKind::FragmentError(inner)
@@ -206,4 +438,38 @@ mod tests {
     fn able_to_box_errors() {
         let _: Box<dyn Error> = Box::new(ErrorKind::CouldNotReadHeader("".into()));
     }
+
+    #[test]
+    fn wrapped_errors_are_exposed_as_source() {
+        let error = ErrorKind::from(io::Error::other("inner"));
+        assert_eq!(error.source().unwrap().to_string(), "inner");
+
+        let error = ErrorKind::from(PacketErrorKind::ExceededMaxPacketSize);
+        assert_eq!(
+            error.source().unwrap().to_string(),
+            PacketErrorKind::ExceededMaxPacketSize.to_string()
+        );
+
+        assert!(ErrorKind::CorruptPacket.source().is_none());
+    }
+
+    #[test]
+    fn wrapped_errors_are_displayed_with_their_reason() {
+        assert_eq!(
+            ErrorKind::from(PacketErrorKind::ExceededMaxPacketSize).to_string(),
+            "Something went wrong with receiving/parsing packets. Reason: The packet size was bigger than the max allowed size."
+        );
+        assert_eq!(
+            ErrorKind::PayloadTooLarge {
+                size: 2000,
+                max: 1450
+            }
+            .to_string(),
+            "The payload of 2000 bytes is bigger than the max allowed size of 1450 bytes."
+        );
+        assert_eq!(
+            ErrorKind::from(io::Error::other("inner")).to_string(),
+            "An IO Error occurred. Reason: inner."
+        );
+    }
 }
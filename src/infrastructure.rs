@@ -3,11 +3,15 @@
 
 pub use self::acknowledgment::AcknowledgmentHandler;
 pub use self::acknowledgment::SentPacket;
+pub use self::bandwidth::{BandwidthLimiter, BandwidthSampler};
 pub use self::congestion::CongestionHandler;
 pub use self::fragmenter::Fragmentation;
+pub use self::priority_lanes::PriorityLanes;
 
 mod acknowledgment;
+mod bandwidth;
 mod congestion;
 mod fragmenter;
+mod priority_lanes;
 
 pub mod arranging;
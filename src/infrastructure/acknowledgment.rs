@@ -1,33 +1,65 @@
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::time::{Duration, Instant};
 
-use crate::packet::{OrderingGuarantee, PacketType, SequenceNumber};
+use crate::net::constants::{MAX_NEGATIVE_ACKS, MAX_SELECTIVE_ACK_RANGES, SELECTIVE_ACK_HISTORY};
+use crate::packet::{AckFieldWidth, OrderingGuarantee, PacketType, SequenceNumber};
 use crate::sequence_buffer::{sequence_greater_than, sequence_less_than, SequenceBuffer};
 
-const REDUNDANT_PACKET_ACKS_SIZE: u16 = 32;
-const DEFAULT_SEND_PACKETS_SIZE: usize = 256;
-
 /// Responsible for handling the acknowledgment of packets.
 pub struct AcknowledgmentHandler {
     // Local sequence number which we'll bump each time we send a new packet over the network.
     sequence_number: SequenceNumber,
     // The last acked sequence number of the packets we've sent to the remote host.
     remote_ack_sequence_num: SequenceNumber,
-    // Using a `Hashmap` to track every packet we send out so we can ensure that we can resend when
-    // dropped.
-    sent_packets: HashMap<u16, SentPacket>,
-    // However, we can only reasonably ack up to `REDUNDANT_PACKET_ACKS_SIZE + 1` packets on each
+    // Tracks every packet we send out so we can ensure that we can resend when dropped.
+    sent_packets: SequenceBuffer<SentPacket>,
+    // The sent packets whose slots were needed for newer ones before they were acknowledged.
+    evicted: Vec<SentPacket>,
+    // The sequence numbers of the sent packets that were declared dropped, they are not acknowledged anymore.
+    declared_dropped: SequenceBuffer<()>,
+    // The number of late acknowledgments of packets that were declared dropped, since they were taken last.
+    spurious_retransmits: usize,
+    // However, we can only reasonably ack up to `ack_field_width.bits() + 1` packets on each
     // message we send so this should be that large.
     received_packets: SequenceBuffer<ReceivedPacket>,
+    // The packets received further back than the bitfield reaches, which are acknowledged selectively.
+    received_history: SequenceBuffer<ReceivedPacket>,
+    // The number of packets that are acknowledged by the bitfield, on both sides.
+    ack_field_width: AckFieldWidth,
+    // The ids of the sent packets that were acknowledged since they were taken last.
+    acknowledged: Vec<u64>,
+    // The sequence numbers that were skipped by received packets since they were taken last.
+    missing: Vec<SequenceNumber>,
 }
 
 impl AcknowledgmentHandler {
     /// Constructs a new `AcknowledgmentHandler` with which you can perform acknowledgment operations.
-    pub fn new() -> Self {
+    ///
+    /// The bitfield it sends and expects covers as many packets as `ack_field_width` specifies.
+    /// The first packet that is sent gets `initial_sequence_number`.
+    /// At least `max_packets_in_flight` sent packets are tracked until they are acknowledged.
+    pub fn new(
+        ack_field_width: AckFieldWidth,
+        initial_sequence_number: SequenceNumber,
+        max_packets_in_flight: u16,
+    ) -> Self {
+        // one spare slot, so that exceeding the limit can be noticed. A power of two divides the
+        // sequence numbers evenly, then the slots stay in order when they wrap around.
+        let send_capacity = (u32::from(max_packets_in_flight) + 1)
+            .next_power_of_two()
+            .min(32768) as u16;
         AcknowledgmentHandler {
-            sequence_number: 0,
-            remote_ack_sequence_num: u16::max_value(),
-            sent_packets: HashMap::with_capacity(DEFAULT_SEND_PACKETS_SIZE),
-            received_packets: SequenceBuffer::with_capacity(REDUNDANT_PACKET_ACKS_SIZE + 1),
+            sequence_number: initial_sequence_number,
+            remote_ack_sequence_num: initial_sequence_number.wrapping_sub(1),
+            sent_packets: SequenceBuffer::with_capacity(send_capacity),
+            evicted: Vec::new(),
+            declared_dropped: SequenceBuffer::with_capacity(send_capacity),
+            spurious_retransmits: 0,
+            received_packets: SequenceBuffer::with_capacity(ack_field_width.bits() + 1),
+            received_history: SequenceBuffer::with_capacity(SELECTIVE_ACK_HISTORY),
+            ack_field_width,
+            acknowledged: Vec::new(),
+            missing: Vec::new(),
         }
     }
 
@@ -41,29 +73,35 @@ impl AcknowledgmentHandler {
         self.sequence_number
     }
 
+    /// Returns the newest of our sequence numbers that the remote host acknowledged.
+    pub fn remote_ack_sequence_num(&self) -> SequenceNumber {
+        self.remote_ack_sequence_num
+    }
+
     /// Returns the last sequence number received from the remote host (+1)
     pub fn remote_sequence_num(&self) -> SequenceNumber {
         self.received_packets.sequence_num().wrapping_sub(1)
     }
 
-    /// Returns the `ack_bitfield` corresponding to which of the past 32 packets we've
-    /// successfully received.
-    pub fn ack_bitfield(&self) -> u32 {
-        let most_recent_remote_seq_num: u16 = self.remote_sequence_num();
-        let mut ack_bitfield: u32 = 0;
-        let mut mask: u32 = 1;
-
-        // iterate the past `REDUNDANT_PACKET_ACKS_SIZE` received packets and set the corresponding
-        // bit for each packet which exists in the buffer.
-        for i in 1..=REDUNDANT_PACKET_ACKS_SIZE {
-            let sequence = most_recent_remote_seq_num.wrapping_sub(i);
-            if self.received_packets.exists(sequence) {
-                ack_bitfield |= mask;
-            }
-            mask <<= 1;
-        }
+    /// Returns true if a packet with this sequence number was already received recently.
+    pub fn already_received(&self, remote_seq_num: SequenceNumber) -> bool {
+        self.received_packets.exists(remote_seq_num)
+    }
 
-        ack_bitfield
+    /// Returns true if a packet with this sequence number arrives after a newer one, without being a duplicate.
+    /// Packets further back than the selective acknowledgment history are not considered.
+    pub fn arrived_out_of_order(&self, remote_seq_num: SequenceNumber) -> bool {
+        let newest = self.remote_sequence_num();
+        sequence_less_than(remote_seq_num, newest)
+            && newest.wrapping_sub(remote_seq_num) < SELECTIVE_ACK_HISTORY
+            && !self.received_history.exists(remote_seq_num)
+    }
+
+    /// Returns the `ack_bitfield` corresponding to which of the past 32 or 64 packets we've
+    /// successfully received.
+    pub fn ack_bitfield(&self) -> u64 {
+        self.received_packets
+            .generate_ack_field(self.ack_field_width.bits())
     }
 
     /// Process the incoming sequence number.
@@ -74,45 +112,100 @@ impl AcknowledgmentHandler {
         &mut self,
         remote_seq_num: u16,
         remote_ack_seq: u16,
-        mut remote_ack_field: u32,
+        mut remote_ack_field: u64,
     ) {
         // ensure that `self.remote_ack_sequence_num` is always increasing (with wrapping)
         if sequence_greater_than(remote_ack_seq, self.remote_ack_sequence_num) {
             self.remote_ack_sequence_num = remote_ack_seq;
         }
 
+        // the packets between the newest received one and this one are missing, unless they are reordered.
+        let newest = self.remote_sequence_num();
+        if self.received_packets.exists(newest) && sequence_greater_than(remote_seq_num, newest) {
+            let gap = remote_seq_num.wrapping_sub(newest) - 1;
+            if gap <= MAX_NEGATIVE_ACKS {
+                self.missing
+                    .extend((1..=gap).map(|i| newest.wrapping_add(i)));
+            }
+        }
+
         self.received_packets
             .insert(remote_seq_num, ReceivedPacket {});
+        self.received_history
+            .insert(remote_seq_num, ReceivedPacket {});
 
         // the current `remote_ack_seq` was (clearly) received so we should remove it
-        self.sent_packets.remove(&remote_ack_seq);
+        self.acknowledge(remote_ack_seq);
 
-        // The `remote_ack_field` is going to include whether or not the past 32 packets have been
+        // The `remote_ack_field` is going to include whether or not the past 32 or 64 packets have been
         // received successfully. If so, we have no need to resend old packets.
-        for i in 1..=REDUNDANT_PACKET_ACKS_SIZE {
+        for i in 1..=self.ack_field_width.bits() {
             let ack_sequence = remote_ack_seq.wrapping_sub(i);
             if remote_ack_field & 1 == 1 {
-                self.sent_packets.remove(&ack_sequence);
+                self.acknowledge(ack_sequence);
             }
             remote_ack_field >>= 1;
         }
     }
 
-    /// Enqueues the outgoing packet for acknowledgment.
+    /// Returns the ranges of received packets that are too old for the bitfield, the newest first.
+    ///
+    /// These are sent in a `SelectiveAckHeader`, so that packets which arrived late are not resent.
+    pub fn selective_acks(&self) -> Vec<(SequenceNumber, SequenceNumber)> {
+        let most_recent_remote_seq_num = self.remote_sequence_num();
+        let mut ranges = Vec::new();
+        let mut range: Option<(SequenceNumber, SequenceNumber)> = None;
+
+        for i in self.ack_field_width.bits() + 1..SELECTIVE_ACK_HISTORY {
+            let sequence = most_recent_remote_seq_num.wrapping_sub(i);
+            if self.received_history.exists(sequence) {
+                range = Some((sequence, range.map_or(sequence, |(_, end)| end)));
+            } else if let Some(range) = range.take() {
+                ranges.push(range);
+                if ranges.len() == usize::from(MAX_SELECTIVE_ACK_RANGES) {
+                    return ranges;
+                }
+            }
+        }
+        ranges.extend(range);
+        ranges
+    }
+
+    /// Acknowledges the packets in the given ranges, which the remote host received.
+    ///
+    /// Only as many ranges as are ever sent are processed, ranges longer than the history are ignored.
+    pub fn process_selective_acks(&mut self, ranges: &[(SequenceNumber, SequenceNumber)]) {
+        for (start, end) in ranges.iter().take(usize::from(MAX_SELECTIVE_ACK_RANGES)) {
+            let length = end.wrapping_sub(*start);
+            if length < SELECTIVE_ACK_HISTORY {
+                for i in 0..=length {
+                    self.acknowledge(start.wrapping_add(i));
+                }
+            }
+        }
+    }
+
+    /// Enqueues the outgoing packet for acknowledgment, `time` is when it is sent.
     pub fn process_outgoing(
         &mut self,
         packet_type: PacketType,
         payload: &[u8],
         ordering_guarantee: OrderingGuarantee,
         item_identifier: Option<SequenceNumber>,
+        time: Instant,
     ) {
+        self.evict_overwritten(self.sequence_number);
         self.sent_packets.insert(
             self.sequence_number,
             SentPacket {
+                sequence: self.sequence_number,
                 packet_type,
                 payload: Box::from(payload),
                 ordering_guarantee,
                 item_identifier,
+                resends: 0,
+                id: None,
+                sent_at: time,
             },
         );
 
@@ -120,32 +213,176 @@ impl AcknowledgmentHandler {
         self.sequence_number = self.sequence_number.wrapping_add(1);
     }
 
+    /// Returns the packet that was enqueued last, if it is not acknowledged yet.
+    pub fn last_sent_mut(&mut self) -> Option<&mut SentPacket> {
+        let last_sequence = self.sequence_number.wrapping_sub(1);
+        self.sent_packets.get_mut(last_sequence)
+    }
+
+    /// Returns the sequence numbers of the packets that are not acknowledged yet,
+    /// with how long they have been waiting at the given time.
+    ///
+    /// The packets are visited in no particular order.
+    pub fn pending_packets(
+        &self,
+        time: Instant,
+    ) -> impl Iterator<Item = (SequenceNumber, Duration)> + '_ {
+        self.sent_packets.iter().map(move |(_, packet)| {
+            (
+                packet.sequence,
+                time.saturating_duration_since(packet.sent_at),
+            )
+        })
+    }
+
+    /// Returns the ids of the packets that were acknowledged since this was called last.
+    ///
+    /// Every id is returned only once, acknowledging a packet again has no effect.
+    pub fn take_acknowledged(&mut self) -> Vec<u64> {
+        std::mem::take(&mut self.acknowledged)
+    }
+
+    /// Returns the sequence numbers of the received packets that were skipped since this was called last, the oldest first.
+    ///
+    /// Only gaps of up to `MAX_NEGATIVE_ACKS` packets are reported.
+    pub fn take_missing(&mut self) -> Vec<SequenceNumber> {
+        std::mem::take(&mut self.missing)
+    }
+
+    /// Removes the packets the remote host reported missing, so that they can be resent right away.
+    ///
+    /// Sequence numbers that are not in flight, because they were acknowledged already or belong to heartbeats, are ignored.
+    pub fn take_missing_packets(&mut self, sequences: &[SequenceNumber]) -> Vec<SentPacket> {
+        let missing: Vec<SentPacket> = sequences
+            .iter()
+            .flat_map(|sequence| self.sent_packets.remove(*sequence))
+            .collect();
+        self.declare_dropped(&missing);
+        missing
+    }
+
+    /// Returns how many packets that were declared dropped were acknowledged by the remote host
+    /// after all, since this was called last. They were resent although they had arrived.
+    ///
+    /// Every packet is counted only once.
+    pub fn take_spurious_retransmits(&mut self) -> usize {
+        std::mem::take(&mut self.spurious_retransmits)
+    }
+
+    // Removes an acknowledged packet and remembers its id, if it has one.
+    fn acknowledge(&mut self, sequence: SequenceNumber) {
+        match self.sent_packets.remove(sequence) {
+            Some(SentPacket { id: Some(id), .. }) => self.acknowledged.push(id),
+            Some(_) => {}
+            None => {
+                if self.declared_dropped.remove(sequence).is_some() {
+                    self.spurious_retransmits += 1;
+                }
+            }
+        }
+    }
+
+    // Remembers the sequence numbers of dropped packets, so that late acknowledgments of them are noticed.
+    fn declare_dropped(&mut self, dropped: &[SentPacket]) {
+        for packet in dropped {
+            self.declared_dropped.insert(packet.sequence, ());
+        }
+    }
+
+    /// Consumes a sequence number for an outgoing packet that carries an acknowledgment header
+    /// but should never be resent, like a heartbeat.
+    pub fn process_outgoing_unreliable(&mut self) {
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+    }
+
     /// Returns a `Vec` of packets we believe have been dropped.
+    ///
+    /// Packets that are in flight for so long that their slots were needed for newer ones count as dropped as well.
     pub fn dropped_packets(&mut self) -> Vec<SentPacket> {
-        let mut sent_sequences: Vec<SequenceNumber> = self.sent_packets.keys().cloned().collect();
-        sent_sequences.sort();
+        let mut dropped = std::mem::take(&mut self.evicted);
+        if self.sent_packets.is_empty() {
+            self.declare_dropped(&dropped);
+            return dropped;
+        }
+
+        // oldest first, the sequence numbers may have wrapped around since those packets were sent.
+        let sequence_number = self.sequence_number;
+        let mut sent_sequences: Vec<SequenceNumber> = self
+            .sent_packets
+            .iter()
+            .map(|(sequence, _)| sequence)
+            .collect();
+        sent_sequences.sort_by_key(|s| Reverse(sequence_number.wrapping_sub(*s)));
 
         let remote_ack_sequence = self.remote_ack_sequence_num;
-        sent_sequences
-            .into_iter()
-            .filter(|s| {
-                if sequence_less_than(*s, remote_ack_sequence) {
-                    remote_ack_sequence.wrapping_sub(*s) > REDUNDANT_PACKET_ACKS_SIZE
-                } else {
-                    false
-                }
-            })
-            .flat_map(|s| self.sent_packets.remove(&s))
-            .collect()
+        let ack_field_size = self.ack_field_width.bits();
+        dropped.extend(
+            sent_sequences
+                .into_iter()
+                .filter(|s| {
+                    if sequence_less_than(*s, remote_ack_sequence) {
+                        remote_ack_sequence.wrapping_sub(*s) > ack_field_size
+                    } else {
+                        false
+                    }
+                })
+                .flat_map(|s| self.sent_packets.remove(s)),
+        );
+        self.declare_dropped(&dropped);
+        dropped
+    }
+
+    // Moves the sent packets out of the buffer that an insert of the given sequence number would overwrite,
+    // the sequence numbers that were skipped by unreliable packets clear their slots as well.
+    fn evict_overwritten(&mut self, sequence: SequenceNumber) {
+        let next = self.sent_packets.sequence_num();
+        // older sequence numbers only ever replace themselves.
+        if self.sent_packets.is_empty() || sequence_less_than(sequence, next) {
+            return;
+        }
+
+        let skipped = sequence.wrapping_sub(next);
+        if usize::from(skipped) >= self.sent_packets.capacity() {
+            let sequences: Vec<SequenceNumber> = self.sent_packets.iter().map(|(s, _)| s).collect();
+            for s in sequences {
+                self.evicted.extend(self.sent_packets.remove(s));
+            }
+            return;
+        }
+
+        for i in 0..=skipped {
+            if let Some(replaced) = self.sent_packets.replaced_by(next.wrapping_add(i)) {
+                self.evicted.extend(self.sent_packets.remove(replaced));
+            }
+        }
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct SentPacket {
+    pub sequence: SequenceNumber,
     pub packet_type: PacketType,
     pub payload: Box<[u8]>,
     pub ordering_guarantee: OrderingGuarantee,
     pub item_identifier: Option<SequenceNumber>,
+    pub resends: u16,
+    pub id: Option<u64>,
+    pub sent_at: Instant,
+}
+
+impl Default for SentPacket {
+    fn default() -> Self {
+        SentPacket {
+            sequence: 0,
+            packet_type: PacketType::Packet,
+            payload: Box::default(),
+            ordering_guarantee: OrderingGuarantee::None,
+            item_identifier: None,
+            resends: 0,
+            id: None,
+            sent_at: Instant::now(),
+        }
+    }
 }
 
 // TODO: At some point we should put something useful here. Possibly timing information or total
@@ -155,15 +392,18 @@ pub struct ReceivedPacket;
 
 #[cfg(test)]
 mod test {
+    use std::time::Instant;
+
     use log::debug;
 
     use crate::infrastructure::acknowledgment::ReceivedPacket;
     use crate::infrastructure::{AcknowledgmentHandler, SentPacket};
-    use crate::packet::{OrderingGuarantee, PacketType};
+    use crate::net::constants::MAX_NEGATIVE_ACKS;
+    use crate::packet::{AckFieldWidth, OrderingGuarantee, PacketType};
 
     #[test]
     fn increment_local_seq_num_on_process_outgoing() {
-        let mut handler = AcknowledgmentHandler::new();
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
         assert_eq!(handler.local_sequence_num(), 0);
         for i in 0..10 {
             handler.process_outgoing(
@@ -171,6 +411,7 @@ mod test {
                 vec![].as_slice(),
                 OrderingGuarantee::None,
                 None,
+                Instant::now(),
             );
             assert_eq!(handler.local_sequence_num(), i + 1);
         }
@@ -178,42 +419,48 @@ mod test {
 
     #[test]
     fn local_seq_num_wraps_on_overflow() {
-        let mut handler = AcknowledgmentHandler::new();
-        handler.sequence_number = u16::max_value();
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
+        handler.sequence_number = u16::MAX;
         handler.process_outgoing(
             PacketType::Packet,
             vec![].as_slice(),
             OrderingGuarantee::None,
             None,
+            Instant::now(),
         );
         assert_eq!(handler.local_sequence_num(), 0);
     }
 
+    #[test]
+    fn unreliable_outgoing_is_not_in_flight() {
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
+        handler.process_outgoing_unreliable();
+
+        assert_eq!(handler.local_sequence_num(), 1);
+        assert_eq!(handler.packets_in_flight(), 0);
+        assert!(handler.dropped_packets().is_empty());
+    }
+
     #[test]
     fn ack_bitfield_with_empty_receive() {
-        let handler = AcknowledgmentHandler::new();
+        let handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
         assert_eq!(handler.ack_bitfield(), 0)
     }
 
     #[test]
     fn ack_bitfield_with_some_values() {
-        let mut handler = AcknowledgmentHandler::new();
-        handler
-            .received_packets
-            .insert(0, ReceivedPacket::default());
-        handler
-            .received_packets
-            .insert(1, ReceivedPacket::default());
-        handler
-            .received_packets
-            .insert(3, ReceivedPacket::default());
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
+        handler.received_packets.insert(0, ReceivedPacket);
+        handler.received_packets.insert(1, ReceivedPacket);
+        handler.received_packets.insert(3, ReceivedPacket);
         assert_eq!(handler.remote_sequence_num(), 3);
         assert_eq!(handler.ack_bitfield(), 0b110)
     }
 
     #[test]
     fn packet_is_not_acked() {
-        let mut handler = AcknowledgmentHandler::new();
+        let time = Instant::now();
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
 
         handler.sequence_number = 0;
         handler.process_outgoing(
@@ -221,6 +468,7 @@ mod test {
             vec![1, 2, 3].as_slice(),
             OrderingGuarantee::None,
             None,
+            time,
         );
         handler.sequence_number = 40;
         handler.process_outgoing(
@@ -228,6 +476,7 @@ mod test {
             vec![1, 2, 4].as_slice(),
             OrderingGuarantee::None,
             None,
+            time,
         );
 
         static ARBITRARY: u16 = 23;
@@ -236,18 +485,77 @@ mod test {
         assert_eq!(
             handler.dropped_packets(),
             vec![SentPacket {
+                sequence: 0,
                 packet_type: PacketType::Packet,
                 payload: vec![1, 2, 3].into_boxed_slice(),
                 ordering_guarantee: OrderingGuarantee::None,
-                item_identifier: None
+                item_identifier: None,
+                resends: 0,
+                id: None,
+                sent_at: time,
             }]
         );
     }
 
+    #[test]
+    fn acknowledged_ids_are_taken_once() {
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
+
+        handler.process_outgoing(
+            PacketType::Packet,
+            &[],
+            OrderingGuarantee::None,
+            None,
+            Instant::now(),
+        );
+        handler.last_sent_mut().unwrap().id = Some(3);
+        handler.process_outgoing(
+            PacketType::Packet,
+            &[],
+            OrderingGuarantee::None,
+            None,
+            Instant::now(),
+        );
+
+        handler.process_incoming(0, 1, 1);
+        assert_eq!(handler.take_acknowledged(), vec![3]);
+
+        handler.process_incoming(1, 1, 1);
+        assert!(handler.take_acknowledged().is_empty());
+    }
+
+    #[test]
+    fn drops_of_consecutive_acks_are_kept_until_drained() {
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
+
+        for (sequence, payload) in [(0, 1), (10, 2), (60, 3)].iter() {
+            handler.sequence_number = *sequence;
+            handler.process_outgoing(
+                PacketType::Packet,
+                &[*payload],
+                OrderingGuarantee::None,
+                None,
+                Instant::now(),
+            );
+        }
+
+        // the first ack pushes packet 0 out of the ack window, the second one packet 10.
+        handler.process_incoming(0, 40, 0);
+        handler.process_incoming(1, 60, 0);
+
+        let payloads: Vec<_> = handler
+            .dropped_packets()
+            .into_iter()
+            .map(|packet| packet.payload)
+            .collect();
+        assert_eq!(payloads, vec![Box::from([1]), Box::from([2])]);
+        assert!(handler.dropped_packets().is_empty());
+    }
+
     #[test]
     fn acking_500_packets_without_packet_drop() {
-        let mut handler = AcknowledgmentHandler::new();
-        let mut other = AcknowledgmentHandler::new();
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
+        let mut other = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
 
         for i in 0..500 {
             handler.sequence_number = i;
@@ -256,6 +564,7 @@ mod test {
                 vec![1, 2, 3].as_slice(),
                 OrderingGuarantee::None,
                 None,
+                Instant::now(),
             );
 
             other.process_incoming(i, handler.remote_sequence_num(), handler.ack_bitfield());
@@ -267,8 +576,8 @@ mod test {
 
     #[test]
     fn acking_many_packets_with_packet_drop() {
-        let mut handler = AcknowledgmentHandler::new();
-        let mut other = AcknowledgmentHandler::new();
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
+        let mut other = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
 
         let mut drop_count = 0;
 
@@ -278,6 +587,7 @@ mod test {
                 vec![1, 2, 3].as_slice(),
                 OrderingGuarantee::None,
                 None,
+                Instant::now(),
             );
             handler.sequence_number = i;
 
@@ -305,9 +615,52 @@ mod test {
         assert_eq!(handler.dropped_packets().len(), 17);
     }
 
+    #[test]
+    fn dropped_packets_are_detected_across_a_full_wrap() {
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
+        let mut other = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
+        let mut expected = Vec::new();
+        let mut dropped = Vec::new();
+
+        for _ in 0..66_600 {
+            let sequence = handler.local_sequence_num();
+            handler.process_outgoing(
+                PacketType::Packet,
+                &[],
+                OrderingGuarantee::None,
+                None,
+                Instant::now(),
+            );
+
+            if sequence % 1000 == 500 {
+                expected.push(sequence);
+            } else {
+                other.process_incoming(
+                    sequence,
+                    handler.remote_sequence_num(),
+                    handler.ack_bitfield(),
+                );
+                let reply = other.local_sequence_num();
+                other.process_outgoing_unreliable();
+                handler.process_incoming(reply, other.remote_sequence_num(), other.ack_bitfield());
+            }
+            dropped.extend(
+                handler
+                    .dropped_packets()
+                    .iter()
+                    .map(|packet| packet.sequence),
+            );
+        }
+
+        // 500 is dropped twice, before and after the sequence numbers wrapped around.
+        assert_eq!(expected.first(), expected.last());
+        assert_eq!(dropped, expected);
+        assert_eq!(handler.packets_in_flight(), 0);
+    }
+
     #[test]
     fn remote_seq_num_will_be_updated() {
-        let mut handler = AcknowledgmentHandler::new();
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
         assert_eq!(handler.remote_sequence_num(), 65535);
         handler.process_incoming(0, 0, 0);
         assert_eq!(handler.remote_sequence_num(), 0);
@@ -317,30 +670,245 @@ mod test {
 
     #[test]
     fn processing_a_full_set_of_packets() {
-        let mut handler = AcknowledgmentHandler::new();
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
         for i in 0..33 {
             handler.process_incoming(i, 0, 0);
         }
         assert_eq!(handler.remote_sequence_num(), 32);
-        assert_eq!(handler.ack_bitfield(), !0);
+        assert_eq!(handler.ack_bitfield(), u64::from(u32::MAX));
+    }
+
+    #[test]
+    fn wide_ack_field_covers_64_packets() {
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits64, 0, 512);
+        let mut other = AcknowledgmentHandler::new(AckFieldWidth::Bits64, 0, 512);
+
+        let mut exchange = |range: std::ops::Range<u16>| {
+            for i in range {
+                handler.sequence_number = i;
+                handler.process_outgoing(
+                    PacketType::Packet,
+                    vec![1, 2, 3].as_slice(),
+                    OrderingGuarantee::None,
+                    None,
+                    Instant::now(),
+                );
+
+                // packet 49 is lost, it is 50 acks back once packet 99 is acknowledged.
+                if i != 49 {
+                    other.process_incoming(
+                        i,
+                        handler.remote_sequence_num(),
+                        handler.ack_bitfield(),
+                    );
+                    handler.process_incoming(i, other.remote_sequence_num(), other.ack_bitfield());
+                }
+            }
+            (other.ack_bitfield(), handler.dropped_packets())
+        };
+
+        // a 32 bit field would have given up on the lost packet, the wide one still tracks it.
+        let (ack_bitfield, dropped) = exchange(0..100);
+        assert_eq!(ack_bitfield, !(1 << 49));
+        assert!(dropped.is_empty());
+
+        // once it falls out of the 64 acknowledged packets it is reported as dropped.
+        let (_, dropped) = exchange(100..115);
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].payload, vec![1, 2, 3].into_boxed_slice());
+    }
+
+    #[test]
+    fn acking_packets_with_random_initial_sequence_numbers() {
+        // both sides start somewhere else, one of them right before the sequence wraps.
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, u16::MAX - 10, 512);
+        let mut other = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 20000, 512);
+
+        for _ in 0..100 {
+            let sequence = handler.local_sequence_num();
+            handler.process_outgoing(
+                PacketType::Packet,
+                &[1],
+                OrderingGuarantee::None,
+                None,
+                Instant::now(),
+            );
+            other.process_incoming(
+                sequence,
+                handler.remote_sequence_num(),
+                handler.ack_bitfield(),
+            );
+
+            let sequence = other.local_sequence_num();
+            other.process_outgoing(
+                PacketType::Packet,
+                &[2],
+                OrderingGuarantee::None,
+                None,
+                Instant::now(),
+            );
+            handler.process_incoming(sequence, other.remote_sequence_num(), other.ack_bitfield());
+        }
+
+        assert_eq!(
+            other.remote_sequence_num(),
+            (u16::MAX - 10).wrapping_add(99)
+        );
+        assert_eq!(handler.remote_sequence_num(), 20099);
+        assert!(handler.dropped_packets().is_empty());
+        assert!(other.dropped_packets().is_empty());
+        assert_eq!(handler.packets_in_flight(), 0);
+        assert_eq!(other.packets_in_flight(), 1);
+    }
+
+    #[test]
+    fn acks_straddling_the_wrap_point_are_processed() {
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 65534, 512);
+        for payload in 0..4 {
+            handler.process_outgoing(
+                PacketType::Packet,
+                &[payload],
+                OrderingGuarantee::None,
+                None,
+                Instant::now(),
+            );
+        }
+        assert_eq!(handler.local_sequence_num(), 2);
+
+        // 1 is acknowledged directly, 0, 65535 and 65534 by the bitfield.
+        handler.process_incoming(0, 1, 0b111);
+
+        assert_eq!(handler.packets_in_flight(), 0);
+        assert!(handler.dropped_packets().is_empty());
+    }
+
+    #[test]
+    fn dropped_packets_straddling_the_wrap_point_are_returned_oldest_first() {
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 65534, 512);
+        for payload in 0..40 {
+            handler.process_outgoing(
+                PacketType::Packet,
+                &[payload],
+                OrderingGuarantee::None,
+                None,
+                Instant::now(),
+            );
+        }
+
+        // only the newest packet is acknowledged, the first seven (65534 up to 4) fell out of the ack window.
+        handler.process_incoming(0, 37, 0);
+
+        let payloads: Vec<_> = handler
+            .dropped_packets()
+            .into_iter()
+            .map(|packet| packet.payload[0])
+            .collect();
+        assert_eq!(payloads, vec![0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(handler.packets_in_flight(), 32);
+    }
+
+    #[test]
+    fn gaps_in_received_sequences_are_reported_missing() {
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
+
+        // nothing is missing before the first packet.
+        handler.process_incoming(65_530, 0, 0);
+        handler.process_incoming(65_533, 0, 0);
+        handler.process_incoming(65_532, 0, 0);
+        handler.process_incoming(1, 0, 0);
+        assert_eq!(
+            handler.take_missing(),
+            vec![65_531, 65_532, 65_534, 65_535, 0]
+        );
+        assert!(handler.take_missing().is_empty());
+
+        // wider gaps are left to the bitfield.
+        handler.process_incoming(1 + MAX_NEGATIVE_ACKS + 2, 0, 0);
+        assert!(handler.take_missing().is_empty());
     }
 
     #[test]
     fn test_process_outgoing() {
-        let mut handler = AcknowledgmentHandler::new();
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
         handler.process_outgoing(
             PacketType::Packet,
             vec![1, 2, 3].as_slice(),
             OrderingGuarantee::None,
             None,
+            Instant::now(),
         );
         assert_eq!(handler.sent_packets.len(), 1);
         assert_eq!(handler.local_sequence_num(), 1);
     }
 
+    #[test]
+    fn packets_whose_slots_are_needed_again_are_dropped() {
+        // room for 4 packets in flight.
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, u16::MAX - 1, 3);
+        for _ in 0..5 {
+            handler.process_outgoing(
+                PacketType::Packet,
+                &[],
+                OrderingGuarantee::None,
+                None,
+                Instant::now(),
+            );
+        }
+        assert_eq!(handler.packets_in_flight(), 4);
+        let dropped: Vec<_> = handler
+            .dropped_packets()
+            .iter()
+            .map(|p| p.sequence)
+            .collect();
+        assert_eq!(dropped, vec![u16::MAX - 1]);
+
+        // the sequence numbers of unreliable packets reuse slots as well.
+        for _ in 0..5 {
+            handler.process_outgoing_unreliable();
+        }
+        handler.process_outgoing(
+            PacketType::Packet,
+            &[],
+            OrderingGuarantee::None,
+            None,
+            Instant::now(),
+        );
+        assert_eq!(handler.packets_in_flight(), 1);
+        let dropped: Vec<_> = handler
+            .dropped_packets()
+            .iter()
+            .map(|p| p.sequence)
+            .collect();
+        assert_eq!(dropped.len(), 4);
+    }
+
+    #[test]
+    fn late_acknowledgments_of_dropped_packets_are_spurious_retransmits() {
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
+        for _ in 0..40 {
+            handler.process_outgoing(
+                PacketType::Packet,
+                &[],
+                OrderingGuarantee::None,
+                None,
+                Instant::now(),
+            );
+        }
+        // 39 back to 7 are acknowledged, the packets before fall out of the bitfield.
+        handler.process_incoming(0, 39, u64::from(u32::MAX));
+        assert_eq!(handler.dropped_packets().len(), 7);
+        assert_eq!(handler.take_spurious_retransmits(), 0);
+
+        // the acknowledgments of 5 back to 0 were only late.
+        handler.process_incoming(1, 5, 0b1_1111);
+        assert_eq!(handler.take_spurious_retransmits(), 6);
+        handler.process_incoming(2, 5, 0b1_1111);
+        assert_eq!(handler.take_spurious_retransmits(), 0);
+    }
+
     #[test]
     fn remote_ack_seq_must_never_be_less_than_prior() {
-        let mut handler = AcknowledgmentHandler::new();
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
         // Second packet received before first
         handler.process_incoming(1, 1, 1);
         assert_eq!(handler.remote_ack_sequence_num, 1);
@@ -351,12 +919,37 @@ mod test {
 
     #[test]
     fn remote_ack_seq_must_never_be_less_than_prior_wrap_boundary() {
-        let mut handler = AcknowledgmentHandler::new();
+        let mut handler = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
         // newer packet received before first
         handler.process_incoming(1, 0, 1);
         assert_eq!(handler.remote_ack_sequence_num, 0);
         // earlier packet received
-        handler.process_incoming(0, u16::max_value(), 0);
+        handler.process_incoming(0, u16::MAX, 0);
         assert_eq!(handler.remote_ack_sequence_num, 0);
     }
+
+    #[test]
+    fn packets_beyond_the_bitfield_are_acknowledged_selectively() {
+        let mut receiver = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
+        for sequence in (0..10).chain(12..20).chain(100..110) {
+            receiver.process_incoming(sequence, 0, 0);
+        }
+        // 77 to 109 are covered by the bitfield.
+        assert_eq!(receiver.selective_acks(), vec![(12, 19), (0, 9)]);
+
+        let mut sender = AcknowledgmentHandler::new(AckFieldWidth::Bits32, 0, 512);
+        for _ in 0..20 {
+            sender.process_outgoing(
+                PacketType::Packet,
+                &[],
+                OrderingGuarantee::None,
+                None,
+                Instant::now(),
+            );
+        }
+        sender.process_selective_acks(&receiver.selective_acks());
+        let mut in_flight: Vec<_> = sender.sent_packets.iter().map(|(s, _)| s).collect();
+        in_flight.sort();
+        assert_eq!(in_flight, vec![10, 11]);
+    }
 }
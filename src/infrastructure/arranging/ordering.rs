@@ -97,7 +97,7 @@ impl<T> OrderingSystem<T> {
     }
 }
 
-impl<'a, T> ArrangingSystem for OrderingSystem<T> {
+impl<T> ArrangingSystem for OrderingSystem<T> {
     type Stream = OrderingStream<T>;
 
     /// Returns the number of ordering streams currently active.
@@ -122,11 +122,12 @@ impl<'a, T> ArrangingSystem for OrderingSystem<T> {
 ///
 /// There are three scenarios that are important to us.
 /// 1. `incoming_index` == `expected_index`.
-/// This package meets the expected order, so we can return it immediately.
+///    This package meets the expected order, so we can return it immediately.
 /// 2. `incoming_index` > `expected_index`.
-/// This package is newer than we expect, so we have to hold it temporarily until we have received all previous packages.
+///    This package is newer than we expect, so we have to hold it temporarily until we have received all previous packages.
 /// 3. `incoming_index`< `expected_index`
-/// This can only happen in cases where we have a duplicated package. Again we don't give anything back.
+///    This can only happen in cases where we have a duplicated package. Again we don't give anything back.
+///
 /// # Remarks
 /// - See [super-module](../index.html) for more information about streams.
 pub struct OrderingStream<T> {
@@ -211,17 +212,30 @@ impl<T> OrderingStream<T> {
     /// # Remarks
     /// - Iterator mutates the `expected_index`.
     /// - You can't use this iterator for iterating trough all cached values.
-    pub fn iter_mut(&mut self) -> IterMut<T> {
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
         IterMut {
             items: &mut self.storage,
             expected_index: &mut self.expected_index,
         }
     }
+
+    /// Declares the missing items lost while more than `window` items are waiting for them,
+    /// and returns how many indices were skipped.
+    ///
+    /// The items that are released by this can be read with `iter_mut` afterwards.
+    pub fn skip_stalled(&mut self, window: usize) -> u16 {
+        let mut skipped = 0;
+        while self.storage.len() > window && !self.storage.contains_key(&self.expected_index) {
+            self.expected_index = self.expected_index.wrapping_add(1);
+            skipped += 1;
+        }
+        skipped
+    }
 }
 
 fn is_u16_within_half_window_from_start(start: u16, incoming: u16) -> bool {
-    // check (with wrapping) if the incoming value lies within the `next u16::max_value()/2` from start
-    incoming.wrapping_sub(start) <= u16::max_value() / 2 + 1
+    // check (with wrapping) if the incoming value lies within the `next u16::MAX/2` from start
+    incoming.wrapping_sub(start) <= u16::MAX / 2 + 1
 }
 
 impl<T> Arranging for OrderingStream<T> {
@@ -235,11 +249,11 @@ impl<T> Arranging for OrderingStream<T> {
     ///
     /// There are three scenarios that are important to us.
     /// 1. `incoming_index` == `expected_index`.
-    /// This package meets the expected order, so we can return it immediately.
+    ///    This package meets the expected order, so we can return it immediately.
     /// 2. `incoming_index` > `expected_index`.
-    /// This package is newer than we expect, so we have to hold it temporarily until we have received all previous packages.
+    ///    This package is newer than we expect, so we have to hold it temporarily until we have received all previous packages.
     /// 3. `incoming_index` < `expected_index`
-    /// This can only happen in cases where we have a duplicated package. Again we don't give anything back.
+    ///    This can only happen in cases where we have a duplicated package. Again we don't give anything back.
     ///
     /// # Remark
     /// - When we receive an item there is a possibility that a gap is filled and one or more items will could be returned.
@@ -288,7 +302,7 @@ impl<'a, T> Iterator for IterMut<'a, T> {
     /// Returns `Some` when there is an item in our cache matching the `expected_index`.
     /// Returns `None` if there are no times matching our `expected` index.
     fn next(&mut self) -> Option<<Self as Iterator>::Item> {
-        match self.items.remove(&self.expected_index) {
+        match self.items.remove(self.expected_index) {
             None => None,
             Some(e) => {
                 *self.expected_index = self.expected_index.wrapping_add(1);
@@ -355,6 +369,21 @@ mod tests {
         assert![stream.iter_mut().next().is_some()];
     }
 
+    #[test]
+    fn out_of_order_items_around_wrap_boundary_are_ordered() {
+        let mut system: OrderingSystem<u16> = OrderingSystem::new();
+
+        let stream = system.get_or_create_stream(1);
+        stream.expected_index = 65534;
+
+        assert_eq![None, stream.arrange(1, 1)];
+        assert_eq![None, stream.arrange(65535, 65535)];
+        assert_eq![None, stream.arrange(0, 0)];
+        assert_eq![Some(65534), stream.arrange(65534, 65534)];
+        assert_eq![vec![65535, 0, 1], stream.iter_mut().collect::<Vec<_>>()];
+        assert_eq![2, stream.expected_index()];
+    }
+
     #[test]
     fn exactly_half_u16_packet_is_stored() {
         let mut system: OrderingSystem<u16> = OrderingSystem::new();
@@ -444,7 +473,7 @@ mod tests {
             // get stream '1' to order the packets on.
             let stream = ordering_system.get_or_create_stream(1);
 
-            let ordered_packets : Vec<_> = before.into_iter()
+            let ordered_packets : Vec<_> = before.iter()
                 .filter_map(|seq| stream.arrange(*seq, Packet::new(*seq, $stream_id))
                     .map(|p| Some(p).into_iter() // if we get some packets, append packets from stream as well
                         .chain(stream.iter_mut())
@@ -459,6 +488,27 @@ mod tests {
         };
     }
 
+    #[test]
+    fn stalled_items_are_released_past_the_gap() {
+        let mut system: OrderingSystem<u16> = OrderingSystem::new();
+        let stream = system.get_or_create_stream(1);
+
+        // 0 and 1 got lost, the window holds two waiting items.
+        assert_eq!(stream.arrange(2, 2), None);
+        assert_eq!(stream.arrange(3, 3), None);
+        assert_eq!(stream.skip_stalled(2), 0);
+
+        assert_eq!(stream.arrange(5, 5), None);
+        assert_eq!(stream.skip_stalled(2), 2);
+        assert_eq!(stream.iter_mut().collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(stream.expected_index(), 4);
+
+        // the lost items are dropped as duplicates when they arrive after all.
+        assert_eq!(stream.arrange(1, 1), None);
+        assert_eq!(stream.arrange(4, 4), Some(4));
+        assert_eq!(stream.iter_mut().collect::<Vec<_>>(), vec![5]);
+    }
+
     #[test]
     fn expect_right_order() {
         // we order on stream 1
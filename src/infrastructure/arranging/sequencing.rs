@@ -60,11 +60,11 @@ impl<T> ArrangingSystem for SequencingSystem<T> {
 ///
 /// There are two scenarios that are important to us.
 /// 1. `incoming_index` >= `top_index`.
-/// This item is the newest or newer than the last one we have seen.
-/// Because of that we should return it back to the user.
+///    This item is the newest or newer than the last one we have seen.
+///    Because of that we should return it back to the user.
 /// 2. `incoming_index` < `top_index`.
-/// This item is older than the newest item we have seen so far.
-/// Since we don't care about old items we can toss it a way.
+///    This item is older than the newest item we have seen so far.
+///    Since we don't care about old items we can toss it a way.
 ///
 /// # Remarks
 /// - See [super-module](../index.html) for more information about streams.
@@ -107,9 +107,9 @@ impl<T> SequencingStream<T> {
 }
 
 fn is_u16_within_half_window_from_start(start: u16, incoming: u16) -> bool {
-    // check (with wrapping) if the incoming value lies within the next u16::max_value()/2 from
+    // check (with wrapping) if the incoming value lies within the next u16::MAX/2 from
     // start.
-    incoming.wrapping_sub(start) <= u16::max_value() / 2 + 1
+    incoming.wrapping_sub(start) <= u16::MAX / 2 + 1
 }
 
 impl<T> Arranging for SequencingStream<T> {
@@ -123,11 +123,11 @@ impl<T> Arranging for SequencingStream<T> {
     ///
     /// There are two scenarios that are important to us.
     /// 1. `incoming_index` >= `top_index`.
-    /// This item is the newest or newer than the last one we have seen.
-    /// Because of that we should return it back to the user.
+    ///    This item is the newest or newer than the last one we have seen.
+    ///    Because of that we should return it back to the user.
     /// 2. `incoming_index` < `top_index`.
-    /// This item is older than we the newest packet we have seen so far.
-    /// Since we don't care about old items we can toss it a way.
+    ///    This item is older than we the newest packet we have seen so far.
+    ///    Since we don't care about old items we can toss it a way.
     ///
     /// # Remark
     /// - All old packets will be tossed away.
@@ -199,7 +199,7 @@ mod tests {
                 let stream = sequence_system.get_or_create_stream(1);
 
                 // get packets arranged in sequence.
-                let sequenced_packets: Vec<_> = before.into_iter()
+                let sequenced_packets: Vec<_> = before.iter()
                     .filter_map(|seq| stream.arrange(*seq, Packet::new(*seq, $stream_id)) // filter sequenced packets
                         .map(|p| p.sequence))
                     .collect();
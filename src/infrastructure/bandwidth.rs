@@ -0,0 +1,188 @@
+use std::time::{Duration, Instant};
+
+use crate::net::Bandwidth;
+
+// the window of a `BandwidthSampler` is split into this many buckets of `SAMPLE_BUCKET_DURATION`.
+const SAMPLE_BUCKETS: usize = 10;
+const SAMPLE_BUCKET_DURATION: Duration = Duration::from_millis(100);
+
+/// Limits the number of bytes sent to a connection per second with a token bucket.
+///
+/// The bucket holds up to `burst_size` bytes and is refilled with `bytes_per_second`,
+/// so a connection that was quiet for a while can send a short burst.
+pub struct BandwidthLimiter {
+    bytes_per_second: u32,
+    burst_size: u32,
+    // may become negative, when more was sent than the bucket held.
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl BandwidthLimiter {
+    /// Constructs a new `BandwidthLimiter` with a full bucket.
+    pub fn new(bytes_per_second: u32, burst_size: u32, time: Instant) -> BandwidthLimiter {
+        BandwidthLimiter {
+            bytes_per_second,
+            burst_size,
+            tokens: f64::from(burst_size),
+            last_refill: time,
+        }
+    }
+
+    /// Returns true if `size` bytes can be sent at the given time.
+    ///
+    /// A full bucket always allows sending, so that packets bigger than the burst size are not stuck forever.
+    pub fn allows(&mut self, size: usize, time: Instant) -> bool {
+        self.refill(time);
+        self.tokens >= size as f64 || self.tokens >= f64::from(self.burst_size)
+    }
+
+    /// Takes the bytes that were sent from the bucket.
+    ///
+    /// This is done for everything that is sent, so that resent packets and heartbeats count as well.
+    pub fn consume(&mut self, size: usize) {
+        self.tokens -= size as f64;
+    }
+
+    fn refill(&mut self, time: Instant) {
+        if time > self.last_refill {
+            let elapsed = time.duration_since(self.last_refill).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * f64::from(self.bytes_per_second))
+                .min(f64::from(self.burst_size));
+            self.last_refill = time;
+        }
+    }
+}
+
+/// Counts the bytes sent to and received from a connection within the last second.
+///
+/// The second is split into a ring of buckets, the oldest bucket is cleared and reused
+/// whenever the time moves into a new one, so no samples have to be stored.
+pub struct BandwidthSampler {
+    // the bytes sent and received within each bucket.
+    buckets: [(u64, u64); SAMPLE_BUCKETS],
+    current: usize,
+    // the time the current bucket started.
+    current_start: Instant,
+}
+
+impl BandwidthSampler {
+    /// Constructs a new `BandwidthSampler` with empty buckets.
+    pub fn new(time: Instant) -> BandwidthSampler {
+        BandwidthSampler {
+            buckets: [(0, 0); SAMPLE_BUCKETS],
+            current: 0,
+            current_start: time,
+        }
+    }
+
+    /// Adds the bytes of a sent datagram to the bucket of the given time.
+    pub fn record_sent(&mut self, size: usize, time: Instant) {
+        self.advance(time);
+        let bucket = &mut self.buckets[self.current];
+        bucket.0 = bucket.0.saturating_add(size as u64);
+    }
+
+    /// Adds the bytes of a received datagram to the bucket of the given time.
+    pub fn record_received(&mut self, size: usize, time: Instant) {
+        self.advance(time);
+        let bucket = &mut self.buckets[self.current];
+        bucket.1 = bucket.1.saturating_add(size as u64);
+    }
+
+    /// Moves on to the bucket of the given time, the buckets that are older than a second are cleared.
+    pub fn advance(&mut self, time: Instant) {
+        let elapsed = time.saturating_duration_since(self.current_start);
+        let buckets = (elapsed.as_nanos() / SAMPLE_BUCKET_DURATION.as_nanos()) as u64;
+        if buckets == 0 {
+            return;
+        }
+
+        for _ in 0..buckets.min(SAMPLE_BUCKETS as u64) {
+            self.current = (self.current + 1) % SAMPLE_BUCKETS;
+            self.buckets[self.current] = (0, 0);
+        }
+        self.current_start +=
+            Duration::from_nanos(buckets.saturating_mul(SAMPLE_BUCKET_DURATION.as_nanos() as u64));
+    }
+
+    /// Returns the bytes sent and received within the last second.
+    pub fn bandwidth(&self) -> Bandwidth {
+        let (sent_bps, recv_bps) =
+            self.buckets
+                .iter()
+                .fold((0u64, 0u64), |(sent, received), bucket| {
+                    (
+                        sent.saturating_add(bucket.0),
+                        received.saturating_add(bucket.1),
+                    )
+                });
+        Bandwidth { sent_bps, recv_bps }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{BandwidthLimiter, BandwidthSampler};
+
+    #[test]
+    fn burst_is_allowed_then_throttled() {
+        let time = Instant::now();
+        let mut limiter = BandwidthLimiter::new(1000, 500, time);
+
+        assert!(limiter.allows(500, time));
+        limiter.consume(500);
+        assert!(!limiter.allows(1, time));
+
+        // after a quarter of a second, 250 bytes were refilled.
+        assert!(!limiter.allows(300, time + Duration::from_millis(250)));
+        assert!(limiter.allows(250, time + Duration::from_millis(250)));
+    }
+
+    #[test]
+    fn refill_is_capped_at_the_burst_size() {
+        let time = Instant::now();
+        let mut limiter = BandwidthLimiter::new(1000, 500, time);
+
+        limiter.consume(500);
+        // ten seconds would refill 10000 bytes, but the bucket only holds 500.
+        assert!(limiter.allows(500, time + Duration::from_secs(10)));
+        limiter.consume(500);
+        assert!(!limiter.allows(1, time + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn packets_bigger_than_the_burst_size_are_sent_from_a_full_bucket() {
+        let time = Instant::now();
+        let mut limiter = BandwidthLimiter::new(1000, 500, time);
+
+        assert!(limiter.allows(2000, time));
+        limiter.consume(2000);
+
+        // the debt is paid off before anything else is sent.
+        assert!(!limiter.allows(1, time + Duration::from_secs(1)));
+        assert!(limiter.allows(500, time + Duration::from_millis(2000)));
+    }
+
+    #[test]
+    fn sampler_forgets_bytes_older_than_a_second() {
+        let time = Instant::now();
+        let mut sampler = BandwidthSampler::new(time);
+
+        sampler.record_sent(100, time);
+        sampler.record_received(40, time + Duration::from_millis(550));
+        sampler.advance(time + Duration::from_millis(999));
+        assert_eq!(sampler.bandwidth().sent_bps, 100);
+        assert_eq!(sampler.bandwidth().recv_bps, 40);
+
+        sampler.advance(time + Duration::from_millis(1000));
+        assert_eq!(sampler.bandwidth().sent_bps, 0);
+        assert_eq!(sampler.bandwidth().recv_bps, 40);
+
+        // a long pause clears every bucket.
+        sampler.advance(time + Duration::from_secs(60));
+        assert_eq!(sampler.bandwidth().recv_bps, 0);
+    }
+}
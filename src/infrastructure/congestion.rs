@@ -1,4 +1,4 @@
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use crate::{
     net::{NetworkQuality, RttMeasurer},
@@ -10,7 +10,10 @@ use crate::{
 pub struct CongestionHandler {
     rtt_measurer: RttMeasurer,
     congestion_data: SequenceBuffer<CongestionData>,
-    _quality: NetworkQuality,
+    quality: NetworkQuality,
+    // Since when the rtt is below the max allowed rtt, only tracked while the network quality is bad.
+    good_conditions_since: Option<Instant>,
+    config: Config,
 }
 
 impl CongestionHandler {
@@ -18,17 +21,72 @@ impl CongestionHandler {
     pub fn new(config: &Config) -> CongestionHandler {
         CongestionHandler {
             rtt_measurer: RttMeasurer::new(config),
-            congestion_data: SequenceBuffer::with_capacity(<u16>::max_value()),
-            _quality: NetworkQuality::Good,
+            // packets which are not acknowledged within `max_packets_in_flight` drop the connection anyway.
+            congestion_data: SequenceBuffer::with_capacity(config.max_packets_in_flight),
+            quality: NetworkQuality::Good,
+            good_conditions_since: None,
+            config: config.clone(),
         }
     }
 
-    /// Processes incoming sequence number.
+    /// Processes the sequence number the remote host acknowledged.
     ///
     /// This will calculate the RTT-time and smooth down the RTT-value to prevent uge RTT-spikes.
-    pub fn process_incoming(&mut self, incoming_seq: u16) {
-        let congestion_data = self.congestion_data.get_mut(incoming_seq);
-        self.rtt_measurer.calculate_rrt(congestion_data);
+    /// The entry is removed afterwards, so that duplicate acknowledgments of the same packet are not measured again.
+    pub fn process_incoming(&mut self, ack_seq: u16, time: Instant) {
+        let congestion_data = self.congestion_data.remove(ack_seq);
+        self.rtt_measurer.calculate_rrt(congestion_data, time);
+        self.update_quality(time);
+    }
+
+    /// Processes a round trip time that was measured with a ping.
+    pub fn process_rtt(&mut self, rtt: Duration, time: Instant) {
+        self.rtt_measurer.record_rtt(rtt);
+        self.update_quality(time);
+    }
+
+    /// Returns the smoothed round trip time.
+    pub fn rtt(&self) -> Duration {
+        self.rtt_measurer.get_rtt()
+    }
+
+    /// Returns the smoothed mean deviation of the round trip time.
+    pub fn jitter(&self) -> Duration {
+        self.rtt_measurer.get_jitter()
+    }
+
+    /// Returns the current network quality.
+    #[cfg(test)]
+    pub fn quality(&self) -> NetworkQuality {
+        self.quality
+    }
+
+    /// Returns the recommended number of packets per second for the current network quality.
+    pub fn send_rate(&self) -> u32 {
+        match self.quality {
+            NetworkQuality::Good => self.config.good_network_send_rate,
+            NetworkQuality::Bad => self.config.bad_network_send_rate,
+        }
+    }
+
+    /// Updates the network quality based on the smoothed round trip time.
+    ///
+    /// The quality drops to bad as soon as the rtt exceeds the max allowed rtt,
+    /// but it only recovers after the rtt stayed below it for `network_recovery_time`.
+    fn update_quality(&mut self, time: Instant) {
+        let rtt_max_value = Duration::from_millis(u64::from(self.config.rtt_max_value));
+
+        if self.rtt() > rtt_max_value {
+            self.quality = NetworkQuality::Bad;
+            self.good_conditions_since = None;
+        } else if self.quality == NetworkQuality::Bad {
+            let good_conditions_since = *self.good_conditions_since.get_or_insert(time);
+
+            if time.duration_since(good_conditions_since) >= self.config.network_recovery_time {
+                self.quality = NetworkQuality::Good;
+                self.good_conditions_since = None;
+            }
+        }
     }
 
     /// Processes outgoing sequence number.
@@ -43,9 +101,10 @@ impl CongestionHandler {
 
 #[cfg(test)]
 mod test {
-    use std::time::Instant;
+    use std::time::{Duration, Instant};
 
     use crate::infrastructure::CongestionHandler;
+    use crate::net::NetworkQuality;
     use crate::Config;
 
     #[test]
@@ -54,19 +113,64 @@ mod test {
 
         congestion_handler.process_outgoing(1, Instant::now());
 
-        assert_eq!(congestion_handler.congestion_data.exists(1), true);
+        assert!(congestion_handler.congestion_data.exists(1));
     }
 
     #[test]
     fn rtt_value_is_updated() {
         let mut congestion_handler = CongestionHandler::new(&Config::default());
+        let time = Instant::now();
 
-        assert_eq!(
-            congestion_handler.rtt_measurer.get_rtt().abs() < std::f32::EPSILON,
-            true
-        );
-        congestion_handler.process_outgoing(1, Instant::now());
-        congestion_handler.process_incoming(1);
-        assert_eq!(congestion_handler.rtt_measurer.get_rtt() != 0., true);
+        assert_eq!(congestion_handler.rtt(), Duration::default());
+        congestion_handler.process_outgoing(1, time);
+        congestion_handler.process_incoming(1, time + Duration::from_millis(20));
+        assert_eq!(congestion_handler.rtt(), Duration::from_millis(20));
+    }
+
+    #[test]
+    fn duplicate_ack_does_not_update_rtt() {
+        let mut congestion_handler = CongestionHandler::new(&Config::default());
+        let time = Instant::now();
+
+        congestion_handler.process_outgoing(1, time);
+        congestion_handler.process_incoming(1, time + Duration::from_millis(20));
+        congestion_handler.process_incoming(1, time + Duration::from_millis(500));
+
+        assert_eq!(congestion_handler.rtt(), Duration::from_millis(20));
+        assert!(!congestion_handler.congestion_data.exists(1));
+    }
+
+    #[test]
+    fn network_quality_transitions_with_hysteresis() {
+        // do not smooth, so that every rtt sample becomes the rtt.
+        let config = Config {
+            rtt_smoothing_factor: 1.0,
+            rtt_max_value: 250,
+            network_recovery_time: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let mut congestion_handler = CongestionHandler::new(&config);
+        let time = Instant::now();
+
+        let mut sample = |seq: u16, sent_after_secs: u64, rtt_millis: u64| {
+            let sending_time = time + Duration::from_secs(sent_after_secs);
+            congestion_handler.process_outgoing(seq, sending_time);
+            congestion_handler
+                .process_incoming(seq, sending_time + Duration::from_millis(rtt_millis));
+            (congestion_handler.quality(), congestion_handler.send_rate())
+        };
+
+        assert_eq!(sample(0, 0, 50), (NetworkQuality::Good, 30));
+        // a too high rtt immediately results in a bad network.
+        assert_eq!(sample(1, 1, 300), (NetworkQuality::Bad, 10));
+        // a good rtt does not directly recover the network.
+        assert_eq!(sample(2, 2, 50), (NetworkQuality::Bad, 10));
+        assert_eq!(sample(3, 8, 50), (NetworkQuality::Bad, 10));
+        // a bad rtt in between restarts the recovery time.
+        assert_eq!(sample(4, 9, 300), (NetworkQuality::Bad, 10));
+        assert_eq!(sample(5, 10, 50), (NetworkQuality::Bad, 10));
+        assert_eq!(sample(6, 15, 50), (NetworkQuality::Bad, 10));
+        // the rtt stayed good for the recovery time.
+        assert_eq!(sample(7, 20, 50), (NetworkQuality::Good, 30));
     }
 }
@@ -1,4 +1,6 @@
-use std::io::Write;
+use std::time::Instant;
+
+use log::debug;
 
 use crate::{
     config::Config,
@@ -11,6 +13,8 @@ use crate::{
 /// Manages fragmentation of packets.
 pub struct Fragmentation {
     fragments: SequenceBuffer<ReassemblyData>,
+    // when the packets were reassembled or discarded, their late fragments must not start them again.
+    finished: SequenceBuffer<Option<Instant>>,
     config: Config,
 }
 
@@ -19,6 +23,7 @@ impl Fragmentation {
     pub fn new(config: &Config) -> Fragmentation {
         Fragmentation {
             fragments: SequenceBuffer::with_capacity(config.fragment_reassembly_buffer_size),
+            finished: SequenceBuffer::with_capacity(config.fragment_reassembly_buffer_size),
             config: config.clone(),
         }
     }
@@ -28,6 +33,7 @@ impl Fragmentation {
     /// For example we have two numbers:
     /// - number 1 = 4000;
     /// - number 2 = 1024;
+    ///
     /// If you do it the easy way the answer will be 4000/1024 = 3.90625.
     /// But since we care about how how many whole times the number fits in we need the result 4.
     ///
@@ -53,12 +59,12 @@ impl Fragmentation {
     /// So for 4000 bytes we need 4 fragments
     /// [fragment: 1024] [fragment: 1024] [fragment: 1024] [fragment: 928]
     pub fn fragments_needed(payload_length: u16, fragment_size: u16) -> u16 {
-        let remainder = if payload_length % fragment_size > 0 {
-            1
-        } else {
+        let remainder = if payload_length.is_multiple_of(fragment_size) {
             0
+        } else {
+            1
         };
-        ((payload_length / fragment_size) + remainder)
+        (payload_length / fragment_size) + remainder
     }
 
     /// Splits the given payload into fragments and write those fragments to the passed packet data.
@@ -94,20 +100,51 @@ impl Fragmentation {
     }
 
     /// Reads fragment data and return the complete packet when all fragments are received.
+    ///
+    /// Late or duplicate fragments of a packet that was already reassembled or discarded are ignored.
     pub fn handle_fragment(
         &mut self,
         fragment_header: FragmentHeader,
         fragment_payload: &[u8],
         acked_header: Option<AckedPacketHeader>,
+        time: Instant,
     ) -> Result<Option<(Vec<u8>, AckedPacketHeader)>> {
+        // the header comes from the remote host, so its lengths are validated before anything is allocated.
+        if fragment_header.fragment_count() == 0
+            || fragment_header.fragment_count() > self.config.max_fragments
+            || fragment_header.id() >= fragment_header.fragment_count()
+        {
+            return Err(FragmentErrorKind::ExceededMaxFragments.into());
+        }
+
+        if fragment_payload.len() > usize::from(self.config.fragment_size) {
+            return Err(FragmentErrorKind::ExceededFragmentSize.into());
+        }
+
+        // every fragment but the last one is full, so that the fragments can be put at their place.
+        if fragment_header.id() + 1 < fragment_header.fragment_count()
+            && fragment_payload.len() != usize::from(self.config.fragment_size)
+        {
+            return Err(FragmentErrorKind::IncompleteFragment.into());
+        }
+
+        if self.is_finished(fragment_header.sequence(), time) {
+            debug!(
+                "Ignoring fragment {} of finished packet {}",
+                fragment_header.id(),
+                fragment_header.sequence()
+            );
+            return Ok(None);
+        }
+
         // read fragment packet
 
-        self.create_fragment_if_not_exists(fragment_header);
+        self.create_fragment_if_not_exists(fragment_header, time);
 
+        let fragment_size = usize::from(self.config.fragment_size);
         let num_fragments_received;
         let num_fragments_total;
         let sequence;
-        let total_buffer;
 
         {
             // get entry of previous received fragments
@@ -126,15 +163,25 @@ impl Fragmentation {
             }
 
             if reassembly_data.fragments_received[usize::from(fragment_header.id())] {
-                return Err(FragmentErrorKind::AlreadyProcessedFragment.into());
+                debug!(
+                    "Ignoring duplicate fragment {} of packet {}",
+                    fragment_header.id(),
+                    fragment_header.sequence()
+                );
+                return Ok(None);
             }
 
             // increase number of received fragments and set the specific fragment to received
             reassembly_data.num_fragments_received += 1;
             reassembly_data.fragments_received[usize::from(fragment_header.id())] = true;
 
-            // add the payload from the fragment to the buffer whe have in cache
-            reassembly_data.buffer.write_all(&*fragment_payload)?;
+            // fragments can arrive in any order, so the payload is written at the place of the fragment.
+            let start = usize::from(fragment_header.id()) * fragment_size;
+            let end = start + fragment_payload.len();
+            if reassembly_data.buffer.len() < end {
+                reassembly_data.buffer.resize(end, 0);
+            }
+            reassembly_data.buffer[start..end].copy_from_slice(fragment_payload);
 
             if let Some(acked_header) = acked_header {
                 if reassembly_data.acked_header.is_none() {
@@ -146,20 +193,20 @@ impl Fragmentation {
 
             num_fragments_received = reassembly_data.num_fragments_received;
             num_fragments_total = reassembly_data.num_fragments_total;
-            sequence = reassembly_data.sequence as u16;
-            total_buffer = reassembly_data.buffer.clone();
+            sequence = reassembly_data.sequence;
         }
 
         // if we received all fragments then remove entry and return the total received bytes.
+        // the buffer is moved out of the removed entry, so that it is not copied.
         if num_fragments_received == num_fragments_total {
-            let sequence = sequence as u16;
-            if let Some(mut reassembly_data) = self.fragments.remove(sequence) {
-                if reassembly_data.acked_header.is_none() {
-                    return Err(FragmentErrorKind::MissingAckHeader.into());
-                }
+            if let Some(reassembly_data) = self.fragments.remove(sequence) {
+                self.finished.insert(sequence, Some(time));
+                let acked_header = match reassembly_data.acked_header {
+                    Some(acked_header) => acked_header,
+                    None => return Err(FragmentErrorKind::MissingAckHeader.into()),
+                };
 
-                let acked_header = reassembly_data.acked_header.take().unwrap();
-                return Ok(Some((total_buffer, acked_header)));
+                return Ok(Some((reassembly_data.buffer, acked_header)));
             } else {
                 return Err(FragmentErrorKind::CouldNotFindFragmentById.into());
             }
@@ -168,13 +215,45 @@ impl Fragmentation {
         Ok(None)
     }
 
+    /// Discards the packets whose first fragment arrived longer than `Config::fragment_timeout` ago,
+    /// their other fragments are assumed to be lost. Returns how many packets were discarded.
+    pub fn discard_expired(&mut self, time: Instant) -> usize {
+        let fragment_timeout = self.config.fragment_timeout;
+        let mut expired = Vec::new();
+        let discarded = self.fragments.remove_where(|reassembly_data| {
+            let is_expired =
+                time.saturating_duration_since(reassembly_data.first_received) >= fragment_timeout;
+            if is_expired {
+                expired.push(reassembly_data.sequence);
+            }
+            is_expired
+        });
+
+        for sequence in expired {
+            self.finished.insert(sequence, Some(time));
+        }
+        discarded
+    }
+
+    /// Whether the packet was reassembled or discarded recently. Only for `Config::fragment_timeout`,
+    /// so that a packet which reuses the sequence number after a wrap is not mistaken for it.
+    fn is_finished(&self, sequence: u16, time: Instant) -> bool {
+        match self.finished.get(sequence) {
+            Some(Some(finished)) => {
+                time.saturating_duration_since(*finished) < self.config.fragment_timeout
+            }
+            _ => false,
+        }
+    }
+
     /// If fragment does not exist we need to insert a new entry.
-    fn create_fragment_if_not_exists(&mut self, fragment_header: FragmentHeader) {
+    fn create_fragment_if_not_exists(&mut self, fragment_header: FragmentHeader, time: Instant) {
         if !self.fragments.exists(fragment_header.sequence()) {
             let reassembly_data = ReassemblyData::new(
                 fragment_header.sequence(),
                 fragment_header.fragment_count(),
                 (u16::from(FRAGMENT_HEADER_SIZE) + self.config.fragment_size) as usize,
+                time,
             );
 
             self.fragments
@@ -185,7 +264,12 @@ impl Fragmentation {
 
 #[cfg(test)]
 mod test {
+    use std::time::{Duration, Instant};
+
     use super::Fragmentation;
+    use crate::config::Config;
+    use crate::error::{ErrorKind, FragmentErrorKind};
+    use crate::packet::header::{AckedPacketHeader, FragmentHeader};
 
     #[test]
     pub fn expect_right_number_of_fragments() {
@@ -195,4 +279,205 @@ mod test {
         assert_eq!(fragment_number, 4);
         assert_eq!(fragment_number1, 1);
     }
+
+    #[test]
+    pub fn expect_no_empty_fragment_for_exact_multiple() {
+        let config = Config {
+            fragment_size: 1024,
+            ..Default::default()
+        };
+        let payload = vec![1; 2048];
+
+        let fragments = Fragmentation::spit_into_fragments(&payload, &config).unwrap();
+
+        assert_eq!(Fragmentation::fragments_needed(2048, 1024), 2);
+        assert_eq!(fragments.len(), 2);
+        assert!(fragments.iter().all(|fragment| fragment.len() == 1024));
+    }
+
+    #[test]
+    pub fn reject_fragments_with_inconsistent_lengths() {
+        let config = Config {
+            max_fragments: 4,
+            fragment_size: 8,
+            ..Default::default()
+        };
+        let mut fragmentation = Fragmentation::new(&config);
+        let acked_header = Some(AckedPacketHeader::new(1, 0, 0));
+
+        // more fragments than allowed, or a fragment id that is out of the announced count.
+        for (id, count) in [(0, 255), (0, 5), (0, 0), (3, 2)].iter() {
+            match fragmentation.handle_fragment(
+                FragmentHeader::new(1, *id, *count),
+                &[1; 8],
+                acked_header,
+                Instant::now(),
+            ) {
+                Err(ErrorKind::FragmentError(FragmentErrorKind::ExceededMaxFragments)) => {}
+                _ => panic!["Supposed to get a fragment count error"],
+            }
+        }
+
+        // a fragment that is bigger than the fragment size.
+        match fragmentation.handle_fragment(
+            FragmentHeader::new(1, 0, 2),
+            &[1; 9],
+            acked_header,
+            Instant::now(),
+        ) {
+            Err(ErrorKind::FragmentError(FragmentErrorKind::ExceededFragmentSize)) => {}
+            _ => panic!["Supposed to get a fragment size error"],
+        }
+
+        // nothing was stored for the rejected fragments.
+        assert!(!fragmentation.fragments.exists(1));
+    }
+
+    #[test]
+    pub fn reassemble_interleaved_fragment_groups() {
+        let config = Config {
+            fragment_size: 2,
+            ..Default::default()
+        };
+        let mut fragmentation = Fragmentation::new(&config);
+
+        let mut handle = |sequence, id, count, payload: &[u8], ack_seq: Option<u16>| {
+            fragmentation
+                .handle_fragment(
+                    FragmentHeader::new(sequence, id, count),
+                    payload,
+                    ack_seq.map(|seq| AckedPacketHeader::new(seq, 0, 0)),
+                    Instant::now(),
+                )
+                .unwrap()
+                .map(|(payload, acked_header)| (payload, acked_header.sequence()))
+        };
+
+        // fragments of group 1 and group 2 arrive mixed and out of order.
+        assert_eq!(handle(1, 0, 2, &[1, 1], Some(1)), None);
+        assert_eq!(handle(2, 0, 3, &[2, 2], Some(2)), None);
+        assert_eq!(handle(2, 1, 3, &[3, 3], None), None);
+        assert_eq!(handle(1, 1, 2, &[4, 4], None), Some((vec![1, 1, 4, 4], 1)));
+        assert_eq!(
+            handle(2, 2, 3, &[5, 5], None),
+            Some((vec![2, 2, 3, 3, 5, 5], 2))
+        );
+    }
+
+    #[test]
+    pub fn reassemble_reordered_fragments() {
+        let config = Config {
+            fragment_size: 2,
+            ..Default::default()
+        };
+        let mut fragmentation = Fragmentation::new(&config);
+        let time = Instant::now();
+
+        let mut handle = |id, payload: &[u8], acked_header| {
+            fragmentation
+                .handle_fragment(FragmentHeader::new(1, id, 3), payload, acked_header, time)
+                .unwrap()
+                .map(|(payload, _)| payload)
+        };
+
+        assert_eq!(handle(1, &[3, 4], None), None);
+        assert_eq!(
+            handle(0, &[1, 2], Some(AckedPacketHeader::new(1, 0, 0))),
+            None
+        );
+        assert_eq!(handle(2, &[5], None), Some(vec![1, 2, 3, 4, 5]));
+    }
+
+    #[test]
+    pub fn reject_fragments_that_are_not_full_before_the_last_one() {
+        let config = Config {
+            fragment_size: 2,
+            ..Default::default()
+        };
+        let mut fragmentation = Fragmentation::new(&config);
+
+        match fragmentation.handle_fragment(
+            FragmentHeader::new(1, 0, 2),
+            &[1],
+            Some(AckedPacketHeader::new(1, 0, 0)),
+            Instant::now(),
+        ) {
+            Err(ErrorKind::FragmentError(FragmentErrorKind::IncompleteFragment)) => {}
+            _ => panic!["Supposed to get an incomplete fragment error"],
+        }
+        assert!(!fragmentation.fragments.exists(1));
+    }
+
+    #[test]
+    pub fn incomplete_fragment_groups_are_discarded_after_the_timeout() {
+        let config = Config {
+            fragment_size: 8,
+            fragment_timeout: Duration::from_secs(1),
+            ..Default::default()
+        };
+        let mut fragmentation = Fragmentation::new(&config);
+        let time = Instant::now();
+
+        let first = fragmentation.handle_fragment(
+            FragmentHeader::new(1, 0, 2),
+            &[1; 8],
+            Some(AckedPacketHeader::new(1, 0, 0)),
+            time,
+        );
+        assert!(first.unwrap().is_none());
+
+        // the fragments are kept until the timeout passed.
+        assert_eq!(
+            fragmentation.discard_expired(time + Duration::from_millis(999)),
+            0
+        );
+        assert!(fragmentation.fragments.exists(1));
+
+        assert_eq!(
+            fragmentation.discard_expired(time + Duration::from_secs(1)),
+            1
+        );
+        assert!(!fragmentation.fragments.exists(1));
+
+        // the second fragment arrives late, it does not start the discarded group again.
+        let late = fragmentation.handle_fragment(
+            FragmentHeader::new(1, 1, 2),
+            &[2; 8],
+            None,
+            time + Duration::from_secs(1),
+        );
+        assert!(late.unwrap().is_none());
+        assert!(!fragmentation.fragments.exists(1));
+    }
+
+    #[test]
+    pub fn late_fragments_of_a_reassembled_group_are_ignored() {
+        let config = Config {
+            fragment_size: 8,
+            ..Default::default()
+        };
+        let mut fragmentation = Fragmentation::new(&config);
+        let time = Instant::now();
+
+        let first = fragmentation.handle_fragment(
+            FragmentHeader::new(1, 0, 2),
+            &[1; 8],
+            Some(AckedPacketHeader::new(1, 0, 0)),
+            time,
+        );
+        assert!(first.unwrap().is_none());
+        let second =
+            fragmentation.handle_fragment(FragmentHeader::new(1, 1, 2), &[2; 8], None, time);
+        assert!(second.unwrap().is_some());
+
+        // a duplicate of the first fragment arrives after the packet was reassembled.
+        let duplicate = fragmentation.handle_fragment(
+            FragmentHeader::new(1, 0, 2),
+            &[1; 8],
+            Some(AckedPacketHeader::new(1, 0, 0)),
+            time,
+        );
+        assert!(duplicate.unwrap().is_none());
+        assert!(!fragmentation.fragments.exists(1));
+    }
 }
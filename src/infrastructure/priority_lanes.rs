@@ -0,0 +1,86 @@
+use std::collections::VecDeque;
+
+use crate::packet::Priority;
+
+/// Queues that keep the items of each priority apart, the items of higher priorities are taken first.
+///
+/// Items of the same priority are taken in the order they were pushed.
+#[derive(Debug)]
+pub struct PriorityLanes<T> {
+    // one lane per priority, the lowest first.
+    lanes: [VecDeque<T>; 3],
+}
+
+impl<T> PriorityLanes<T> {
+    /// Constructs new, empty `PriorityLanes`.
+    pub fn new() -> PriorityLanes<T> {
+        PriorityLanes {
+            lanes: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+        }
+    }
+
+    /// Appends an item to the lane of the given priority.
+    pub fn push_back(&mut self, priority: Priority, item: T) {
+        self.lanes[priority as usize].push_back(item);
+    }
+
+    /// Returns true if items of the given priority or a higher one are waiting,
+    /// an item of this priority has to wait behind them.
+    pub fn holds_back(&self, priority: Priority) -> bool {
+        self.lanes[priority as usize..]
+            .iter()
+            .any(|lane| !lane.is_empty())
+    }
+
+    /// Returns the item that is taken next.
+    pub fn front(&self) -> Option<&T> {
+        self.lanes.iter().rev().find_map(VecDeque::front)
+    }
+
+    /// Removes and returns the oldest item of the highest priority.
+    pub fn pop_front(&mut self) -> Option<T> {
+        self.lanes.iter_mut().rev().find_map(VecDeque::pop_front)
+    }
+
+    /// Returns the number of waiting items of all priorities.
+    pub fn len(&self) -> usize {
+        self.lanes.iter().map(VecDeque::len).sum()
+    }
+}
+
+impl<T> Default for PriorityLanes<T> {
+    fn default() -> Self {
+        PriorityLanes::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PriorityLanes;
+    use crate::packet::Priority;
+
+    #[test]
+    fn higher_priorities_are_taken_first_in_order() {
+        let mut lanes = PriorityLanes::new();
+        lanes.push_back(Priority::Low, 0);
+        lanes.push_back(Priority::Normal, 1);
+        lanes.push_back(Priority::High, 2);
+        lanes.push_back(Priority::Normal, 3);
+        lanes.push_back(Priority::High, 4);
+
+        assert_eq!(lanes.len(), 5);
+        assert_eq!(lanes.front(), Some(&2));
+        let taken: Vec<_> = std::iter::from_fn(|| lanes.pop_front()).collect();
+        assert_eq!(taken, vec![2, 4, 1, 3, 0]);
+    }
+
+    #[test]
+    fn only_equal_or_higher_priorities_hold_back() {
+        let mut lanes = PriorityLanes::new();
+        lanes.push_back(Priority::Normal, 0);
+
+        assert!(lanes.holds_back(Priority::Low));
+        assert!(lanes.holds_back(Priority::Normal));
+        assert!(!lanes.holds_back(Priority::High));
+    }
+}
@@ -22,10 +22,23 @@
 #![warn(missing_docs)]
 #![allow(clippy::trivially_copy_pass_by_ref)]
 
-pub use self::config::Config;
-pub use self::error::{ErrorKind, Result};
-pub use self::net::{LinkConditioner, Socket, SocketEvent};
-pub use self::packet::{DeliveryGuarantee, OrderingGuarantee, Packet};
+pub use self::config::{Config, ConfigBuilder, OverflowPolicy};
+pub use self::error::{ConfigErrorKind, ErrorKind, Result};
+#[cfg(feature = "std-threads")]
+pub use self::net::PollingHandle;
+#[cfg(feature = "json")]
+pub use self::net::STATS_SCHEMA_VERSION;
+pub use self::net::{
+    AddressFilter, Bandwidth, Clock, ConnectionQuality, ConnectionSnapshot, ConnectionState,
+    ConnectionStats, DroppedPacket, LinkConditioner, ManualClock, NetworkStats, PayloadSocket,
+    QualityThresholds, Socket, SocketEvent, Subnet, SystemClock,
+};
+#[cfg(feature = "encryption")]
+pub use self::packet::EncryptionKey;
+pub use self::packet::{
+    AckFieldWidth, Compression, DeliveryGuarantee, HeaderInfo, OrderingGuarantee, Packet,
+    PacketType, Priority,
+};
 #[cfg(feature = "tester")]
 pub use self::throughput::ThroughputMonitoring;
 
@@ -42,4 +55,5 @@ mod sequence_buffer;
 mod throughput;
 
 #[cfg(test)]
+#[allow(missing_docs)]
 pub mod test_utils;
@@ -1,21 +1,37 @@
 //! This module provides the logic between the low-level abstract types and the types that the user will be interacting with.
 //! You can think of the socket, connection management, congestion control.
 
-pub use self::connection::{Connection, ConnectionEventAddress, ConnectionMessenger};
+pub use self::address_filter::{AddressFilter, Subnet};
+pub use self::clock::{Clock, ManualClock, SystemClock};
+pub use self::connection::{
+    Connection, ConnectionEventAddress, ConnectionMessenger, ConnectionState, ResumeRequest,
+};
 pub use self::connection_manager::{ConnectionManager, DatagramSocket};
-pub use self::events::SocketEvent;
+pub use self::events::{DroppedPacket, SocketEvent};
 pub use self::link_conditioner::LinkConditioner;
-pub use self::quality::{NetworkQuality, RttMeasurer};
-pub use self::socket::Socket;
+pub use self::quality::{ConnectionQuality, NetworkQuality, QualityThresholds, RttMeasurer};
+#[cfg(feature = "std-threads")]
+pub use self::socket::PollingHandle;
+pub use self::socket::{PayloadSocket, Socket};
+#[cfg(feature = "json")]
+pub(crate) use self::stats::stats_json;
+#[cfg(feature = "json")]
+pub use self::stats::STATS_SCHEMA_VERSION;
+pub use self::stats::{Bandwidth, ConnectionSnapshot, ConnectionStats, NetworkStats};
 pub use self::virtual_connection::VirtualConnection;
 
+mod address_filter;
+mod clock;
 mod connection;
 mod connection_impl;
 mod connection_manager;
 mod events;
+#[cfg(feature = "fast-hash")]
+mod fx_hasher;
 mod link_conditioner;
 mod quality;
 mod socket;
+mod stats;
 mod virtual_connection;
 
 pub mod constants;
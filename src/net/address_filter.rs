@@ -0,0 +1,152 @@
+//! This module provides the filter that decides which remote addresses packets are accepted from.
+
+use std::net::{IpAddr, SocketAddr};
+
+/// A range of ip addresses, given by an address and the number of leading bits that have to match.
+///
+/// IPv4 subnets match the IPv4-mapped IPv6 addresses of dual-stack sockets as well.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Subnet {
+    address: IpAddr,
+    prefix_length: u8,
+}
+
+impl Subnet {
+    /// Creates a subnet of the addresses whose first `prefix_length` bits are the same as those of `address`.
+    ///
+    /// Prefix lengths longer than the address are shortened to it, which matches only `address` itself.
+    pub fn new(address: IpAddr, prefix_length: u8) -> Subnet {
+        let (address, prefix_length) = match (address, canonical(address)) {
+            // the prefix of an IPv4-mapped address includes the 96 bits of the mapping.
+            (IpAddr::V6(_), IpAddr::V4(v4)) => (IpAddr::V4(v4), prefix_length.saturating_sub(96)),
+            (_, address) => (address, prefix_length),
+        };
+        let bits = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        Subnet {
+            address,
+            prefix_length: prefix_length.min(bits),
+        }
+    }
+
+    /// Returns true if the given address is part of this subnet.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, canonical(address)) {
+            // IPv4 addresses are aligned with the start of the 128 bits, so that the prefix counts from there.
+            (IpAddr::V4(subnet), IpAddr::V4(address)) => prefix_matches(
+                u128::from(u32::from(subnet)) << 96,
+                u128::from(u32::from(address)) << 96,
+                self.prefix_length,
+            ),
+            (IpAddr::V6(subnet), IpAddr::V6(address)) => {
+                prefix_matches(u128::from(subnet), u128::from(address), self.prefix_length)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl From<IpAddr> for Subnet {
+    /// Creates a subnet that only contains the given address.
+    fn from(address: IpAddr) -> Subnet {
+        Subnet::new(address, 128)
+    }
+}
+
+/// Decides which remote addresses packets are accepted from, see `Socket::set_address_filter`.
+///
+/// Blocked addresses are always rejected. When any subnet is allowed, addresses that are not part of
+/// an allowed subnet are rejected as well, an empty allowlist allows every address that is not blocked.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AddressFilter {
+    allowed: Vec<Subnet>,
+    blocked: Vec<Subnet>,
+}
+
+impl AddressFilter {
+    /// Creates a filter that accepts packets from every address.
+    pub fn new() -> AddressFilter {
+        AddressFilter::default()
+    }
+
+    /// Adds a subnet to the allowlist, only the addresses of allowed subnets are accepted from then on.
+    pub fn allow(mut self, subnet: impl Into<Subnet>) -> AddressFilter {
+        self.allowed.push(subnet.into());
+        self
+    }
+
+    /// Adds a subnet to the blocklist, its addresses are rejected even if they are allowed.
+    pub fn block(mut self, subnet: impl Into<Subnet>) -> AddressFilter {
+        self.blocked.push(subnet.into());
+        self
+    }
+
+    /// Returns true if packets from the given address are accepted.
+    pub fn accepts(&self, address: &SocketAddr) -> bool {
+        let ip = address.ip();
+        !self.blocked.iter().any(|subnet| subnet.contains(ip))
+            && (self.allowed.is_empty() || self.allowed.iter().any(|subnet| subnet.contains(ip)))
+    }
+}
+
+// IPv4 addresses that arrive on a dual-stack socket are mapped into IPv6.
+fn canonical(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(address, IpAddr::V4),
+        IpAddr::V4(_) => address,
+    }
+}
+
+fn prefix_matches(subnet: u128, address: u128, prefix_length: u8) -> bool {
+    let mask = u128::MAX
+        .checked_shl(128 - u32::from(prefix_length))
+        .unwrap_or(0);
+    subnet & mask == address & mask
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    use super::{AddressFilter, Subnet};
+
+    fn address(address: &str) -> SocketAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn subnets_match_their_prefix() {
+        let subnet = Subnet::new(IpAddr::V4(Ipv4Addr::new(10, 1, 0, 0)), 16);
+        assert!(subnet.contains(IpAddr::V4(Ipv4Addr::new(10, 1, 255, 3))));
+        assert!(!subnet.contains(IpAddr::V4(Ipv4Addr::new(10, 2, 0, 1))));
+        assert!(subnet.contains(IpAddr::V6(Ipv4Addr::new(10, 1, 0, 9).to_ipv6_mapped())));
+        assert!(!subnet.contains(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+
+        let everything = Subnet::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0);
+        assert!(everything.contains(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+        assert!(
+            Subnet::from(IpAddr::V6(Ipv6Addr::LOCALHOST)).contains(IpAddr::V6(Ipv6Addr::LOCALHOST))
+        );
+    }
+
+    #[test]
+    fn empty_allowlist_accepts_everything_that_is_not_blocked() {
+        let filter = AddressFilter::new().block(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7)));
+
+        assert!(filter.accepts(&address("10.0.0.8:9000")));
+        assert!(!filter.accepts(&address("10.0.0.7:9000")));
+    }
+
+    #[test]
+    fn allowlist_accepts_only_its_subnets() {
+        let filter = AddressFilter::new()
+            .allow(Subnet::new(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 0)), 24))
+            .block(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 13)));
+
+        assert!(filter.accepts(&address("192.168.0.200:9000")));
+        assert!(!filter.accepts(&address("192.168.1.1:9000")));
+        assert!(!filter.accepts(&address("192.168.0.13:9000")));
+    }
+}
@@ -0,0 +1,75 @@
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Source of the current time for a `Socket`.
+///
+/// The socket asks its clock for the time whenever it polls by itself, e.g. in `Socket::poll` or in its polling loop.
+/// `Socket::manual_poll` uses the time it is given instead.
+pub trait Clock: Debug + Send {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+}
+
+/// Clock that returns the real time, this is what a socket uses by default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Clock that only moves forward when it is advanced, useful to test timeouts without sleeping.
+///
+/// Clones share the same time, so a clone can be advanced after the clock was handed to a socket.
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl ManualClock {
+    /// Creates a clock which starts at the current real time.
+    pub fn new() -> ManualClock {
+        ManualClock {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Moves the time of this clock, and all its clones, forward.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().expect("Clock is never poisoned") += duration;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().expect("Clock is never poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Clock, ManualClock};
+
+    #[test]
+    fn manual_clock_only_moves_when_advanced() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+
+        clock.clone().advance(Duration::from_secs(10));
+        assert_eq!(clock.now(), start + Duration::from_secs(10));
+    }
+}
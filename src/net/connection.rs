@@ -1,73 +1,184 @@
-use std::{self, fmt::Debug, net::SocketAddr, time::Instant};
-
-use crate::config::Config;
-
-/// Allows connection to send packet, send event and get global configuration.
-pub trait ConnectionMessenger<ReceiveEvent: Debug> {
-    /// Returns global configuration.
-    fn config(&self) -> &Config;
-
-    /// Sends a connection event.
-    fn send_event(&mut self, address: &SocketAddr, event: ReceiveEvent);
-    /// Sends a packet.
-    fn send_packet(&mut self, address: &SocketAddr, payload: &[u8]);
-}
-
-/// Returns an address of an event.
-/// This is used by a `ConnectionManager`, because it doesn't know anything about connection events.
-pub trait ConnectionEventAddress {
-    /// Returns event address
-    fn address(&self) -> SocketAddr;
-}
-
-/// Allows to implement actual connection.
-/// Defines a type of `Send` and `Receive` events, that will be used by a connection.
-pub trait Connection: Debug {
-    /// Defines a user event type.
-    type SendEvent: Debug + ConnectionEventAddress;
-    /// Defines a connection event type.
-    type ReceiveEvent: Debug + ConnectionEventAddress;
-
-    /// Creates new connection and initialize it by sending an connection event to the user.
-    /// * messenger - allows to send packets and events, also provides a config.
-    /// * address - defines a address that connection is associated with.
-    /// * time - creation time, used by connection, so that it doesn't get dropped immediately or send heartbeat packet.
-    /// * initial_data - if initiated by remote host, this will hold that a packet data.
-    fn create_connection(
-        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
-        address: SocketAddr,
-        time: Instant,
-        initial_data: Option<&[u8]>,
-    ) -> Self;
-
-    /// Determines if the connection should be dropped due to its state.
-    fn should_drop(
-        &mut self,
-        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
-        time: Instant,
-    ) -> bool;
-
-    /// Processes a received packet: parse it and emit an event.
-    fn process_packet(
-        &mut self,
-        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
-        payload: &[u8],
-        time: Instant,
-    );
-
-    /// Processes a received event and send a packet.
-    fn process_event(
-        &mut self,
-        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
-        event: Self::SendEvent,
-        time: Instant,
-    );
-
-    /// Processes various connection-related tasks: resend dropped packets, send heartbeat packet, etc...
-    /// This function gets called frequently.
-    fn update(
-        &mut self,
-        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
-        time: Instant,
-    );
-}
+use std::{self, fmt::Debug, io, net::SocketAddr, time::Instant};
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::net::ConnectionStats;
+
+/// Allows connection to send packet, send event and get global configuration.
+pub trait ConnectionMessenger<ReceiveEvent: Debug> {
+    /// Returns global configuration.
+    fn config(&self) -> &Config;
+
+    /// Sends a connection event.
+    fn send_event(&mut self, address: &SocketAddr, event: ReceiveEvent);
+    /// Sends an event that reports dropped packets, it may be held back, see `Config::max_dropped_reports`.
+    fn report_dropped(&mut self, address: &SocketAddr, event: ReceiveEvent);
+    /// Sends a packet.
+    fn send_packet(&mut self, address: &SocketAddr, payload: &[u8]);
+    /// Reports a connection that timed out, before it is removed.
+    fn connection_timed_out(&mut self, address: &SocketAddr);
+}
+
+/// A request to resume a connection from an address without a connection, see `Socket::resume`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ResumeRequest {
+    /// The token of the connection that is resumed, see `Connection::session`.
+    pub session: u64,
+    /// The token of the challenge that the address answers, if it was challenged already, see `Connection::challenge_resume`.
+    pub challenge: Option<u64>,
+}
+
+/// The state of a connection with a remote host.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum ConnectionState {
+    /// The handshake was started, but it did not complete yet.
+    /// Connections are only in this state when `Config::require_handshake` is set.
+    Connecting,
+    /// Packets can be exchanged with the remote host.
+    Connected,
+    /// The remote host closed the connection, it is removed with the next timeout check.
+    Disconnecting,
+    /// The connection was closed or timed out and is no longer used.
+    Disconnected,
+}
+
+/// Returns an address of an event.
+/// This is used by a `ConnectionManager`, because it doesn't know anything about connection events.
+pub trait ConnectionEventAddress {
+    /// Returns event address
+    fn address(&self) -> SocketAddr;
+}
+
+/// Allows to implement actual connection.
+/// Defines a type of `Send` and `Receive` events, that will be used by a connection.
+pub trait Connection: Debug {
+    /// Defines a user event type.
+    type SendEvent: Debug + ConnectionEventAddress;
+    /// Defines a connection event type.
+    type ReceiveEvent: Debug + ConnectionEventAddress;
+
+    /// Creates new connection and initialize it by sending an connection event to the user.
+    /// * messenger - allows to send packets and events, also provides a config.
+    /// * address - defines a address that connection is associated with.
+    /// * time - creation time, used by connection, so that it doesn't get dropped immediately or send heartbeat packet.
+    /// * initial_data - if initiated by remote host, this will hold that a packet data.
+    /// * previous_epoch - the epoch of the last connection with this address, if it was removed recently.
+    fn create_connection(
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        address: SocketAddr,
+        time: Instant,
+        initial_data: Option<&[u8]>,
+        previous_epoch: Option<u8>,
+    ) -> Self;
+
+    /// Determines if the connection should be dropped due to its state.
+    fn should_drop(
+        &mut self,
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        time: Instant,
+    ) -> bool;
+
+    /// Processes a received packet: parse it and emit an event.
+    fn process_packet(
+        &mut self,
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        payload: &[u8],
+        time: Instant,
+    );
+
+    /// Processes a received event and send a packet.
+    fn process_event(
+        &mut self,
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        event: Self::SendEvent,
+        time: Instant,
+    );
+
+    /// Closes the connection: notifies the remote host and emits an event to the user.
+    /// The connection is removed from the active connections list afterwards.
+    fn disconnect(
+        &mut self,
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        time: Instant,
+    );
+
+    /// Sends a ping, which the remote host echoes back to measure the round trip time.
+    fn ping(&mut self, messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>, time: Instant);
+
+    /// Sends the acknowledgments of the received reliable packets right away, if they were not sent yet.
+    fn flush_acks(
+        &mut self,
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        time: Instant,
+    );
+
+    /// Starts the connection over in a new epoch, which the remote host is notified about right away.
+    /// Packets of the previous epoch are ignored from then on.
+    fn reset(
+        &mut self,
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        time: Instant,
+    );
+
+    /// Asks the remote host to move the connection to the address it is reached from now,
+    /// after the address of this side changed.
+    fn resume(
+        &mut self,
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        time: Instant,
+    );
+
+    /// Checks that a datagram from an address without a connection holds a valid header,
+    /// before a connection is created for it.
+    ///
+    /// Returns the request to resume a connection, if the datagram is one.
+    fn check_new_datagram(config: &Config, payload: &[u8]) -> Result<Option<ResumeRequest>>;
+
+    /// Returns the token the connection can be resumed with, once its handshake completed.
+    fn session(&self) -> Option<u64>;
+
+    /// Returns the event that reports a datagram to the given address which could not be sent.
+    fn send_failed_event(address: SocketAddr, error: io::ErrorKind) -> Self::ReceiveEvent;
+
+    /// Challenges the address the remote host asks to resume the connection from,
+    /// the connection is only moved once the address answers the challenge.
+    fn challenge_resume(
+        &mut self,
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        address: SocketAddr,
+        time: Instant,
+    );
+
+    /// Returns true if the given address answers the challenge it was sent last, see `challenge_resume`.
+    fn answers_resume(&self, address: &SocketAddr, challenge: u64) -> bool;
+
+    /// Moves the connection to the address the remote host resumed it from and emits an event to the user.
+    /// The connection gets a new token, so that the resume can not be replayed.
+    fn move_to(
+        &mut self,
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        address: SocketAddr,
+    );
+
+    /// Returns the packet counters of the connection.
+    fn stats(&self) -> ConnectionStats;
+
+    /// Returns the epoch the connection is in, packets of earlier epochs are ignored.
+    fn epoch(&self) -> u8;
+
+    /// Returns the state of the connection.
+    fn state(&self) -> ConnectionState;
+
+    /// Returns true if the remote host asked for the connection, but did not answer its challenge yet.
+    /// These connections are kept apart from the active connections until they are verified.
+    fn is_challenged(&self) -> bool;
+
+    /// Processes various connection-related tasks: resend dropped packets, send heartbeat packet, etc...
+    /// This function gets called frequently.
+    fn update(
+        &mut self,
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        time: Instant,
+    );
+}
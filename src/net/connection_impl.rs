@@ -1,175 +1,681 @@
-use std::net::SocketAddr;
-use std::time::Instant;
-
-use log::error;
-
-use crate::error::{ErrorKind, Result};
-use crate::packet::{DeliveryGuarantee, OutgoingPackets, Packet, PacketInfo};
-
-use super::{
-    events::SocketEvent, Connection, ConnectionEventAddress, ConnectionMessenger, VirtualConnection,
-};
-
-/// Required by `ConnectionManager` to properly handle connection event.
-impl ConnectionEventAddress for SocketEvent {
-    /// Returns event address.
-    fn address(&self) -> SocketAddr {
-        match self {
-            SocketEvent::Packet(packet) => packet.addr(),
-            SocketEvent::Connect(addr) => *addr,
-            SocketEvent::Timeout(addr) => *addr,
-        }
-    }
-}
-
-/// Required by `ConnectionManager` to properly handle user event.
-impl ConnectionEventAddress for Packet {
-    /// Returns event address.
-    fn address(&self) -> SocketAddr {
-        self.addr()
-    }
-}
-
-impl Connection for VirtualConnection {
-    /// Defines a user event type.
-    type SendEvent = Packet;
-    /// Defines a connection event type.
-    type ReceiveEvent = SocketEvent;
-
-    /// Creates new connection and initialize it by sending an connection event to the user.
-    /// * address - defines a address that connection is associated with.
-    /// * time - creation time, used by connection, so that it doesn't get dropped immediately or send heartbeat packet.
-    /// * initial_data - if initiated by remote host, this will hold that a packet data.
-    fn create_connection(
-        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
-        address: SocketAddr,
-        time: Instant,
-        initial_data: Option<&[u8]>,
-    ) -> VirtualConnection {
-        // emit connect event if this is initiated by the remote host.
-        if initial_data.is_some() {
-            messenger.send_event(&address, SocketEvent::Connect(address));
-        }
-        VirtualConnection::new(address, messenger.config(), time)
-    }
-
-    /// Determines if the given `Connection` should be dropped due to its state.
-    fn should_drop(
-        &mut self,
-        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
-        time: Instant,
-    ) -> bool {
-        let should_drop = self.packets_in_flight() > messenger.config().max_packets_in_flight
-            || self.last_heard(time) >= messenger.config().idle_connection_timeout;
-        if should_drop {
-            messenger.send_event(
-                &self.remote_address,
-                SocketEvent::Timeout(self.remote_address),
-            );
-        }
-        should_drop
-    }
-
-    /// Processes a received packet: parse it and emit an event.
-    fn process_packet(
-        &mut self,
-        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
-        payload: &[u8],
-        time: Instant,
-    ) {
-        if !payload.is_empty() {
-            match self.process_incoming(payload, time) {
-                Ok(packets) => {
-                    for incoming in packets {
-                        messenger.send_event(&self.remote_address, SocketEvent::Packet(incoming.0));
-                    }
-                }
-                Err(err) => error!("Error occured processing incomming packet: {:?}", err),
-            }
-        } else {
-            error!(
-                "Error processing packet: {}",
-                ErrorKind::ReceivedDataToShort
-            );
-        }
-    }
-
-    /// Processes a received event and send a packet.
-    fn process_event(
-        &mut self,
-        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
-        event: Self::SendEvent,
-        time: Instant,
-    ) {
-        let addr = self.remote_address;
-        send_packets(
-            messenger,
-            &addr,
-            self.process_outgoing(
-                PacketInfo::user_packet(
-                    event.payload(),
-                    event.delivery_guarantee(),
-                    event.order_guarantee(),
-                ),
-                None,
-                time,
-            ),
-            "user packet",
-        );
-    }
-
-    /// Processes various connection-related tasks: resend dropped packets, send heartbeat packet, etc...
-    /// This function gets called very frequently.
-    fn update(
-        &mut self,
-        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
-        time: Instant,
-    ) {
-        // resend dropped packets
-        for dropped in self.gather_dropped_packets() {
-            let packets = self.process_outgoing(
-                PacketInfo {
-                    packet_type: dropped.packet_type,
-                    payload: &dropped.payload,
-                    // because a delivery guarantee is only sent with reliable packets
-                    delivery: DeliveryGuarantee::Reliable,
-                    // this is stored with the dropped packet because they could be mixed
-                    ordering: dropped.ordering_guarantee,
-                },
-                dropped.item_identifier,
-                time,
-            );
-            send_packets(messenger, &self.remote_address, packets, "dropped packets");
-        }
-
-        // send heartbeat packets if required
-        if let Some(heartbeat_interval) = messenger.config().heartbeat_interval {
-            let addr = self.remote_address;
-            if self.last_sent(time) >= heartbeat_interval {
-                send_packets(
-                    messenger,
-                    &addr,
-                    self.process_outgoing(PacketInfo::heartbeat_packet(&[]), None, time),
-                    "heatbeat packet",
-                );
-            }
-        }
-    }
-}
-
-// Sends multiple outgoing packets.
-fn send_packets(
-    ctx: &mut impl ConnectionMessenger<SocketEvent>,
-    address: &SocketAddr,
-    packets: Result<OutgoingPackets>,
-    err_context: &str,
-) {
-    match packets {
-        Ok(packets) => {
-            for outgoing in packets {
-                ctx.send_packet(address, &outgoing.contents());
-            }
-        }
-        Err(error) => error!("Error occured processing {}: {:?}", err_context, error),
-    }
-}
+use std::fmt::Debug;
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use log::{debug, error, info, trace};
+
+use crate::config::Config;
+use crate::error::{ErrorKind, Result};
+use crate::infrastructure::SentPacket;
+use crate::packet::{
+    batch, Checksum, DeliveryGuarantee, MessageBatch, OutgoingPackets, Packet, PacketInfo,
+    PacketType,
+};
+
+use super::{
+    constants::HANDSHAKE_RESEND_INTERVAL_MS,
+    events::{DroppedPacket, SocketEvent},
+    virtual_connection::HandshakeReply,
+    Connection, ConnectionEventAddress, ConnectionMessenger, ConnectionState, ConnectionStats,
+    ResumeRequest, VirtualConnection,
+};
+
+/// Required by `ConnectionManager` to properly handle connection event.
+impl ConnectionEventAddress for SocketEvent {
+    /// Returns event address.
+    fn address(&self) -> SocketAddr {
+        match self {
+            SocketEvent::Packet(packet) | SocketEvent::DeliveryFailed(packet) => packet.addr(),
+            SocketEvent::Connect(addr) => *addr,
+            SocketEvent::Connected(addr) => *addr,
+            SocketEvent::AddressChanged(_, addr) => *addr,
+            SocketEvent::Timeout(addr) => *addr,
+            SocketEvent::Disconnect(addr) => *addr,
+            SocketEvent::ConnectionFailed(addr) => *addr,
+            SocketEvent::PacketAcked(addr, _) => *addr,
+            SocketEvent::PacketsDropped(addr, _) => *addr,
+            SocketEvent::SendFailed(addr, _) => *addr,
+            SocketEvent::SequenceSkipped(addr, _) => *addr,
+        }
+    }
+}
+
+/// Required by `ConnectionManager` to properly handle user event.
+impl<P: AsRef<[u8]>> ConnectionEventAddress for Packet<P> {
+    /// Returns event address.
+    fn address(&self) -> SocketAddr {
+        self.addr()
+    }
+}
+
+impl<P: AsRef<[u8]> + Debug> Connection for VirtualConnection<P> {
+    /// Defines a user event type.
+    type SendEvent = Packet<P>;
+    /// Defines a connection event type.
+    type ReceiveEvent = SocketEvent;
+
+    /// Creates new connection and initialize it by sending an connection event to the user.
+    /// * address - defines a address that connection is associated with.
+    /// * time - creation time, used by connection, so that it doesn't get dropped immediately or send heartbeat packet.
+    /// * initial_data - if initiated by remote host, this will hold that a packet data.
+    /// * previous_epoch - the connection starts in the epoch after it, so that late packets of the previous one are ignored.
+    fn create_connection(
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        address: SocketAddr,
+        time: Instant,
+        initial_data: Option<&[u8]>,
+        previous_epoch: Option<u8>,
+    ) -> VirtualConnection<P> {
+        // emit connect event if this is initiated by the remote host.
+        if initial_data.is_some() {
+            messenger.send_event(&address, SocketEvent::Connect(address));
+        }
+        let mut connection = VirtualConnection::new(address, messenger.config(), time);
+        if let Some(epoch) = previous_epoch {
+            connection.continue_after_epoch(epoch);
+        }
+        // the side that starts the connection asks for the handshake.
+        if initial_data.is_none() && connection.state == ConnectionState::Connecting {
+            let packets = connection.process_outgoing(PacketInfo::connect_packet(), None, time);
+            send_packets(&mut connection, messenger, packets, "handshake packet");
+        }
+        connection
+    }
+
+    /// Determines if the given `Connection` should be dropped due to its state.
+    fn should_drop(
+        &mut self,
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        time: Instant,
+    ) -> bool {
+        // unanswered challenges are forgotten quickly, without bothering the user.
+        if self.is_challenged() {
+            return self.is_stale(messenger.config().handshake_timeout, time);
+        }
+
+        // the disconnect event was already emitted when the disconnect packet was processed.
+        if self.state == ConnectionState::Disconnecting {
+            self.state = ConnectionState::Disconnected;
+            return true;
+        }
+
+        let should_drop = self.packets_in_flight() > messenger.config().max_packets_in_flight
+            || self.is_stale(self.idle_timeout(), time);
+        if should_drop {
+            messenger.connection_timed_out(&self.remote_address);
+            self.state = ConnectionState::Disconnected;
+            messenger.send_event(
+                &self.remote_address,
+                SocketEvent::Timeout(self.remote_address),
+            );
+        }
+        should_drop
+    }
+
+    /// Processes a received packet: parse it and emit an event.
+    fn process_packet(
+        &mut self,
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        payload: &[u8],
+        time: Instant,
+    ) {
+        if !payload.is_empty() {
+            trace!(
+                "Received {} bytes from {}",
+                payload.len(),
+                self.remote_address
+            );
+            self.record_received_bytes(payload.len(), time);
+            // corrupt packets and packets of other protocol versions are dropped before they reach the connection state.
+            let received = self.open(payload);
+            let state = self.state;
+            match received.and_then(|payload| self.process_incoming(&payload, time)) {
+                Ok(packets) => {
+                    if let Some(reply) = self.handshake_reply.take() {
+                        send_handshake_reply(self, messenger, reply, time);
+                    }
+                    if let Some(timestamp) = self.pong_reply.take() {
+                        let payload = self.pong_payload(timestamp, time);
+                        let packets =
+                            self.process_outgoing(PacketInfo::pong_packet(&payload), None, time);
+                        send_packets(self, messenger, packets, "pong packet");
+                    }
+                    if let Some(payload) = self.negative_ack_payload() {
+                        let packets =
+                            self.process_outgoing(PacketInfo::nack_packet(&payload), None, time);
+                        send_packets(self, messenger, packets, "negative acknowledgment");
+                    }
+                    if state == ConnectionState::Connecting
+                        && self.state == ConnectionState::Connected
+                    {
+                        info!("Handshake with {} completed", self.remote_address);
+                        messenger.send_event(
+                            &self.remote_address,
+                            SocketEvent::Connected(self.remote_address),
+                        );
+                        // the packets that were sent during the handshake can go out now.
+                        for packet in std::mem::take(&mut self.queued_packets) {
+                            self.process_event(messenger, packet, time);
+                        }
+                    }
+                    for incoming in packets {
+                        messenger.send_event(&self.remote_address, SocketEvent::Packet(incoming.0));
+                    }
+                    for id in self.take_acknowledged() {
+                        messenger.send_event(
+                            &self.remote_address,
+                            SocketEvent::PacketAcked(self.remote_address, id),
+                        );
+                    }
+                    for stream_id in self.take_skipped_sequences() {
+                        messenger.send_event(
+                            &self.remote_address,
+                            SocketEvent::SequenceSkipped(self.remote_address, stream_id),
+                        );
+                    }
+                    if self.state == ConnectionState::Disconnecting {
+                        info!("Connection closed by {}", self.remote_address);
+                        messenger.send_event(
+                            &self.remote_address,
+                            SocketEvent::Disconnect(self.remote_address),
+                        );
+                    }
+                }
+                // the network duplicates datagrams, so the replays of encrypted ones are dropped quietly.
+                Err(ErrorKind::ReplayedPacket) => {
+                    debug!("Dropping replayed packet from {}", self.remote_address)
+                }
+                Err(err) => error!("Error occured processing incomming packet: {:?}", err),
+            }
+        } else {
+            error!(
+                "Error processing packet: {}",
+                ErrorKind::ReceivedDataToShort
+            );
+        }
+    }
+
+    /// Processes a received event and send a packet.
+    fn process_event(
+        &mut self,
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        event: Self::SendEvent,
+        time: Instant,
+    ) {
+        if self.state == ConnectionState::Connecting {
+            self.queued_packets.push(event);
+            return;
+        }
+
+        // packets that are held back by the bandwidth limit keep their order, unless they have a lower priority.
+        if self.rate_limited_packets.holds_back(event.priority())
+            || !self.bandwidth_allows(event.payload().len(), time)
+        {
+            if messenger.config().defer_rate_limited_packets {
+                self.rate_limited_packets.push_back(event.priority(), event);
+            } else {
+                debug!(
+                    "Not sending a packet to {}: {}",
+                    self.remote_address,
+                    ErrorKind::RateLimited
+                );
+                messenger.send_event(
+                    &self.remote_address,
+                    SocketEvent::DeliveryFailed(event.into_vec_payload()),
+                );
+            }
+            return;
+        }
+
+        send_user_packet(self, messenger, event, time);
+    }
+
+    /// Notifies the remote host that the connection is closed and emits a disconnect event.
+    fn disconnect(
+        &mut self,
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        time: Instant,
+    ) {
+        flush_batch(self, messenger, time);
+        let packets = self.process_outgoing(PacketInfo::disconnect_packet(), None, time);
+        send_packets(self, messenger, packets, "disconnect packet");
+
+        let addr = self.remote_address;
+        info!("Closed the connection with {}", addr);
+        messenger.send_event(&addr, SocketEvent::Disconnect(addr));
+        self.state = ConnectionState::Disconnected;
+    }
+
+    /// Sends a ping packet with the current timestamp, nothing is sent before the handshake completed.
+    fn ping(
+        &mut self,
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        time: Instant,
+    ) {
+        if self.state == ConnectionState::Connecting {
+            return;
+        }
+
+        let timestamp = self.ping_timestamp(time).to_be_bytes();
+        let packets = self.process_outgoing(PacketInfo::ping_packet(&timestamp), None, time);
+        send_packets(self, messenger, packets, "ping packet");
+    }
+
+    /// Sends a heartbeat if there are acknowledgments that did not piggyback on another packet yet.
+    fn flush_acks(
+        &mut self,
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        time: Instant,
+    ) {
+        if self.state == ConnectionState::Connecting || !self.has_pending_acks() {
+            return;
+        }
+
+        let packets = self.process_outgoing(PacketInfo::heartbeat_packet(&[]), None, time);
+        send_packets(self, messenger, packets, "heartbeat packet");
+    }
+
+    /// Starts a new epoch and sends a heartbeat in it, so that the remote host resets its side as well.
+    /// Nothing is reset before the handshake completed.
+    fn reset(
+        &mut self,
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        time: Instant,
+    ) {
+        if self.state == ConnectionState::Connecting {
+            return;
+        }
+
+        info!("Resetting the connection with {}", self.remote_address);
+        self.start_new_epoch();
+        let packets = self.process_outgoing(PacketInfo::heartbeat_packet(&[]), None, time);
+        send_packets(self, messenger, packets, "heartbeat packet");
+    }
+
+    /// Sends the token of the connection from the current address, nothing is sent before the handshake completed.
+    fn resume(
+        &mut self,
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        time: Instant,
+    ) {
+        let session = match self.session {
+            Some(session) if self.state == ConnectionState::Connected => session,
+            _ => return,
+        };
+
+        self.resuming = true;
+        let token = session.to_be_bytes();
+        let packets = self.process_outgoing(PacketInfo::resume_packet(&token), None, time);
+        send_packets(self, messenger, packets, "resume packet");
+    }
+
+    /// Checks the checksum and the standard header of a datagram from an address without a connection,
+    /// and returns the request to resume a connection, if it is one.
+    fn check_new_datagram(config: &Config, payload: &[u8]) -> Result<Option<ResumeRequest>> {
+        VirtualConnection::<P>::read_new_datagram(config, payload).map(|(_, request)| request)
+    }
+
+    /// Sends a new challenge to the address the remote host asks to resume the connection from,
+    /// only the address that asked last can answer.
+    fn challenge_resume(
+        &mut self,
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        address: SocketAddr,
+        time: Instant,
+    ) {
+        if self.state != ConnectionState::Connected {
+            return;
+        }
+
+        debug!(
+            "Challenging {} to resume the connection with {}",
+            address, self.remote_address
+        );
+        let challenge: u64 = rand::random();
+        self.resume_challenge = Some((address, challenge));
+        let token = challenge.to_be_bytes();
+        let packets = self.process_outgoing(PacketInfo::challenge_packet(&token), None, time);
+        send_packets_to(self, messenger, address, packets, "resume challenge");
+    }
+
+    /// Returns true if the given address answers the challenge it was sent last.
+    fn answers_resume(&self, address: &SocketAddr, challenge: u64) -> bool {
+        self.resume_challenge == Some((*address, challenge))
+    }
+
+    /// Returns the token the connection can be resumed with, once its handshake completed.
+    fn session(&self) -> Option<u64> {
+        self.session
+    }
+
+    /// Returns the event that reports a datagram to the given address which could not be sent.
+    fn send_failed_event(address: SocketAddr, error: io::ErrorKind) -> SocketEvent {
+        SocketEvent::SendFailed(address, error)
+    }
+
+    /// Moves the connection to the address the remote host resumed it from and emits an event to the user.
+    fn move_to(
+        &mut self,
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        address: SocketAddr,
+    ) {
+        let previous = self.remote_address;
+        info!("Connection with {} resumed from {}", previous, address);
+        self.remote_address = address;
+        self.previous_session = self.session;
+        self.session = Some(rand::random());
+        self.resume_challenge = None;
+        messenger.send_event(&address, SocketEvent::AddressChanged(previous, address));
+    }
+
+    /// Returns the packet counters of the connection.
+    fn stats(&self) -> ConnectionStats {
+        VirtualConnection::stats(self)
+    }
+
+    /// Returns the epoch the connection is in.
+    fn epoch(&self) -> u8 {
+        VirtualConnection::epoch(self)
+    }
+
+    /// Returns the state of the connection.
+    fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    /// Returns true if the remote host asked for the connection, but did not answer its challenge yet.
+    fn is_challenged(&self) -> bool {
+        VirtualConnection::is_challenged(self)
+    }
+
+    /// Processes various connection-related tasks: resend dropped packets, send heartbeat packet, etc...
+    /// This function gets called very frequently.
+    fn update(
+        &mut self,
+        messenger: &mut impl ConnectionMessenger<Self::ReceiveEvent>,
+        time: Instant,
+    ) {
+        self.age_bandwidth(time);
+
+        // nothing is sent before the handshake completed, except for asking for it again.
+        if self.state == ConnectionState::Connecting {
+            if !self.is_challenged()
+                && self.last_sent(time) >= Duration::from_millis(HANDSHAKE_RESEND_INTERVAL_MS)
+            {
+                let packets = self.process_outgoing(PacketInfo::connect_packet(), None, time);
+                send_packets(self, messenger, packets, "handshake packet");
+            }
+            return;
+        }
+
+        // fragmented packets whose other fragments got lost are not kept forever.
+        self.discard_expired_fragments(time);
+
+        // resend dropped packets, until they were resent too often.
+        // Those the remote host reported missing since the last update are resent as well, see `Config::negative_acks`.
+        let max_resends = messenger.config().max_resends;
+        let mut dropped_packets = self.gather_dropped_packets();
+        dropped_packets.extend(self.gather_nacked_packets());
+        if !dropped_packets.is_empty() {
+            let address = self.remote_address;
+            let reports = dropped_packets
+                .iter()
+                .flat_map(|dropped| {
+                    user_packets(address, dropped)
+                        .into_iter()
+                        .map(move |packet| DroppedPacket {
+                            packet,
+                            sequence: dropped.sequence,
+                            resends: dropped.resends,
+                        })
+                })
+                .collect();
+            messenger.report_dropped(&address, SocketEvent::PacketsDropped(address, reports));
+        }
+        for dropped in dropped_packets {
+            if max_resends.is_some_and(|max_resends| dropped.resends >= max_resends) {
+                debug!(
+                    "Giving up on a packet to {} after {} resends",
+                    self.remote_address, dropped.resends
+                );
+                report_delivery_failure(self, messenger, dropped);
+
+                // the packets that are still in flight are not reported, the whole connection failed.
+                if messenger.config().disconnect_on_max_resends {
+                    info!("Connection with {} failed", self.remote_address);
+                    self.state = ConnectionState::Disconnected;
+                    messenger.send_event(
+                        &self.remote_address,
+                        SocketEvent::ConnectionFailed(self.remote_address),
+                    );
+                    return;
+                }
+                continue;
+            }
+
+            debug!("Resending a dropped packet to {}", self.remote_address);
+            let packets = self.process_outgoing(
+                PacketInfo {
+                    packet_type: dropped.packet_type,
+                    payload: &dropped.payload,
+                    // because a delivery guarantee is only sent with reliable packets
+                    delivery: DeliveryGuarantee::Reliable,
+                    // this is stored with the dropped packet because they could be mixed
+                    ordering: dropped.ordering_guarantee,
+                },
+                dropped.item_identifier,
+                time,
+            );
+            // a packet that could not be sent is not tracked, the last sent packet is another one then.
+            if packets.is_ok() {
+                self.record_resends(dropped.resends + 1);
+                self.record_id(dropped.id);
+            }
+            send_packets(self, messenger, packets, "dropped packets");
+        }
+
+        // send the packets that were held back, the higher priorities first, as far as the bandwidth limit allows.
+        while let Some(size) = self
+            .rate_limited_packets
+            .front()
+            .map(|packet| packet.payload().len())
+        {
+            if !self.bandwidth_allows(size, time) {
+                break;
+            }
+            if let Some(packet) = self.rate_limited_packets.pop_front() {
+                send_user_packet(self, messenger, packet, time);
+            }
+        }
+
+        // the messages that were batched during this poll go out together.
+        flush_batch(self, messenger, time);
+
+        // send heartbeat packets if required
+        if let Some(heartbeat_interval) = messenger.config().heartbeat_interval {
+            if self.last_sent(time) >= heartbeat_interval {
+                let packets = self.process_outgoing(PacketInfo::heartbeat_packet(&[]), None, time);
+                send_packets(self, messenger, packets, "heatbeat packet");
+            }
+        }
+    }
+}
+
+// Sends a packet of the user.
+fn send_user_packet<P: AsRef<[u8]>>(
+    connection: &mut VirtualConnection<P>,
+    ctx: &mut impl ConnectionMessenger<SocketEvent>,
+    packet: Packet<P>,
+    time: Instant,
+) {
+    let config = ctx.config();
+    let max_batch_size = usize::from(config.fragment_size).min(config.receive_buffer_max_size);
+    let size = packet.payload().len();
+    if config.batch_messages && packet.id().is_none() && batch::framed_size(size) <= max_batch_size
+    {
+        let (delivery, ordering) = (packet.delivery_guarantee(), packet.order_guarantee());
+        let accepted = connection
+            .message_batch
+            .as_ref()
+            .is_none_or(|batch| batch.accepts(delivery, ordering, size, max_batch_size));
+        if !accepted {
+            flush_batch(connection, ctx, time);
+        }
+        connection
+            .message_batch
+            .get_or_insert_with(|| MessageBatch::new(delivery, ordering))
+            .push(packet.payload());
+        return;
+    }
+
+    // messages that were batched before this one are sent first, so that they keep their order.
+    flush_batch(connection, ctx, time);
+    let packets = connection.process_outgoing(
+        PacketInfo::user_packet(
+            packet.payload(),
+            packet.delivery_guarantee(),
+            packet.order_guarantee(),
+        ),
+        None,
+        time,
+    );
+    // the id is kept with the sent packet, so it can be reported once that is acknowledged.
+    if packets.is_ok() && packet.delivery_guarantee() == DeliveryGuarantee::Reliable {
+        connection.record_id(packet.id());
+    }
+    send_packets(connection, ctx, packets, "user packet");
+}
+
+// Sends the messages that were batched since the last flush, a single message is sent as a normal packet.
+fn flush_batch<P>(
+    connection: &mut VirtualConnection<P>,
+    ctx: &mut impl ConnectionMessenger<SocketEvent>,
+    time: Instant,
+) {
+    if let Some(batch) = connection.message_batch.take() {
+        let packet = match batch.single_message() {
+            Some(message) => PacketInfo::user_packet(
+                message,
+                batch.delivery_guarantee(),
+                batch.order_guarantee(),
+            ),
+            None => PacketInfo::batch_packet(
+                batch.payload(),
+                batch.delivery_guarantee(),
+                batch.order_guarantee(),
+            ),
+        };
+        let packets = connection.process_outgoing(packet, None, time);
+        send_packets(connection, ctx, packets, "message batch");
+    }
+}
+
+// Reports a reliable packet that was given up on, every message of a batch is reported on its own.
+fn report_delivery_failure<P>(
+    connection: &VirtualConnection<P>,
+    ctx: &mut impl ConnectionMessenger<SocketEvent>,
+    dropped: SentPacket,
+) {
+    let address = connection.remote_address;
+    for packet in user_packets(address, &dropped) {
+        ctx.send_event(&address, SocketEvent::DeliveryFailed(packet));
+    }
+}
+
+// Rebuilds the packets the user sent from a sent packet, a batch holds several of them.
+fn user_packets(address: SocketAddr, sent: &SentPacket) -> Vec<Packet> {
+    if sent.packet_type == PacketType::Batch {
+        return batch::read_messages(&sent.payload)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|message| {
+                Packet::new(
+                    address,
+                    message.into(),
+                    DeliveryGuarantee::Reliable,
+                    sent.ordering_guarantee,
+                )
+            })
+            .collect();
+    }
+
+    let packet = Packet::new(
+        address,
+        sent.payload.clone(),
+        DeliveryGuarantee::Reliable,
+        sent.ordering_guarantee,
+    );
+    match sent.id {
+        Some(id) => vec![packet.with_id(id)],
+        None => vec![packet],
+    }
+}
+
+// Sends the handshake packet that answers the one received last.
+fn send_handshake_reply<P>(
+    connection: &mut VirtualConnection<P>,
+    ctx: &mut impl ConnectionMessenger<SocketEvent>,
+    reply: HandshakeReply,
+    time: Instant,
+) {
+    let token;
+    let answer;
+    let packet = match reply {
+        HandshakeReply::Challenge(challenge) => {
+            token = challenge.to_be_bytes();
+            PacketInfo::challenge_packet(&token)
+        }
+        HandshakeReply::Response(challenge) => {
+            token = challenge.to_be_bytes();
+            PacketInfo::response_packet(&token)
+        }
+        HandshakeReply::Accept => PacketInfo::accept_packet(),
+        HandshakeReply::ResumeResponse(challenge) => {
+            let session = connection.session.unwrap_or_default();
+            answer = [session.to_be_bytes(), challenge.to_be_bytes()].concat();
+            PacketInfo::resume_packet(&answer)
+        }
+        HandshakeReply::ResumeAccept(session) => {
+            token = session.to_be_bytes();
+            PacketInfo::resume_accept_packet(&token)
+        }
+    };
+    let packets = connection.process_outgoing(packet, None, time);
+    send_packets(connection, ctx, packets, "handshake packet");
+}
+
+// Sends multiple outgoing packets to the remote address of the connection.
+fn send_packets<P>(
+    connection: &mut VirtualConnection<P>,
+    ctx: &mut impl ConnectionMessenger<SocketEvent>,
+    packets: Result<OutgoingPackets>,
+    err_context: &str,
+) {
+    let address = connection.remote_address;
+    send_packets_to(connection, ctx, address, packets, err_context);
+}
+
+// Sends multiple outgoing packets of the connection to the given address.
+fn send_packets_to<P>(
+    connection: &mut VirtualConnection<P>,
+    ctx: &mut impl ConnectionMessenger<SocketEvent>,
+    address: SocketAddr,
+    packets: Result<OutgoingPackets>,
+    err_context: &str,
+) {
+    match packets {
+        Ok(packets) => {
+            for outgoing in packets {
+                let mut contents = connection.take_send_buffer();
+                outgoing.write_into(&mut contents);
+                let mut contents = connection.encrypt(contents);
+                Checksum::append(&mut contents);
+                ctx.send_packet(&address, &contents);
+                connection.record_sent_bytes(contents.len());
+                connection.reuse_send_buffer(contents);
+            }
+        }
+        Err(error) => error!("Error occured processing {}: {:?}", err_context, error),
+    }
+}
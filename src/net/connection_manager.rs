@@ -1,913 +1,3985 @@
-use std::{self, collections::HashMap, fmt::Debug, io::Result, net::SocketAddr, time::Instant};
-
-use crossbeam_channel::{self, unbounded, Receiver, Sender};
-use log::error;
-
-use crate::{
-    config::Config, net::Connection, net::ConnectionEventAddress, net::ConnectionMessenger,
-};
-
-// TODO: maybe we can make a breaking change and use this instead of `ConnectionEventAddress` trait?
-// #[derive(Debug)]
-// pub struct ConnectionEvent<Event: Debug>(pub SocketAddr, pub Event);
-
-/// A datagram socket is a type of network socket which provides a connectionless point for sending or receiving data packets.
-pub trait DatagramSocket: Debug {
-    /// Sends a single packet to the socket.
-    fn send_packet(&mut self, addr: &SocketAddr, payload: &[u8]) -> Result<usize>;
-
-    /// Receives a single packet from the socket.
-    fn receive_packet<'a>(&mut self, buffer: &'a mut [u8]) -> Result<(&'a [u8], SocketAddr)>;
-
-    /// Returns the socket address that this socket was created from.
-    fn local_addr(&self) -> Result<SocketAddr>;
-
-    /// Returns whether socket operates in blocking or non-blocking mode.
-    fn is_blocking_mode(&self) -> bool;
-}
-
-// This will be used by a `Connection`.
-#[derive(Debug)]
-struct SocketEventSenderAndConfig<TSocket: DatagramSocket, ReceiveEvent: Debug> {
-    config: Config,
-    socket: TSocket,
-    event_sender: Sender<ReceiveEvent>,
-}
-
-impl<TSocket: DatagramSocket, ReceiveEvent: Debug>
-    SocketEventSenderAndConfig<TSocket, ReceiveEvent>
-{
-    fn new(config: Config, socket: TSocket, event_sender: Sender<ReceiveEvent>) -> Self {
-        Self {
-            config,
-            socket,
-            event_sender,
-        }
-    }
-}
-
-impl<TSocket: DatagramSocket, ReceiveEvent: Debug> ConnectionMessenger<ReceiveEvent>
-    for SocketEventSenderAndConfig<TSocket, ReceiveEvent>
-{
-    fn config(&self) -> &Config {
-        &self.config
-    }
-
-    fn send_event(&mut self, _address: &SocketAddr, event: ReceiveEvent) {
-        self.event_sender.send(event).expect("Receiver must exists");
-    }
-
-    fn send_packet(&mut self, address: &SocketAddr, payload: &[u8]) {
-        if let Err(err) = self.socket.send_packet(address, payload) {
-            error!("Error occured sending a packet (to {}): {}", address, err)
-        }
-    }
-}
-
-/// Implements a concept of connections on top of datagram socket.
-/// Connection capabilities depends on what is an actual `Connection` type.
-/// Connection type also defines a type of sending and receiving events.
-#[derive(Debug)]
-pub struct ConnectionManager<TSocket: DatagramSocket, TConnection: Connection> {
-    connections: HashMap<SocketAddr, TConnection>,
-    receive_buffer: Vec<u8>,
-    user_event_receiver: Receiver<TConnection::SendEvent>,
-    messenger: SocketEventSenderAndConfig<TSocket, TConnection::ReceiveEvent>,
-    event_receiver: Receiver<TConnection::ReceiveEvent>,
-    user_event_sender: Sender<TConnection::SendEvent>,
-}
-
-impl<TSocket: DatagramSocket, TConnection: Connection> ConnectionManager<TSocket, TConnection> {
-    /// Creates an instance of `ConnectionManager` by passing a socket and config.
-    pub fn new(socket: TSocket, config: Config) -> Self {
-        let (event_sender, event_receiver) = unbounded();
-        let (user_event_sender, user_event_receiver) = unbounded();
-        ConnectionManager {
-            receive_buffer: vec![0; config.receive_buffer_max_size],
-            connections: Default::default(),
-            user_event_receiver,
-            messenger: SocketEventSenderAndConfig::new(config, socket, event_sender),
-            user_event_sender,
-            event_receiver,
-        }
-    }
-
-    /// Processes any inbound/outbound packets and events.
-    /// Processes connection specific logic for active connections.
-    /// Removes dropped connections from active connections list.
-    pub fn manual_poll(&mut self, time: Instant) {
-        let messenger = &mut self.messenger;
-        // first we pull all newly arrived packets and handle them
-        loop {
-            match messenger
-                .socket
-                .receive_packet(self.receive_buffer.as_mut())
-            {
-                Ok((payload, address)) => {
-                    if let Some(conn) = self.connections.get_mut(&address) {
-                        conn.process_packet(messenger, payload, time);
-                    } else {
-                        // create connection, but do not add to active connections list
-                        let mut conn =
-                            TConnection::create_connection(messenger, address, time, Some(payload));
-                        conn.process_packet(messenger, payload, time);
-                    }
-                }
-                Err(e) => {
-                    if e.kind() != std::io::ErrorKind::WouldBlock {
-                        error!("Encountered an error receiving data: {:?}", e);
-                    }
-                    break;
-                }
-            }
-            // prevent from blocking, break after receiving first packet
-            if messenger.socket.is_blocking_mode() {
-                break;
-            }
-        }
-
-        // now grab all the waiting packets and send them
-        while let Ok(event) = self.user_event_receiver.try_recv() {
-            // get or create connection
-            let conn = self.connections.entry(event.address()).or_insert_with(|| {
-                TConnection::create_connection(messenger, event.address(), time, None)
-            });
-            conn.process_event(messenger, event, time);
-        }
-
-        // update all connections
-        for conn in self.connections.values_mut() {
-            conn.update(messenger, time);
-        }
-
-        // iterate through all connections and remove those that should be dropped
-        self.connections
-            .retain(|_, conn| !conn.should_drop(messenger, time));
-    }
-
-    /// Returns a handle to the event sender which provides a thread-safe way to enqueue user events
-    /// to be processed. This should be used when the socket is busy running its polling loop in a
-    /// separate thread.
-    pub fn event_sender(&self) -> &Sender<TConnection::SendEvent> {
-        &self.user_event_sender
-    }
-
-    /// Returns a handle to the event receiver which provides a thread-safe way to retrieve events
-    /// from the connections. This should be used when the socket is busy running its polling loop in
-    /// a separate thread.
-    pub fn event_receiver(&self) -> &Receiver<TConnection::ReceiveEvent> {
-        &self.event_receiver
-    }
-
-    /// Returns socket reference.
-    pub fn socket(&self) -> &TSocket {
-        &self.messenger.socket
-    }
-
-    /// Returns socket mutable reference.
-    #[allow(dead_code)]
-    pub fn socket_mut(&mut self) -> &mut TSocket {
-        &mut self.messenger.socket
-    }
-
-    /// Returns a number of active connections.
-    #[cfg(test)]
-    pub fn connections_count(&self) -> usize {
-        self.connections.len()
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use std::{
-        collections::HashSet,
-        net::SocketAddr,
-        time::{Duration, Instant},
-    };
-
-    use crate::net::LinkConditioner;
-    use crate::test_utils::*;
-    use crate::{Config, Packet, SocketEvent};
-
-    /// The socket address of where the server is located.
-    const SERVER_ADDR: &str = "127.0.0.1:10001";
-    // The client address from where the data is sent.
-    const CLIENT_ADDR: &str = "127.0.0.1:10002";
-
-    fn client_address() -> SocketAddr {
-        CLIENT_ADDR.parse().unwrap()
-    }
-
-    fn server_address() -> SocketAddr {
-        SERVER_ADDR.parse().unwrap()
-    }
-
-    fn create_server_client_network() -> (FakeSocket, FakeSocket, NetworkEmulator) {
-        let network = NetworkEmulator::default();
-        let server = FakeSocket::bind(&network, server_address(), Config::default()).unwrap();
-        let client = FakeSocket::bind(&network, client_address(), Config::default()).unwrap();
-        (server, client, network)
-    }
-
-    fn create_server_client(config: Config) -> (FakeSocket, FakeSocket) {
-        let network = NetworkEmulator::default();
-        let server = FakeSocket::bind(&network, server_address(), config.clone()).unwrap();
-        let client = FakeSocket::bind(&network, client_address(), config).unwrap();
-        (server, client)
-    }
-
-    #[test]
-    fn using_sender_and_receiver() {
-        let (mut server, mut client, _) = create_server_client_network();
-
-        let sender = client.get_packet_sender();
-        let receiver = server.get_event_receiver();
-
-        sender
-            .send(Packet::reliable_unordered(
-                server_address(),
-                b"Hello world!".to_vec(),
-            ))
-            .unwrap();
-
-        let time = Instant::now();
-        client.manual_poll(time);
-        server.manual_poll(time);
-
-        assert_eq![Ok(SocketEvent::Connect(client_address())), receiver.recv()];
-        if let SocketEvent::Packet(packet) = receiver.recv().unwrap() {
-            assert_eq![b"Hello world!", packet.payload()];
-        } else {
-            panic!["Did not receive a packet when it should"];
-        }
-    }
-
-    #[test]
-    fn initial_packet_is_resent() {
-        let (mut server, mut client, network) = create_server_client_network();
-        let time = Instant::now();
-
-        // send a packet that the server ignores/drops
-        client
-            .send(Packet::reliable_unordered(
-                server_address(),
-                b"Do not arrive".to_vec(),
-            ))
-            .unwrap();
-        client.manual_poll(time);
-
-        // drop the inbound packet, this simulates a network error
-        network.clear_packets(server_address());
-
-        // send a packet that the server receives
-        for id in 0..u8::max_value() {
-            client
-                .send(Packet::reliable_unordered(server_address(), vec![id]))
-                .unwrap();
-
-            server
-                .send(Packet::reliable_unordered(client_address(), vec![id]))
-                .unwrap();
-
-            client.manual_poll(time);
-            server.manual_poll(time);
-
-            while let Some(SocketEvent::Packet(pkt)) = server.recv() {
-                if pkt.payload() == b"Do not arrive" {
-                    return;
-                }
-            }
-            while let Some(_) = client.recv() {}
-        }
-
-        panic!["Did not receive the ignored packet"];
-    }
-
-    #[test]
-    fn receiving_does_not_allow_denial_of_service() {
-        let (mut server, mut client, _) = create_server_client_network();
-        // send a bunch of packets to a server
-        for _ in 0..3 {
-            client
-                .send(Packet::unreliable(
-                    server_address(),
-                    vec![1, 2, 3, 4, 5, 6, 7, 8, 9],
-                ))
-                .unwrap();
-        }
-
-        let time = Instant::now();
-
-        client.manual_poll(time);
-        server.manual_poll(time);
-
-        for _ in 0..6 {
-            assert![server.recv().is_some()];
-        }
-        assert![server.recv().is_none()];
-
-        // the server shall not have any connection in its connection table even though it received
-        // packets
-        assert_eq![0, server.connection_count()];
-
-        server
-            .send(Packet::unreliable(client_address(), vec![1]))
-            .unwrap();
-
-        server.manual_poll(time);
-
-        // the server only adds to its table after having sent explicitly
-        assert_eq![1, server.connection_count()];
-    }
-
-    #[test]
-    fn initial_sequenced_is_resent() {
-        let (mut server, mut client, network) = create_server_client_network();
-        let time = Instant::now();
-
-        // send a packet that the server ignores/drops
-        client
-            .send(Packet::reliable_sequenced(
-                server_address(),
-                b"Do not arrive".to_vec(),
-                None,
-            ))
-            .unwrap();
-        client.manual_poll(time);
-
-        // drop the inbound packet, this simulates a network error
-        network.clear_packets(server_address());
-
-        // send a packet that the server receives
-        for id in 0..36 {
-            client
-                .send(Packet::reliable_sequenced(server_address(), vec![id], None))
-                .unwrap();
-
-            server
-                .send(Packet::reliable_sequenced(client_address(), vec![id], None))
-                .unwrap();
-
-            client.manual_poll(time);
-            server.manual_poll(time);
-
-            while let Some(SocketEvent::Packet(pkt)) = server.recv() {
-                if pkt.payload() == b"Do not arrive" {
-                    panic!["Sequenced packet arrived while it should not"];
-                }
-            }
-            while let Some(_) = client.recv() {}
-        }
-    }
-
-    #[test]
-    fn initial_ordered_is_resent() {
-        let (mut server, mut client, network) = create_server_client_network();
-        let time = Instant::now();
-
-        // send a packet that the server ignores/drops
-        client
-            .send(Packet::reliable_ordered(
-                server_address(),
-                b"Do not arrive".to_vec(),
-                None,
-            ))
-            .unwrap();
-        client.manual_poll(time);
-
-        // drop the inbound packet, this simulates a network error
-        network.clear_packets(server_address());
-
-        // send a packet that the server receives
-        for id in 0..35 {
-            client
-                .send(Packet::reliable_ordered(server_address(), vec![id], None))
-                .unwrap();
-
-            server
-                .send(Packet::reliable_ordered(client_address(), vec![id], None))
-                .unwrap();
-
-            client.manual_poll(time);
-            server.manual_poll(time);
-
-            while let Some(SocketEvent::Packet(pkt)) = server.recv() {
-                if pkt.payload() == b"Do not arrive" {
-                    return;
-                }
-            }
-            while let Some(_) = client.recv() {}
-        }
-
-        panic!["Did not receive the ignored packet"];
-    }
-
-    #[test]
-    fn do_not_duplicate_sequenced_packets_when_received() {
-        let (mut server, mut client, _) = create_server_client_network();
-        let time = Instant::now();
-
-        for id in 0..100 {
-            client
-                .send(Packet::reliable_sequenced(server_address(), vec![id], None))
-                .unwrap();
-            client.manual_poll(time);
-            server.manual_poll(time);
-        }
-
-        let mut seen = HashSet::new();
-
-        while let Some(message) = server.recv() {
-            match message {
-                SocketEvent::Connect(_) => {}
-                SocketEvent::Packet(packet) => {
-                    let byte = packet.payload()[0];
-                    assert![!seen.contains(&byte)];
-                    seen.insert(byte);
-                }
-                SocketEvent::Timeout(_) => {
-                    panic!["This should not happen, as we've not advanced time"];
-                }
-            }
-        }
-
-        assert_eq![100, seen.len()];
-    }
-
-    #[test]
-    fn more_than_65536_sequenced_packets() {
-        let (mut server, mut client, _) = create_server_client_network();
-        // acknowledge the client
-        server
-            .send(Packet::unreliable(client_address(), vec![0]))
-            .unwrap();
-
-        let time = Instant::now();
-
-        for id in 0..65536 + 100 {
-            client
-                .send(Packet::unreliable_sequenced(
-                    server_address(),
-                    id.to_string().as_bytes().to_vec(),
-                    None,
-                ))
-                .unwrap();
-            client.manual_poll(time);
-            server.manual_poll(time);
-        }
-
-        let mut cnt = 0;
-        while let Some(message) = server.recv() {
-            match message {
-                SocketEvent::Connect(_) => {}
-                SocketEvent::Packet(_) => {
-                    cnt += 1;
-                }
-                SocketEvent::Timeout(_) => {
-                    panic!["This should not happen, as we've not advanced time"];
-                }
-            }
-        }
-        assert_eq![65536 + 100, cnt];
-    }
-
-    #[test]
-    fn sequenced_packets_pathological_case() {
-        let mut config = Config::default();
-        config.max_packets_in_flight = 100;
-        let (_, mut client) = create_server_client(config.clone());
-
-        let time = Instant::now();
-
-        for id in 0..101 {
-            client
-                .send(Packet::reliable_sequenced(
-                    server_address(),
-                    id.to_string().as_bytes().to_vec(),
-                    None,
-                ))
-                .unwrap();
-            client.manual_poll(time);
-
-            while let Some(event) = client.recv() {
-                match event {
-                    SocketEvent::Timeout(remote_addr) => {
-                        assert_eq![100, id];
-                        assert_eq![remote_addr, server_address()];
-                        return;
-                    }
-                    _ => {
-                        panic!["No other event possible"];
-                    }
-                }
-            }
-        }
-
-        panic!["Should have received a timeout event"];
-    }
-
-    #[test]
-    fn manual_polling_socket() {
-        let (mut server, mut client, _) = create_server_client_network();
-        for _ in 0..3 {
-            client
-                .send(Packet::unreliable(
-                    server_address(),
-                    vec![1, 2, 3, 4, 5, 6, 7, 8, 9],
-                ))
-                .unwrap();
-        }
-
-        let time = Instant::now();
-
-        client.manual_poll(time);
-        server.manual_poll(time);
-
-        assert!(server.recv().is_some());
-        assert!(server.recv().is_some());
-        assert!(server.recv().is_some());
-    }
-
-    #[test]
-    fn can_send_and_receive() {
-        let (mut server, mut client, _) = create_server_client_network();
-        for _ in 0..3 {
-            client
-                .send(Packet::unreliable(
-                    server_address(),
-                    vec![1, 2, 3, 4, 5, 6, 7, 8, 9],
-                ))
-                .unwrap();
-        }
-
-        let now = Instant::now();
-        client.manual_poll(now);
-        server.manual_poll(now);
-
-        assert!(server.recv().is_some());
-        assert!(server.recv().is_some());
-        assert!(server.recv().is_some());
-    }
-
-    #[test]
-    fn connect_event_occurs() {
-        let (mut server, mut client, _) = create_server_client_network();
-
-        client
-            .send(Packet::unreliable(server_address(), vec![0, 1, 2]))
-            .unwrap();
-
-        let now = Instant::now();
-        client.manual_poll(now);
-        server.manual_poll(now);
-
-        assert_eq!(
-            server.recv().unwrap(),
-            SocketEvent::Connect(client_address())
-        );
-    }
-
-    #[test]
-    fn disconnect_event_occurs() {
-        let mut config = Config::default();
-        config.idle_connection_timeout = Duration::from_millis(1);
-        let (mut server, mut client) = create_server_client(config.clone());
-
-        client
-            .send(Packet::unreliable(server_address(), vec![0, 1, 2]))
-            .unwrap();
-
-        let now = Instant::now();
-        client.manual_poll(now);
-        server.manual_poll(now);
-
-        assert_eq!(
-            server.recv().unwrap(),
-            SocketEvent::Connect(client_address())
-        );
-        assert_eq!(
-            server.recv().unwrap(),
-            SocketEvent::Packet(Packet::unreliable(client_address(), vec![0, 1, 2]))
-        );
-
-        // acknowledge the client
-        server
-            .send(Packet::unreliable(client_address(), vec![]))
-            .unwrap();
-
-        server.manual_poll(now);
-        client.manual_poll(now);
-
-        // make sure the connection was successful on the client side
-        assert_eq!(
-            client.recv().unwrap(),
-            SocketEvent::Packet(Packet::unreliable(server_address(), vec![]))
-        );
-
-        // give just enough time for no timeout events to occur (yet)
-        server.manual_poll(now + config.idle_connection_timeout - Duration::from_millis(1));
-        client.manual_poll(now + config.idle_connection_timeout - Duration::from_millis(1));
-
-        assert_eq!(server.recv(), None);
-        assert_eq!(client.recv(), None);
-
-        // give enough time for timeouts to be detected
-        server.manual_poll(now + config.idle_connection_timeout);
-        client.manual_poll(now + config.idle_connection_timeout);
-
-        assert_eq!(
-            server.recv().unwrap(),
-            SocketEvent::Timeout(client_address())
-        );
-        assert_eq!(
-            client.recv().unwrap(),
-            SocketEvent::Timeout(server_address())
-        );
-    }
-
-    #[test]
-    fn heartbeats_work() {
-        let mut config = Config::default();
-        config.idle_connection_timeout = Duration::from_millis(10);
-        config.heartbeat_interval = Some(Duration::from_millis(4));
-        let (mut server, mut client) = create_server_client(config.clone());
-        // initiate a connection
-        client
-            .send(Packet::unreliable(server_address(), vec![0, 1, 2]))
-            .unwrap();
-
-        let now = Instant::now();
-        client.manual_poll(now);
-        server.manual_poll(now);
-
-        // make sure the connection was successful on the server side
-        assert_eq!(
-            server.recv().unwrap(),
-            SocketEvent::Connect(client_address())
-        );
-        assert_eq!(
-            server.recv().unwrap(),
-            SocketEvent::Packet(Packet::unreliable(client_address(), vec![0, 1, 2]))
-        );
-
-        // acknowledge the client
-        // this way, the server also knows about the connection and sends heartbeats
-        server
-            .send(Packet::unreliable(client_address(), vec![]))
-            .unwrap();
-
-        server.manual_poll(now);
-        client.manual_poll(now);
-
-        // make sure the connection was successful on the client side
-        assert_eq!(
-            client.recv().unwrap(),
-            SocketEvent::Packet(Packet::unreliable(server_address(), vec![]))
-        );
-
-        // give time to send heartbeats
-        client.manual_poll(now + config.heartbeat_interval.unwrap());
-        server.manual_poll(now + config.heartbeat_interval.unwrap());
-
-        // give time for timeouts to occur if no heartbeats were sent
-        client.manual_poll(now + config.idle_connection_timeout);
-        server.manual_poll(now + config.idle_connection_timeout);
-
-        // assert that no disconnection events occurred
-        assert_eq!(client.recv(), None);
-        assert_eq!(server.recv(), None);
-    }
-
-    #[test]
-    fn multiple_sends_should_start_sending_dropped() {
-        let (mut server, mut client, _) = create_server_client_network();
-
-        let now = Instant::now();
-
-        // send enough packets to ensure that we must have dropped packets.
-        for i in 0..35 {
-            client
-                .send(Packet::unreliable(server_address(), vec![i]))
-                .unwrap();
-            client.manual_poll(now);
-        }
-
-        let mut events = Vec::new();
-
-        loop {
-            server.manual_poll(now);
-            if let Some(event) = server.recv() {
-                events.push(event);
-            } else {
-                break;
-            }
-        }
-
-        // ensure that we get the correct number of events to the server.
-        // 35 connect events plus the 35 messages
-        assert_eq!(events.len(), 70);
-
-        // finally the server decides to send us a message back. This necessarily will include
-        // the ack information for 33 of the sent 35 packets.
-        server
-            .send(Packet::unreliable(client_address(), vec![0]))
-            .unwrap();
-        server.manual_poll(now);
-
-        // loop to ensure that the client gets the server message before moving on
-        loop {
-            client.manual_poll(now);
-            if client.recv().is_some() {
-                break;
-            }
-        }
-
-        // this next sent message should end up sending the 2 unacked messages plus the new messages
-        // with payload 35
-        events.clear();
-        client
-            .send(Packet::unreliable(server_address(), vec![35]))
-            .unwrap();
-        client.manual_poll(now);
-
-        loop {
-            server.manual_poll(now);
-            if let Some(event) = server.recv() {
-                events.push(event);
-                break;
-            }
-        }
-
-        let sent_events: Vec<u8> = events
-            .iter()
-            .flat_map(|e| match e {
-                SocketEvent::Packet(p) => Some(p.payload()[0]),
-                _ => None,
-            })
-            .collect();
-        assert_eq!(sent_events, vec![35]);
-    }
-
-    #[test]
-    fn really_bad_network_keeps_chugging_along() {
-        let (mut server, mut client, _) = create_server_client_network();
-
-        let time = Instant::now();
-
-        // we give both the server and the client a really bad bidirectional link
-        let link_conditioner = {
-            let mut lc = LinkConditioner::new();
-            lc.set_packet_loss(0.9);
-            Some(lc)
-        };
-
-        client.set_link_conditioner(link_conditioner.clone());
-        server.set_link_conditioner(link_conditioner);
-
-        let mut set = HashSet::new();
-
-        // we chat 100 packets between the client and server, which will re-send any non-acked
-        // packets
-        let mut send_many_packets = |dummy: Option<u8>| {
-            for id in 0..100 {
-                client
-                    .send(Packet::reliable_unordered(
-                        server_address(),
-                        vec![dummy.unwrap_or(id)],
-                    ))
-                    .unwrap();
-
-                server
-                    .send(Packet::reliable_unordered(client_address(), vec![255]))
-                    .unwrap();
-
-                client.manual_poll(time);
-                server.manual_poll(time);
-
-                while let Some(_) = client.recv() {}
-                while let Some(event) = server.recv() {
-                    match event {
-                        SocketEvent::Packet(pkt) => {
-                            set.insert(pkt.payload()[0]);
-                        }
-                        SocketEvent::Timeout(_) => {
-                            panic!["Unable to time out, time has not advanced"]
-                        }
-                        SocketEvent::Connect(_) => {}
-                    }
-                }
-            }
-
-            set.len()
-        };
-
-        // the first chatting sequence sends packets 0..100 from the client to the server. After
-        // this we just chat with a value of 255 so we don't accidentally overlap those chatting
-        // packets with the packets we want to ack.
-        send_many_packets(None);
-        send_many_packets(Some(255));
-        send_many_packets(Some(255));
-        send_many_packets(Some(255));
-
-        // 101 because we have 0..100 and 255 from the dummies
-        assert_eq![101, send_many_packets(Some(255))];
-    }
-
-    #[test]
-    fn fragmented_ordered_gets_acked() {
-        let mut config = Config::default();
-        config.fragment_size = 10;
-        let (mut server, mut client) = create_server_client(config.clone());
-
-        let time = Instant::now();
-        let dummy = vec![0];
-
-        // ---
-
-        client
-            .send(Packet::unreliable(server_address(), dummy.clone()))
-            .unwrap();
-        client.manual_poll(time);
-        server
-            .send(Packet::unreliable(client_address(), dummy.clone()))
-            .unwrap();
-        server.manual_poll(time);
-
-        // ---
-
-        let exceeds = b"Fragmented string".to_vec();
-        client
-            .send(Packet::reliable_ordered(server_address(), exceeds, None))
-            .unwrap();
-        client.manual_poll(time);
-
-        server.manual_poll(time);
-        server.manual_poll(time);
-        server
-            .send(Packet::reliable_ordered(
-                client_address(),
-                dummy.clone(),
-                None,
-            ))
-            .unwrap();
-
-        client
-            .send(Packet::unreliable(server_address(), dummy.clone()))
-            .unwrap();
-        client.manual_poll(time);
-        server.manual_poll(time);
-
-        for _ in 0..4 {
-            assert![server.recv().is_some()];
-        }
-        assert![server.recv().is_none()];
-
-        for _ in 0..34 {
-            client
-                .send(Packet::reliable_ordered(
-                    server_address(),
-                    dummy.clone(),
-                    None,
-                ))
-                .unwrap();
-            client.manual_poll(time);
-            server
-                .send(Packet::reliable_ordered(
-                    client_address(),
-                    dummy.clone(),
-                    None,
-                ))
-                .unwrap();
-            server.manual_poll(time);
-            assert![client.recv().is_some()];
-            // If the last iteration returns `None` here, it indicates we just received a re-sent
-            // fragment, because `manual_poll` only processes a single incoming UDP packet per
-            // `manual_poll` if and only if the socket is in blocking mode.
-            //
-            // If that functionality is changed, we will receive something unexpected here
-            match server.recv() {
-                Some(SocketEvent::Packet(pkt)) => {
-                    assert_eq![dummy, pkt.payload()];
-                }
-                _ => {
-                    panic!["Did not receive expected dummy packet"];
-                }
-            }
-        }
-    }
-
-    #[quickcheck_macros::quickcheck]
-    fn do_not_panic_on_arbitrary_packets(bytes: Vec<u8>) {
-        use crate::net::DatagramSocket;
-        let network = NetworkEmulator::default();
-        let mut server = FakeSocket::bind(&network, server_address(), Config::default()).unwrap();
-        let mut client_socket = network.new_socket(client_address()).unwrap();
-
-        client_socket
-            .send_packet(&server_address(), &bytes)
-            .unwrap();
-
-        let time = Instant::now();
-        server.manual_poll(time);
-    }
-}
+use std::{
+    self,
+    collections::{hash_map::Entry, HashMap, VecDeque},
+    fmt::Debug,
+    io::{self, Result},
+    net::SocketAddr,
+    time::Instant,
+};
+
+#[cfg(not(feature = "fast-hash"))]
+use std::collections::hash_map::RandomState;
+#[cfg(feature = "fast-hash")]
+use std::hash::BuildHasherDefault;
+
+use crossbeam_channel::{self, unbounded, Receiver, Sender};
+use log::{debug, error, info};
+
+use crate::{
+    config::{Config, OverflowPolicy},
+    error::ErrorKind,
+    net::AddressFilter,
+    net::Connection,
+    net::ConnectionEventAddress,
+    net::ConnectionMessenger,
+    net::ConnectionState,
+    net::NetworkStats,
+};
+
+#[cfg(feature = "fast-hash")]
+use super::fx_hasher::FxHasher;
+
+// the remote addresses are hashed with SipHash by default, which is resistant to hash flooding.
+#[cfg(not(feature = "fast-hash"))]
+type ConnectionHasher = RandomState;
+#[cfg(feature = "fast-hash")]
+type ConnectionHasher = BuildHasherDefault<FxHasher>;
+
+type ConnectionMap<TConnection> = HashMap<SocketAddr, TConnection, ConnectionHasher>;
+
+// TODO: maybe we can make a breaking change and use this instead of `ConnectionEventAddress` trait?
+// #[derive(Debug)]
+// pub struct ConnectionEvent<Event: Debug>(pub SocketAddr, pub Event);
+
+/// A datagram socket is a type of network socket which provides a connectionless point for sending or receiving data packets.
+pub trait DatagramSocket: Debug {
+    /// Sends a single packet to the socket.
+    fn send_packet(&mut self, addr: &SocketAddr, payload: &[u8]) -> Result<usize>;
+
+    /// Receives a single packet from the socket.
+    fn receive_packet<'a>(&mut self, buffer: &'a mut [u8]) -> Result<(&'a [u8], SocketAddr)>;
+
+    /// Returns the socket address that this socket was created from.
+    fn local_addr(&self) -> Result<SocketAddr>;
+
+    /// Returns whether socket operates in blocking or non-blocking mode.
+    fn is_blocking_mode(&self) -> bool;
+}
+
+// Called with the address of every connection that timed out, see `ConnectionManager::set_timeout_hook`.
+struct TimeoutHook(Box<dyn FnMut(SocketAddr) + Send>);
+
+impl Debug for TimeoutHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TimeoutHook")
+    }
+}
+
+// This will be used by a `Connection`.
+#[derive(Debug)]
+struct SocketEventSenderAndConfig<TSocket: DatagramSocket, ReceiveEvent: Debug> {
+    config: Config,
+    socket: TSocket,
+    event_sender: Sender<ReceiveEvent>,
+    stats: NetworkStats,
+    timeout_hook: Option<TimeoutHook>,
+    // reports of dropped packets that wait for the application to take its events, see `Config::max_dropped_reports`.
+    dropped_reports: VecDeque<ReceiveEvent>,
+    // datagrams the socket was too busy for, they are tried once more in the next poll.
+    deferred_datagrams: Vec<(SocketAddr, Vec<u8>)>,
+    // datagrams that could not be sent, they are reported at the end of the poll.
+    send_failures: Vec<(SocketAddr, io::ErrorKind)>,
+}
+
+impl<TSocket: DatagramSocket, ReceiveEvent: Debug>
+    SocketEventSenderAndConfig<TSocket, ReceiveEvent>
+{
+    fn new(config: Config, socket: TSocket, event_sender: Sender<ReceiveEvent>) -> Self {
+        Self {
+            config,
+            socket,
+            event_sender,
+            stats: NetworkStats::default(),
+            timeout_hook: None,
+            dropped_reports: VecDeque::new(),
+            deferred_datagrams: Vec::new(),
+            send_failures: Vec::new(),
+        }
+    }
+
+    // Sends the datagrams that were deferred by the previous poll, those that fail again are given up on.
+    fn send_deferred_datagrams(&mut self) {
+        for (address, payload) in std::mem::take(&mut self.deferred_datagrams) {
+            match send_with_retries(&mut self.socket, &self.config, &address, &payload) {
+                Ok(bytes_sent) => self.stats.record_sent(bytes_sent),
+                Err(err) => self.record_send_failure(&address, err),
+            }
+        }
+    }
+
+    fn record_send_failure(&mut self, address: &SocketAddr, err: io::Error) {
+        self.stats.record_send_failure();
+        error!("Error occured sending a packet (to {}): {}", address, err);
+        self.send_failures.push((*address, err.kind()));
+    }
+
+    // Hands out the reports that were held back.
+    fn send_dropped_reports(&mut self) {
+        for event in self.dropped_reports.drain(..) {
+            self.event_sender.send(event).expect("Receiver must exists");
+        }
+    }
+
+    // Returns true if no more reports can be held back and new reliable packets have to be refused.
+    fn dropped_reports_full(&self) -> bool {
+        self.config.dropped_reports_overflow == OverflowPolicy::Error
+            && self
+                .config
+                .max_dropped_reports
+                .is_some_and(|max| self.dropped_reports.len() >= max)
+    }
+}
+
+impl<TSocket: DatagramSocket, ReceiveEvent: Debug> ConnectionMessenger<ReceiveEvent>
+    for SocketEventSenderAndConfig<TSocket, ReceiveEvent>
+{
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn send_event(&mut self, _address: &SocketAddr, event: ReceiveEvent) {
+        self.event_sender.send(event).expect("Receiver must exists");
+    }
+
+    fn report_dropped(&mut self, address: &SocketAddr, event: ReceiveEvent) {
+        let max = match self.config.max_dropped_reports {
+            Some(max) => max,
+            None => return self.send_event(address, event),
+        };
+        if self.dropped_reports.len() < max {
+            self.dropped_reports.push_back(event);
+            return;
+        }
+        match self.config.dropped_reports_overflow {
+            OverflowPolicy::DropOldest => {
+                if self.dropped_reports.pop_front().is_some() {
+                    self.dropped_reports.push_back(event);
+                }
+            }
+            OverflowPolicy::DropNewest => {}
+            OverflowPolicy::Error => error!(
+                "Error reporting dropped packets (to {}): {}",
+                address,
+                ErrorKind::DroppedBufferFull
+            ),
+        }
+        debug!("Discarded a report of dropped packets (to {})", address);
+    }
+
+    fn send_packet(&mut self, address: &SocketAddr, payload: &[u8]) {
+        match send_with_retries(&mut self.socket, &self.config, address, payload) {
+            Ok(bytes_sent) => self.stats.record_sent(bytes_sent),
+            Err(err) if is_transient(&err) => {
+                debug!(
+                    "Deferring a packet (to {}) to the next poll: {}",
+                    address, err
+                );
+                self.deferred_datagrams.push((*address, payload.to_vec()));
+            }
+            Err(err) => self.record_send_failure(address, err),
+        }
+    }
+
+    fn connection_timed_out(&mut self, address: &SocketAddr) {
+        match self.timeout_hook.as_mut() {
+            Some(TimeoutHook(hook)) => hook(*address),
+            None => debug!("Connection with {} timed out", address),
+        }
+    }
+}
+
+// Sends a datagram, errors the socket may recover from are retried right away, as often as
+// `Config::send_retries` specifies. The poll must not block, so there is no backoff between the retries.
+fn send_with_retries(
+    socket: &mut impl DatagramSocket,
+    config: &Config,
+    address: &SocketAddr,
+    payload: &[u8],
+) -> Result<usize> {
+    let mut retries = 0;
+    loop {
+        match socket.send_packet(address, payload) {
+            Err(err) if is_transient(&err) && retries < config.send_retries => {
+                retries += 1;
+                debug!("Retrying to send a packet (to {}): {}", address, err);
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+    ) || is_out_of_buffer_space(err)
+}
+
+// The socket ran out of buffer space for outgoing datagrams, std has no `io::ErrorKind` for this.
+#[cfg(unix)]
+fn is_out_of_buffer_space(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::ENOBUFS)
+}
+
+#[cfg(windows)]
+fn is_out_of_buffer_space(err: &io::Error) -> bool {
+    // WSAENOBUFS
+    err.raw_os_error() == Some(10055)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_out_of_buffer_space(_err: &io::Error) -> bool {
+    false
+}
+
+/// Implements a concept of connections on top of datagram socket.
+/// Connection capabilities depends on what is an actual `Connection` type.
+/// Connection type also defines a type of sending and receiving events.
+#[derive(Debug)]
+pub struct ConnectionManager<TSocket: DatagramSocket, TConnection: Connection> {
+    connections: ConnectionMap<TConnection>,
+    // connections the remote host asked for, which are kept apart until it answers their challenge.
+    challenged_connections: ConnectionMap<TConnection>,
+    receive_buffer: Vec<u8>,
+    user_event_receiver: Receiver<TConnection::SendEvent>,
+    messenger: SocketEventSenderAndConfig<TSocket, TConnection::ReceiveEvent>,
+    event_receiver: Receiver<TConnection::ReceiveEvent>,
+    user_event_sender: Sender<TConnection::SendEvent>,
+    last_timeout_check: Option<Instant>,
+    // the address packets are rejected from, when `Config::reject_self_packets` is set.
+    local_address: Option<SocketAddr>,
+    // the epochs of recently removed connections and when they were removed, see `remember_epoch`.
+    previous_epochs: HashMap<SocketAddr, (u8, Instant), ConnectionHasher>,
+    // the addresses of the connections by the token they can be resumed with, see `update_session`.
+    sessions: HashMap<u64, SocketAddr, ConnectionHasher>,
+    // the remote addresses packets are accepted from, see `set_address_filter`.
+    address_filter: AddressFilter,
+}
+
+impl<TSocket: DatagramSocket, TConnection: Connection> ConnectionManager<TSocket, TConnection> {
+    /// Creates an instance of `ConnectionManager` by passing a socket and config.
+    pub fn new(socket: TSocket, config: Config) -> Self {
+        let (event_sender, event_receiver) = unbounded();
+        let (user_event_sender, user_event_receiver) = unbounded();
+        let local_address = if config.reject_self_packets {
+            socket.local_addr().ok()
+        } else {
+            None
+        };
+        ConnectionManager {
+            receive_buffer: vec![0; config.receive_buffer_max_size],
+            connections: Default::default(),
+            challenged_connections: Default::default(),
+            user_event_receiver,
+            messenger: SocketEventSenderAndConfig::new(config, socket, event_sender),
+            user_event_sender,
+            event_receiver,
+            last_timeout_check: None,
+            local_address,
+            previous_epochs: Default::default(),
+            sessions: Default::default(),
+            address_filter: AddressFilter::new(),
+        }
+    }
+
+    /// Processes any inbound/outbound packets and events.
+    /// Processes connection specific logic for active connections.
+    /// Removes dropped connections from active connections list.
+    pub fn manual_poll(&mut self, time: Instant) {
+        let check_timeouts = self.timeout_check_due(time);
+        let events_taken = self.event_receiver.is_empty();
+        let messenger = &mut self.messenger;
+        // first we pull all newly arrived packets and handle them
+        loop {
+            match messenger
+                .socket
+                .receive_packet(self.receive_buffer.as_mut())
+            {
+                Ok((payload, address)) => {
+                    messenger.stats.record_received(payload.len());
+                    if self
+                        .local_address
+                        .is_some_and(|local| is_own_address(local, address))
+                    {
+                        error!(
+                            "Error processing packet from {}: {}",
+                            address,
+                            ErrorKind::SelfPacket
+                        );
+                    } else if !self.address_filter.accepts(&address) {
+                        error!(
+                            "Error processing packet from {}: {}",
+                            address,
+                            ErrorKind::AddressRejected
+                        );
+                    } else if let Some(conn) = self.connections.get_mut(&address) {
+                        let session = conn.session();
+                        conn.process_packet(messenger, payload, time);
+                        update_session(&mut self.sessions, address, session, conn.session());
+                    } else {
+                        let sessions = &self.sessions;
+                        match TConnection::check_new_datagram(&messenger.config, payload).map(
+                            |request| {
+                                request.map(|request| {
+                                    (request.challenge, sessions.get(&request.session).copied())
+                                })
+                            },
+                        ) {
+                            // junk from an unknown address does not get a connection.
+                            Err(error) => {
+                                error!("Error processing packet from {}: {}", address, error)
+                            }
+                            // a connection that is not known, or whose token was replaced, can not be resumed.
+                            Ok(Some((_, None))) => {
+                                debug!(
+                                    "Ignoring a resume of an unknown connection from {}",
+                                    address
+                                )
+                            }
+                            // the remote host resumes its connection from a new address, which has to answer a
+                            // challenge first, so that the connection can not be taken over by replaying the request.
+                            Ok(Some((None, Some(previous)))) => {
+                                if let Some(conn) = self.connections.get_mut(&previous) {
+                                    conn.challenge_resume(messenger, address, time);
+                                }
+                            }
+                            Ok(Some((Some(challenge), Some(previous)))) => {
+                                let answered = self
+                                    .connections
+                                    .get(&previous)
+                                    .is_some_and(|conn| conn.answers_resume(&address, challenge));
+                                let resumed = if answered {
+                                    self.connections.remove(&previous)
+                                } else {
+                                    None
+                                };
+                                if let Some(mut conn) = resumed {
+                                    update_session(
+                                        &mut self.sessions,
+                                        previous,
+                                        conn.session(),
+                                        None,
+                                    );
+                                    conn.move_to(messenger, address);
+                                    // the new token is sent in reply to the answer.
+                                    conn.process_packet(messenger, payload, time);
+                                    update_session(
+                                        &mut self.sessions,
+                                        address,
+                                        None,
+                                        conn.session(),
+                                    );
+                                    self.connections.insert(address, conn);
+                                }
+                            }
+                            Ok(None) => {
+                                let challenged = self.challenged_connections.len();
+                                let limit_reached = challenged
+                                    >= messenger.config.max_challenged_connections
+                                    || challenged + self.connections.len()
+                                        >= messenger.config.max_connections;
+                                let conn = match self.challenged_connections.remove(&address) {
+                                    Some(conn) => Some(conn),
+                                    // new addresses are refused, the connections that are kept stay as they are.
+                                    None if limit_reached => {
+                                        error!(
+                                            "Error processing packet from {}: {}",
+                                            address,
+                                            ErrorKind::ConnectionLimitReached
+                                        );
+                                        None
+                                    }
+                                    // create connection, but do not add to active connections list
+                                    None => Some(TConnection::create_connection(
+                                        messenger,
+                                        address,
+                                        time,
+                                        Some(payload),
+                                        previous_epoch(&self.previous_epochs, &address),
+                                    )),
+                                };
+                                if let Some(mut conn) = conn {
+                                    let state = conn.state();
+                                    conn.process_packet(messenger, payload, time);
+                                    // unless the remote host completed a handshake with it, or was challenged to do so.
+                                    if conn.is_challenged() {
+                                        self.challenged_connections.insert(address, conn);
+                                    } else if state == ConnectionState::Connecting
+                                        && conn.state() == ConnectionState::Connected
+                                    {
+                                        info!("New connection with {}", address);
+                                        messenger.stats.record_connection_created();
+                                        update_session(
+                                            &mut self.sessions,
+                                            address,
+                                            None,
+                                            conn.session(),
+                                        );
+                                        self.connections.insert(address, conn);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    if e.kind() != std::io::ErrorKind::WouldBlock {
+                        error!("Encountered an error receiving data: {:?}", e);
+                    }
+                    break;
+                }
+            }
+            // prevent from blocking, break after receiving first packet
+            if messenger.socket.is_blocking_mode() {
+                break;
+            }
+        }
+
+        // the datagrams the socket was too busy for in the previous poll go out first
+        messenger.send_deferred_datagrams();
+
+        // now grab all the waiting packets and send them
+        while let Ok(event) = self.user_event_receiver.try_recv() {
+            // get or create connection, the handshake of a challenged connection continues as it is.
+            let address = event.address();
+            let kept = self.connections.len() + self.challenged_connections.len();
+            let conn = match self.connections.entry(address) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let conn = match self.challenged_connections.remove(&address) {
+                        Some(conn) => conn,
+                        None if kept >= messenger.config.max_connections => {
+                            error!(
+                                "Error processing packet to {}: {}",
+                                address,
+                                ErrorKind::ConnectionLimitReached
+                            );
+                            continue;
+                        }
+                        None => TConnection::create_connection(
+                            messenger,
+                            address,
+                            time,
+                            None,
+                            previous_epoch(&self.previous_epochs, &address),
+                        ),
+                    };
+                    info!("New connection with {}", address);
+                    messenger.stats.record_connection_created();
+                    entry.insert(conn)
+                }
+            };
+            conn.process_event(messenger, event, time);
+        }
+
+        // update all connections, keeping track of the packets they declare dropped
+        let mut failed = false;
+        for conn in self.connections.values_mut() {
+            let dropped_before = conn.stats().packets_dropped;
+            conn.update(messenger, time);
+            messenger
+                .stats
+                .record_dropped(conn.stats().packets_dropped - dropped_before);
+            failed |= conn.state() == ConnectionState::Disconnected;
+        }
+
+        // connections that failed during their update are removed right away, see `Config::disconnect_on_max_resends`.
+        let previous_epochs = &mut self.previous_epochs;
+        let sessions = &mut self.sessions;
+        if failed {
+            self.connections.retain(|address, conn| {
+                let keep = conn.state() != ConnectionState::Disconnected;
+                if !keep {
+                    remember_epoch(previous_epochs, *address, conn, time);
+                    update_session(sessions, *address, conn.session(), None);
+                }
+                keep
+            });
+        }
+
+        // iterate through all connections and remove those that should be dropped
+        if check_timeouts {
+            self.last_timeout_check = Some(time);
+            self.connections.retain(|address, conn| {
+                let keep = !conn.should_drop(messenger, time);
+                if !keep {
+                    remember_epoch(previous_epochs, *address, conn, time);
+                    update_session(sessions, *address, conn.session(), None);
+                }
+                keep
+            });
+            self.challenged_connections
+                .retain(|_, conn| !conn.should_drop(messenger, time));
+            // late packets of removed connections arrive within the idle timeout, if at all.
+            let forget_after = messenger.config.idle_connection_timeout;
+            previous_epochs.retain(|_, (_, removed)| time.duration_since(*removed) < forget_after);
+        }
+
+        for (address, error) in std::mem::take(&mut messenger.send_failures) {
+            messenger.send_event(&address, TConnection::send_failed_event(address, error));
+        }
+
+        // the held back reports are handed out once the application took the events of earlier polls.
+        if events_taken {
+            messenger.send_dropped_reports();
+        }
+    }
+
+    /// Returns true if reliable packets are refused, because `Config::max_dropped_reports` reports
+    /// of dropped packets are held back and `Config::dropped_reports_overflow` is `OverflowPolicy::Error`.
+    pub fn dropped_reports_full(&self) -> bool {
+        self.messenger.dropped_reports_full()
+    }
+
+    // Returns true if `Config::timeout_check_interval` has passed since connections were last checked.
+    fn timeout_check_due(&self, time: Instant) -> bool {
+        match (
+            self.messenger.config.timeout_check_interval,
+            self.last_timeout_check,
+        ) {
+            (Some(interval), Some(last_check)) => time.duration_since(last_check) >= interval,
+            _ => true,
+        }
+    }
+
+    /// Sets a closure that is called with the address of every connection that times out, see `Socket::set_timeout_hook`.
+    pub fn set_timeout_hook(&mut self, hook: impl FnMut(SocketAddr) + Send + 'static) {
+        self.messenger.timeout_hook = Some(TimeoutHook(Box::new(hook)));
+    }
+
+    /// Sets which remote addresses packets are accepted from, see `Socket::set_address_filter`.
+    pub fn set_address_filter(&mut self, filter: AddressFilter) {
+        self.address_filter = filter;
+    }
+
+    /// Closes the connection with the given address and removes it from the active connections list.
+    /// Does nothing if there is no active connection with this address.
+    pub fn disconnect(&mut self, address: &SocketAddr, time: Instant) {
+        if let Some(mut conn) = self.connections.remove(address) {
+            conn.disconnect(&mut self.messenger, time);
+            remember_epoch(&mut self.previous_epochs, *address, &conn, time);
+            update_session(&mut self.sessions, *address, conn.session(), None);
+        }
+    }
+
+    /// Sends a ping to the given address, the round trip time is updated once it is echoed back.
+    /// Does nothing if there is no active connection with this address.
+    pub fn ping(&mut self, address: &SocketAddr, time: Instant) {
+        if let Some(conn) = self.connections.get_mut(address) {
+            conn.ping(&mut self.messenger, time);
+        }
+    }
+
+    /// Sends the pending acknowledgments of the connection with the given address, see `Socket::flush_acks`.
+    /// Does nothing if there is no active connection with this address.
+    pub fn flush_acks(&mut self, address: &SocketAddr, time: Instant) {
+        if let Some(conn) = self.connections.get_mut(address) {
+            conn.flush_acks(&mut self.messenger, time);
+        }
+    }
+
+    /// Asks the remote host at the given address to move the connection to the current address of the socket,
+    /// see `Socket::resume`. Does nothing if there is no active connection with this address.
+    pub fn resume(&mut self, address: &SocketAddr, time: Instant) {
+        if let Some(conn) = self.connections.get_mut(address) {
+            conn.resume(&mut self.messenger, time);
+        }
+    }
+
+    /// Resets the sequence numbers and acknowledgments of the connection with the given address, see `Socket::reset`.
+    /// Does nothing if there is no active connection with this address.
+    pub fn reset(&mut self, address: &SocketAddr, time: Instant) {
+        if let Some(conn) = self.connections.get_mut(address) {
+            conn.reset(&mut self.messenger, time);
+        }
+    }
+
+    /// Returns a handle to the event sender which provides a thread-safe way to enqueue user events
+    /// to be processed. This should be used when the socket is busy running its polling loop in a
+    /// separate thread.
+    pub fn event_sender(&self) -> &Sender<TConnection::SendEvent> {
+        &self.user_event_sender
+    }
+
+    /// Returns a handle to the event receiver which provides a thread-safe way to retrieve events
+    /// from the connections. This should be used when the socket is busy running its polling loop in
+    /// a separate thread.
+    pub fn event_receiver(&self) -> &Receiver<TConnection::ReceiveEvent> {
+        &self.event_receiver
+    }
+
+    /// Takes the events that are ready and for which `take` returns `Ok`.
+    /// The other events are kept in the event receiver, in the order they occurred.
+    ///
+    /// The events are taken out of the shared channel and the others are sent into it again, so this must
+    /// not be mixed with a clone of the event receiver that is read elsewhere: that reader can miss the events
+    /// while they are taken out, and events sent in the meantime end up in front of the kept ones.
+    pub fn take_events<T>(
+        &mut self,
+        mut take: impl FnMut(
+            TConnection::ReceiveEvent,
+        ) -> std::result::Result<T, TConnection::ReceiveEvent>,
+    ) -> Vec<T> {
+        let ready: Vec<_> = self.event_receiver.try_iter().collect();
+        let mut taken = Vec::new();
+        for event in ready {
+            match take(event) {
+                Ok(item) => taken.push(item),
+                Err(event) => self
+                    .messenger
+                    .event_sender
+                    .send(event)
+                    .expect("Receiver must exists"),
+            }
+        }
+        taken
+    }
+
+    /// Returns the active connection with the given address, if there is any.
+    pub fn connection(&self, address: &SocketAddr) -> Option<&TConnection> {
+        self.connections.get(address)
+    }
+
+    /// Returns the active connection with the given address mutably, if there is any.
+    pub fn connection_mut(&mut self, address: &SocketAddr) -> Option<&mut TConnection> {
+        self.connections.get_mut(address)
+    }
+
+    /// Returns an iterator over all active connections.
+    pub fn connections(&self) -> impl Iterator<Item = &TConnection> {
+        self.connections.values()
+    }
+
+    /// Returns socket reference.
+    pub fn socket(&self) -> &TSocket {
+        &self.messenger.socket
+    }
+
+    /// Returns the configuration connections are created with.
+    pub fn config(&self) -> &Config {
+        &self.messenger.config
+    }
+
+    /// Returns socket mutable reference.
+    #[allow(dead_code)]
+    pub fn socket_mut(&mut self) -> &mut TSocket {
+        &mut self.messenger.socket
+    }
+
+    /// Returns a number of active connections.
+    pub fn connections_count(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Returns the addresses of all active connections.
+    pub fn connected_addresses(&self) -> Vec<SocketAddr> {
+        self.connections.keys().cloned().collect()
+    }
+
+    /// Returns the totals over all connections.
+    pub fn aggregate_stats(&self) -> NetworkStats {
+        NetworkStats {
+            active_connections: self.connections.len(),
+            ..self.messenger.stats
+        }
+    }
+
+    /// Resets the totals over all connections, the counters of the connections themselves are kept.
+    pub fn reset_stats(&mut self) {
+        self.messenger.stats = NetworkStats::default();
+    }
+}
+
+// Remembers the epoch of a removed connection, a new connection with the same address starts in the epoch after it.
+// Otherwise late packets of the removed connection would be taken for packets of the new one.
+fn remember_epoch(
+    previous_epochs: &mut HashMap<SocketAddr, (u8, Instant), ConnectionHasher>,
+    address: SocketAddr,
+    conn: &impl Connection,
+    time: Instant,
+) {
+    previous_epochs.insert(address, (conn.epoch(), time));
+}
+
+// Returns the epoch of the last removed connection with the given address, see `remember_epoch`.
+fn previous_epoch(
+    previous_epochs: &HashMap<SocketAddr, (u8, Instant), ConnectionHasher>,
+    address: &SocketAddr,
+) -> Option<u8> {
+    previous_epochs.get(address).map(|(epoch, _)| *epoch)
+}
+
+// Replaces the token the connection with the given address can be resumed with, see `Connection::session`.
+// The tokens are indexed, so that a resume does not search the connections.
+fn update_session(
+    sessions: &mut HashMap<u64, SocketAddr, ConnectionHasher>,
+    address: SocketAddr,
+    previous: Option<u64>,
+    current: Option<u64>,
+) {
+    if previous == current {
+        return;
+    }
+    if let Some(previous) = previous {
+        if sessions.get(&previous) == Some(&address) {
+            sessions.remove(&previous);
+        }
+    }
+    if let Some(current) = current {
+        sessions.insert(current, address);
+    }
+}
+
+// Returns true if the address is the one of the socket, a socket bound to all interfaces is reached over loopback.
+fn is_own_address(local: SocketAddr, address: SocketAddr) -> bool {
+    local == address
+        || (local.ip().is_unspecified()
+            && address.ip().is_loopback()
+            && local.port() == address.port())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use crate::config::OverflowPolicy;
+    use crate::error::ErrorKind;
+    use crate::net::constants::HANDSHAKE_RESEND_INTERVAL_MS;
+    use crate::net::{
+        AddressFilter, Clock, ConnectionState, DroppedPacket, LinkConditioner, NetworkStats, Subnet,
+    };
+    use crate::test_utils::*;
+    use crate::{
+        Config, DeliveryGuarantee, HeaderInfo, OrderingGuarantee, Packet, PacketType, Priority,
+        SocketEvent,
+    };
+
+    /// The socket address of where the server is located.
+    const SERVER_ADDR: &str = "127.0.0.1:10001";
+    // The client address from where the data is sent.
+    const CLIENT_ADDR: &str = "127.0.0.1:10002";
+
+    fn client_address() -> SocketAddr {
+        CLIENT_ADDR.parse().unwrap()
+    }
+
+    fn server_address() -> SocketAddr {
+        SERVER_ADDR.parse().unwrap()
+    }
+
+    fn create_server_client_network() -> (FakeSocket, FakeSocket, NetworkEmulator) {
+        let network = NetworkEmulator::default();
+        let server = FakeSocket::bind(&network, server_address(), Config::default()).unwrap();
+        let client = FakeSocket::bind(&network, client_address(), Config::default()).unwrap();
+        (server, client, network)
+    }
+
+    fn create_server_client(config: Config) -> (FakeSocket, FakeSocket) {
+        let network = NetworkEmulator::default();
+        let server = FakeSocket::bind(&network, server_address(), config.clone()).unwrap();
+        let client = FakeSocket::bind(&network, client_address(), config).unwrap();
+        (server, client)
+    }
+
+    #[test]
+    fn ready_packets_of_all_connections_are_drained_at_once() {
+        let network = NetworkEmulator::default();
+        let mut server = FakeSocket::bind(&network, server_address(), Config::default()).unwrap();
+        let addresses: Vec<SocketAddr> = (10010..10013)
+            .map(|port| format!("127.0.0.1:{}", port).parse().unwrap())
+            .collect();
+
+        let time = Instant::now();
+        for (i, address) in addresses.iter().enumerate() {
+            let mut client = FakeSocket::bind(&network, *address, Config::default()).unwrap();
+            client
+                .send(Packet::reliable_ordered(
+                    server_address(),
+                    vec![i as u8],
+                    None,
+                ))
+                .unwrap();
+            client.manual_poll(time);
+        }
+        server.manual_poll(time);
+
+        let mut drained: Vec<_> = server
+            .drain_received()
+            .into_iter()
+            .map(|packet| (packet.addr(), packet.payload().to_vec()))
+            .collect();
+        drained.sort();
+        assert_eq!(
+            drained,
+            vec![
+                (addresses[0], vec![0]),
+                (addresses[1], vec![1]),
+                (addresses[2], vec![2])
+            ]
+        );
+        assert!(server.drain_received().is_empty());
+
+        // the connect events are left for `recv`.
+        for _ in 0..3 {
+            match server.recv() {
+                Some(SocketEvent::Connect(address)) => assert!(addresses.contains(&address)),
+                event => panic!("Expected a connect event, got {:?}", event),
+            }
+        }
+        assert_eq!(server.recv(), None);
+    }
+
+    #[test]
+    fn using_sender_and_receiver() {
+        let (mut server, mut client, _) = create_server_client_network();
+
+        let sender = client.get_packet_sender();
+        let receiver = server.get_event_receiver();
+
+        sender
+            .send(Packet::reliable_unordered(
+                server_address(),
+                b"Hello world!".to_vec(),
+            ))
+            .unwrap();
+
+        let time = Instant::now();
+        client.manual_poll(time);
+        server.manual_poll(time);
+
+        assert_eq![Ok(SocketEvent::Connect(client_address())), receiver.recv()];
+        if let SocketEvent::Packet(packet) = receiver.recv().unwrap() {
+            assert_eq![b"Hello world!", packet.payload()];
+        } else {
+            panic!["Did not receive a packet when it should"];
+        }
+    }
+
+    #[test]
+    fn concurrent_senders_to_one_address() {
+        let (mut server, mut client, _) = create_server_client_network();
+
+        let threads: Vec<_> = (0..4u8)
+            .map(|thread| {
+                let sender = client.get_packet_sender();
+                std::thread::spawn(move || {
+                    for i in 0..64u8 {
+                        sender
+                            .send(Packet::reliable_unordered(
+                                server_address(),
+                                vec![thread, i],
+                            ))
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let time = Instant::now();
+        client.manual_poll(time);
+        server.manual_poll(time);
+
+        let mut seen = HashSet::new();
+        while let Some(event) = server.recv() {
+            if let SocketEvent::Packet(packet) = event {
+                assert![seen.insert(packet.payload().to_vec())];
+            }
+        }
+        assert_eq![4 * 64, seen.len()];
+    }
+
+    #[test]
+    fn initial_packet_is_resent() {
+        let (mut server, mut client, network) = create_server_client_network();
+        let time = Instant::now();
+
+        // send a packet that the server ignores/drops
+        client
+            .send(Packet::reliable_unordered(
+                server_address(),
+                b"Do not arrive".to_vec(),
+            ))
+            .unwrap();
+        client.manual_poll(time);
+
+        // drop the inbound packet, this simulates a network error
+        network.clear_packets(server_address());
+
+        // send a packet that the server receives
+        for id in 0..u8::MAX {
+            client
+                .send(Packet::reliable_unordered(server_address(), vec![id]))
+                .unwrap();
+
+            server
+                .send(Packet::reliable_unordered(client_address(), vec![id]))
+                .unwrap();
+
+            client.manual_poll(time);
+            server.manual_poll(time);
+
+            while let Some(SocketEvent::Packet(pkt)) = server.recv() {
+                if pkt.payload() == b"Do not arrive" {
+                    return;
+                }
+            }
+            while client.recv().is_some() {}
+        }
+
+        panic!["Did not receive the ignored packet"];
+    }
+
+    #[test]
+    fn receiving_does_not_allow_denial_of_service() {
+        let (mut server, mut client, _) = create_server_client_network();
+        // send a bunch of packets to a server
+        for _ in 0..3 {
+            client
+                .send(Packet::unreliable(
+                    server_address(),
+                    vec![1, 2, 3, 4, 5, 6, 7, 8, 9],
+                ))
+                .unwrap();
+        }
+
+        let time = Instant::now();
+
+        client.manual_poll(time);
+        server.manual_poll(time);
+
+        for _ in 0..6 {
+            assert![server.recv().is_some()];
+        }
+        assert![server.recv().is_none()];
+
+        // the server shall not have any connection in its connection table even though it received
+        // packets
+        assert_eq![0, server.connection_count()];
+
+        server
+            .send(Packet::unreliable(client_address(), vec![1]))
+            .unwrap();
+
+        server.manual_poll(time);
+
+        // the server only adds to its table after having sent explicitly
+        assert_eq![1, server.connection_count()];
+    }
+
+    #[test]
+    fn initial_sequenced_is_resent() {
+        let (mut server, mut client, network) = create_server_client_network();
+        let time = Instant::now();
+
+        // send a packet that the server ignores/drops
+        client
+            .send(Packet::reliable_sequenced(
+                server_address(),
+                b"Do not arrive".to_vec(),
+                None,
+            ))
+            .unwrap();
+        client.manual_poll(time);
+
+        // drop the inbound packet, this simulates a network error
+        network.clear_packets(server_address());
+
+        // send a packet that the server receives
+        for id in 0..36 {
+            client
+                .send(Packet::reliable_sequenced(server_address(), vec![id], None))
+                .unwrap();
+
+            server
+                .send(Packet::reliable_sequenced(client_address(), vec![id], None))
+                .unwrap();
+
+            client.manual_poll(time);
+            server.manual_poll(time);
+
+            while let Some(SocketEvent::Packet(pkt)) = server.recv() {
+                if pkt.payload() == b"Do not arrive" {
+                    panic!["Sequenced packet arrived while it should not"];
+                }
+            }
+            while client.recv().is_some() {}
+        }
+    }
+
+    #[test]
+    fn initial_ordered_is_resent() {
+        let (mut server, mut client, network) = create_server_client_network();
+        let time = Instant::now();
+
+        // send a packet that the server ignores/drops
+        client
+            .send(Packet::reliable_ordered(
+                server_address(),
+                b"Do not arrive".to_vec(),
+                None,
+            ))
+            .unwrap();
+        client.manual_poll(time);
+
+        // drop the inbound packet, this simulates a network error
+        network.clear_packets(server_address());
+
+        // send a packet that the server receives
+        for id in 0..35 {
+            client
+                .send(Packet::reliable_ordered(server_address(), vec![id], None))
+                .unwrap();
+
+            server
+                .send(Packet::reliable_ordered(client_address(), vec![id], None))
+                .unwrap();
+
+            client.manual_poll(time);
+            server.manual_poll(time);
+
+            while let Some(SocketEvent::Packet(pkt)) = server.recv() {
+                if pkt.payload() == b"Do not arrive" {
+                    return;
+                }
+            }
+            while client.recv().is_some() {}
+        }
+
+        panic!["Did not receive the ignored packet"];
+    }
+
+    #[test]
+    fn gap_in_one_ordered_stream_does_not_stall_another() {
+        let (mut server, mut client, network) = create_server_client_network();
+        let time = Instant::now();
+
+        // the server has to know the client, otherwise it would not remember the ordering state
+        server
+            .send(Packet::unreliable(client_address(), vec![0]))
+            .unwrap();
+        server.manual_poll(time);
+
+        // lose the first packet of stream 1
+        client
+            .send(Packet::reliable_ordered(server_address(), vec![0], Some(1)))
+            .unwrap();
+        client.manual_poll(time);
+        network.clear_packets(server_address());
+
+        client
+            .send(Packet::reliable_ordered(server_address(), vec![1], Some(1)))
+            .unwrap();
+        client
+            .send(Packet::reliable_ordered(server_address(), vec![2], Some(2)))
+            .unwrap();
+        client.manual_poll(time);
+        server.manual_poll(time);
+
+        // stream 2 is delivered while stream 1 waits for the lost packet
+        let mut delivered = Vec::new();
+        while let Some(event) = server.recv() {
+            if let SocketEvent::Packet(packet) = event {
+                delivered.push(packet);
+            }
+        }
+        assert_eq!(
+            delivered,
+            vec![Packet::reliable_ordered(client_address(), vec![2], Some(2))]
+        );
+
+        // once the lost packet is resent, stream 1 is delivered in order
+        let mut stream = Vec::new();
+        for id in 0..35 {
+            client
+                .send(Packet::reliable_ordered(
+                    server_address(),
+                    vec![id],
+                    Some(3),
+                ))
+                .unwrap();
+            server
+                .send(Packet::reliable_unordered(client_address(), vec![id]))
+                .unwrap();
+
+            client.manual_poll(time);
+            server.manual_poll(time);
+
+            while let Some(event) = server.recv() {
+                if let SocketEvent::Packet(packet) = event {
+                    if packet.order_guarantee() == OrderingGuarantee::Ordered(Some(1)) {
+                        stream.push(packet.payload().to_vec());
+                    }
+                }
+            }
+            while client.recv().is_some() {}
+        }
+        assert_eq!(stream, vec![vec![0], vec![1]]);
+    }
+
+    #[test]
+    fn reliable_sends_are_refused_while_too_many_packets_are_pending() {
+        let config = Config::builder().max_pending_packets(2).build().unwrap();
+        let (mut server, mut client) = create_server_client(config);
+        let time = Instant::now();
+
+        // the client keeps the connection, so that it can acknowledge what the server sends.
+        client
+            .send(Packet::unreliable(server_address(), vec![0]))
+            .unwrap();
+        client.manual_poll(time);
+
+        for id in 0..2 {
+            server
+                .send(Packet::reliable_unordered(client_address(), vec![id]))
+                .unwrap();
+            server.manual_poll(time);
+        }
+        assert_eq!(server.packets_in_flight(&client_address()), Some(2));
+
+        match server.send(Packet::reliable_unordered(client_address(), vec![2])) {
+            Err(ErrorKind::SendQueueFull) => {}
+            _ => panic!["Supposed to get a send queue full error"],
+        }
+        // unreliable packets do not wait for acknowledgments.
+        assert!(server
+            .send(Packet::unreliable(client_address(), vec![3]))
+            .is_ok());
+        server.manual_poll(time);
+
+        // once the client acknowledges the packets, sending is possible again.
+        client.manual_poll(time);
+        client
+            .send(Packet::reliable_unordered(server_address(), vec![0]))
+            .unwrap();
+        client.manual_poll(time);
+        server.manual_poll(time);
+
+        assert_eq!(server.packets_in_flight(&client_address()), Some(0));
+        assert!(server
+            .send(Packet::reliable_unordered(client_address(), vec![2]))
+            .is_ok());
+    }
+
+    #[test]
+    fn packets_over_the_bandwidth_limit_are_sent_later_in_order() {
+        let config = Config::builder()
+            .max_bytes_per_second(1000)
+            .send_burst_size(1000)
+            .build()
+            .unwrap();
+        let (mut server, mut client) = create_server_client(config);
+        let mut time = Instant::now();
+
+        for id in 0..10 {
+            client
+                .send(Packet::unreliable(server_address(), vec![id; 400]))
+                .unwrap();
+        }
+        client.manual_poll(time);
+        server.manual_poll(time);
+
+        let mut received = Vec::new();
+        while let Some(event) = server.recv() {
+            if let SocketEvent::Packet(packet) = event {
+                received.push(packet.payload()[0]);
+            }
+        }
+        // the burst only holds two of the packets.
+        assert_eq!(received, vec![0, 1]);
+
+        for _ in 0..5 {
+            time += Duration::from_secs(1);
+            client.manual_poll(time);
+            server.manual_poll(time);
+        }
+        while let Some(event) = server.recv() {
+            if let SocketEvent::Packet(packet) = event {
+                received.push(packet.payload()[0]);
+            }
+        }
+        assert_eq!(received, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn held_back_packets_of_a_higher_priority_are_sent_first() {
+        let config = Config::builder()
+            .max_bytes_per_second(1000)
+            .send_burst_size(1000)
+            .build()
+            .unwrap();
+        let (mut server, mut client) = create_server_client(config);
+        let mut time = Instant::now();
+
+        // the burst only holds the first two, the rest of the normal packets fill their queue.
+        for id in 0..6 {
+            client
+                .send(Packet::unreliable(server_address(), vec![id; 400]))
+                .unwrap();
+        }
+        client
+            .send(Packet::unreliable(server_address(), vec![100; 400]).with_priority(Priority::Low))
+            .unwrap();
+        client
+            .send(
+                Packet::unreliable(server_address(), vec![200; 400]).with_priority(Priority::High),
+            )
+            .unwrap();
+        client.manual_poll(time);
+
+        for _ in 0..5 {
+            time += Duration::from_secs(1);
+            client.manual_poll(time);
+        }
+        server.manual_poll(time);
+
+        let received: Vec<_> = server
+            .drain_received()
+            .iter()
+            .map(|packet| packet.payload()[0])
+            .collect();
+        assert_eq!(received, vec![0, 1, 200, 2, 3, 4, 5, 100]);
+    }
+
+    #[test]
+    fn high_priority_packets_are_not_refused_while_too_many_packets_are_pending() {
+        let config = Config::builder().max_pending_packets(1).build().unwrap();
+        let (mut server, _client) = create_server_client(config);
+        let time = Instant::now();
+
+        server
+            .send(Packet::reliable_unordered(client_address(), vec![0]))
+            .unwrap();
+        server.manual_poll(time);
+
+        match server.send(Packet::reliable_unordered(client_address(), vec![1])) {
+            Err(ErrorKind::SendQueueFull) => {}
+            _ => panic!["Supposed to get a send queue full error"],
+        }
+        assert!(server
+            .send(
+                Packet::reliable_unordered(client_address(), vec![1]).with_priority(Priority::High)
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn packets_from_the_own_address_are_rejected() {
+        let network = NetworkEmulator::default();
+        let config = Config::builder().reject_self_packets(true).build().unwrap();
+        let mut server = FakeSocket::bind(&network, server_address(), config).unwrap();
+        let time = Instant::now();
+
+        // the packet arrives with the address of the server as its source.
+        server
+            .send(Packet::unreliable(server_address(), vec![1, 2, 3]))
+            .unwrap();
+        server.manual_poll(time);
+        server.manual_poll(time);
+
+        while let Some(event) = server.recv() {
+            if let SocketEvent::Packet(_) | SocketEvent::Connect(_) = event {
+                panic!["Packets from the own address are supposed to be dropped"];
+            }
+        }
+        // the datagram itself was received.
+        assert!(server.aggregate_stats().bytes_received > 0);
+    }
+
+    #[test]
+    fn packets_are_only_accepted_from_addresses_the_filter_accepts() {
+        let loopback = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let filters = [
+            (AddressFilter::new().allow(Subnet::new(loopback, 8)), true),
+            (
+                AddressFilter::new().allow(Subnet::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 8)),
+                false,
+            ),
+            (AddressFilter::new().block(loopback), false),
+            (
+                AddressFilter::new()
+                    .allow(Subnet::new(loopback, 8))
+                    .block(client_address().ip()),
+                false,
+            ),
+        ];
+
+        for (filter, accepted) in filters.iter() {
+            let network = NetworkEmulator::default();
+            let mut server =
+                FakeSocket::bind(&network, server_address(), Config::default()).unwrap();
+            let mut client =
+                FakeSocket::bind(&network, client_address(), Config::default()).unwrap();
+            let time = Instant::now();
+            server.set_address_filter(filter.clone());
+
+            client
+                .send(Packet::unreliable(server_address(), vec![1, 2, 3]))
+                .unwrap();
+            client.manual_poll(time);
+            server.manual_poll(time);
+
+            assert_eq!(server.drain_received().len(), usize::from(*accepted));
+        }
+    }
+
+    #[test]
+    fn own_address_includes_loopback_of_an_unspecified_address() {
+        use super::is_own_address;
+
+        let any: SocketAddr = "0.0.0.0:9000".parse().unwrap();
+        assert!(is_own_address(any, "127.0.0.1:9000".parse().unwrap()));
+        assert!(!is_own_address(any, "127.0.0.1:9001".parse().unwrap()));
+        assert!(!is_own_address(any, "10.0.0.1:9000".parse().unwrap()));
+        assert!(is_own_address(server_address(), server_address()));
+        assert!(!is_own_address(server_address(), client_address()));
+    }
+
+    #[test]
+    fn small_messages_of_one_poll_are_sent_in_one_datagram() {
+        let config = Config::builder().batch_messages(true).build().unwrap();
+        let (mut server, mut client) = create_server_client(config);
+        let time = Instant::now();
+
+        for id in 0..3 {
+            client
+                .send(Packet::reliable_unordered(server_address(), vec![id; 10]))
+                .unwrap();
+        }
+        client.manual_poll(time);
+        server.manual_poll(time);
+
+        let mut received = Vec::new();
+        while let Some(event) = server.recv() {
+            if let SocketEvent::Packet(packet) = event {
+                assert_eq!(packet.delivery_guarantee(), DeliveryGuarantee::Reliable);
+                received.push(packet.payload().to_vec());
+            }
+        }
+        assert_eq!(received, vec![vec![0; 10], vec![1; 10], vec![2; 10]]);
+        assert_eq!(client.snapshot()[0].stats.packets_sent, 1);
+    }
+
+    #[test]
+    fn packets_over_the_bandwidth_limit_are_reported_when_not_deferred() {
+        let config = Config::builder()
+            .max_bytes_per_second(1000)
+            .send_burst_size(1000)
+            .defer_rate_limited_packets(false)
+            .build()
+            .unwrap();
+        let (_server, mut client) = create_server_client(config);
+        let time = Instant::now();
+
+        for id in 0..3 {
+            client
+                .send(Packet::unreliable(server_address(), vec![id; 400]))
+                .unwrap();
+        }
+        client.manual_poll(time);
+
+        match client.recv() {
+            Some(SocketEvent::DeliveryFailed(packet)) => assert_eq!(packet.payload()[0], 2),
+            _ => panic!["Supposed to get a delivery failed event"],
+        }
+    }
+
+    #[test]
+    fn ping_measures_the_round_trip_time_of_an_idle_connection() {
+        let (mut server, mut client) = create_server_client(Config::default());
+        let time = Instant::now();
+
+        client
+            .send(Packet::unreliable(server_address(), vec![0]))
+            .unwrap();
+        client.manual_poll(time);
+        assert_eq!(
+            client.rtt(&server_address()),
+            Some(Duration::from_millis(0))
+        );
+
+        // the ping takes 30ms to the server and the pong 50ms back.
+        client.ping(&server_address(), time);
+        server.manual_poll(time + Duration::from_millis(30));
+        client.manual_poll(time + Duration::from_millis(80));
+
+        assert_eq!(
+            client.rtt(&server_address()),
+            Some(Duration::from_millis(80))
+        );
+    }
+
+    #[test]
+    fn flushed_acks_reach_a_sender_that_gets_no_replies() {
+        let (mut server, mut client) = create_server_client(Config::default());
+        let time = Instant::now();
+
+        server
+            .send(Packet::unreliable(client_address(), vec![]))
+            .unwrap();
+        server.manual_poll(time);
+        for _ in 0..3 {
+            client
+                .send(Packet::reliable_unordered(server_address(), vec![0]))
+                .unwrap();
+        }
+        client.manual_poll(time);
+        let first = client.local_sequence(&server_address()).unwrap() - 3;
+        assert_eq!(client.packets_in_flight(&server_address()), Some(3));
+
+        // the server sends nothing else, so the acknowledgments only arrive because they are flushed.
+        server.manual_poll(time);
+        let sent = server.snapshot()[0].stats.packets_sent;
+        server.flush_acks(&client_address(), time);
+        // there is nothing left to acknowledge the second time.
+        server.flush_acks(&client_address(), time);
+        assert_eq!(server.snapshot()[0].stats.packets_sent, sent + 1);
+
+        client.manual_poll(time);
+        assert_eq!(client.packets_in_flight(&server_address()), Some(0));
+        assert_eq!(
+            client.remote_ack_sequence(&server_address()),
+            Some(first.wrapping_add(2))
+        );
+    }
+
+    #[test]
+    fn bandwidth_covers_the_bytes_of_the_last_second() {
+        let (mut server, mut client) = create_server_client(Config::default());
+        let time = Instant::now();
+
+        server
+            .send(Packet::unreliable(client_address(), vec![]))
+            .unwrap();
+        server.manual_poll(time);
+
+        // ten packets of the same size within one second.
+        for index in 0..10 {
+            let time = time + Duration::from_millis(100 * index);
+            client
+                .send(Packet::unreliable(server_address(), vec![0; 100]))
+                .unwrap();
+            client.manual_poll(time);
+            server.manual_poll(time);
+        }
+        server.manual_poll(time + Duration::from_millis(950));
+
+        let bytes = client.aggregate_stats().bytes_sent;
+        assert_eq!(client.bandwidth(&server_address()).unwrap().sent_bps, bytes);
+        assert_eq!(server.bandwidth(&client_address()).unwrap().recv_bps, bytes);
+
+        // the first packets are older than a second once the connection is updated again.
+        server.manual_poll(time + Duration::from_millis(1000));
+        let bandwidth = server.bandwidth(&client_address()).unwrap();
+        assert_eq!(bandwidth.recv_bps, bytes / 10 * 9);
+        assert_eq!(bandwidth.sent_bps, 0);
+    }
+
+    #[test]
+    fn clock_offset_is_estimated_with_pings() {
+        let (mut server, mut client) = create_server_client(Config::default());
+        let time = Instant::now();
+
+        // the connection of the server is created 100ms before the one of the client, its clock is ahead.
+        server
+            .send(Packet::unreliable(client_address(), vec![]))
+            .unwrap();
+        server.manual_poll(time);
+        let time = time + Duration::from_millis(100);
+        client
+            .send(Packet::unreliable(server_address(), vec![]))
+            .unwrap();
+        client.manual_poll(time);
+        assert_eq!(client.clock_offset(&server_address()), None);
+
+        // the pings take 30ms to the server and the pongs 50ms back, half the difference skews the estimate.
+        for i in 0..10 {
+            let ping = time + Duration::from_millis(100 * i);
+            client.ping(&server_address(), ping);
+            server.manual_poll(ping + Duration::from_millis(30));
+            client.manual_poll(ping + Duration::from_millis(80));
+        }
+
+        // the estimate is 10ms below the real offset of 100ms.
+        assert_eq!(client.clock_offset(&server_address()), Some(90_000));
+        // only the side that pings gets an estimate.
+        assert_eq!(server.clock_offset(&client_address()), None);
+    }
+
+    #[test]
+    fn sequence_numbers_advance_with_reliable_packets() {
+        let (mut server, mut client) = create_server_client(Config::default());
+        let time = Instant::now();
+
+        server
+            .send(Packet::unreliable(client_address(), vec![]))
+            .unwrap();
+        server.manual_poll(time);
+        assert_eq!(client.local_sequence(&server_address()), None);
+
+        client
+            .send(Packet::reliable_unordered(server_address(), vec![0]))
+            .unwrap();
+        client.manual_poll(time);
+        let first = client.local_sequence(&server_address()).unwrap();
+
+        for i in 1..3 {
+            client
+                .send(Packet::reliable_unordered(server_address(), vec![i]))
+                .unwrap();
+        }
+        client.manual_poll(time);
+        assert_eq!(
+            client.local_sequence(&server_address()),
+            Some(first.wrapping_add(2))
+        );
+
+        // the reply of the server acknowledges the newest packet of the client.
+        server.manual_poll(time);
+        server
+            .send(Packet::reliable_unordered(client_address(), vec![]))
+            .unwrap();
+        server.manual_poll(time);
+        client.manual_poll(time);
+        assert_eq!(
+            client.remote_ack_sequence(&server_address()),
+            Some(first.wrapping_add(1))
+        );
+        assert_eq!(client.packets_in_flight(&server_address()), Some(0));
+    }
+
+    #[test]
+    fn packets_sent_before_a_reset_are_ignored() {
+        let (mut server, mut client) = create_server_client(Config::default());
+        let time = Instant::now();
+
+        server
+            .send(Packet::unreliable(client_address(), vec![0]))
+            .unwrap();
+        server.manual_poll(time);
+        client
+            .send(Packet::reliable_unordered(server_address(), vec![1]))
+            .unwrap();
+        client.manual_poll(time);
+
+        // the packet of the client is still on its way when the server resets the connection.
+        server.reset(&client_address(), time);
+        server.manual_poll(time);
+        client.manual_poll(time);
+        assert_eq!(client.snapshot()[0].epoch, 1);
+
+        client
+            .send(Packet::reliable_unordered(server_address(), vec![2]))
+            .unwrap();
+        client.manual_poll(time);
+        server.manual_poll(time);
+
+        let mut received = Vec::new();
+        while let Some(event) = server.recv() {
+            if let SocketEvent::Packet(packet) = event {
+                received.push(packet.payload().to_vec());
+            }
+        }
+        assert_eq!(received, vec![vec![2]]);
+        assert_eq!(server.snapshot()[0].epoch, 1);
+    }
+
+    #[test]
+    fn do_not_duplicate_sequenced_packets_when_received() {
+        let (mut server, mut client, _) = create_server_client_network();
+        let time = Instant::now();
+
+        for id in 0..100 {
+            client
+                .send(Packet::reliable_sequenced(server_address(), vec![id], None))
+                .unwrap();
+            client.manual_poll(time);
+            server.manual_poll(time);
+        }
+
+        let mut seen = HashSet::new();
+
+        while let Some(message) = server.recv() {
+            match message {
+                SocketEvent::Connect(_) => {}
+                SocketEvent::Packet(packet) => {
+                    let byte = packet.payload()[0];
+                    assert![!seen.contains(&byte)];
+                    seen.insert(byte);
+                }
+                SocketEvent::Timeout(_)
+                | SocketEvent::Disconnect(_)
+                | SocketEvent::DeliveryFailed(_)
+                | SocketEvent::PacketAcked(..)
+                | SocketEvent::SequenceSkipped(..)
+                | SocketEvent::ConnectionFailed(..)
+                | SocketEvent::AddressChanged(..)
+                | SocketEvent::PacketsDropped(..)
+                | SocketEvent::SendFailed(..)
+                | SocketEvent::Connected(_) => {
+                    panic!["This should not happen, as we've not advanced time"];
+                }
+            }
+        }
+
+        assert_eq![100, seen.len()];
+    }
+
+    #[test]
+    fn more_than_65536_sequenced_packets() {
+        let (mut server, mut client, _) = create_server_client_network();
+        // acknowledge the client
+        server
+            .send(Packet::unreliable(client_address(), vec![0]))
+            .unwrap();
+
+        let time = Instant::now();
+
+        for id in 0..65536 + 100 {
+            client
+                .send(Packet::unreliable_sequenced(
+                    server_address(),
+                    id.to_string().as_bytes().to_vec(),
+                    None,
+                ))
+                .unwrap();
+            client.manual_poll(time);
+            server.manual_poll(time);
+        }
+
+        let mut cnt = 0;
+        while let Some(message) = server.recv() {
+            match message {
+                SocketEvent::Connect(_) => {}
+                SocketEvent::Packet(_) => {
+                    cnt += 1;
+                }
+                SocketEvent::Timeout(_)
+                | SocketEvent::Disconnect(_)
+                | SocketEvent::DeliveryFailed(_)
+                | SocketEvent::PacketAcked(..)
+                | SocketEvent::SequenceSkipped(..)
+                | SocketEvent::ConnectionFailed(..)
+                | SocketEvent::AddressChanged(..)
+                | SocketEvent::PacketsDropped(..)
+                | SocketEvent::SendFailed(..)
+                | SocketEvent::Connected(_) => {
+                    panic!["This should not happen, as we've not advanced time"];
+                }
+            }
+        }
+        assert_eq![65536 + 100, cnt];
+    }
+
+    #[test]
+    #[allow(clippy::never_loop)]
+    fn sequenced_packets_pathological_case() {
+        let config = Config {
+            max_packets_in_flight: 100,
+            ..Default::default()
+        };
+        let (_, mut client) = create_server_client(config.clone());
+
+        let time = Instant::now();
+
+        for id in 0..101 {
+            client
+                .send(Packet::reliable_sequenced(
+                    server_address(),
+                    id.to_string().as_bytes().to_vec(),
+                    None,
+                ))
+                .unwrap();
+            client.manual_poll(time);
+
+            while let Some(event) = client.recv() {
+                match event {
+                    SocketEvent::Timeout(remote_addr) => {
+                        assert_eq![100, id];
+                        assert_eq![remote_addr, server_address()];
+                        return;
+                    }
+                    _ => {
+                        panic!["No other event possible"];
+                    }
+                }
+            }
+        }
+
+        panic!["Should have received a timeout event"];
+    }
+
+    #[test]
+    fn manual_polling_socket() {
+        let (mut server, mut client, _) = create_server_client_network();
+        for _ in 0..3 {
+            client
+                .send(Packet::unreliable(
+                    server_address(),
+                    vec![1, 2, 3, 4, 5, 6, 7, 8, 9],
+                ))
+                .unwrap();
+        }
+
+        let time = Instant::now();
+
+        client.manual_poll(time);
+        server.manual_poll(time);
+
+        assert!(server.recv().is_some());
+        assert!(server.recv().is_some());
+        assert!(server.recv().is_some());
+    }
+
+    #[test]
+    fn can_send_and_receive() {
+        let (mut server, mut client, _) = create_server_client_network();
+        for _ in 0..3 {
+            client
+                .send(Packet::unreliable(
+                    server_address(),
+                    vec![1, 2, 3, 4, 5, 6, 7, 8, 9],
+                ))
+                .unwrap();
+        }
+
+        let now = Instant::now();
+        client.manual_poll(now);
+        server.manual_poll(now);
+
+        assert!(server.recv().is_some());
+        assert!(server.recv().is_some());
+        assert!(server.recv().is_some());
+    }
+
+    #[test]
+    fn connect_event_occurs() {
+        let (mut server, mut client, _) = create_server_client_network();
+
+        client
+            .send(Packet::unreliable(server_address(), vec![0, 1, 2]))
+            .unwrap();
+
+        let now = Instant::now();
+        client.manual_poll(now);
+        server.manual_poll(now);
+
+        assert_eq!(
+            server.recv().unwrap(),
+            SocketEvent::Connect(client_address())
+        );
+    }
+
+    #[test]
+    fn disconnect_event_occurs() {
+        let config = Config {
+            idle_connection_timeout: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let (mut server, mut client) = create_server_client(config.clone());
+
+        client
+            .send(Packet::unreliable(server_address(), vec![0, 1, 2]))
+            .unwrap();
+
+        let now = Instant::now();
+        client.manual_poll(now);
+        server.manual_poll(now);
+
+        assert_eq!(
+            server.recv().unwrap(),
+            SocketEvent::Connect(client_address())
+        );
+        assert_eq!(
+            server.recv().unwrap(),
+            SocketEvent::Packet(Packet::unreliable(client_address(), vec![0, 1, 2]))
+        );
+
+        // acknowledge the client
+        server
+            .send(Packet::unreliable(client_address(), vec![]))
+            .unwrap();
+
+        server.manual_poll(now);
+        client.manual_poll(now);
+
+        // make sure the connection was successful on the client side
+        assert_eq!(
+            client.recv().unwrap(),
+            SocketEvent::Packet(Packet::unreliable(server_address(), vec![]))
+        );
+
+        // give just enough time for no timeout events to occur (yet)
+        server.manual_poll(now + config.idle_connection_timeout - Duration::from_millis(1));
+        client.manual_poll(now + config.idle_connection_timeout - Duration::from_millis(1));
+
+        assert_eq!(server.recv(), None);
+        assert_eq!(client.recv(), None);
+        assert_eq!(server.connection_count(), 1);
+        assert_eq!(client.connection_count(), 1);
+
+        // give enough time for timeouts to be detected
+        server.manual_poll(now + config.idle_connection_timeout);
+        client.manual_poll(now + config.idle_connection_timeout);
+
+        assert_eq!(
+            server.recv().unwrap(),
+            SocketEvent::Timeout(client_address())
+        );
+        assert_eq!(
+            client.recv().unwrap(),
+            SocketEvent::Timeout(server_address())
+        );
+
+        // timed out connections are removed
+        assert_eq!(server.connection_count(), 0);
+        assert_eq!(client.connection_count(), 0);
+    }
+
+    #[test]
+    fn timeouts_are_checked_at_the_configured_interval() {
+        let config = Config::builder()
+            .idle_connection_timeout(Duration::from_secs(1))
+            .timeout_check_interval(Duration::from_millis(300))
+            .build()
+            .unwrap();
+        let (_server, mut client) = create_server_client(config);
+
+        // the server never answers, so the connection of the client times out.
+        client
+            .send(Packet::unreliable(server_address(), vec![0, 1, 2]))
+            .unwrap();
+
+        let now = Instant::now();
+        let mut reaped_after = None;
+        for millis in (0..2000).step_by(10) {
+            let elapsed = Duration::from_millis(millis);
+            client.manual_poll(now + elapsed);
+            if client.recv() == Some(SocketEvent::Timeout(server_address())) {
+                reaped_after = Some(elapsed);
+                break;
+            }
+        }
+
+        // timeouts were checked at 0, 300, 600, 900 and 1200 milliseconds.
+        assert_eq!(reaped_after, Some(Duration::from_millis(1200)));
+        assert_eq!(client.connection_count(), 0);
+    }
+
+    #[test]
+    fn connections_expire_on_their_own_timeout() {
+        let network = NetworkEmulator::default();
+        let mut client = FakeSocket::bind(&network, client_address(), Config::default()).unwrap();
+        let lan_address: SocketAddr = "127.0.0.1:10003".parse().unwrap();
+
+        // neither peer answers, so both connections of the client time out.
+        client
+            .send(Packet::unreliable(server_address(), vec![0]))
+            .unwrap();
+        client
+            .send(Packet::unreliable(lan_address, vec![0]))
+            .unwrap();
+
+        let now = Instant::now();
+        client.manual_poll(now);
+        client.set_timeout(&lan_address, Duration::from_secs(1));
+        client.set_timeout(&server_address(), Duration::from_secs(3));
+
+        client.manual_poll(now + Duration::from_secs(1));
+        assert_eq!(client.recv(), Some(SocketEvent::Timeout(lan_address)));
+        assert_eq!(client.recv(), None);
+        assert_eq!(client.connection_count(), 1);
+
+        client.manual_poll(now + Duration::from_secs(3));
+        assert_eq!(client.recv(), Some(SocketEvent::Timeout(server_address())));
+        assert_eq!(client.connection_count(), 0);
+    }
+
+    #[test]
+    fn user_data_is_dropped_with_its_connection() {
+        #[derive(Debug, PartialEq)]
+        struct Player {
+            id: u32,
+            region: &'static str,
+        }
+
+        let network = NetworkEmulator::default();
+        let mut client = FakeSocket::bind(&network, client_address(), Config::default()).unwrap();
+        client
+            .send(Packet::unreliable(server_address(), vec![0]))
+            .unwrap();
+        let now = Instant::now();
+        client.manual_poll(now);
+
+        let token = Arc::new("token");
+        client.set_user_data(
+            &server_address(),
+            (
+                Player {
+                    id: 7,
+                    region: "eu",
+                },
+                token.clone(),
+            ),
+        );
+        assert_eq!(
+            client
+                .user_data::<(Player, Arc<&str>)>(&server_address())
+                .map(|(player, _)| player),
+            Some(&Player {
+                id: 7,
+                region: "eu"
+            })
+        );
+        assert_eq!(client.user_data::<Player>(&server_address()), None);
+        assert_eq!(Arc::strong_count(&token), 2);
+
+        // the server never answers, so the connection times out and takes the data with it.
+        client.manual_poll(now + Config::default().idle_connection_timeout);
+        assert_eq!(client.recv(), Some(SocketEvent::Timeout(server_address())));
+        assert_eq!(Arc::strong_count(&token), 1);
+    }
+
+    #[test]
+    fn timeout_hook_is_called_with_the_address_of_the_connection() {
+        let (_server, mut client) = create_server_client(Config::default());
+        let now = Instant::now();
+        let timed_out = Arc::new(Mutex::new(Vec::new()));
+        let hook_timed_out = timed_out.clone();
+        client.set_timeout_hook(move |address| hook_timed_out.lock().unwrap().push(address));
+
+        client
+            .send(Packet::unreliable(server_address(), vec![1]))
+            .unwrap();
+        client.manual_poll(now);
+        assert!(timed_out.lock().unwrap().is_empty());
+
+        // the server never answers.
+        client.manual_poll(now + Config::default().idle_connection_timeout);
+        assert_eq!(*timed_out.lock().unwrap(), vec![server_address()]);
+        // the event is emitted as well.
+        assert_eq!(client.recv(), Some(SocketEvent::Timeout(server_address())));
+    }
+
+    #[test]
+    fn disconnect_notifies_remote_host() {
+        let (mut server, mut client) = create_server_client(Config::default());
+
+        client
+            .send(Packet::unreliable(server_address(), vec![0, 1, 2]))
+            .unwrap();
+
+        let now = Instant::now();
+        client.manual_poll(now);
+        server.manual_poll(now);
+
+        // acknowledge the client, so that the server keeps the connection
+        server
+            .send(Packet::unreliable(client_address(), vec![]))
+            .unwrap();
+        server.manual_poll(now);
+        client.manual_poll(now);
+
+        // drain the connect and packet events
+        while server.recv().is_some() {}
+        while client.recv().is_some() {}
+        assert_eq!(server.connection_count(), 1);
+        assert_eq!(client.connection_count(), 1);
+
+        client.disconnect(&server_address(), now);
+
+        assert_eq!(
+            client.recv().unwrap(),
+            SocketEvent::Disconnect(server_address())
+        );
+        assert_eq!(client.connection_count(), 0);
+
+        server.manual_poll(now);
+
+        assert_eq!(
+            server.recv().unwrap(),
+            SocketEvent::Disconnect(client_address())
+        );
+        assert_eq!(server.recv(), None);
+        assert_eq!(server.connection_count(), 0);
+    }
+
+    #[test]
+    fn late_packets_of_a_removed_connection_are_ignored() {
+        use crate::net::{DatagramSocket, VirtualConnection};
+        use crate::packet::{Checksum, PacketInfo};
+
+        let network = NetworkEmulator::default();
+        let config = Config::default();
+        let mut server = FakeSocket::bind(&network, server_address(), config.clone()).unwrap();
+        let mut client_socket = network.new_socket(client_address()).unwrap();
+        let time = Instant::now();
+        let datagram = |connection: &mut VirtualConnection, payload: &[u8]| {
+            let packet = PacketInfo::user_packet(
+                payload,
+                DeliveryGuarantee::Reliable,
+                OrderingGuarantee::None,
+            );
+            let mut contents = connection
+                .process_outgoing(packet, None, time)
+                .unwrap()
+                .into_iter()
+                .next()
+                .unwrap()
+                .contents()
+                .to_vec();
+            Checksum::append(&mut contents);
+            contents
+        };
+        let received = |server: &mut FakeSocket| -> Vec<Vec<u8>> {
+            std::iter::from_fn(|| server.recv())
+                .filter_map(|event| match event {
+                    SocketEvent::Packet(packet) => Some(packet.payload().to_vec()),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // the client side of the first connection.
+        let mut first = VirtualConnection::new(server_address(), &config, time);
+        server
+            .send(Packet::unreliable(client_address(), vec![]))
+            .unwrap();
+        server.manual_poll(time);
+        client_socket
+            .send_packet(&server_address(), &datagram(&mut first, &[1]))
+            .unwrap();
+        server.manual_poll(time);
+        assert_eq!(received(&mut server), vec![vec![1]]);
+
+        // both sides remove the connection and create a new one, in the epoch after the first.
+        server.disconnect(&client_address(), time);
+        let mut second = VirtualConnection::new(server_address(), &config, time);
+        second.continue_after_epoch(first.epoch());
+        server
+            .send(Packet::unreliable(client_address(), vec![]))
+            .unwrap();
+        server.manual_poll(time);
+        assert_eq!(server.snapshot()[0].epoch, first.epoch() + 1);
+        client_socket
+            .send_packet(&server_address(), &datagram(&mut second, &[2]))
+            .unwrap();
+        server.manual_poll(time);
+        assert_eq!(received(&mut server), vec![vec![2]]);
+
+        // a late packet of the first connection does not reach the new one.
+        client_socket
+            .send_packet(&server_address(), &datagram(&mut first, &[3]))
+            .unwrap();
+        server.manual_poll(time);
+        assert!(received(&mut server).is_empty());
+        assert_eq!(server.snapshot()[0].epoch, first.epoch() + 1);
+        assert_eq!(server.snapshot()[0].stats.packets_received, 1);
+    }
+
+    #[test]
+    fn disconnect_unknown_address_does_nothing() {
+        let (mut server, mut client) = create_server_client(Config::default());
+
+        let now = Instant::now();
+        client.disconnect(&server_address(), now);
+        server.manual_poll(now);
+
+        assert_eq!(client.recv(), None);
+        assert_eq!(server.recv(), None);
+        assert_eq!(client.connection_count(), 0);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn stats_are_exported_as_json() {
+        let (mut server, mut client) = create_server_client(Config::default());
+
+        client
+            .send(Packet::unreliable(server_address(), vec![0, 1, 2]))
+            .unwrap();
+        let now = Instant::now();
+        client.manual_poll(now);
+        server.manual_poll(now);
+
+        let json: serde_json::Value = serde_json::from_str(&client.stats_json()).unwrap();
+        assert_eq!(json["schema_version"], crate::STATS_SCHEMA_VERSION);
+        assert_eq!(json["aggregate"]["active_connections"], 1);
+        assert_eq!(
+            json["aggregate"]["bytes_sent"],
+            client.aggregate_stats().bytes_sent
+        );
+
+        let connection = &json["connections"][0];
+        assert_eq!(connection["address"], SERVER_ADDR);
+        assert_eq!(connection["state"], "Connected");
+        assert_eq!(connection["stats"]["packets_sent"], 1);
+        assert_eq!(connection["loss_rate"], 0.0);
+        assert!(connection["rtt_micros"].is_u64());
+        assert!(connection["bandwidth"]["sent_bps"].as_u64().unwrap() > 3);
+    }
+
+    #[test]
+    fn aggregate_stats_are_kept_per_socket() {
+        let (mut server, mut client) = create_server_client(Config::default());
+
+        client
+            .send(Packet::unreliable(server_address(), vec![0, 1, 2]))
+            .unwrap();
+
+        let now = Instant::now();
+        client.manual_poll(now);
+        server.manual_poll(now);
+
+        let client_stats = client.aggregate_stats();
+        let server_stats = server.aggregate_stats();
+        assert_eq!(client_stats.connections_created, 1);
+        assert_eq!(client_stats.active_connections, 1);
+        assert!(client_stats.bytes_sent > 3);
+        assert_eq!(client_stats.bytes_received, 0);
+        // the server did not send anything yet, so the connection is not accepted.
+        assert_eq!(server_stats.connections_created, 0);
+        assert_eq!(server_stats.active_connections, 0);
+        assert_eq!(server_stats.bytes_received, client_stats.bytes_sent);
+
+        client.reset_stats();
+
+        assert_eq!(
+            client.aggregate_stats(),
+            NetworkStats {
+                active_connections: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[cfg(any(feature = "lz4", feature = "deflate"))]
+    #[test]
+    fn compressed_packets_are_received_intact() {
+        use crate::Compression;
+
+        let codecs = vec![
+            #[cfg(feature = "lz4")]
+            Compression::Lz4,
+            #[cfg(feature = "deflate")]
+            Compression::Deflate,
+        ];
+
+        for compression in codecs {
+            let (mut server, mut client) = create_server_client(Config {
+                compression,
+                ..Default::default()
+            });
+            let now = Instant::now();
+
+            // the server accepts the client first, so that fragments can be reassembled.
+            server
+                .send(Packet::unreliable(client_address(), vec![]))
+                .unwrap();
+            server.manual_poll(now);
+
+            // a pseudo random block that is repeated, so that it still needs fragments once compressed.
+            let block: Vec<u8> = (0..3000u32)
+                .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+                .collect();
+            let packets = [
+                Packet::unreliable(server_address(), vec![7; 1000]),
+                Packet::reliable_ordered(server_address(), vec![7; 1000], None),
+                Packet::reliable_unordered(server_address(), block.repeat(4)),
+                Packet::reliable_unordered(server_address(), vec![1, 2, 3]),
+            ];
+            for packet in packets.iter() {
+                client.send(packet.clone()).unwrap();
+            }
+            client.manual_poll(now);
+            server.manual_poll(now);
+
+            let received: Vec<_> = std::iter::from_fn(|| server.recv())
+                .filter_map(|event| match event {
+                    SocketEvent::Packet(packet) => Some(packet),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(received.len(), packets.len());
+            for (received, sent) in received.iter().zip(packets.iter()) {
+                assert_eq!(received.payload(), sent.payload());
+            }
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_packets_need_the_same_key() {
+        let config = |key: u8| Config {
+            encryption_key: Some([key; 32].into()),
+            ..Default::default()
+        };
+        let network = NetworkEmulator::default();
+        let mut server = FakeSocket::bind(&network, server_address(), config(1)).unwrap();
+        let mut client = FakeSocket::bind(&network, client_address(), config(1)).unwrap();
+        let other_address = "127.0.0.1:10003".parse().unwrap();
+        let mut other = FakeSocket::bind(&network, other_address, config(2)).unwrap();
+
+        let now = Instant::now();
+        client
+            .send(Packet::reliable_unordered(server_address(), vec![1, 2, 3]))
+            .unwrap();
+        other
+            .send(Packet::reliable_unordered(server_address(), vec![4, 5, 6]))
+            .unwrap();
+        client.manual_poll(now);
+        other.manual_poll(now);
+        server.manual_poll(now);
+
+        let received: Vec<_> = std::iter::from_fn(|| server.recv())
+            .filter_map(|event| match event {
+                SocketEvent::Packet(packet) => Some(packet),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            received,
+            vec![Packet::reliable_unordered(client_address(), vec![1, 2, 3])]
+        );
+    }
+
+    #[test]
+    fn receiving_keeps_connection_alive() {
+        let config = Config {
+            idle_connection_timeout: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let (mut server, mut client) = create_server_client(config.clone());
+
+        client
+            .send(Packet::unreliable(server_address(), vec![0, 1, 2]))
+            .unwrap();
+
+        let now = Instant::now();
+        client.manual_poll(now);
+        server.manual_poll(now);
+
+        assert_eq!(
+            server.recv().unwrap(),
+            SocketEvent::Connect(client_address())
+        );
+        assert_eq!(
+            server.recv().unwrap(),
+            SocketEvent::Packet(Packet::unreliable(client_address(), vec![0, 1, 2]))
+        );
+
+        // accept the client, from now on the server only receives.
+        server
+            .send(Packet::unreliable(client_address(), vec![]))
+            .unwrap();
+
+        server.manual_poll(now);
+        client.manual_poll(now);
+
+        assert_eq!(
+            client.recv().unwrap(),
+            SocketEvent::Packet(Packet::unreliable(server_address(), vec![]))
+        );
+
+        let later = now + config.idle_connection_timeout / 2 + Duration::from_millis(1);
+        client
+            .send(Packet::unreliable(server_address(), vec![3, 4, 5]))
+            .unwrap();
+        client.manual_poll(later);
+        server.manual_poll(later);
+
+        assert_eq!(
+            server.recv().unwrap(),
+            SocketEvent::Packet(Packet::unreliable(client_address(), vec![3, 4, 5]))
+        );
+
+        // the first packet is now older than the timeout, but the second one is not.
+        server.manual_poll(now + config.idle_connection_timeout + Duration::from_millis(1));
+
+        assert_eq!(server.recv(), None);
+        assert_eq!(server.connection_count(), 1);
+    }
+
+    #[test]
+    fn heartbeats_work() {
+        let config = Config {
+            idle_connection_timeout: Duration::from_millis(10),
+            heartbeat_interval: Some(Duration::from_millis(4)),
+            ..Default::default()
+        };
+        let (mut server, mut client) = create_server_client(config.clone());
+        // initiate a connection
+        client
+            .send(Packet::unreliable(server_address(), vec![0, 1, 2]))
+            .unwrap();
+
+        let now = Instant::now();
+        client.manual_poll(now);
+        server.manual_poll(now);
+
+        // make sure the connection was successful on the server side
+        assert_eq!(
+            server.recv().unwrap(),
+            SocketEvent::Connect(client_address())
+        );
+        assert_eq!(
+            server.recv().unwrap(),
+            SocketEvent::Packet(Packet::unreliable(client_address(), vec![0, 1, 2]))
+        );
+
+        // acknowledge the client
+        // this way, the server also knows about the connection and sends heartbeats
+        server
+            .send(Packet::unreliable(client_address(), vec![]))
+            .unwrap();
+
+        server.manual_poll(now);
+        client.manual_poll(now);
+
+        // make sure the connection was successful on the client side
+        assert_eq!(
+            client.recv().unwrap(),
+            SocketEvent::Packet(Packet::unreliable(server_address(), vec![]))
+        );
+
+        // give time to send heartbeats
+        client.manual_poll(now + config.heartbeat_interval.unwrap());
+        server.manual_poll(now + config.heartbeat_interval.unwrap());
+
+        // give time for timeouts to occur if no heartbeats were sent
+        client.manual_poll(now + config.idle_connection_timeout);
+        server.manual_poll(now + config.idle_connection_timeout);
+
+        // assert that no disconnection events occurred
+        assert_eq!(client.recv(), None);
+        assert_eq!(server.recv(), None);
+    }
+
+    #[test]
+    fn lost_sequence_numbers_are_reported() {
+        let (mut server, mut client, network) = create_server_client_network();
+        let now = Instant::now();
+
+        server
+            .send(Packet::unreliable(client_address(), vec![]))
+            .unwrap();
+        server.manual_poll(now);
+        client
+            .send(Packet::reliable_unordered(server_address(), vec![0]))
+            .unwrap();
+        client.manual_poll(now);
+        server.manual_poll(now);
+        let first = client
+            .local_sequence(&server_address())
+            .unwrap()
+            .wrapping_sub(1);
+
+        // four of the packets are lost, the server acknowledges each of the others.
+        let mut lost = Vec::new();
+        for i in 1..40 {
+            client
+                .send(Packet::reliable_unordered(server_address(), vec![i]))
+                .unwrap();
+            client.manual_poll(now);
+            if [1, 3, 4, 30].contains(&i) {
+                network.clear_packets(server_address());
+            }
+            server.manual_poll(now);
+            server
+                .send(Packet::reliable_unordered(client_address(), vec![]))
+                .unwrap();
+            server.manual_poll(now);
+            client.manual_poll(now);
+
+            while let Some(event) = client.recv() {
+                if let SocketEvent::PacketsDropped(address, dropped) = event {
+                    assert_eq!(address, server_address());
+                    lost.extend(dropped.iter().map(|dropped| dropped.sequence));
+                }
+            }
+        }
+
+        // 30 is still within the acknowledgment bitfield.
+        assert_eq!(
+            lost,
+            vec![
+                first.wrapping_add(1),
+                first.wrapping_add(3),
+                first.wrapping_add(4)
+            ]
+        );
+    }
+
+    #[test]
+    fn dropped_packets_are_reported_with_their_sequence_and_resends() {
+        let (mut server, mut client, network) = create_server_client_network();
+        let now = Instant::now();
+
+        server
+            .send(Packet::unreliable(client_address(), vec![]))
+            .unwrap();
+        server.manual_poll(now);
+        client
+            .send(Packet::reliable_unordered(server_address(), vec![0]))
+            .unwrap();
+        client.manual_poll(now);
+        server.manual_poll(now);
+        let first = client.local_sequence(&server_address()).unwrap();
+
+        // the packet is lost, and so is its first resend.
+        let mut reports = Vec::new();
+        for i in 1..80 {
+            let packet = Packet::reliable_unordered(server_address(), vec![i]);
+            client
+                .send(if i == 1 { packet.with_id(7) } else { packet })
+                .unwrap();
+            client.manual_poll(now);
+            if i == 1 {
+                network.clear_packets(server_address());
+            }
+            server.manual_poll(now);
+            server
+                .send(Packet::reliable_unordered(client_address(), vec![]))
+                .unwrap();
+            server.manual_poll(now);
+            client.manual_poll(now);
+
+            while let Some(event) = client.recv() {
+                if let SocketEvent::PacketsDropped(address, dropped) = event {
+                    assert_eq!(address, server_address());
+                    // the resend went out while the drop was detected.
+                    if reports.is_empty() {
+                        network.clear_packets(server_address());
+                    }
+                    reports.extend(dropped);
+                }
+            }
+        }
+
+        let lost = Packet::reliable_unordered(server_address(), vec![1]).with_id(7);
+        assert_eq!(reports.len(), 2);
+        assert_eq!(
+            reports[0],
+            DroppedPacket {
+                packet: lost.clone(),
+                sequence: first,
+                resends: 0,
+            }
+        );
+        assert_eq!(reports[1].packet, lost);
+        assert_ne!(reports[1].sequence, first);
+        assert_eq!(reports[1].resends, 1);
+    }
+
+    #[test]
+    fn late_acknowledgments_are_counted_as_spurious_retransmits() {
+        let (mut server, mut client, network) = create_server_client_network();
+        let clock = network.clock();
+
+        server
+            .send(Packet::unreliable(client_address(), vec![]))
+            .unwrap();
+        server.manual_poll(clock.now());
+        client
+            .send(Packet::reliable_unordered(server_address(), vec![0]))
+            .unwrap();
+        client.manual_poll(clock.now());
+        server.manual_poll(clock.now());
+
+        // every packet arrives, but the acknowledgments of the first ones are held back until newer
+        // acknowledgments made the client declare them dropped.
+        let delayed = {
+            let mut lc = LinkConditioner::with_seed(0);
+            lc.set_latency(Duration::from_secs(1));
+            Some(lc)
+        };
+        server.set_link_conditioner(delayed);
+        for i in 1..60 {
+            if i == 40 {
+                server.set_link_conditioner(None);
+            }
+            client
+                .send(Packet::reliable_unordered(server_address(), vec![i]))
+                .unwrap();
+            client.manual_poll(clock.now());
+            server.manual_poll(clock.now());
+            server
+                .send(Packet::reliable_unordered(client_address(), vec![]))
+                .unwrap();
+            server.manual_poll(clock.now());
+            client.manual_poll(clock.now());
+        }
+        let stats = client.stats(&server_address()).unwrap();
+        assert!(stats.packets_dropped > 0);
+        assert_eq!(stats.spurious_retransmits, 0);
+
+        clock.advance(Duration::from_secs(1));
+        client.manual_poll(clock.now());
+
+        let stats = client.stats(&server_address()).unwrap();
+        assert_eq!(stats.spurious_retransmits, stats.packets_dropped);
+    }
+
+    #[test]
+    fn missing_packet_is_resent_right_away_with_negative_acks() {
+        for negative_acks in [false, true].iter().copied() {
+            let network = NetworkEmulator::default();
+            let config = Config::builder()
+                .negative_acks(negative_acks)
+                .build()
+                .unwrap();
+            let mut server = FakeSocket::bind(&network, server_address(), config.clone()).unwrap();
+            let mut client = FakeSocket::bind(&network, client_address(), config).unwrap();
+            let now = Instant::now();
+
+            server
+                .send(Packet::unreliable(client_address(), vec![]))
+                .unwrap();
+            server.manual_poll(now);
+
+            // the second of three reliable packets is lost.
+            for payload in 0..3 {
+                client
+                    .send(Packet::reliable_unordered(server_address(), vec![payload]))
+                    .unwrap();
+                client.manual_poll(now);
+                if payload == 1 {
+                    network.clear_packets(server_address());
+                }
+                server.manual_poll(now);
+            }
+
+            // no time passes, the client only learns about the loss from the negative acknowledgment.
+            client.manual_poll(now);
+            server.manual_poll(now);
+
+            let payloads: Vec<_> = server
+                .drain_received()
+                .iter()
+                .map(|packet| packet.payload()[0])
+                .collect();
+            if negative_acks {
+                assert_eq!(payloads, vec![0, 2, 1]);
+                assert_eq!(client.aggregate_stats().packets_dropped, 1);
+            } else {
+                assert_eq!(payloads, vec![0, 2]);
+                assert_eq!(client.aggregate_stats().packets_dropped, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn dropped_packet_is_given_up_on_after_max_resends() {
+        let network = NetworkEmulator::default();
+        let config = Config::builder().max_resends(1).build().unwrap();
+        let mut server = FakeSocket::bind(&network, server_address(), config.clone()).unwrap();
+        let mut client = FakeSocket::bind(&network, client_address(), config).unwrap();
+        let now = Instant::now();
+
+        // the server accepts the client, then the first reliable packet of the client is lost.
+        server
+            .send(Packet::unreliable(client_address(), vec![]))
+            .unwrap();
+        server.manual_poll(now);
+        client
+            .send(Packet::reliable_unordered(server_address(), vec![0]))
+            .unwrap();
+        client.manual_poll(now);
+        network.clear_packets(server_address());
+
+        let mut server_payloads = Vec::new();
+        let mut failed = Vec::new();
+        for i in 1..100 {
+            // the acknowledgments of the server make the client detect the drop, its resend is lost as well.
+            server
+                .send(Packet::reliable_unordered(client_address(), vec![]))
+                .unwrap();
+            server.manual_poll(now);
+            let dropped_before = client.aggregate_stats().packets_dropped;
+            client.manual_poll(now);
+            if client.aggregate_stats().packets_dropped != dropped_before {
+                network.clear_packets(server_address());
+            }
+
+            client
+                .send(Packet::reliable_unordered(server_address(), vec![i]))
+                .unwrap();
+            client.manual_poll(now);
+            server.manual_poll(now);
+
+            while let Some(event) = client.recv() {
+                if let SocketEvent::DeliveryFailed(packet) = event {
+                    failed.push(packet);
+                }
+            }
+            while let Some(event) = server.recv() {
+                if let SocketEvent::Packet(packet) = event {
+                    server_payloads.push(packet.payload()[0]);
+                }
+            }
+        }
+
+        assert_eq!(
+            failed,
+            vec![Packet::reliable_unordered(server_address(), vec![0])]
+        );
+        // it was dropped once, resent, and dropped again.
+        assert_eq!(client.aggregate_stats().packets_dropped, 2);
+        assert!(!server_payloads.contains(&0));
+        assert_eq!(server_payloads.len(), 99);
+    }
+
+    #[test]
+    fn connection_fails_when_a_packet_is_given_up_on() {
+        let network = NetworkEmulator::default();
+        let config = Config::builder()
+            .max_resends(1)
+            .disconnect_on_max_resends(true)
+            .build()
+            .unwrap();
+        let mut server = FakeSocket::bind(&network, server_address(), config.clone()).unwrap();
+        let mut client = FakeSocket::bind(&network, client_address(), config).unwrap();
+        let now = Instant::now();
+
+        // the first reliable packet of the client and its resend are never acknowledged.
+        server
+            .send(Packet::unreliable(client_address(), vec![]))
+            .unwrap();
+        server.manual_poll(now);
+        client
+            .send(Packet::reliable_unordered(server_address(), vec![0]))
+            .unwrap();
+        client.manual_poll(now);
+        network.clear_packets(server_address());
+
+        let mut events = Vec::new();
+        for i in 1..100 {
+            server
+                .send(Packet::reliable_unordered(client_address(), vec![]))
+                .unwrap();
+            server.manual_poll(now);
+            let dropped_before = client.aggregate_stats().packets_dropped;
+            client.manual_poll(now);
+            if client.aggregate_stats().packets_dropped != dropped_before {
+                network.clear_packets(server_address());
+            }
+            while let Some(event) = client.recv() {
+                if let SocketEvent::DeliveryFailed(..) | SocketEvent::ConnectionFailed(..) = event {
+                    events.push(event);
+                }
+            }
+            if !events.is_empty() {
+                break;
+            }
+
+            client
+                .send(Packet::reliable_unordered(server_address(), vec![i]))
+                .unwrap();
+            client.manual_poll(now);
+            server.manual_poll(now);
+        }
+
+        // the connection is removed in the same poll, long before it would time out.
+        assert_eq!(
+            events,
+            vec![
+                SocketEvent::DeliveryFailed(Packet::reliable_unordered(server_address(), vec![0])),
+                SocketEvent::ConnectionFailed(server_address()),
+            ]
+        );
+        assert_eq!(client.connection_count(), 0);
+        assert_eq!(server.connection_count(), 1);
+    }
+
+    #[test]
+    fn acknowledged_packet_with_id_is_reported_once() {
+        let (mut server, mut client, _) = create_server_client_network();
+        let now = Instant::now();
+
+        // the server accepts the client.
+        server
+            .send(Packet::unreliable(client_address(), vec![]))
+            .unwrap();
+        server.manual_poll(now);
+
+        client
+            .send(Packet::reliable_unordered(server_address(), vec![1]).with_id(7))
+            .unwrap();
+        client
+            .send(Packet::unreliable(server_address(), vec![2]).with_id(8))
+            .unwrap();
+        client.manual_poll(now);
+        server.manual_poll(now);
+
+        // every packet of the server acknowledges the packet again.
+        let mut acked = Vec::new();
+        for _ in 0..5 {
+            server
+                .send(Packet::reliable_unordered(client_address(), vec![]))
+                .unwrap();
+            server.manual_poll(now);
+            client.manual_poll(now);
+
+            while let Some(event) = client.recv() {
+                if let SocketEvent::PacketAcked(addr, id) = event {
+                    acked.push((addr, id));
+                }
+            }
+        }
+
+        assert_eq!(acked, vec![(server_address(), 7)]);
+    }
+
+    #[test]
+    fn hundred_packets_are_delivered_and_acknowledged_in_both_directions() {
+        let (mut server, mut client, _) = create_server_client_network();
+        let now = Instant::now();
+
+        // collects the received payloads and the acknowledged ids from the events of a socket.
+        let take_events =
+            |socket: &mut FakeSocket, received: &mut Vec<u8>, acked: &mut Vec<u64>| {
+                while let Some(event) = socket.recv() {
+                    match event {
+                        SocketEvent::Packet(packet) if !packet.payload().is_empty() => {
+                            received.push(packet.payload()[0])
+                        }
+                        SocketEvent::PacketAcked(_, id) => acked.push(id),
+                        _ => {}
+                    }
+                }
+            };
+        let (mut server_received, mut server_acked) = (Vec::new(), Vec::new());
+        let (mut client_received, mut client_acked) = (Vec::new(), Vec::new());
+
+        // the server accepts the client.
+        server
+            .send(Packet::unreliable(client_address(), vec![]))
+            .unwrap();
+        server.manual_poll(now);
+
+        for i in 0..100 {
+            client
+                .send(
+                    Packet::reliable_ordered(server_address(), vec![i], None).with_id(u64::from(i)),
+                )
+                .unwrap();
+            client.manual_poll(now);
+            take_events(&mut client, &mut client_received, &mut client_acked);
+
+            server
+                .send(
+                    Packet::reliable_ordered(client_address(), vec![i], None).with_id(u64::from(i)),
+                )
+                .unwrap();
+            server.manual_poll(now);
+            take_events(&mut server, &mut server_received, &mut server_acked);
+        }
+
+        // the last packets of each side are acknowledged by a ping and its pong.
+        client.manual_poll(now);
+        client.ping(&server_address(), now);
+        server.manual_poll(now);
+        client.manual_poll(now);
+        take_events(&mut client, &mut client_received, &mut client_acked);
+        take_events(&mut server, &mut server_received, &mut server_acked);
+
+        let expected: Vec<u8> = (0..100).collect();
+        assert_eq!(server_received, expected);
+        assert_eq!(client_received, expected);
+
+        let expected: Vec<u64> = (0..100).collect();
+        assert_eq!(client_acked, expected);
+        assert_eq!(server_acked, expected);
+        assert_eq!(client.packets_in_flight(&server_address()), Some(0));
+        assert_eq!(server.packets_in_flight(&client_address()), Some(0));
+    }
+
+    #[test]
+    fn multiple_sends_should_start_sending_dropped() {
+        let (mut server, mut client, _) = create_server_client_network();
+
+        let now = Instant::now();
+
+        // send enough packets to ensure that we must have dropped packets.
+        for i in 0..35 {
+            client
+                .send(Packet::unreliable(server_address(), vec![i]))
+                .unwrap();
+            client.manual_poll(now);
+        }
+
+        let mut events = Vec::new();
+
+        loop {
+            server.manual_poll(now);
+            if let Some(event) = server.recv() {
+                events.push(event);
+            } else {
+                break;
+            }
+        }
+
+        // ensure that we get the correct number of events to the server.
+        // 35 connect events plus the 35 messages
+        assert_eq!(events.len(), 70);
+
+        // finally the server decides to send us a message back. This necessarily will include
+        // the ack information for 33 of the sent 35 packets.
+        server
+            .send(Packet::unreliable(client_address(), vec![0]))
+            .unwrap();
+        server.manual_poll(now);
+
+        // loop to ensure that the client gets the server message before moving on
+        loop {
+            client.manual_poll(now);
+            if client.recv().is_some() {
+                break;
+            }
+        }
+
+        // this next sent message should end up sending the 2 unacked messages plus the new messages
+        // with payload 35
+        events.clear();
+        client
+            .send(Packet::unreliable(server_address(), vec![35]))
+            .unwrap();
+        client.manual_poll(now);
+
+        loop {
+            server.manual_poll(now);
+            if let Some(event) = server.recv() {
+                events.push(event);
+                break;
+            }
+        }
+
+        let sent_events: Vec<u8> = events
+            .iter()
+            .flat_map(|e| match e {
+                SocketEvent::Packet(p) => Some(p.payload()[0]),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(sent_events, vec![35]);
+    }
+
+    #[test]
+    fn really_bad_network_keeps_chugging_along() {
+        let (mut server, mut client, _) = create_server_client_network();
+
+        let time = Instant::now();
+
+        // we give both the server and the client a really bad bidirectional link
+        let link_conditioner = {
+            let mut lc = LinkConditioner::new();
+            lc.set_packet_loss(0.9);
+            Some(lc)
+        };
+
+        client.set_link_conditioner(link_conditioner.clone());
+        server.set_link_conditioner(link_conditioner);
+
+        let mut set = HashSet::new();
+
+        // we chat 100 packets between the client and server, which will re-send any non-acked
+        // packets
+        let mut send_many_packets = |dummy: Option<u8>| {
+            for id in 0..100 {
+                client
+                    .send(Packet::reliable_unordered(
+                        server_address(),
+                        vec![dummy.unwrap_or(id)],
+                    ))
+                    .unwrap();
+
+                server
+                    .send(Packet::reliable_unordered(client_address(), vec![255]))
+                    .unwrap();
+
+                client.manual_poll(time);
+                server.manual_poll(time);
+
+                while client.recv().is_some() {}
+                while let Some(event) = server.recv() {
+                    match event {
+                        SocketEvent::Packet(pkt) => {
+                            set.insert(pkt.payload()[0]);
+                        }
+                        SocketEvent::Timeout(_)
+                        | SocketEvent::Disconnect(_)
+                        | SocketEvent::DeliveryFailed(_)
+                        | SocketEvent::PacketAcked(..)
+                        | SocketEvent::SequenceSkipped(..)
+                        | SocketEvent::ConnectionFailed(..)
+                        | SocketEvent::AddressChanged(..)
+                        | SocketEvent::SendFailed(..)
+                        | SocketEvent::Connected(_) => {
+                            panic!["Unable to time out, time has not advanced"]
+                        }
+                        SocketEvent::Connect(_) | SocketEvent::PacketsDropped(..) => {}
+                    }
+                }
+            }
+
+            set.len()
+        };
+
+        // the first chatting sequence sends packets 0..100 from the client to the server. After
+        // this we just chat with a value of 255 so we don't accidentally overlap those chatting
+        // packets with the packets we want to ack.
+        send_many_packets(None);
+        send_many_packets(Some(255));
+        send_many_packets(Some(255));
+        send_many_packets(Some(255));
+
+        // 101 because we have 0..100 and 255 from the dummies
+        assert_eq![101, send_many_packets(Some(255))];
+    }
+
+    #[test]
+    fn handshake_challenges_the_remote_host_before_packets_are_sent() {
+        let config = Config::builder().require_handshake(true).build().unwrap();
+        let (mut server, mut client) = create_server_client(config);
+        let time = Instant::now();
+
+        // the client asks for the connection, its packet is queued.
+        client
+            .send(Packet::reliable_unordered(server_address(), vec![1]))
+            .unwrap();
+        client.manual_poll(time);
+        assert_eq!(
+            client.connection_state(&server_address()),
+            Some(ConnectionState::Connecting)
+        );
+
+        // the server challenges the client, it is not an active connection yet.
+        server.manual_poll(time);
+        assert_eq!(server.recv(), Some(SocketEvent::Connect(client_address())));
+        assert_eq!(server.recv(), None);
+        assert_eq!(server.connection_count(), 0);
+
+        // the client answers the challenge, the server accepts it.
+        client.manual_poll(time);
+        assert_eq!(client.recv(), None);
+        server.manual_poll(time);
+        assert_eq!(
+            server.recv(),
+            Some(SocketEvent::Connected(client_address()))
+        );
+        assert_eq!(
+            server.connection_state(&client_address()),
+            Some(ConnectionState::Connected)
+        );
+
+        client.manual_poll(time);
+        assert_eq!(
+            client.recv(),
+            Some(SocketEvent::Connected(server_address()))
+        );
+        assert_eq!(
+            client.connection_state(&server_address()),
+            Some(ConnectionState::Connected)
+        );
+
+        server.manual_poll(time);
+        assert_eq!(
+            server.recv(),
+            Some(SocketEvent::Packet(Packet::reliable_unordered(
+                client_address(),
+                vec![1]
+            )))
+        );
+    }
+
+    #[test]
+    fn resumed_connection_keeps_its_state_on_the_new_address() {
+        let config = Config::builder().require_handshake(true).build().unwrap();
+        let (mut server, mut client) = create_server_client(config);
+        let time = Instant::now();
+
+        client
+            .send(Packet::reliable_unordered(server_address(), vec![1]))
+            .unwrap();
+        for _ in 0..3 {
+            client.manual_poll(time);
+            server.manual_poll(time);
+        }
+        client.manual_poll(time);
+        while server.recv().is_some() {}
+        while client.recv().is_some() {}
+        server.set_user_data(&client_address(), 7u32);
+        let sequence = server.local_sequence(&client_address()).unwrap();
+
+        // the address of the client changes, it sends the token of its handshake from the new one.
+        let new_address: SocketAddr = "127.0.0.1:10004".parse().unwrap();
+        client.rebind(new_address);
+        client.resume(&server_address(), time);
+        server.manual_poll(time);
+
+        // the new address is challenged before the connection is moved to it.
+        assert_eq!(server.recv(), None);
+        assert_eq!(server.connection_count(), 1);
+        assert_eq!(server.local_sequence(&new_address), None);
+        client.manual_poll(time);
+        server.manual_poll(time);
+
+        assert_eq!(
+            server.recv(),
+            Some(SocketEvent::AddressChanged(client_address(), new_address))
+        );
+        assert_eq!(server.connection_count(), 1);
+        assert_eq!(server.connection_state(&client_address()), None);
+        assert_eq!(server.local_sequence(&new_address), Some(sequence));
+        assert_eq!(server.user_data::<u32>(&new_address), Some(&7));
+
+        // packets are exchanged in both directions without another handshake.
+        client
+            .send(Packet::reliable_unordered(server_address(), vec![2]))
+            .unwrap();
+        client.manual_poll(time);
+        server.manual_poll(time);
+        assert_eq!(
+            server.recv(),
+            Some(SocketEvent::Packet(Packet::reliable_unordered(
+                new_address,
+                vec![2]
+            )))
+        );
+
+        server
+            .send(Packet::reliable_unordered(new_address, vec![3]))
+            .unwrap();
+        server.manual_poll(time);
+        client.manual_poll(time);
+        assert_eq!(
+            client.recv(),
+            Some(SocketEvent::Packet(Packet::reliable_unordered(
+                server_address(),
+                vec![3]
+            )))
+        );
+        assert_eq!(server.packets_in_flight(&new_address), Some(1));
+        assert_eq!(client.packets_in_flight(&server_address()), Some(0));
+    }
+
+    // Records the datagrams it sends, so that they can be replayed.
+    #[derive(Debug)]
+    struct TappedSocket {
+        socket: EmulatedSocket,
+        sent: Vec<Vec<u8>>,
+    }
+
+    impl super::DatagramSocket for TappedSocket {
+        fn send_packet(&mut self, addr: &SocketAddr, payload: &[u8]) -> std::io::Result<usize> {
+            self.sent.push(payload.to_vec());
+            self.socket.send_packet(addr, payload)
+        }
+
+        fn receive_packet<'a>(
+            &mut self,
+            buffer: &'a mut [u8],
+        ) -> std::io::Result<(&'a [u8], SocketAddr)> {
+            self.socket.receive_packet(buffer)
+        }
+
+        fn local_addr(&self) -> std::io::Result<SocketAddr> {
+            self.socket.local_addr()
+        }
+
+        fn is_blocking_mode(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn replayed_resumes_do_not_move_the_connection() {
+        use super::DatagramSocket;
+
+        let config = Config::builder().require_handshake(true).build().unwrap();
+        let network = NetworkEmulator::default();
+        let mut server = FakeSocket::bind(&network, server_address(), config.clone()).unwrap();
+        let socket = TappedSocket {
+            socket: network.new_socket(client_address()).unwrap(),
+            sent: Vec::new(),
+        };
+        let mut client: super::ConnectionManager<_, crate::net::VirtualConnection> =
+            super::ConnectionManager::new(socket, config);
+        let time = Instant::now();
+
+        client
+            .event_sender()
+            .send(Packet::reliable_unordered(server_address(), vec![1]))
+            .unwrap();
+        for _ in 0..3 {
+            client.manual_poll(time);
+            server.manual_poll(time);
+        }
+        client.manual_poll(time);
+        while server.recv().is_some() {}
+
+        // the client resumes from a new address, it answers the challenge the server sends there.
+        let new_address: SocketAddr = "127.0.0.1:10004".parse().unwrap();
+        client.socket_mut().socket.rebind(new_address);
+        let sent = client.socket().sent.len();
+        client.resume(&server_address(), time);
+        server.manual_poll(time);
+        client.manual_poll(time);
+        server.manual_poll(time);
+        client.manual_poll(time);
+        assert_eq!(
+            server.recv(),
+            Some(SocketEvent::AddressChanged(client_address(), new_address))
+        );
+
+        // the request and the answer are replayed from another address, the token they carry was replaced.
+        let replayed: Vec<_> = client.socket().sent[sent..]
+            .iter()
+            .filter(|datagram| {
+                HeaderInfo::parse(datagram).unwrap().packet_type() == PacketType::Resume
+            })
+            .cloned()
+            .collect();
+        assert_eq!(replayed.len(), 2);
+        let attacker_address: SocketAddr = "127.0.0.1:10005".parse().unwrap();
+        let mut attacker = network.new_socket(attacker_address).unwrap();
+        for datagram in &replayed {
+            attacker.send_packet(&server_address(), datagram).unwrap();
+        }
+        server.manual_poll(time);
+        assert_eq!(server.recv(), None);
+        assert_eq!(server.connection_count(), 1);
+        assert!(server.local_sequence(&new_address).is_some());
+
+        // the client resumes with the token it was given instead.
+        let last_address: SocketAddr = "127.0.0.1:10006".parse().unwrap();
+        client.socket_mut().socket.rebind(last_address);
+        client.resume(&server_address(), time);
+        server.manual_poll(time);
+        client.manual_poll(time);
+        server.manual_poll(time);
+        assert_eq!(
+            server.recv(),
+            Some(SocketEvent::AddressChanged(new_address, last_address))
+        );
+    }
+
+    #[test]
+    fn resume_requests_from_an_address_that_does_not_answer_are_ignored() {
+        let config = Config::builder().require_handshake(true).build().unwrap();
+        let (mut server, mut client) = create_server_client(config);
+        let time = Instant::now();
+
+        client
+            .send(Packet::reliable_unordered(server_address(), vec![1]))
+            .unwrap();
+        for _ in 0..3 {
+            client.manual_poll(time);
+            server.manual_poll(time);
+        }
+        client.manual_poll(time);
+        while server.recv().is_some() {}
+
+        // the request is sent from an address the client leaves before the challenge arrives there.
+        let asking_address: SocketAddr = "127.0.0.1:10004".parse().unwrap();
+        client.rebind(asking_address);
+        client.resume(&server_address(), time);
+        client.rebind("127.0.0.1:10005".parse().unwrap());
+        for _ in 0..3 {
+            server.manual_poll(time);
+            client.manual_poll(time);
+        }
+
+        assert_eq!(server.recv(), None);
+        assert_eq!(server.connection_count(), 1);
+        assert!(server.local_sequence(&client_address()).is_some());
+        assert_eq!(server.local_sequence(&asking_address), None);
+    }
+
+    #[test]
+    fn snapshot_contains_every_active_connection() {
+        let network = NetworkEmulator::default();
+        let mut server = FakeSocket::bind(&network, server_address(), Config::default()).unwrap();
+        let peer_address = |index: u8| -> SocketAddr {
+            format!("127.0.0.1:{}", 30000 + u16::from(index))
+                .parse()
+                .unwrap()
+        };
+        let mut peers: Vec<FakeSocket> = (0..3)
+            .map(|index| {
+                FakeSocket::bind(&network, peer_address(index), Config::default()).unwrap()
+            })
+            .collect();
+        let time = Instant::now();
+
+        for index in 0..3 {
+            server
+                .send(Packet::unreliable(peer_address(index), vec![0]))
+                .unwrap();
+        }
+        server.manual_poll(time);
+
+        // every peer sends a different number of packets.
+        for (index, peer) in peers.iter_mut().enumerate() {
+            for id in 0..=index {
+                peer.send(Packet::reliable_unordered(server_address(), vec![id as u8]))
+                    .unwrap();
+            }
+            peer.manual_poll(time);
+        }
+        server.manual_poll(time + Duration::from_millis(10));
+
+        let mut snapshot = server.snapshot();
+        snapshot.sort_by_key(|connection| connection.address);
+
+        assert_eq!(snapshot.len(), 3);
+        for (index, connection) in snapshot.iter().enumerate() {
+            assert_eq!(connection.address, peer_address(index as u8));
+            assert_eq!(connection.state, ConnectionState::Connected);
+            assert_eq!(connection.stats.packets_received, index as u64 + 1);
+            assert_eq!(connection.last_heard, time + Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn broadcast_is_sent_to_connected_peers_with_their_own_sequence_numbers() {
+        let config = Config::builder().require_handshake(true).build().unwrap();
+        let network = NetworkEmulator::default();
+        let mut server = FakeSocket::bind(&network, server_address(), config.clone()).unwrap();
+        let peer_address = |index: u8| -> SocketAddr {
+            format!("127.0.0.1:{}", 30000 + u16::from(index))
+                .parse()
+                .unwrap()
+        };
+        let mut peers: Vec<FakeSocket> = (0..3)
+            .map(|index| FakeSocket::bind(&network, peer_address(index), config.clone()).unwrap())
+            .collect();
+        let time = Instant::now();
+
+        // every peer gets a different number of ordered packets before the broadcast.
+        for index in 0..3 {
+            for id in 0..=index {
+                server
+                    .send(Packet::reliable_ordered(
+                        peer_address(index),
+                        vec![id],
+                        None,
+                    ))
+                    .unwrap();
+            }
+        }
+        // nobody answers the handshake of this one, so it is not connected.
+        let silent: SocketAddr = "127.0.0.1:30010".parse().unwrap();
+        server.send(Packet::unreliable(silent, vec![0])).unwrap();
+
+        for _ in 0..3 {
+            server.manual_poll(time);
+            for peer in peers.iter_mut() {
+                peer.manual_poll(time);
+            }
+        }
+        server.manual_poll(time);
+        assert_eq!(
+            server.connection_state(&silent),
+            Some(ConnectionState::Connecting)
+        );
+
+        let mut addresses = server
+            .broadcast(
+                &[9],
+                DeliveryGuarantee::Reliable,
+                OrderingGuarantee::Ordered(None),
+            )
+            .unwrap();
+        addresses.sort();
+        assert_eq!(addresses, (0..3).map(peer_address).collect::<Vec<_>>());
+        server.manual_poll(time);
+
+        // the broadcast follows the packets each peer got before, so it has the next ordering id of that peer.
+        for (index, peer) in (0..3).zip(peers.iter_mut()) {
+            peer.manual_poll(time);
+            let mut payloads = Vec::new();
+            while let Some(event) = peer.recv() {
+                if let SocketEvent::Packet(packet) = event {
+                    payloads.push(packet.payload()[0]);
+                }
+            }
+            let mut expected: Vec<u8> = (0..=index).collect();
+            expected.push(9);
+            assert_eq!(payloads, expected);
+        }
+    }
+
+    #[test]
+    fn unanswered_challenges_are_forgotten_after_the_handshake_timeout() {
+        let config = Config::builder().require_handshake(true).build().unwrap();
+        let network = NetworkEmulator::default();
+        let mut server = FakeSocket::bind(&network, server_address(), config.clone()).unwrap();
+        let mut client = FakeSocket::bind(&network, client_address(), config.clone()).unwrap();
+        let time = Instant::now();
+
+        // none of the challenges reach the client.
+        client
+            .send(Packet::unreliable(server_address(), vec![1]))
+            .unwrap();
+        client.manual_poll(time);
+        server.manual_poll(time);
+        assert_eq!(server.recv(), Some(SocketEvent::Connect(client_address())));
+        network.clear_packets(client_address());
+
+        // the challenged connection is still known when the client asks again.
+        let retry = time + Duration::from_millis(HANDSHAKE_RESEND_INTERVAL_MS);
+        client.manual_poll(retry);
+        server.manual_poll(retry);
+        assert_eq!(server.recv(), None);
+        network.clear_packets(client_address());
+
+        // but it is forgotten once the client did not answer for too long.
+        let expired = retry + config.handshake_timeout;
+        server.manual_poll(expired);
+        client.manual_poll(expired);
+        server.manual_poll(expired);
+        assert_eq!(server.recv(), Some(SocketEvent::Connect(client_address())));
+        assert_eq!(server.connection_count(), 0);
+    }
+
+    #[test]
+    fn flood_of_handshakes_does_not_push_out_kept_connections() {
+        let config = Config::builder()
+            .require_handshake(true)
+            .max_connections(3)
+            .max_challenged_connections(1)
+            .build()
+            .unwrap();
+        let network = NetworkEmulator::default();
+        let mut server = FakeSocket::bind(&network, server_address(), config.clone()).unwrap();
+        let mut client = FakeSocket::bind(&network, client_address(), config.clone()).unwrap();
+        let time = Instant::now();
+
+        // the client completes its handshake: request, challenge, response and accept.
+        client
+            .send(Packet::unreliable(server_address(), vec![1]))
+            .unwrap();
+        for _ in 0..3 {
+            client.manual_poll(time);
+            server.manual_poll(time);
+        }
+        assert_eq!(server.connection_count(), 1);
+        while server.recv().is_some() {}
+
+        // none of the flooding hosts answers its challenge, only the first one is challenged.
+        let mut flood: Vec<FakeSocket> = (0..10)
+            .map(|port| {
+                let address = SocketAddr::from(([127, 0, 0, 1], 20000 + port));
+                FakeSocket::bind(&network, address, config.clone()).unwrap()
+            })
+            .collect();
+        for host in flood.iter_mut() {
+            host.send(Packet::unreliable(server_address(), vec![2]))
+                .unwrap();
+            host.manual_poll(time);
+        }
+        server.manual_poll(time);
+        let connects = std::iter::from_fn(|| server.recv())
+            .filter(|event| matches!(event, SocketEvent::Connect(_)))
+            .count();
+        assert_eq!(connects, 1);
+
+        // the client that was already accepted keeps its connection.
+        client
+            .send(Packet::unreliable(server_address(), vec![3]))
+            .unwrap();
+        client.manual_poll(time);
+        server.manual_poll(time);
+        assert_eq!(server.connection_count(), 1);
+        assert_eq!(
+            server.connection_state(&client_address()),
+            Some(ConnectionState::Connected)
+        );
+        assert_eq!(
+            server.recv(),
+            Some(SocketEvent::Packet(Packet::unreliable(
+                client_address(),
+                vec![3]
+            )))
+        );
+    }
+
+    #[test]
+    fn new_addresses_are_refused_once_max_connections_is_reached() {
+        let config = Config::builder().max_connections(1).build().unwrap();
+        let (mut server, mut client) = create_server_client(config);
+        let time = Instant::now();
+
+        server
+            .send(Packet::unreliable(client_address(), vec![1]))
+            .unwrap();
+        server
+            .send(Packet::unreliable(
+                SocketAddr::from(([127, 0, 0, 1], 20000)),
+                vec![1],
+            ))
+            .unwrap();
+        server.manual_poll(time);
+
+        assert_eq!(server.connection_count(), 1);
+        client.manual_poll(time);
+        assert_eq!(client.recv(), Some(SocketEvent::Connect(server_address())));
+    }
+
+    #[test]
+    fn packets_without_handshake_are_rejected_by_strict_peers() {
+        let network = NetworkEmulator::default();
+        let strict = Config::builder().require_handshake(true).build().unwrap();
+        let mut server = FakeSocket::bind(&network, server_address(), strict).unwrap();
+        let mut client = FakeSocket::bind(&network, client_address(), Config::default()).unwrap();
+        let time = Instant::now();
+
+        client
+            .send(Packet::unreliable(server_address(), vec![1]))
+            .unwrap();
+        client.manual_poll(time);
+        server.manual_poll(time);
+
+        assert_eq!(server.recv(), Some(SocketEvent::Connect(client_address())));
+        assert_eq!(server.recv(), None);
+        assert_eq!(server.connection_count(), 0);
+    }
+
+    #[test]
+    fn lost_handshake_is_asked_for_again() {
+        let config = Config::builder().require_handshake(true).build().unwrap();
+        let network = NetworkEmulator::default();
+        let mut server = FakeSocket::bind(&network, server_address(), config.clone()).unwrap();
+        let mut client = FakeSocket::bind(&network, client_address(), config).unwrap();
+        let time = Instant::now();
+
+        client
+            .send(Packet::unreliable(server_address(), vec![1]))
+            .unwrap();
+        client.manual_poll(time);
+        network.clear_packets(server_address());
+
+        // the handshake is not repeated right away.
+        client.manual_poll(time + Duration::from_millis(50));
+        server.manual_poll(time + Duration::from_millis(50));
+        assert_eq!(server.connection_count(), 0);
+
+        // request, challenge, response and accept.
+        let retry = time + Duration::from_millis(HANDSHAKE_RESEND_INTERVAL_MS);
+        client.manual_poll(retry);
+        server.manual_poll(retry);
+        client.manual_poll(retry);
+        server.manual_poll(retry);
+        client.manual_poll(retry);
+        assert_eq!(
+            client.recv(),
+            Some(SocketEvent::Connected(server_address()))
+        );
+    }
+
+    #[test]
+    fn reliable_packets_survive_a_lossy_delayed_network() {
+        let (mut server, mut client, network) = create_server_client_network();
+        let clock = network.clock();
+
+        let link_conditioner = {
+            let mut lc = LinkConditioner::with_seed(42);
+            lc.set_packet_loss(0.2);
+            lc.set_latency(Duration::from_millis(20));
+            lc.set_jitter(Duration::from_millis(30));
+            lc.set_duplication_rate(0.05);
+            lc.set_reorder_rate(0.1);
+            Some(lc)
+        };
+        client.set_link_conditioner(link_conditioner.clone());
+        server.set_link_conditioner(link_conditioner);
+
+        let mut received = Vec::new();
+        for i in 0..300 {
+            // after the packets we are interested in, both keep talking so the client learns which
+            // packets were lost.
+            client
+                .send(Packet::reliable_unordered(
+                    server_address(),
+                    vec![i.min(255) as u8],
+                ))
+                .unwrap();
+            server
+                .send(Packet::reliable_unordered(client_address(), vec![]))
+                .unwrap();
+
+            client.manual_poll(clock.now());
+            server.manual_poll(clock.now());
+            clock.advance(Duration::from_millis(10));
+
+            while client.recv().is_some() {}
+            while let Some(event) = server.recv() {
+                if let SocketEvent::Packet(packet) = event {
+                    received.push(packet.payload()[0]);
+                }
+            }
+        }
+
+        assert!(client.aggregate_stats().packets_dropped > 0);
+        received.retain(|&payload| payload < 100);
+        received.sort_unstable();
+        assert_eq!(received, (0..100).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn fragmented_ordered_gets_acked() {
+        let config = Config {
+            fragment_size: 10,
+            ..Default::default()
+        };
+        let (mut server, mut client) = create_server_client(config.clone());
+
+        let time = Instant::now();
+        let dummy = vec![0];
+
+        // ---
+
+        client
+            .send(Packet::unreliable(server_address(), dummy.clone()))
+            .unwrap();
+        client.manual_poll(time);
+        server
+            .send(Packet::unreliable(client_address(), dummy.clone()))
+            .unwrap();
+        server.manual_poll(time);
+
+        // ---
+
+        let exceeds = b"Fragmented string".to_vec();
+        client
+            .send(Packet::reliable_ordered(server_address(), exceeds, None))
+            .unwrap();
+        client.manual_poll(time);
+
+        server.manual_poll(time);
+        server.manual_poll(time);
+        server
+            .send(Packet::reliable_ordered(
+                client_address(),
+                dummy.clone(),
+                None,
+            ))
+            .unwrap();
+
+        client
+            .send(Packet::unreliable(server_address(), dummy.clone()))
+            .unwrap();
+        client.manual_poll(time);
+        server.manual_poll(time);
+
+        for _ in 0..4 {
+            assert![server.recv().is_some()];
+        }
+        assert![server.recv().is_none()];
+
+        for _ in 0..34 {
+            client
+                .send(Packet::reliable_ordered(
+                    server_address(),
+                    dummy.clone(),
+                    None,
+                ))
+                .unwrap();
+            client.manual_poll(time);
+            server
+                .send(Packet::reliable_ordered(
+                    client_address(),
+                    dummy.clone(),
+                    None,
+                ))
+                .unwrap();
+            server.manual_poll(time);
+            assert![client.recv().is_some()];
+            // If the last iteration returns `None` here, it indicates we just received a re-sent
+            // fragment, because `manual_poll` only processes a single incoming UDP packet per
+            // `manual_poll` if and only if the socket is in blocking mode.
+            //
+            // If that functionality is changed, we will receive something unexpected here
+            match server.recv() {
+                Some(SocketEvent::Packet(pkt)) => {
+                    assert_eq![dummy, pkt.payload()];
+                }
+                _ => {
+                    panic!["Did not receive expected dummy packet"];
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_datagrams_from_new_addresses_do_not_create_connections() {
+        use crate::net::DatagramSocket;
+        let network = NetworkEmulator::default();
+        let mut server = FakeSocket::bind(&network, server_address(), Config::default()).unwrap();
+        let mut client_socket = network.new_socket(client_address()).unwrap();
+
+        client_socket.send_packet(&server_address(), &[1]).unwrap();
+        server.manual_poll(Instant::now());
+
+        assert!(server.recv().is_none());
+        assert!(server.snapshot().is_empty());
+        assert_eq!(server.aggregate_stats().connections_created, 0);
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn do_not_panic_on_arbitrary_packets(bytes: Vec<u8>) {
+        use crate::net::DatagramSocket;
+        let network = NetworkEmulator::default();
+        let mut server = FakeSocket::bind(&network, server_address(), Config::default()).unwrap();
+        let mut client_socket = network.new_socket(client_address()).unwrap();
+
+        client_socket
+            .send_packet(&server_address(), &bytes)
+            .unwrap();
+
+        let time = Instant::now();
+        server.manual_poll(time);
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn do_not_panic_on_arbitrary_packets_with_valid_checksum(mut bytes: Vec<u8>) {
+        use crate::net::DatagramSocket;
+        use crate::packet::Checksum;
+        let network = NetworkEmulator::default();
+        let mut server = FakeSocket::bind(&network, server_address(), Config::default()).unwrap();
+        let mut client_socket = network.new_socket(client_address()).unwrap();
+
+        Checksum::append(&mut bytes);
+        client_socket
+            .send_packet(&server_address(), &bytes)
+            .unwrap();
+
+        let time = Instant::now();
+        server.manual_poll(time);
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn do_not_panic_on_arbitrary_headers_of_an_active_connection(
+        packet_type: u8,
+        delivery_guarantee: u8,
+        ordering_guarantee: u8,
+        rest: Vec<u8>,
+    ) {
+        use crate::net::DatagramSocket;
+        use crate::packet::Checksum;
+        use crate::protocol_version::ProtocolVersion;
+        let network = NetworkEmulator::default();
+        let config = Config::builder()
+            .selective_acks(true)
+            .batch_messages(true)
+            .max_reorder_window(4)
+            .build()
+            .unwrap();
+        let mut server = FakeSocket::bind(&network, server_address(), config).unwrap();
+        let mut client_socket = network.new_socket(client_address()).unwrap();
+        let time = Instant::now();
+
+        // the packet passes the protocol version and checksum checks, so that it reaches the connection.
+        server
+            .send(Packet::unreliable(client_address(), vec![]))
+            .unwrap();
+        server.manual_poll(time);
+
+        let mut bytes = ProtocolVersion::get_crc16().to_be_bytes().to_vec();
+        bytes.extend_from_slice(&[packet_type, delivery_guarantee, ordering_guarantee]);
+        bytes.extend_from_slice(&rest);
+        Checksum::append(&mut bytes);
+        client_socket
+            .send_packet(&server_address(), &bytes)
+            .unwrap();
+
+        server.manual_poll(time);
+    }
+
+    // Fails with the given errors, before it sends anything.
+    #[derive(Debug)]
+    struct FlakySocket {
+        errors: Vec<std::io::Error>,
+        attempts: usize,
+    }
+
+    impl super::DatagramSocket for FlakySocket {
+        fn send_packet(&mut self, _addr: &SocketAddr, payload: &[u8]) -> std::io::Result<usize> {
+            self.attempts += 1;
+            if self.errors.is_empty() {
+                Ok(payload.len())
+            } else {
+                Err(self.errors.remove(0))
+            }
+        }
+
+        fn receive_packet<'a>(
+            &mut self,
+            _buffer: &'a mut [u8],
+        ) -> std::io::Result<(&'a [u8], SocketAddr)> {
+            Err(std::io::ErrorKind::WouldBlock.into())
+        }
+
+        fn local_addr(&self) -> std::io::Result<SocketAddr> {
+            Ok(client_address())
+        }
+
+        fn is_blocking_mode(&self) -> bool {
+            false
+        }
+    }
+
+    // Sends a datagram over a socket that fails with the given errors first.
+    fn send_over_flaky_socket(
+        send_retries: u8,
+        errors: Vec<std::io::Error>,
+    ) -> super::SocketEventSenderAndConfig<FlakySocket, SocketEvent> {
+        use crate::net::ConnectionMessenger;
+
+        let config = Config::builder()
+            .send_retries(send_retries)
+            .build()
+            .unwrap();
+        let socket = FlakySocket {
+            errors,
+            attempts: 0,
+        };
+        let (event_sender, _event_receiver) = crossbeam_channel::unbounded::<SocketEvent>();
+        let mut messenger = super::SocketEventSenderAndConfig::new(config, socket, event_sender);
+        messenger.send_packet(&server_address(), &[1, 2, 3]);
+        messenger
+    }
+
+    #[test]
+    fn transient_send_errors_are_retried() {
+        use std::io::ErrorKind::{Interrupted, WouldBlock};
+
+        let messenger = send_over_flaky_socket(2, vec![WouldBlock.into(), Interrupted.into()]);
+        assert_eq!(messenger.socket.attempts, 3);
+        assert_eq!(messenger.stats.bytes_sent, 3);
+        assert_eq!(messenger.stats.send_failures, 0);
+
+        // the retries are used up, the datagram is tried again in the next poll.
+        let mut messenger = send_over_flaky_socket(1, vec![WouldBlock.into(), Interrupted.into()]);
+        assert_eq!(messenger.socket.attempts, 2);
+        assert_eq!(messenger.stats.bytes_sent, 0);
+        assert_eq!(messenger.deferred_datagrams.len(), 1);
+
+        messenger.send_deferred_datagrams();
+        assert_eq!(messenger.socket.attempts, 3);
+        assert_eq!(messenger.stats.bytes_sent, 3);
+        assert_eq!(messenger.stats.send_failures, 0);
+        assert!(messenger.deferred_datagrams.is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn out_of_buffer_space_is_retried() {
+        let error = std::io::Error::from_raw_os_error(libc::ENOBUFS);
+        let messenger = send_over_flaky_socket(1, vec![error]);
+        assert_eq!(messenger.socket.attempts, 2);
+        assert_eq!(messenger.stats.bytes_sent, 3);
+    }
+
+    #[test]
+    fn deferred_datagrams_are_given_up_on_when_they_fail_again() {
+        use std::io::ErrorKind::WouldBlock;
+
+        let mut messenger = send_over_flaky_socket(0, vec![WouldBlock.into(), WouldBlock.into()]);
+        assert_eq!(messenger.stats.send_failures, 0);
+
+        messenger.send_deferred_datagrams();
+        assert_eq!(messenger.socket.attempts, 2);
+        assert_eq!(messenger.stats.send_failures, 1);
+        assert_eq!(
+            messenger.send_failures,
+            vec![(server_address(), WouldBlock)]
+        );
+        assert!(messenger.deferred_datagrams.is_empty());
+    }
+
+    #[test]
+    fn other_send_errors_are_not_retried() {
+        use std::io::ErrorKind::PermissionDenied;
+
+        let messenger = send_over_flaky_socket(5, vec![PermissionDenied.into()]);
+        assert_eq!(messenger.socket.attempts, 1);
+        assert_eq!(messenger.stats.send_failures, 1);
+        assert_eq!(
+            messenger.send_failures,
+            vec![(server_address(), PermissionDenied)]
+        );
+        assert!(messenger.deferred_datagrams.is_empty());
+    }
+
+    #[test]
+    fn failed_sends_are_reported_at_the_end_of_the_poll() {
+        let socket = FlakySocket {
+            errors: vec![std::io::ErrorKind::PermissionDenied.into()],
+            attempts: 0,
+        };
+        let mut manager: super::ConnectionManager<_, crate::net::VirtualConnection> =
+            super::ConnectionManager::new(socket, Config::default());
+        manager
+            .event_sender()
+            .send(Packet::unreliable(server_address(), vec![1]))
+            .unwrap();
+        manager.manual_poll(Instant::now());
+
+        let failed = manager.event_receiver().try_iter().any(|event| {
+            event == SocketEvent::SendFailed(server_address(), std::io::ErrorKind::PermissionDenied)
+        });
+        assert!(failed);
+        assert_eq!(manager.aggregate_stats().send_failures, 1);
+    }
+
+    // Reports dropped packets with the sequence numbers 0 to `reports` over a messenger that holds back
+    // two reports, returns the sequence numbers of the held back reports and whether it refuses packets.
+    fn report_over_capped_messenger(policy: OverflowPolicy, reports: u16) -> (Vec<u16>, bool) {
+        use crate::net::ConnectionMessenger;
+
+        let config = Config::builder()
+            .max_dropped_reports(2)
+            .dropped_reports_overflow(policy)
+            .build()
+            .unwrap();
+        let socket = FlakySocket {
+            errors: Vec::new(),
+            attempts: 0,
+        };
+        let (event_sender, event_receiver) = crossbeam_channel::unbounded::<SocketEvent>();
+        let mut messenger = super::SocketEventSenderAndConfig::new(config, socket, event_sender);
+        for sequence in 0..reports {
+            let dropped = DroppedPacket {
+                packet: Packet::reliable_unordered(server_address(), vec![]),
+                sequence,
+                resends: 0,
+            };
+            messenger.report_dropped(
+                &server_address(),
+                SocketEvent::PacketsDropped(server_address(), vec![dropped]),
+            );
+        }
+        assert!(event_receiver.is_empty());
+
+        let full = messenger.dropped_reports_full();
+        messenger.send_dropped_reports();
+        let held_back = event_receiver
+            .try_iter()
+            .flat_map(|event| match event {
+                SocketEvent::PacketsDropped(_, dropped) => dropped,
+                _ => panic!["Only reports of dropped packets are held back"],
+            })
+            .map(|dropped| dropped.sequence)
+            .collect();
+        (held_back, full)
+    }
+
+    #[test]
+    fn dropped_reports_up_to_the_cap_are_held_back() {
+        for policy in [
+            OverflowPolicy::DropOldest,
+            OverflowPolicy::DropNewest,
+            OverflowPolicy::Error,
+        ]
+        .iter()
+        {
+            assert_eq!(report_over_capped_messenger(*policy, 1), (vec![0], false));
+            assert_eq!(
+                report_over_capped_messenger(*policy, 2),
+                (vec![0, 1], *policy == OverflowPolicy::Error)
+            );
+        }
+    }
+
+    #[test]
+    fn oldest_dropped_report_is_discarded_over_the_cap() {
+        assert_eq!(
+            report_over_capped_messenger(OverflowPolicy::DropOldest, 3),
+            (vec![1, 2], false)
+        );
+    }
+
+    #[test]
+    fn newest_dropped_report_is_discarded_over_the_cap() {
+        assert_eq!(
+            report_over_capped_messenger(OverflowPolicy::DropNewest, 3),
+            (vec![0, 1], false)
+        );
+    }
+
+    #[test]
+    fn reliable_packets_are_refused_while_dropped_reports_are_full() {
+        assert_eq!(
+            report_over_capped_messenger(OverflowPolicy::Error, 3),
+            (vec![0, 1], true)
+        );
+
+        let config = Config::builder()
+            .max_dropped_reports(1)
+            .dropped_reports_overflow(OverflowPolicy::Error)
+            .build()
+            .unwrap();
+        let (mut server, mut client, network) = {
+            let network = NetworkEmulator::default();
+            let server = FakeSocket::bind(&network, server_address(), config.clone()).unwrap();
+            let client = FakeSocket::bind(&network, client_address(), config).unwrap();
+            (server, client, network)
+        };
+        let now = Instant::now();
+
+        server
+            .send(Packet::unreliable(client_address(), vec![]))
+            .unwrap();
+        server.manual_poll(now);
+
+        // the first packet is lost, the events of the client are not taken meanwhile.
+        let mut refused = false;
+        for i in 0..80 {
+            match client.send(Packet::reliable_unordered(server_address(), vec![i])) {
+                Err(ErrorKind::DroppedBufferFull) => {
+                    refused = true;
+                    break;
+                }
+                result => result.unwrap(),
+            }
+            client.manual_poll(now);
+            if i == 0 {
+                network.clear_packets(server_address());
+            }
+            server.manual_poll(now);
+            server
+                .send(Packet::reliable_unordered(client_address(), vec![]))
+                .unwrap();
+            server.manual_poll(now);
+            client.manual_poll(now);
+        }
+        assert!(refused);
+
+        // the report is held back until the earlier events are taken.
+        while let Some(event) = client.recv() {
+            if let SocketEvent::PacketsDropped(..) = event {
+                panic!["The report should be held back"];
+            }
+        }
+        client.manual_poll(now);
+        match client.recv() {
+            Some(SocketEvent::PacketsDropped(address, dropped)) => {
+                assert_eq!(address, server_address());
+                assert_eq!(dropped[0].packet.payload(), &[0]);
+            }
+            event => panic!["Expected the held back report, got {:?}", event],
+        }
+        client
+            .send(Packet::reliable_unordered(server_address(), vec![]))
+            .unwrap();
+    }
+}
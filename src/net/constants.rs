@@ -2,14 +2,39 @@
 pub const FRAGMENT_HEADER_SIZE: u8 = 4;
 /// The size of the acknowledgment header.
 pub const ACKED_PACKET_HEADER: u8 = 8;
+/// The size of each range of a selective acknowledgment header.
+pub const SELECTIVE_ACK_RANGE_SIZE: u8 = 4;
+/// The maximal number of ranges a selective acknowledgment header carries, see `Config::selective_acks`.
+pub const MAX_SELECTIVE_ACK_RANGES: u8 = 8;
+/// How many of the last received sequence numbers are covered by selective acknowledgments.
+pub const SELECTIVE_ACK_HISTORY: u16 = 256;
+/// The maximal number of missing packets a negative acknowledgment reports, see `Config::negative_acks`.
+/// Wider gaps are left to the acknowledgment bitfield.
+pub const MAX_NEGATIVE_ACKS: u16 = 16;
 /// The size of the arranging header.
 pub const ARRANGING_PACKET_HEADER: u8 = 3;
 /// The size of the standard header.
 pub const STANDARD_HEADER_SIZE: u8 = 5;
+/// The size of the length that is prepended to each message of a batch.
+pub const BATCH_MESSAGE_HEADER_SIZE: u8 = 2;
+/// The highest epoch a connection can be in, epochs wrap around after it, see `Socket::reset`.
+pub const MAX_EPOCH: u8 = 0x7F;
+/// The size of the send time that is appended to user packets, see `Config::packet_timestamps`.
+pub const TIMESTAMP_SIZE: u8 = 4;
+/// The size of the checksum that is appended to each packet.
+pub const CHECKSUM_SIZE: u8 = 4;
+/// The size of the nonce that is prepended to each encrypted packet.
+#[cfg(feature = "encryption")]
+pub const ENCRYPTION_HEADER_SIZE: u8 = 24;
+/// The size of the authentication tag that is appended to each encrypted packet.
+#[cfg(feature = "encryption")]
+pub const ENCRYPTION_TAG_SIZE: u8 = 16;
 /// The ordering stream that will be used to order on if none was specified.
 pub const DEFAULT_ORDERING_STREAM: u8 = 255;
 /// The sequencing stream that will be used to sequence packets on if none was specified.
 pub const DEFAULT_SEQUENCING_STREAM: u8 = 255;
+/// How long a connection waits for the handshake to be accepted before it asks again, in milliseconds.
+pub const HANDSHAKE_RESEND_INTERVAL_MS: u64 = 100;
 /// Default maximal number of fragments to size.
 pub const MAX_FRAGMENTS_DEFAULT: u16 = 16;
 /// Default maximal size of each fragment.
@@ -27,4 +52,4 @@ pub const DEFAULT_MTU: u16 = 1452;
 /// It is used for:
 /// - Generating crc16 for the packet header.
 /// - Validating if arriving packets have the same protocol version.
-pub const PROTOCOL_VERSION: &str = "laminar-0.1.0";
+pub const PROTOCOL_VERSION: &str = "laminar-0.4.0";
@@ -1,4 +1,4 @@
-use std::net::SocketAddr;
+use std::{io, net::SocketAddr};
 
 use crate::packet::Packet;
 
@@ -10,7 +10,41 @@ pub enum SocketEvent {
     /// A new client connected.
     /// Clients are uniquely identified by the ip:port combination at this layer.
     Connect(SocketAddr),
+    /// The handshake with the remote host completed, this is only emitted when `Config::require_handshake` is set.
+    Connected(SocketAddr),
+    /// The remote host resumed its connection from another address, see `Socket::resume`.
+    /// The connection is known by the second address from now on, instead of the first one.
+    AddressChanged(SocketAddr, SocketAddr),
     /// The client has been idling for a configurable amount of time.
     /// You can control the timeout in the config.
     Timeout(SocketAddr),
+    /// The connection was closed, either by calling `Socket::disconnect` or by the remote host.
+    Disconnect(SocketAddr),
+    /// A reliable packet was given up on after it was resent `Config::max_resends` times without being acknowledged.
+    DeliveryFailed(Packet),
+    /// The connection was removed, because a reliable packet was given up on and `Config::disconnect_on_max_resends` is set.
+    ConnectionFailed(SocketAddr),
+    /// A reliable packet that was given an id with `Packet::with_id` was acknowledged by the remote host.
+    /// This is emitted only once per packet.
+    PacketAcked(SocketAddr, u64),
+    /// Reliable packets were declared dropped, because they were not acknowledged in time or the remote host
+    /// reported them missing, the oldest first. They are resent with new sequence numbers, unless they are given up on.
+    PacketsDropped(SocketAddr, Vec<DroppedPacket>),
+    /// A datagram to the address could not be sent, because the socket failed with this error.
+    /// Transient errors are only reported once the retries of `Config::send_retries` are used up.
+    SendFailed(SocketAddr, io::ErrorKind),
+    /// An ordered packet on the given stream was declared lost, because `Config::max_reorder_window`
+    /// newer packets were waiting for it. This is emitted once for every skipped packet.
+    SequenceSkipped(SocketAddr, u8),
+}
+
+/// A reliable packet that was declared dropped, see `SocketEvent::PacketsDropped`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DroppedPacket {
+    /// The packet as it was sent, the messages of a batch are reported on their own.
+    pub packet: Packet,
+    /// The sequence number the packet was sent with, the messages of a batch share the one of the batch.
+    pub sequence: u16,
+    /// How often the packet was resent before, it is given up on when this reaches `Config::max_resends`.
+    pub resends: u16,
 }
@@ -0,0 +1,77 @@
+use std::hash::Hasher;
+
+// the multiplier of the hash function of the rust compiler.
+const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+/// A fast hasher for the connection map, enabled with the `fast-hash` feature.
+///
+/// This is the hash function of the rust compiler, it is not resistant to hash flooding,
+/// so it should only be used when the remote addresses can be trusted.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FxHasher {
+    hash: u64,
+}
+
+impl FxHasher {
+    fn add_to_hash(&mut self, value: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ value).wrapping_mul(SEED);
+    }
+}
+
+impl Hasher for FxHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.add_to_hash(u64::from_le_bytes(word));
+        }
+    }
+
+    fn write_u8(&mut self, value: u8) {
+        self.add_to_hash(u64::from(value));
+    }
+
+    fn write_u16(&mut self, value: u16) {
+        self.add_to_hash(u64::from(value));
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.add_to_hash(u64::from(value));
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.add_to_hash(value);
+    }
+
+    fn write_usize(&mut self, value: usize) {
+        self.add_to_hash(value as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{Hash, Hasher};
+    use std::net::SocketAddr;
+
+    use super::FxHasher;
+
+    fn hash(address: &SocketAddr) -> u64 {
+        let mut hasher = FxHasher::default();
+        address.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn addresses_are_hashed_by_ip_and_port() {
+        let address: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+
+        assert_eq!(hash(&address), hash(&"127.0.0.1:12345".parse().unwrap()));
+        assert_ne!(hash(&address), hash(&"127.0.0.1:12346".parse().unwrap()));
+        assert_ne!(hash(&address), hash(&"127.0.0.2:12345".parse().unwrap()));
+        assert_ne!(hash(&address), hash(&"[::1]:12345".parse().unwrap()));
+    }
+}
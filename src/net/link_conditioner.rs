@@ -16,6 +16,12 @@ pub struct LinkConditioner {
     packet_loss: f64,
     // Duration of the delay imposed between packets
     latency: Duration,
+    // Maximum random delay that is added to the latency of each packet
+    jitter: Duration,
+    // Value between 0 and 1, representing the % chance a packet will be sent twice
+    duplication_rate: f64,
+    // Value between 0 and 1, representing the % chance a packet will overtake the one sent before it
+    reorder_rate: f64,
     // Random number generator
     random: Random,
 }
@@ -24,10 +30,19 @@ impl LinkConditioner {
     /// Creates and returns a LinkConditioner
     #[allow(dead_code)]
     pub fn new() -> LinkConditioner {
+        LinkConditioner::with_seed(0)
+    }
+
+    /// Creates and returns a LinkConditioner whose random decisions are derived from the given seed,
+    /// so the same seed always drops, delays and reorders the same packets.
+    pub fn with_seed(seed: u128) -> LinkConditioner {
         LinkConditioner {
             packet_loss: 0.0,
             latency: Duration::default(),
-            random: Random::new(0),
+            jitter: Duration::default(),
+            duplication_rate: 0.0,
+            reorder_rate: 0.0,
+            random: Random::new(seed),
         }
     }
 
@@ -43,10 +58,49 @@ impl LinkConditioner {
         self.latency = latency
     }
 
+    /// Sets the maximum random delay that is added to the latency of each packet
+    #[allow(dead_code)]
+    pub fn set_jitter(&mut self, jitter: Duration) {
+        self.jitter = jitter
+    }
+
+    /// Sets the rate at which packets are sent twice
+    #[allow(dead_code)]
+    pub fn set_duplication_rate(&mut self, rate: f64) {
+        self.duplication_rate = rate;
+    }
+
+    /// Sets the rate at which packets overtake the packet that was sent before them
+    #[allow(dead_code)]
+    pub fn set_reorder_rate(&mut self, rate: f64) {
+        self.reorder_rate = rate;
+    }
+
     /// Function that checks to see if a packet should be dropped or not
     pub fn should_send(&mut self) -> bool {
         self.random.gen_range(0.0, 1.0) >= self.packet_loss
     }
+
+    /// Function that checks to see if a packet should be sent twice or not
+    #[allow(dead_code)]
+    pub fn should_duplicate(&mut self) -> bool {
+        self.duplication_rate > 0.0 && self.random.gen_range(0.0, 1.0) < self.duplication_rate
+    }
+
+    /// Function that checks to see if a packet should overtake the one sent before it or not
+    #[allow(dead_code)]
+    pub fn should_reorder(&mut self) -> bool {
+        self.reorder_rate > 0.0 && self.random.gen_range(0.0, 1.0) < self.reorder_rate
+    }
+
+    /// Returns how long a packet should be delayed, which is the latency plus a random part of the jitter
+    #[allow(dead_code)]
+    pub fn delay(&mut self) -> Duration {
+        if self.jitter == Duration::default() {
+            return self.latency;
+        }
+        self.latency + self.jitter.mul_f64(self.random.gen_range(0.0, 1.0))
+    }
 }
 
 impl Default for LinkConditioner {
@@ -54,3 +108,34 @@ impl Default for LinkConditioner {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::LinkConditioner;
+
+    #[test]
+    fn same_seed_makes_same_decisions() {
+        let conditioner = || {
+            let mut conditioner = LinkConditioner::with_seed(7);
+            conditioner.set_packet_loss(0.5);
+            conditioner.set_latency(Duration::from_millis(10));
+            conditioner.set_jitter(Duration::from_millis(5));
+            conditioner
+        };
+        let decide = |mut conditioner: LinkConditioner| {
+            (0..100)
+                .map(|_| (conditioner.should_send(), conditioner.delay()))
+                .collect::<Vec<_>>()
+        };
+
+        let decisions = decide(conditioner());
+        assert_eq!(decisions, decide(conditioner()));
+        assert!(decisions.iter().any(|(send, _)| *send));
+        assert!(decisions.iter().any(|(send, _)| !*send));
+        assert!(decisions.iter().all(|(_, delay)| {
+            *delay >= Duration::from_millis(10) && *delay <= Duration::from_millis(15)
+        }));
+    }
+}
@@ -1,10 +1,10 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::config::Config;
 use crate::sequence_buffer::CongestionData;
 
 /// Represents the quality of a network.
-#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum NetworkQuality {
     /// Connection is generally good, minimal packet loss or latency.
     Good,
@@ -12,11 +12,82 @@ pub enum NetworkQuality {
     Bad,
 }
 
+/// A simple rating of a connection, e.g. for a connection bars indicator, see `VirtualConnection::quality`.
+///
+/// Qualities are ordered from `Bad` to `Excellent`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConnectionQuality {
+    /// The connection exceeds the thresholds of `Config::poor_quality`.
+    Bad,
+    /// The connection stays within the thresholds of `Config::poor_quality`.
+    Poor,
+    /// The connection stays within the thresholds of `Config::good_quality`.
+    Good,
+    /// The connection stays within the thresholds of `Config::excellent_quality`.
+    Excellent,
+}
+
+impl ConnectionQuality {
+    /// Rates a connection with the given round trip time, jitter and ratio of dropped packets.
+    ///
+    /// A connection gets the best quality whose thresholds it stays within for all three.
+    pub fn from_measurements(
+        config: &Config,
+        rtt: Duration,
+        jitter: Duration,
+        loss_rate: f32,
+    ) -> ConnectionQuality {
+        if config.excellent_quality.allows(rtt, jitter, loss_rate) {
+            ConnectionQuality::Excellent
+        } else if config.good_quality.allows(rtt, jitter, loss_rate) {
+            ConnectionQuality::Good
+        } else if config.poor_quality.allows(rtt, jitter, loss_rate) {
+            ConnectionQuality::Poor
+        } else {
+            ConnectionQuality::Bad
+        }
+    }
+}
+
+/// The highest round trip time, jitter and loss rate a connection may have for a `ConnectionQuality`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QualityThresholds {
+    /// The highest smoothed round trip time.
+    pub max_rtt: Duration,
+    /// The highest smoothed mean deviation of the round trip time.
+    pub max_jitter: Duration,
+    /// The highest ratio of dropped to sent packets, 0 allows no loss at all and 1 allows everything.
+    pub max_loss_rate: f32,
+}
+
+impl QualityThresholds {
+    /// Creates thresholds with the given limits.
+    pub fn new(max_rtt: Duration, max_jitter: Duration, max_loss_rate: f32) -> QualityThresholds {
+        QualityThresholds {
+            max_rtt,
+            max_jitter,
+            max_loss_rate,
+        }
+    }
+
+    /// Returns true if none of these thresholds is looser than the ones of `other`.
+    pub(crate) fn is_within(&self, other: &QualityThresholds) -> bool {
+        other.allows(self.max_rtt, self.max_jitter, self.max_loss_rate)
+    }
+
+    fn allows(&self, rtt: Duration, jitter: Duration, loss_rate: f32) -> bool {
+        rtt <= self.max_rtt && jitter <= self.max_jitter && loss_rate <= self.max_loss_rate
+    }
+}
+
 /// This type helps with calculating the round trip time from any packet.
 /// It is able to smooth out the network jitter if there is any.
 pub struct RttMeasurer {
     config: Config,
-    rtt: f32,
+    rtt: Option<Duration>,
+    // the mean deviation of the rtt samples from the smoothed rtt.
+    jitter: Duration,
 }
 
 impl RttMeasurer {
@@ -24,55 +95,65 @@ impl RttMeasurer {
     pub fn new(config: &Config) -> RttMeasurer {
         RttMeasurer {
             config: config.clone(),
-            rtt: 0.,
+            rtt: None,
+            jitter: Duration::default(),
         }
     }
 
     /// Calculates the round trip time (rtt) from the given acknowledgment.
     /// Whereafter it updates the rtt from the given connection.
-    pub fn calculate_rrt(&mut self, congestion_data: Option<&mut CongestionData>) {
-        self.rtt = self.get_smoothed_rtt(congestion_data);
+    ///
+    /// `time` is the moment the acknowledgment arrived.
+    pub fn calculate_rrt(&mut self, congestion_data: Option<CongestionData>, time: Instant) {
+        if let Some(congestion_data) = congestion_data {
+            self.record_rtt(time.duration_since(congestion_data.sending_time));
+        }
     }
 
-    #[cfg(test)]
-    pub fn get_rtt(&self) -> f32 {
-        self.rtt
+    /// Updates the rtt with a round trip time that was measured otherwise, e.g. with a ping.
+    pub fn record_rtt(&mut self, rtt: Duration) {
+        self.jitter = self.smooth_out_jitter(rtt);
+        self.rtt = Some(self.smooth_out_rtt(rtt));
     }
 
-    /// This will get the smoothed round trip time (rtt) from the time we last heard from a packet.
-    fn get_smoothed_rtt(&self, congestion_avoidance_entry: Option<&mut CongestionData>) -> f32 {
-        match congestion_avoidance_entry {
-            Some(avoidance_data) => {
-                let elapsed_time = avoidance_data.sending_time.elapsed();
-
-                let rtt_time = self.as_milliseconds(elapsed_time);
-
-                self.smooth_out_rtt(rtt_time)
-            }
-            None => 0.0,
-        }
+    /// Returns the smoothed round trip time (rtt), this is zero until the first acknowledgment arrived.
+    pub fn get_rtt(&self) -> Duration {
+        self.rtt.unwrap_or_default()
     }
 
-    /// Converts a duration to milliseconds.
-    ///
-    /// `as_milliseconds` is not supported yet supported in rust stable.
-    /// See this stackoverflow post for more info: https://stackoverflow.com/questions/36816072/how-do-i-get-a-duration-as-a-number-of-milliseconds-in-rust
-    fn as_milliseconds(&self, duration: Duration) -> u64 {
-        let nanos = u64::from(duration.subsec_nanos());
-        (1000 * 1000 * 1000 * duration.as_secs() + nanos) / (1000 * 1000)
+    /// Returns the smoothed mean deviation of the round trip time, this is zero until the second acknowledgment arrived.
+    pub fn get_jitter(&self) -> Duration {
+        self.jitter
     }
 
     /// Smooths out round trip time (rtt) value by the specified smoothing factor.
     ///
-    /// First we subtract the max allowed rtt.
-    /// This way we can see by how many we are off from the max allowed rtt.
-    /// Then we multiply with or smoothing factor.
-    ///
+    /// This is an exponentially weighted moving average, the new rtt only moves the estimation by the smoothing factor.
     /// We do this so that if one packet has an bad rtt it will not directly bring down the or network quality estimation.
-    /// The default is 10% smoothing so if in total or packet is 50 milliseconds later than max allowed rtt we will increase or rtt estimation with 5.
-    fn smooth_out_rtt(&self, rtt: u64) -> f32 {
-        let exceeded_rrt_time = rtt as i64 - i64::from(self.config.rtt_max_value);
-        exceeded_rrt_time as f32 * self.config.rtt_smoothing_factor
+    /// The default is 10% smoothing so if the estimation is 100 milliseconds and a packet takes 150 milliseconds we
+    /// will increase or rtt estimation with 5.
+    ///
+    /// The first measured rtt is taken as is.
+    fn smooth_out_rtt(&self, rtt: Duration) -> Duration {
+        match self.rtt {
+            Some(current) => {
+                let factor = self.config.rtt_smoothing_factor;
+                current.mul_f32(1.0 - factor) + rtt.mul_f32(factor)
+            }
+            None => rtt,
+        }
+    }
+
+    /// Smooths out the deviation of the given rtt from the current estimation, with the same factor as the rtt.
+    fn smooth_out_jitter(&self, rtt: Duration) -> Duration {
+        match self.rtt {
+            Some(current) => {
+                let deviation = rtt.abs_diff(current);
+                let factor = self.config.rtt_smoothing_factor;
+                self.jitter.mul_f32(1.0 - factor) + deviation.mul_f32(factor)
+            }
+            None => Duration::default(),
+        }
     }
 }
 
@@ -83,8 +164,9 @@ mod test {
 
     use crate::config::Config;
     use crate::net::VirtualConnection;
+    use crate::sequence_buffer::CongestionData;
 
-    use super::RttMeasurer;
+    use super::{ConnectionQuality, RttMeasurer};
 
     static TEST_HOST_IP: &str = "127.0.0.1";
     static TEST_PORT: &str = "20000";
@@ -94,33 +176,98 @@ mod test {
         let mut addr = format!("{}:{}", TEST_HOST_IP, TEST_PORT)
             .to_socket_addrs()
             .unwrap();
-        let _new_conn =
+        let _new_conn: VirtualConnection =
             VirtualConnection::new(addr.next().unwrap(), &Config::default(), Instant::now());
     }
 
     #[test]
-    fn convert_duration_to_milliseconds_test() {
-        let network_quality = RttMeasurer::new(&Config::default());
-        let milliseconds1 = network_quality.as_milliseconds(Duration::from_secs(1));
-        let milliseconds2 = network_quality.as_milliseconds(Duration::from_millis(1500));
-        let milliseconds3 = network_quality.as_milliseconds(Duration::from_millis(1671));
+    fn first_rtt_is_taken_as_is() {
+        let mut rtt_measurer = RttMeasurer::new(&Config::default());
+        let sending_time = Instant::now();
 
-        assert_eq!(milliseconds1, 1000);
-        assert_eq!(milliseconds2, 1500);
-        assert_eq!(milliseconds3, 1671);
+        assert_eq!(rtt_measurer.get_rtt(), Duration::default());
+
+        rtt_measurer.calculate_rrt(
+            Some(CongestionData::new(0, sending_time)),
+            sending_time + Duration::from_millis(100),
+        );
+
+        assert_eq!(rtt_measurer.get_rtt(), Duration::from_millis(100));
     }
 
     #[test]
     fn smooth_out_rtt() {
-        let mut config = Config::default();
         // for test purpose make sure we set smoothing factor to 10%.
-        config.rtt_smoothing_factor = 0.10;
-        config.rtt_max_value = 250;
+        let config = Config {
+            rtt_smoothing_factor: 0.10,
+            ..Default::default()
+        };
+
+        let mut rtt_measurer = RttMeasurer::new(&config);
+        let sending_time = Instant::now();
+
+        rtt_measurer.calculate_rrt(
+            Some(CongestionData::new(0, sending_time)),
+            sending_time + Duration::from_millis(100),
+        );
+        rtt_measurer.calculate_rrt(
+            Some(CongestionData::new(1, sending_time)),
+            sending_time + Duration::from_millis(150),
+        );
+
+        // 150ms is 50ms over the current estimation. So we check if or estimation has increased by 10% from 50.
+        let difference = rtt_measurer.get_rtt().as_secs_f32() - 0.105;
+        assert!(difference.abs() < 0.000_01);
+    }
+
+    #[test]
+    fn jitter_rises_with_variable_rtt() {
+        let mut rtt_measurer = RttMeasurer::new(&Config::default());
+
+        for _ in 0..20 {
+            rtt_measurer.record_rtt(Duration::from_millis(100));
+        }
+        assert_eq!(rtt_measurer.get_jitter(), Duration::default());
+
+        for index in 0..20 {
+            rtt_measurer.record_rtt(Duration::from_millis(if index % 2 == 0 { 20 } else { 180 }));
+        }
+        assert!(rtt_measurer.get_jitter() > Duration::from_millis(40));
+        // the rtt itself stays around the mean of the samples.
+        assert!(rtt_measurer.get_rtt() > Duration::from_millis(80));
+        assert!(rtt_measurer.get_rtt() < Duration::from_millis(120));
+    }
+
+    #[test]
+    fn quality_drops_as_measurements_cross_the_thresholds() {
+        let config = Config::default();
+        let quality = |rtt: u64, jitter: u64, loss_rate: f32| {
+            ConnectionQuality::from_measurements(
+                &config,
+                Duration::from_millis(rtt),
+                Duration::from_millis(jitter),
+                loss_rate,
+            )
+        };
+
+        assert_eq!(quality(20, 5, 0.0), ConnectionQuality::Excellent);
+        assert_eq!(quality(50, 10, 0.01), ConnectionQuality::Excellent);
+        assert_eq!(quality(51, 10, 0.01), ConnectionQuality::Good);
+        assert_eq!(quality(150, 10, 0.0), ConnectionQuality::Poor);
+        assert_eq!(quality(251, 10, 0.0), ConnectionQuality::Bad);
+
+        // a single measurement that is too bad is enough to lower the quality.
+        assert_eq!(quality(20, 30, 0.0), ConnectionQuality::Poor);
+        assert_eq!(quality(20, 5, 0.02), ConnectionQuality::Good);
+        assert_eq!(quality(20, 5, 0.5), ConnectionQuality::Bad);
+    }
+
+    #[test]
+    fn missing_congestion_data_is_ignored() {
+        let mut rtt_measurer = RttMeasurer::new(&Config::default());
 
-        let network_quality = RttMeasurer::new(&config);
-        let smoothed_rtt = network_quality.smooth_out_rtt(300);
+        rtt_measurer.calculate_rrt(None, Instant::now());
 
-        // 300ms has exceeded 50ms over the max allowed rtt. So we check if or smoothing factor is now 10% from 50.
-        assert_eq!((smoothed_rtt - 5.0f32).abs() < std::f32::EPSILON, true);
+        assert_eq!(rtt_measurer.get_rtt(), Duration::default());
     }
 }
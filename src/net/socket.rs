@@ -1,19 +1,34 @@
 use std::{
     self,
+    any::Any,
+    fmt::Debug,
     net::{Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs, UdpSocket},
-    thread::{sleep, yield_now},
     time::{Duration, Instant},
 };
+#[cfg(feature = "std-threads")]
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, sleep, yield_now, JoinHandle},
+};
+
+use crate::error::ErrorKind;
 
 use crossbeam_channel::{self, Receiver, Sender, TryRecvError};
+#[cfg(feature = "std-threads")]
+use log::error;
 
 use crate::{
     config::Config,
     error::Result,
     net::{
-        events::SocketEvent, ConnectionManager, DatagramSocket, LinkConditioner, VirtualConnection,
+        events::SocketEvent, AddressFilter, Bandwidth, Clock, Connection, ConnectionManager,
+        ConnectionQuality, ConnectionSnapshot, ConnectionState, ConnectionStats, DatagramSocket,
+        LinkConditioner, NetworkStats, SystemClock, VirtualConnection,
     },
-    packet::Packet,
+    packet::{DeliveryGuarantee, OrderingGuarantee, Packet},
 };
 
 // Wraps `LinkConditioner` and `UdpSocket` together. LinkConditioner is enabled when building with a "tester" feature.
@@ -76,12 +91,19 @@ impl DatagramSocket for SocketWithConditioner {
 }
 
 /// A reliable UDP socket implementation with configurable reliability and ordering guarantees.
+pub type Socket = PayloadSocket<Vec<u8>>;
+
+/// A `Socket` that sends packets with payloads of type `P`, see `Packet`.
+///
+/// This avoids copying shared or pooled buffers into a `Vec<u8>` before they are sent,
+/// e.g. with `PayloadSocket::<Arc<[u8]>>::bind_any()`. Received packets always own a `Vec<u8>`.
 #[derive(Debug)]
-pub struct Socket {
-    handler: ConnectionManager<SocketWithConditioner, VirtualConnection>,
+pub struct PayloadSocket<P: AsRef<[u8]> + Debug> {
+    handler: ConnectionManager<SocketWithConditioner, VirtualConnection<P>>,
+    clock: Box<dyn Clock>,
 }
 
-impl Socket {
+impl<P: AsRef<[u8]> + Debug> PayloadSocket<P> {
     /// Binds to the socket and then sets up `ActiveConnections` to manage the "connections".
     /// Because UDP connections are not persistent, we can only infer the status of the remote
     /// endpoint by looking to see if they are still sending packets or not
@@ -113,30 +135,55 @@ impl Socket {
     }
 
     fn bind_internal(socket: UdpSocket, config: Config) -> Result<Self> {
-        Ok(Socket {
+        config.validate()?;
+        Ok(PayloadSocket {
             handler: ConnectionManager::new(
                 SocketWithConditioner::new(socket, config.blocking_mode)?,
                 config,
             ),
+            clock: Box::new(SystemClock),
         })
     }
 
     /// Returns a handle to the packet sender which provides a thread-safe way to enqueue packets
     /// to be processed. This should be used when the socket is busy running its polling loop in a
     /// separate thread.
-    pub fn get_packet_sender(&self) -> Sender<Packet> {
+    pub fn get_packet_sender(&self) -> Sender<Packet<P>> {
         self.handler.event_sender().clone()
     }
 
     /// Returns a handle to the event receiver which provides a thread-safe way to retrieve events
     /// from the socket. This should be used when the socket is busy running its polling loop in
     /// a separate thread.
+    ///
+    /// Do not read from it while `drain_received` or `send_reliable_blocking` are used on the socket,
+    /// those take events out of the same channel and put the others back.
     pub fn get_event_receiver(&self) -> Receiver<SocketEvent> {
         self.handler.event_receiver().clone()
     }
 
     /// Sends a single packet
-    pub fn send(&mut self, packet: Packet) -> Result<()> {
+    ///
+    /// Fails with `ErrorKind::SendQueueFull` for a reliable packet if `Config::max_pending_packets`
+    /// reliable packets to the same address are not acknowledged yet.
+    ///
+    /// Fails with `ErrorKind::DroppedBufferFull` for a reliable packet if no more reports of dropped
+    /// packets can be held back, see `OverflowPolicy::Error`.
+    ///
+    /// Fails with `ErrorKind::PayloadTooLarge` if the payload can not be sent with the delivery
+    /// guarantee of the packet, see `Config::check_payload_size`.
+    pub fn send(&mut self, packet: Packet<P>) -> Result<()> {
+        self.handler
+            .config()
+            .check_payload_size(packet.delivery_guarantee(), packet.payload().len())?;
+        if packet.delivery_guarantee() == DeliveryGuarantee::Reliable
+            && self.handler.dropped_reports_full()
+        {
+            return Err(ErrorKind::DroppedBufferFull);
+        }
+        if let Some(connection) = self.handler.connection(&packet.addr()) {
+            connection.check_send_queue(packet.delivery_guarantee(), packet.priority())?;
+        }
         self.handler
             .event_sender()
             .send(packet)
@@ -153,23 +200,39 @@ impl Socket {
         }
     }
 
-    /// Runs the polling loop with the default '1ms' sleep duration. This should run in a spawned thread
-    /// since calls to `self.manual_poll` are blocking.
-    pub fn start_polling(&mut self) {
-        self.start_polling_with_duration(Some(Duration::from_millis(1)))
+    /// Returns an iterator over the events that are ready, it ends once no event is left.
+    /// Nothing is allocated for this, events are taken from the event receiver one by one.
+    ///
+    /// This is meant to be used after every `poll` in a game loop:
+    /// `socket.poll(); for event in socket.events() { .. }`
+    pub fn events(&mut self) -> impl Iterator<Item = SocketEvent> + '_ {
+        self.handler.event_receiver().try_iter()
     }
 
-    /// Runs the polling loop with a specified sleep duration. This should run in a spawned thread
-    /// since calls to `self.manual_poll` are blocking.
-    pub fn start_polling_with_duration(&mut self, sleep_duration: Option<Duration>) {
-        // nothing should break out of this loop!
-        loop {
-            self.manual_poll(Instant::now());
-            match sleep_duration {
-                None => yield_now(),
-                Some(duration) => sleep(duration),
-            };
-        }
+    /// Returns the packets of all connections that are ready to be handed to the application.
+    ///
+    /// Received datagrams are processed by `poll`, which also holds back ordered and sequenced packets
+    /// until they can be delivered; this only collects what those polls delivered since the last call.
+    /// It is meant for tick-based servers: `socket.poll(); for packet in socket.drain_received() { .. }`
+    ///
+    /// Other events, like `SocketEvent::Connect`, are kept for `recv` and `events`.
+    /// They are put back into the event channel, so this must not be mixed with reading from a
+    /// receiver of `get_event_receiver` in another thread.
+    pub fn drain_received(&mut self) -> Vec<Packet> {
+        self.handler.take_events(|event| match event {
+            SocketEvent::Packet(packet) => Ok(packet),
+            event => Err(event),
+        })
+    }
+
+    /// Processes any inbound/outbound packets and handle idle clients at the time of the socket clock.
+    ///
+    /// One call receives every datagram that is available, sends the queued packets, resends dropped ones,
+    /// sends heartbeats and drops connections that timed out. The resulting events can be taken with `events`.
+    /// Call this at least as often as `Config::heartbeat_interval` and `Config::timeout_check_interval`,
+    /// once per frame or tick is usually enough.
+    pub fn poll(&mut self) {
+        self.manual_poll(self.clock.now());
     }
 
     /// Processes any inbound/outbound packets and handle idle clients
@@ -177,11 +240,253 @@ impl Socket {
         self.handler.manual_poll(time);
     }
 
+    /// Replaces the clock used by `poll`, `disconnect` and the polling loop, which is the real time by default.
+    /// A `ManualClock` makes it possible to test timeouts without waiting for them.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Box::new(clock);
+    }
+
+    /// Closes the connection with the given address.
+    /// The remote host is notified and a `SocketEvent::Disconnect` is emitted locally.
+    /// Does nothing if there is no active connection with this address.
+    pub fn disconnect(&mut self, address: &SocketAddr) {
+        self.handler.disconnect(address, self.clock.now());
+    }
+
+    /// Sends a ping to the given address, which the remote host echoes back.
+    /// This measures the round trip time returned by `rtt` even if the connection is idle.
+    /// Does nothing if there is no active connection with this address.
+    pub fn ping(&mut self, address: &SocketAddr) {
+        self.handler.ping(address, self.clock.now());
+    }
+
+    /// Sends the acknowledgments of the reliable packets received from the given address right away.
+    ///
+    /// Acknowledgments piggyback on outgoing packets, so a host that sends nothing else does not acknowledge
+    /// anything until its next heartbeat, and the remote host resends its packets in the meantime. Unlike
+    /// heartbeats, this only sends a packet when there are acknowledgments that were not sent yet.
+    /// Does nothing if there is no active connection with this address.
+    pub fn flush_acks(&mut self, address: &SocketAddr) {
+        self.handler.flush_acks(address, self.clock.now());
+    }
+
+    /// Resets the connection with the given address, when its acknowledgments got out of sync.
+    ///
+    /// Both endpoints start over with new sequence numbers, acknowledgments and arranging streams,
+    /// while packets that were sent before the reset are ignored. Reliable packets that were not
+    /// acknowledged yet are not resent. Does nothing if there is no active connection with this address.
+    pub fn reset(&mut self, address: &SocketAddr) {
+        self.handler.reset(address, self.clock.now());
+    }
+
+    /// Asks the remote host at the given address to move the connection to the address this socket is
+    /// reached from now, e.g. after a mobile client switched networks or its NAT mapping changed.
+    ///
+    /// The remote host challenges the new address first, the connection is moved once this socket answers
+    /// from there in one of the next polls. It keeps its sequence numbers, acknowledgments and unacknowledged
+    /// packets, the remote host emits `SocketEvent::AddressChanged`. This needs `Config::require_handshake` on
+    /// both sides, as the connection is identified by the token of its handshake, which is only secret when
+    /// encryption is enabled. The token is replaced after each resume, so that it can not be replayed.
+    /// Does nothing if there is no active connection with this address.
+    pub fn resume(&mut self, address: &SocketAddr) {
+        self.handler.resume(address, self.clock.now());
+    }
+
+    /// Sets how long the remote host at the given address may be silent before its connection times out,
+    /// overriding `Config::idle_connection_timeout`.
+    /// Does nothing if there is no active connection with this address.
+    pub fn set_timeout(&mut self, address: &SocketAddr, timeout: Duration) {
+        if let Some(connection) = self.handler.connection_mut(address) {
+            connection.timeout = Some(timeout);
+        }
+    }
+
+    /// Sets a closure that is called with the address of every connection that times out, before it is removed.
+    ///
+    /// This runs cleanup right when the socket is polled, e.g. to free the slot of a player, while
+    /// `SocketEvent::Timeout` is still emitted. Without a hook, timeouts are only logged at debug level.
+    pub fn set_timeout_hook(&mut self, hook: impl FnMut(SocketAddr) + Send + 'static) {
+        self.handler.set_timeout_hook(hook);
+    }
+
+    /// Sets which remote addresses packets are accepted from, by default packets from every address are.
+    ///
+    /// Packets from rejected addresses are dropped before they create a connection or reach an existing one,
+    /// so connections with addresses that are rejected later on time out.
+    pub fn set_address_filter(&mut self, filter: AddressFilter) {
+        self.handler.set_address_filter(filter);
+    }
+
+    /// Stores application data with the connection of the given address, e.g. the id of a player.
+    /// The data is dropped together with the connection, when it times out or is disconnected.
+    /// Does nothing if there is no active connection with this address.
+    pub fn set_user_data<T: Any + Send + Sync>(&mut self, address: &SocketAddr, data: T) {
+        if let Some(connection) = self.handler.connection_mut(address) {
+            connection.user_data = Some(Box::new(data));
+        }
+    }
+
+    /// Returns the application data stored with `set_user_data` for the given address.
+    /// Returns `None` when there is no active connection with this address, or no data of type `T` was stored.
+    pub fn user_data<T: Any>(&self, address: &SocketAddr) -> Option<&T> {
+        self.handler
+            .connection(address)
+            .and_then(|connection| connection.user_data.as_ref())
+            .and_then(|data| data.downcast_ref())
+    }
+
     /// Returns the local socket address
     pub fn local_addr(&self) -> Result<SocketAddr> {
         Ok(self.handler.socket().local_addr()?)
     }
 
+    /// Returns the number of active connections.
+    pub fn connection_count(&self) -> usize {
+        self.handler.connections_count()
+    }
+
+    /// Returns the addresses of all active connections.
+    pub fn connected_addresses(&self) -> Vec<SocketAddr> {
+        self.handler.connected_addresses()
+    }
+
+    /// Returns the state of the connection with the given remote address.
+    /// Returns `None` when there is no active connection with this address.
+    pub fn connection_state(&self, address: &SocketAddr) -> Option<ConnectionState> {
+        self.handler.connection(address).map(Connection::state)
+    }
+
+    /// Returns the smoothed round trip time to the given remote address.
+    /// Returns `None` when there is no active connection with this address.
+    pub fn rtt(&self, address: &SocketAddr) -> Option<Duration> {
+        self.handler.connection(address).map(VirtualConnection::rtt)
+    }
+
+    /// Returns how long the remote host at the given address has been silent, at the time of the socket clock.
+    /// The connection times out once this reaches its idle timeout, see `set_timeout`.
+    /// Returns `None` when there is no active connection with this address.
+    pub fn idle_for(&self, address: &SocketAddr) -> Option<Duration> {
+        let time = self.clock.now();
+        self.handler
+            .connection(address)
+            .map(|connection| connection.idle_for(time))
+    }
+
+    /// Returns how much the round trip time to the given remote address varies, e.g. to size an interpolation buffer.
+    /// Returns `None` when there is no active connection with this address.
+    pub fn jitter(&self, address: &SocketAddr) -> Option<Duration> {
+        self.handler
+            .connection(address)
+            .map(VirtualConnection::jitter)
+    }
+
+    /// Returns an estimate of how many microseconds the clock of the given remote address is ahead of ours,
+    /// which is measured with `ping`, see `ConnectionSnapshot::clock_offset` for the assumptions it makes.
+    /// Returns `None` when there is no active connection with this address, or no pong was received yet.
+    pub fn clock_offset(&self, address: &SocketAddr) -> Option<i64> {
+        self.handler
+            .connection(address)
+            .and_then(VirtualConnection::clock_offset)
+    }
+
+    /// Returns the recommended number of packets per second to send to the given remote address.
+    /// This drops to `Config::bad_network_send_rate` while the round trip time is too high.
+    /// Returns `None` when there is no active connection with this address.
+    pub fn send_rate(&self, address: &SocketAddr) -> Option<u32> {
+        self.handler
+            .connection(address)
+            .map(VirtualConnection::send_rate)
+    }
+
+    /// Returns the sequence number the next reliable packet to the given remote address is sent with.
+    /// Heartbeats, pings and pongs take a sequence number as well.
+    /// Returns `None` when there is no active connection with this address.
+    pub fn local_sequence(&self, address: &SocketAddr) -> Option<u16> {
+        self.handler
+            .connection(address)
+            .map(VirtualConnection::local_sequence)
+    }
+
+    /// Returns the newest sequence number that the given remote address acknowledged.
+    /// Returns `None` when there is no active connection with this address.
+    pub fn remote_ack_sequence(&self, address: &SocketAddr) -> Option<u16> {
+        self.handler
+            .connection(address)
+            .map(VirtualConnection::remote_ack_sequence)
+    }
+
+    /// Returns the number of reliable packets to the given remote address that are not acknowledged yet.
+    /// Returns `None` when there is no active connection with this address.
+    pub fn packets_in_flight(&self, address: &SocketAddr) -> Option<u16> {
+        self.handler
+            .connection(address)
+            .map(VirtualConnection::packets_in_flight)
+    }
+
+    /// Returns the sequence numbers of the reliable packets to the given remote address that are not
+    /// acknowledged yet, with how long each has been waiting. The packets are in no particular order.
+    /// Returns `None` when there is no active connection with this address.
+    pub fn pending_packets(&self, address: &SocketAddr) -> Option<Vec<(u16, Duration)>> {
+        let time = self.clock.now();
+        self.handler
+            .connection(address)
+            .map(|connection| connection.pending_packets(time).collect())
+    }
+
+    /// Returns a rating of the connection with the given remote address, e.g. for a connection bars indicator.
+    /// The thresholds are configured with `Config::excellent_quality`, `good_quality` and `poor_quality`.
+    /// Returns `None` when there is no active connection with this address.
+    pub fn quality(&self, address: &SocketAddr) -> Option<ConnectionQuality> {
+        self.handler
+            .connection(address)
+            .map(VirtualConnection::quality)
+    }
+
+    /// Returns the bytes per second sent to and received from the given remote address, over the last second.
+    /// This is updated when the socket is polled. Returns `None` when there is no active connection with this address.
+    pub fn bandwidth(&self, address: &SocketAddr) -> Option<Bandwidth> {
+        self.handler
+            .connection(address)
+            .map(VirtualConnection::bandwidth)
+    }
+
+    /// Returns the packet counters of the connection with the given remote address.
+    /// Returns `None` when there is no active connection with this address.
+    pub fn stats(&self, address: &SocketAddr) -> Option<ConnectionStats> {
+        self.handler
+            .connection(address)
+            .map(VirtualConnection::stats)
+    }
+
+    /// Returns a copy of the state of every active connection.
+    /// The copies do not borrow the socket, so it can be polled while they are inspected.
+    pub fn snapshot(&self) -> Vec<ConnectionSnapshot> {
+        self.handler
+            .connections()
+            .map(VirtualConnection::snapshot)
+            .collect()
+    }
+
+    /// Returns the totals over all connections of this socket.
+    pub fn aggregate_stats(&self) -> NetworkStats {
+        self.handler.aggregate_stats()
+    }
+
+    /// Returns the totals of this socket and the stats of every active connection as JSON, requires the `json` feature.
+    ///
+    /// The object has a `schema_version` (see `STATS_SCHEMA_VERSION`), the `aggregate` fields of `NetworkStats`,
+    /// and `connections` with the `address`, `state`, `rtt_micros`, `jitter_micros`, `loss_rate`, `bandwidth`
+    /// and `stats` of each connection, ordered by address.
+    #[cfg(feature = "json")]
+    pub fn stats_json(&self) -> String {
+        crate::net::stats_json(self.handler.aggregate_stats(), self.handler.connections())
+    }
+
+    /// Resets the totals returned by `aggregate_stats`.
+    pub fn reset_stats(&mut self) {
+        self.handler.reset_stats();
+    }
+
     /// Sets the link conditioner for this socket. See [LinkConditioner] for further details.
     #[cfg(feature = "tester")]
     pub fn set_link_conditioner(&mut self, link_conditioner: Option<LinkConditioner>) {
@@ -190,3 +495,153 @@ impl Socket {
             .set_link_conditioner(link_conditioner);
     }
 }
+
+impl Socket {
+    /// Sends the same payload to every connection in the `Connected` state.
+    /// Each connection still sends it with its own sequence numbers and acknowledgments.
+    ///
+    /// Returns the addresses the payload is sent to.
+    pub fn broadcast(
+        &mut self,
+        payload: &[u8],
+        delivery: DeliveryGuarantee,
+        ordering: OrderingGuarantee,
+    ) -> Result<Vec<SocketAddr>> {
+        let addresses: Vec<SocketAddr> = self
+            .connected_addresses()
+            .into_iter()
+            .filter(|address| self.connection_state(address) == Some(ConnectionState::Connected))
+            .collect();
+        for address in &addresses {
+            self.send(Packet::new(*address, payload.into(), delivery, ordering))?;
+        }
+        Ok(addresses)
+    }
+}
+
+// the polling loop and blocking calls are left out without the `std-threads` feature,
+// the application calls `poll` or `manual_poll` itself then.
+#[cfg(feature = "std-threads")]
+impl<P: AsRef<[u8]> + Debug> PayloadSocket<P> {
+    /// Sends a reliable packet and polls the socket until it is acknowledged, e.g. for a login request.
+    ///
+    /// Fails with `ErrorKind::AcknowledgmentTimeout` if no acknowledgment arrived within `timeout`,
+    /// which is measured in real time. The packet is resent while waiting, like any reliable packet.
+    /// The acknowledgment arrives with the next packet of the remote host, e.g. its answer or a heartbeat.
+    /// Events that occur in the meantime are kept for `recv` and `events`. They are put back into the
+    /// event channel, so this must not be mixed with reading from a receiver of `get_event_receiver`.
+    pub fn send_reliable_blocking(
+        &mut self,
+        address: SocketAddr,
+        payload: P,
+        timeout: Duration,
+    ) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let id = rand::random();
+        self.send(Packet::reliable_unordered(address, payload).with_id(id))?;
+
+        loop {
+            self.poll();
+            let acked = self.handler.take_events(|event| match event {
+                SocketEvent::PacketAcked(acked, acked_id) if acked == address && acked_id == id => {
+                    Ok(())
+                }
+                event => Err(event),
+            });
+            if !acked.is_empty() {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(ErrorKind::AcknowledgmentTimeout);
+            }
+            sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Runs the polling loop with the default '1ms' sleep duration. This should run in a spawned thread
+    /// since calls to `self.manual_poll` are blocking.
+    pub fn start_polling(&mut self) {
+        self.start_polling_with_duration(Some(Duration::from_millis(1)))
+    }
+
+    /// Runs the polling loop with a specified sleep duration. This should run in a spawned thread
+    /// since calls to `self.manual_poll` are blocking.
+    pub fn start_polling_with_duration(&mut self, sleep_duration: Option<Duration>) {
+        // nothing should break out of this loop!
+        loop {
+            self.poll_once(sleep_duration);
+        }
+    }
+
+    /// Moves the socket into a spawned thread which runs the polling loop with a specified sleep duration.
+    /// Unlike `start_polling`, the loop can be stopped through the returned `PollingHandle`.
+    ///
+    /// Take the packet sender and event receiver before calling this, because the socket is moved into the thread.
+    pub fn start_polling_thread(self, sleep_duration: Option<Duration>) -> PollingHandle<P>
+    where
+        P: Send + 'static,
+    {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let thread_shutdown = shutdown.clone();
+
+        let thread = thread::spawn(move || {
+            let mut socket = self;
+            while !thread_shutdown.load(Ordering::Relaxed) {
+                socket.poll_once(sleep_duration);
+            }
+            socket
+        });
+
+        PollingHandle {
+            shutdown,
+            thread: Some(thread),
+        }
+    }
+
+    fn poll_once(&mut self, sleep_duration: Option<Duration>) {
+        self.poll();
+        // never sleep past the next timeout check.
+        let check_interval = self.handler.config().timeout_check_interval;
+        match sleep_duration {
+            None => yield_now(),
+            Some(duration) => {
+                sleep(check_interval.map_or(duration, |interval| duration.min(interval)))
+            }
+        };
+    }
+}
+
+/// Handle to a polling loop started with `Socket::start_polling_thread`.
+///
+/// Dropping the handle stops the polling loop and waits for its thread to finish.
+#[cfg(feature = "std-threads")]
+#[derive(Debug)]
+pub struct PollingHandle<P: AsRef<[u8]> + Debug = Vec<u8>> {
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<PayloadSocket<P>>>,
+}
+
+#[cfg(feature = "std-threads")]
+impl<P: AsRef<[u8]> + Debug> PollingHandle<P> {
+    /// Stops the polling loop, waits for its thread to finish and gives the socket back.
+    pub fn shutdown(mut self) -> PayloadSocket<P> {
+        self.stop()
+            .expect("The polling thread is only joined once")
+            .expect("The polling thread panicked")
+    }
+
+    fn stop(&mut self) -> Option<thread::Result<PayloadSocket<P>>> {
+        self.shutdown.store(true, Ordering::Relaxed);
+        self.thread.take().map(JoinHandle::join)
+    }
+}
+
+#[cfg(feature = "std-threads")]
+impl<P: AsRef<[u8]> + Debug> Drop for PollingHandle<P> {
+    fn drop(&mut self) {
+        // panicking in drop could abort the process, so a panicked thread is only logged.
+        if let Some(Err(_)) = self.stop() {
+            error!("The polling thread panicked");
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::net::ConnectionState;
+#[cfg(feature = "json")]
+use crate::net::VirtualConnection;
+
+/// Packet counters of a single connection.
+///
+/// Packets are counted before fragmentation, a resent packet is counted again.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ConnectionStats {
+    /// Number of packets sent to the remote endpoint, including heartbeats and resent packets.
+    pub packets_sent: u64,
+    /// Number of packets received from the remote endpoint.
+    pub packets_received: u64,
+    /// Number of reliable packets that were declared dropped because they were not acknowledged in time.
+    pub packets_dropped: u64,
+    /// Number of reliable packets that arrived after a newer one, duplicates are not counted.
+    pub packets_reordered: u64,
+    /// Number of dropped reliable packets that the remote endpoint acknowledged after all, the acknowledgment was only late.
+    ///
+    /// These were resent needlessly, many of them hint that packets are declared dropped too early.
+    pub spurious_retransmits: u64,
+}
+
+impl ConnectionStats {
+    pub(crate) fn record_sent(&mut self) {
+        self.packets_sent = self.packets_sent.saturating_add(1);
+    }
+
+    pub(crate) fn record_received(&mut self) {
+        self.packets_received = self.packets_received.saturating_add(1);
+    }
+
+    pub(crate) fn record_dropped(&mut self, count: usize) {
+        self.packets_dropped = self.packets_dropped.saturating_add(count as u64);
+    }
+
+    pub(crate) fn record_reordered(&mut self) {
+        self.packets_reordered = self.packets_reordered.saturating_add(1);
+    }
+
+    pub(crate) fn record_spurious_retransmits(&mut self, count: usize) {
+        self.spurious_retransmits = self.spurious_retransmits.saturating_add(count as u64);
+    }
+}
+
+/// The bytes sent to and received from a single connection within the last second, e.g. for a bandwidth graph.
+///
+/// Whole datagrams are counted, including their headers.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Bandwidth {
+    /// Number of bytes sent to the remote endpoint per second.
+    pub sent_bps: u64,
+    /// Number of bytes received from the remote endpoint per second.
+    pub recv_bps: u64,
+}
+
+/// Copy of the state of a single connection at the time it was taken, see `Socket::snapshot`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ConnectionSnapshot {
+    /// The address of the remote endpoint.
+    pub address: SocketAddr,
+    /// The state of the connection.
+    pub state: ConnectionState,
+    /// The sequence number the next reliable packet is sent with.
+    pub local_sequence: u16,
+    /// The newest sequence number that was received from the remote endpoint.
+    pub remote_sequence: u16,
+    /// The newest local sequence number that the remote endpoint acknowledged.
+    pub remote_ack_sequence: u16,
+    /// Number of reliable packets that are not acknowledged yet.
+    pub packets_in_flight: u16,
+    /// How often the connection was reset, see `Socket::reset`. This wraps around after `MAX_EPOCH`.
+    pub epoch: u8,
+    /// The smoothed round trip time.
+    pub rtt: Duration,
+    /// The smoothed mean deviation of the round trip time.
+    pub jitter: Duration,
+    /// The estimated number of microseconds the clock of the remote endpoint is ahead, it is measured with pings.
+    ///
+    /// Both clocks count the time since the connection was created on their side. Like NTP, the estimate assumes
+    /// that a ping takes as long as its pong, half of the difference between both ends up in the estimate.
+    /// `None` until the first pong was received.
+    pub clock_offset: Option<i64>,
+    /// The last time a packet was received from the remote endpoint.
+    pub last_heard: Instant,
+    /// The packet counters of the connection.
+    pub stats: ConnectionStats,
+}
+
+/// Totals over all connections of a socket.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct NetworkStats {
+    /// Number of connections that were added to the active connections list.
+    pub connections_created: u64,
+    /// Number of currently active connections.
+    pub active_connections: usize,
+    /// Number of bytes sent over the socket, including headers.
+    pub bytes_sent: u64,
+    /// Number of bytes received on the socket, including headers.
+    pub bytes_received: u64,
+    /// Number of reliable packets that were declared dropped, over all connections.
+    pub packets_dropped: u64,
+    /// Number of datagrams the socket failed to send, after the retries of `Config::send_retries` and the
+    /// one in the next poll.
+    pub send_failures: u64,
+}
+
+impl NetworkStats {
+    pub(crate) fn record_connection_created(&mut self) {
+        self.connections_created = self.connections_created.saturating_add(1);
+    }
+
+    pub(crate) fn record_sent(&mut self, bytes: usize) {
+        self.bytes_sent = self.bytes_sent.saturating_add(bytes as u64);
+    }
+
+    pub(crate) fn record_received(&mut self, bytes: usize) {
+        self.bytes_received = self.bytes_received.saturating_add(bytes as u64);
+    }
+
+    pub(crate) fn record_dropped(&mut self, count: u64) {
+        self.packets_dropped = self.packets_dropped.saturating_add(count);
+    }
+
+    pub(crate) fn record_send_failure(&mut self) {
+        self.send_failures = self.send_failures.saturating_add(1);
+    }
+}
+
+/// The version of the schema `Socket::stats_json` writes.
+///
+/// Fields may be added without raising it, it is raised when a field is removed or changes its meaning.
+#[cfg(feature = "json")]
+pub const STATS_SCHEMA_VERSION: u32 = 1;
+
+// The schema of `Socket::stats_json`, durations are given in microseconds.
+#[cfg(feature = "json")]
+#[derive(serde::Serialize)]
+struct StatsReport {
+    schema_version: u32,
+    aggregate: NetworkStats,
+    connections: Vec<ConnectionReport>,
+}
+
+#[cfg(feature = "json")]
+#[derive(serde::Serialize)]
+struct ConnectionReport {
+    address: SocketAddr,
+    state: ConnectionState,
+    rtt_micros: u64,
+    jitter_micros: u64,
+    loss_rate: f32,
+    bandwidth: Bandwidth,
+    stats: ConnectionStats,
+}
+
+// Writes the totals of a socket and the stats of its connections as JSON, the connections ordered by their address.
+#[cfg(feature = "json")]
+pub(crate) fn stats_json<'a, P: 'a>(
+    aggregate: NetworkStats,
+    connections: impl Iterator<Item = &'a VirtualConnection<P>>,
+) -> String {
+    let mut connections: Vec<ConnectionReport> = connections
+        .map(|connection| ConnectionReport {
+            address: connection.remote_address,
+            state: connection.state,
+            rtt_micros: connection.rtt().as_micros() as u64,
+            jitter_micros: connection.jitter().as_micros() as u64,
+            loss_rate: connection.loss_rate(),
+            bandwidth: connection.bandwidth(),
+            stats: connection.stats(),
+        })
+        .collect();
+    connections.sort_by_key(|connection| connection.address);
+
+    let report = StatsReport {
+        schema_version: STATS_SCHEMA_VERSION,
+        aggregate,
+        connections,
+    };
+    serde_json::to_string(&report).expect("the stats consist of numbers and strings only")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConnectionStats;
+
+    #[test]
+    fn counters_saturate() {
+        let mut stats = ConnectionStats {
+            packets_sent: u64::MAX,
+            packets_received: u64::MAX,
+            packets_dropped: u64::MAX - 1,
+            packets_reordered: u64::MAX,
+            spurious_retransmits: u64::MAX,
+        };
+
+        stats.record_sent();
+        stats.record_received();
+        stats.record_dropped(5);
+        stats.record_reordered();
+        stats.record_spurious_retransmits(1);
+
+        assert_eq!(stats.packets_sent, u64::MAX);
+        assert_eq!(stats.packets_received, u64::MAX);
+        assert_eq!(stats.packets_dropped, u64::MAX);
+        assert_eq!(stats.packets_reordered, u64::MAX);
+        assert_eq!(stats.spurious_retransmits, u64::MAX);
+    }
+}
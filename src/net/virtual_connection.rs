@@ -1,68 +1,363 @@
+use std::any::Any;
+use std::borrow::Cow;
 use std::fmt;
 use std::net::SocketAddr;
 use std::time::{Duration, Instant};
 
+use log::{debug, info, trace};
+
 use crate::{
     config::Config,
-    error::{ErrorKind, PacketErrorKind, Result},
+    error::{DecodingErrorKind, ErrorKind, PacketErrorKind, Result},
     infrastructure::{
         arranging::{Arranging, ArrangingSystem, OrderingSystem, SequencingSystem},
-        AcknowledgmentHandler, CongestionHandler, Fragmentation, SentPacket,
+        AcknowledgmentHandler, BandwidthLimiter, BandwidthSampler, CongestionHandler,
+        Fragmentation, PriorityLanes, SentPacket,
     },
-    net::constants::{
-        ACKED_PACKET_HEADER, DEFAULT_ORDERING_STREAM, DEFAULT_SEQUENCING_STREAM,
-        STANDARD_HEADER_SIZE,
+    net::{
+        constants::{
+            DEFAULT_ORDERING_STREAM, DEFAULT_SEQUENCING_STREAM, MAX_EPOCH, MAX_NEGATIVE_ACKS,
+        },
+        Bandwidth, ConnectionQuality, ConnectionSnapshot, ConnectionState, ConnectionStats,
+        ResumeRequest,
     },
     packet::{
-        DeliveryGuarantee, IncomingPackets, OrderingGuarantee, OutgoingPacketBuilder,
-        OutgoingPackets, Packet, PacketInfo, PacketReader, PacketType, SequenceNumber,
+        header::{SelectiveAckHeader, StandardHeader},
+        Checksum, Compression, DeliveryGuarantee, HeaderInfo, IncomingPackets, MessageBatch,
+        OrderingGuarantee, OutgoingPacketBuilder, OutgoingPackets, Packet, PacketInfo,
+        PacketReader, PacketType, Priority, SequenceNumber,
     },
+    protocol_version::ProtocolVersion,
 };
 
+#[cfg(feature = "encryption")]
+use crate::packet::Encryption;
+
+/// Handshake packet that is sent in reply to a received handshake packet.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HandshakeReply {
+    /// Challenges the remote host to send the token back.
+    Challenge(u64),
+    /// Sends the token of the challenge back.
+    Response(u64),
+    /// Accepts the connection.
+    Accept,
+    /// Sends the token of the challenge of a resume back, together with the token of the connection.
+    ResumeResponse(u64),
+    /// Accepts a resume, with the token the connection can be resumed with from now on.
+    ResumeAccept(u64),
+}
+
 /// Contains the information about a certain 'virtual connection' over udp.
 /// This connections also keeps track of network quality, processing packets, buffering data related to connection etc.
-pub struct VirtualConnection {
+pub struct VirtualConnection<P = Vec<u8>> {
     /// Last time we received a packet from this client
     pub last_heard: Instant,
     /// Last time we sent a packet to this client
     pub last_sent: Instant,
     /// The address of the remote endpoint
     pub remote_address: SocketAddr,
-
-    ordering_system: OrderingSystem<(Box<[u8]>, PacketType)>,
+    /// The state of the connection, see `ConnectionState`
+    pub state: ConnectionState,
+    /// The handshake packet that has to be sent in reply to the last received one
+    pub handshake_reply: Option<HandshakeReply>,
+    /// The token the remote endpoint was challenged with, it has to be sent back before it is accepted
+    pub challenge: Option<u64>,
+    /// The token of the completed handshake, the connection can be resumed from another address with it
+    pub session: Option<u64>,
+    /// The token the connection had before it was resumed last, the remote host asks with it again if the accept got lost
+    pub previous_session: Option<u64>,
+    /// The address the remote host asked to resume the connection from, and the token that address was challenged with
+    pub resume_challenge: Option<(SocketAddr, u64)>,
+    /// True if this side asked the remote host to resume the connection, until the remote host accepts it
+    pub resuming: bool,
+    /// The timestamp of the last received ping, which has to be echoed back
+    pub pong_reply: Option<u64>,
+    /// User packets that are sent once the handshake completed
+    pub queued_packets: Vec<Packet<P>>,
+    /// User packets that are sent once the bandwidth limit allows it, those of higher priorities first,
+    /// see `Config::max_bytes_per_second`
+    pub rate_limited_packets: PriorityLanes<Packet<P>>,
+    /// Small user messages that are sent together at the end of the poll, see `Config::batch_messages`
+    pub message_batch: Option<MessageBatch>,
+    /// Overrides `Config::idle_connection_timeout` for this connection when set
+    pub timeout: Option<Duration>,
+    /// Application data that belongs to this connection, it is dropped together with the connection
+    pub user_data: Option<Box<dyn Any + Send + Sync>>,
+
+    ordering_system: OrderingSystem<(Box<[u8]>, PacketType, Option<u32>)>,
     sequencing_system: SequencingSystem<Box<[u8]>>,
     acknowledge_handler: AcknowledgmentHandler,
     congestion_handler: CongestionHandler,
+    bandwidth_limiter: Option<BandwidthLimiter>,
+    bandwidth_sampler: BandwidthSampler,
+    // the ordering streams on which packets were skipped, once for every skipped packet.
+    skipped_sequences: Vec<u8>,
+    // the sequence numbers the remote endpoint reported missing, see `gather_nacked_packets`.
+    nacked_sequences: Vec<SequenceNumber>,
+    // ping timestamps are the microseconds since this time.
+    created: Instant,
+    // the smoothed estimate of how many microseconds the clock of the remote endpoint is ahead, see `clock_offset`.
+    clock_offset: Option<i64>,
+    // the number of times the connection was reset, packets of other epochs were sent before that.
+    epoch: u8,
+    stats: ConnectionStats,
+    // whether reliable packets were received since the acknowledgments were last sent, see `has_pending_acks`.
+    acks_pending: bool,
+    // the contents of outgoing packets are written into this buffer, which is kept for the next one.
+    send_buffer: Vec<u8>,
+
+    #[cfg(feature = "encryption")]
+    encryption: Option<Encryption>,
 
     config: Config,
     fragmentation: Fragmentation,
 }
 
-impl VirtualConnection {
+impl<P> VirtualConnection<P> {
     /// Creates and returns a new Connection that wraps the provided socket address
-    pub fn new(addr: SocketAddr, config: &Config, time: Instant) -> VirtualConnection {
+    pub fn new(addr: SocketAddr, config: &Config, time: Instant) -> VirtualConnection<P> {
         VirtualConnection {
             last_heard: time,
             last_sent: time,
             remote_address: addr,
+            state: if config.require_handshake {
+                ConnectionState::Connecting
+            } else {
+                ConnectionState::Connected
+            },
+            handshake_reply: None,
+            challenge: None,
+            session: None,
+            previous_session: None,
+            resume_challenge: None,
+            resuming: false,
+            pong_reply: None,
+            queued_packets: Vec::new(),
+            rate_limited_packets: PriorityLanes::new(),
+            message_batch: None,
+            timeout: None,
+            user_data: None,
             ordering_system: OrderingSystem::new(),
             sequencing_system: SequencingSystem::new(),
-            acknowledge_handler: AcknowledgmentHandler::new(),
+            // the first sequence number is random, so that it is harder to guess for spoofed packets.
+            // It is taken from the lower half, then the acknowledgment a remote host sends before it
+            // received anything (`u16::MAX`) always refers to a packet that is older than the first one.
+            acknowledge_handler: AcknowledgmentHandler::new(
+                config.ack_field_width,
+                rand::random::<u16>() >> 1,
+                config.max_packets_in_flight,
+            ),
             congestion_handler: CongestionHandler::new(config),
+            bandwidth_limiter: config
+                .max_bytes_per_second
+                .map(|rate| BandwidthLimiter::new(rate, config.send_burst_size, time)),
+            bandwidth_sampler: BandwidthSampler::new(time),
+            skipped_sequences: Vec::new(),
+            nacked_sequences: Vec::new(),
+            created: time,
+            clock_offset: None,
+            epoch: 0,
+            stats: ConnectionStats::default(),
+            acks_pending: false,
+            send_buffer: Vec::new(),
+            #[cfg(feature = "encryption")]
+            encryption: config.encryption_key.as_ref().map(Encryption::new),
             fragmentation: Fragmentation::new(config),
             config: config.to_owned(),
         }
     }
 
+    /// Returns the smoothed round trip time to the remote endpoint.
+    pub fn rtt(&self) -> Duration {
+        self.congestion_handler.rtt()
+    }
+
+    /// Returns how much the round trip time to the remote endpoint varies, as its smoothed mean deviation.
+    pub fn jitter(&self) -> Duration {
+        self.congestion_handler.jitter()
+    }
+
+    /// Returns an estimate of how many microseconds the clock of the remote endpoint is ahead of ours,
+    /// or `None` before the first pong was received. A negative offset means that it is behind.
+    ///
+    /// Both clocks count the time since the connection was created on their side. Like NTP, the estimate assumes
+    /// that a ping takes as long as its pong, half of the difference between both ends up in the estimate.
+    /// It is smoothed with `Config::rtt_smoothing_factor`.
+    pub fn clock_offset(&self) -> Option<i64> {
+        self.clock_offset
+    }
+
+    /// Returns the recommended number of packets per second to send to the remote endpoint.
+    pub fn send_rate(&self) -> u32 {
+        self.congestion_handler.send_rate()
+    }
+
+    /// Returns a rating of this connection from its round trip time, jitter and loss rate,
+    /// with the thresholds of the config.
+    ///
+    /// The loss rate is the ratio of dropped to sent packets over the lifetime of the connection.
+    pub fn quality(&self) -> ConnectionQuality {
+        ConnectionQuality::from_measurements(
+            &self.config,
+            self.rtt(),
+            self.jitter(),
+            self.loss_rate(),
+        )
+    }
+
+    /// Returns the ratio of dropped to sent packets over the lifetime of this connection.
+    pub fn loss_rate(&self) -> f32 {
+        if self.stats.packets_sent == 0 {
+            0.0
+        } else {
+            self.stats.packets_dropped as f32 / self.stats.packets_sent as f32
+        }
+    }
+
+    /// Returns true if reliable packets were received that were not acknowledged to the remote endpoint yet.
+    pub fn has_pending_acks(&self) -> bool {
+        self.acks_pending
+    }
+
+    /// Returns the packet counters of this connection.
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats
+    }
+
+    /// Returns a copy of the state of this connection.
+    pub fn snapshot(&self) -> ConnectionSnapshot {
+        ConnectionSnapshot {
+            address: self.remote_address,
+            state: self.state,
+            local_sequence: self.acknowledge_handler.local_sequence_num(),
+            remote_sequence: self.acknowledge_handler.remote_sequence_num(),
+            remote_ack_sequence: self.remote_ack_sequence(),
+            packets_in_flight: self.packets_in_flight(),
+            rtt: self.rtt(),
+            jitter: self.jitter(),
+            clock_offset: self.clock_offset,
+            epoch: self.epoch(),
+            last_heard: self.last_heard,
+            stats: self.stats,
+        }
+    }
+
+    /// Returns the sequence number the next packet with acknowledgments is sent with.
+    pub fn local_sequence(&self) -> SequenceNumber {
+        self.acknowledge_handler.local_sequence_num()
+    }
+
+    /// Returns the newest local sequence number that the remote endpoint acknowledged.
+    pub fn remote_ack_sequence(&self) -> SequenceNumber {
+        self.acknowledge_handler.remote_ack_sequence_num()
+    }
+
+    /// Returns the number of reliable packets that are not acknowledged yet.
     pub fn packets_in_flight(&self) -> u16 {
         self.acknowledge_handler.packets_in_flight()
     }
 
-    /// Returns a [Duration] representing the interval since we last heard from the client
-    pub fn last_heard(&self, time: Instant) -> Duration {
-        // TODO: Replace with `saturating_duration_since` once it becomes stable.
-        // this function panics if the user supplies a time instant earlier than last_heard
-        time.duration_since(self.last_heard)
+    /// Returns the sequence numbers of the reliable packets that are not acknowledged yet,
+    /// with how long each has been waiting for its acknowledgment at the given time.
+    pub fn pending_packets(
+        &self,
+        time: Instant,
+    ) -> impl Iterator<Item = (SequenceNumber, Duration)> + '_ {
+        self.acknowledge_handler.pending_packets(time)
+    }
+
+    /// Returns true if a payload of the given size can be sent now, see `Config::max_bytes_per_second`.
+    pub fn bandwidth_allows(&mut self, size: usize, time: Instant) -> bool {
+        match self.bandwidth_limiter.as_mut() {
+            Some(limiter) => limiter.allows(size, time),
+            None => true,
+        }
+    }
+
+    /// Takes the bytes of a sent datagram from the bandwidth budget.
+    pub fn record_sent_bytes(&mut self, size: usize) {
+        if let Some(limiter) = self.bandwidth_limiter.as_mut() {
+            limiter.consume(size);
+        }
+        // every datagram went through `process_outgoing` right before, which set the time it is sent at.
+        self.bandwidth_sampler.record_sent(size, self.last_sent);
+    }
+
+    /// Counts the bytes of a datagram received at the given time, see `bandwidth`.
+    pub fn record_received_bytes(&mut self, size: usize, time: Instant) {
+        self.bandwidth_sampler.record_received(size, time);
+    }
+
+    /// Forgets the bytes sent and received more than a second before the given time, see `bandwidth`.
+    pub fn age_bandwidth(&mut self, time: Instant) {
+        self.bandwidth_sampler.advance(time);
+    }
+
+    /// Returns the bytes sent to and received from the remote endpoint within the last second,
+    /// as of the last time the connection was updated.
+    pub fn bandwidth(&self) -> Bandwidth {
+        self.bandwidth_sampler.bandwidth()
+    }
+
+    /// Checks whether another packet with the given delivery guarantee and priority can be sent,
+    /// see `Config::max_pending_packets`.
+    ///
+    /// High priority packets are never refused, they are still limited by `Config::max_packets_in_flight`.
+    pub fn check_send_queue(&self, delivery: DeliveryGuarantee, priority: Priority) -> Result<()> {
+        match self.config.max_pending_packets {
+            Some(max_pending_packets)
+                if delivery == DeliveryGuarantee::Reliable
+                    && priority != Priority::High
+                    && self.packets_in_flight() >= max_pending_packets =>
+            {
+                Err(ErrorKind::SendQueueFull)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the timestamp a ping sent at the given time carries.
+    pub fn ping_timestamp(&self, time: Instant) -> u64 {
+        time.duration_since(self.created).as_micros() as u64
+    }
+
+    /// Returns the payload of a pong that answers a ping with the given timestamp,
+    /// it carries our own timestamp as well, see `clock_offset`.
+    pub fn pong_payload(&self, timestamp: u64, time: Instant) -> [u8; 16] {
+        let mut payload = [0; 16];
+        payload[..8].copy_from_slice(&timestamp.to_be_bytes());
+        payload[8..].copy_from_slice(&self.ping_timestamp(time).to_be_bytes());
+        payload
+    }
+
+    // The remote endpoint answered the ping halfway between sending it and receiving the pong, as far as we know.
+    fn record_clock_offset(&mut self, timestamp: u64, remote_timestamp: u64, time: Instant) {
+        let midpoint = (i128::from(timestamp) + i128::from(self.ping_timestamp(time))) / 2;
+        let sample = (i128::from(remote_timestamp) - midpoint) as i64;
+        let factor = f64::from(self.config.rtt_smoothing_factor);
+        self.clock_offset = Some(match self.clock_offset {
+            Some(offset) => offset + ((sample - offset) as f64 * factor) as i64,
+            None => sample,
+        });
+    }
+
+    /// Returns how long the remote endpoint may be silent before the connection times out.
+    pub fn idle_timeout(&self) -> Duration {
+        self.timeout.unwrap_or(self.config.idle_connection_timeout)
+    }
+
+    /// Returns how long the remote endpoint has been silent at the given time,
+    /// which is zero if the time is earlier than the last received packet.
+    pub fn idle_for(&self, time: Instant) -> Duration {
+        time.saturating_duration_since(self.last_heard)
+    }
+
+    /// Returns true if the remote endpoint has been silent for at least `timeout` at the given time.
+    ///
+    /// This is the check used to time out connections, with `idle_timeout` as the timeout.
+    pub fn is_stale(&self, timeout: Duration, time: Instant) -> bool {
+        self.idle_for(time) >= timeout
     }
 
     /// Returns a [Duration] representing the interval since we last sent to the client
@@ -72,6 +367,50 @@ impl VirtualConnection {
         time.duration_since(self.last_sent)
     }
 
+    /// Returns the buffer outgoing packets are written into, it is put back with `reuse_send_buffer`.
+    pub fn take_send_buffer(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.send_buffer)
+    }
+
+    /// Keeps the buffer of a sent packet for the next one, so that its memory is reused.
+    pub fn reuse_send_buffer(&mut self, buffer: Vec<u8>) {
+        self.send_buffer = buffer;
+    }
+
+    /// Encrypts the contents of an outgoing packet, if encryption is enabled.
+    pub fn encrypt(&mut self, contents: Vec<u8>) -> Vec<u8> {
+        #[cfg(feature = "encryption")]
+        {
+            if let Some(encryption) = self.encryption.as_mut() {
+                return encryption.encrypt(&contents);
+            }
+        }
+        contents
+    }
+
+    /// Verifies the checksum of a received datagram and decrypts it, if encryption is enabled.
+    ///
+    /// Datagrams that are not encrypted are checked for the protocol version first.
+    pub fn open<'a>(&mut self, datagram: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        if !is_encrypted(&self.config) {
+            ProtocolVersion::verify_datagram(datagram)?;
+        }
+        let datagram = Checksum::verify(datagram)?;
+        self.decrypt(datagram)
+    }
+
+    /// Decrypts the received data, if encryption is enabled.
+    /// Data that can not be authenticated, or that was received before, is rejected before it reaches the connection state.
+    pub fn decrypt<'a>(&mut self, received_data: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        #[cfg(feature = "encryption")]
+        {
+            if let Some(encryption) = self.encryption.as_mut() {
+                return encryption.decrypt(received_data).map(Cow::Owned);
+            }
+        }
+        Ok(Cow::Borrowed(received_data))
+    }
+
     /// Pre-processes the given buffer to be sent over the network.
     pub fn process_outgoing<'a>(
         &mut self,
@@ -79,17 +418,48 @@ impl VirtualConnection {
         last_item_identifier: Option<SequenceNumber>,
         time: Instant,
     ) -> Result<OutgoingPackets<'a>> {
+        self.config
+            .check_payload_size(packet.delivery, packet.payload.len())?;
         self.last_sent = time;
+
+        // compress the payload if that makes it smaller, the uncompressed payload is kept for resending.
+        let (payload, compression) = match self.config.compression.compress(packet.payload) {
+            Some(compressed) => (Cow::Owned(compressed), self.config.compression),
+            None => (Cow::Borrowed(packet.payload), Compression::None),
+        };
+
         match packet.delivery {
             DeliveryGuarantee::Unreliable => {
-                if packet.payload.len() <= self.config.receive_buffer_max_size {
-                    if packet.packet_type == PacketType::Heartbeat {
-                        self.congestion_handler
-                            .process_outgoing(self.acknowledge_handler.local_sequence_num(), time);
-                    }
+                if payload.len() <= self.config.max_payload_size(packet.delivery) {
+                    let mut builder = OutgoingPacketBuilder::new(payload).with_default_header(
+                        packet.packet_type,
+                        packet.delivery,
+                        packet.ordering,
+                        compression,
+                        self.config.ack_field_width,
+                        self.epoch,
+                    );
+
+                    if packet.packet_type.piggybacks_acknowledgments() {
+                        // heartbeats, pings and pongs piggyback acknowledgments, but they are never resent.
+                        builder = builder.with_acknowledgment_header(
+                            self.acknowledge_handler.local_sequence_num(),
+                            self.acknowledge_handler.remote_sequence_num(),
+                            self.acknowledge_handler.ack_bitfield(),
+                            self.config.ack_field_width,
+                        );
+                        builder = self.with_selective_acks(builder);
 
-                    let mut builder = OutgoingPacketBuilder::new(packet.payload)
-                        .with_default_header(packet.packet_type, packet.delivery, packet.ordering);
+                        // the round trip of a ping is measured by its pong, not by the acknowledgment as well.
+                        if packet.packet_type != PacketType::Ping {
+                            self.congestion_handler.process_outgoing(
+                                self.acknowledge_handler.local_sequence_num(),
+                                time,
+                            );
+                        }
+                        self.acknowledge_handler.process_outgoing_unreliable();
+                        self.acks_pending = false;
+                    }
 
                     if let OrderingGuarantee::Sequenced(stream_id) = packet.ordering {
                         let item_identifier = self
@@ -97,33 +467,39 @@ impl VirtualConnection {
                             .get_or_create_stream(stream_id.unwrap_or(DEFAULT_SEQUENCING_STREAM))
                             .new_item_identifier();
 
-                        builder = builder.with_sequencing_header(item_identifier as u16, stream_id);
+                        builder = builder.with_sequencing_header(item_identifier, stream_id);
                     };
 
+                    self.stats.record_sent();
+                    let builder = self.with_timestamp(builder, packet.packet_type, time);
                     Ok(OutgoingPackets::one(builder.build()))
                 } else {
                     Err(PacketErrorKind::ExceededMaxPacketSize.into())
                 }
             }
             DeliveryGuarantee::Reliable => {
-                let payload_length = packet.payload.len() as u16;
+                let payload_length = payload.len() as u16;
 
                 let mut item_identifier_value = None;
                 let outgoing = {
                     // split the packet if the payload length is greater than the allowed fragment size.
                     if payload_length <= self.config.fragment_size {
-                        let mut builder = OutgoingPacketBuilder::new(packet.payload)
-                            .with_default_header(
-                                packet.packet_type,
-                                packet.delivery,
-                                packet.ordering,
-                            );
+                        let mut builder = OutgoingPacketBuilder::new(payload).with_default_header(
+                            packet.packet_type,
+                            packet.delivery,
+                            packet.ordering,
+                            compression,
+                            self.config.ack_field_width,
+                            self.epoch,
+                        );
 
                         builder = builder.with_acknowledgment_header(
                             self.acknowledge_handler.local_sequence_num(),
                             self.acknowledge_handler.remote_sequence_num(),
                             self.acknowledge_handler.ack_bitfield(),
+                            self.config.ack_field_width,
                         );
+                        builder = self.with_selective_acks(builder);
 
                         if let OrderingGuarantee::Ordered(stream_id) = packet.ordering {
                             let item_identifier =
@@ -159,13 +535,28 @@ impl VirtualConnection {
                             builder = builder.with_sequencing_header(item_identifier, stream_id);
                         };
 
+                        let builder = self.with_timestamp(builder, packet.packet_type, time);
                         OutgoingPackets::one(builder.build())
                     } else {
                         if packet.packet_type != PacketType::Packet {
                             return Err(PacketErrorKind::PacketCannotBeFragmented.into());
                         }
+                        let fragments: Vec<Cow<'a, [u8]>> = match payload {
+                            Cow::Borrowed(payload) => {
+                                Fragmentation::spit_into_fragments(payload, &self.config)?
+                                    .into_iter()
+                                    .map(Cow::Borrowed)
+                                    .collect()
+                            }
+                            Cow::Owned(payload) => {
+                                Fragmentation::spit_into_fragments(&payload, &self.config)?
+                                    .into_iter()
+                                    .map(|fragment| Cow::Owned(fragment.to_vec()))
+                                    .collect()
+                            }
+                        };
                         OutgoingPackets::many(
-                            Fragmentation::spit_into_fragments(packet.payload, &self.config)?
+                            fragments
                                 .into_iter()
                                 .enumerate()
                                 .map(|(fragment_id, fragment)| {
@@ -180,6 +571,9 @@ impl VirtualConnection {
                                             PacketType::Fragment, // change from Packet to Fragment type, it only matters when assembling/dissasembling packet header.
                                             packet.delivery,
                                             packet.ordering,
+                                            compression,
+                                            self.config.ack_field_width,
+                                            self.epoch,
                                         );
 
                                     builder = builder.with_fragment_header(
@@ -193,10 +587,12 @@ impl VirtualConnection {
                                             self.acknowledge_handler.local_sequence_num(),
                                             self.acknowledge_handler.remote_sequence_num(),
                                             self.acknowledge_handler.ack_bitfield(),
+                                            self.config.ack_field_width,
                                         );
                                     }
 
-                                    builder.build()
+                                    self.with_timestamp(builder, PacketType::Fragment, time)
+                                        .build()
                                 })
                                 .collect(),
                         )
@@ -210,42 +606,134 @@ impl VirtualConnection {
                     packet.payload,
                     packet.ordering,
                     item_identifier_value,
+                    time,
                 );
+                self.acks_pending = false;
 
+                self.stats.record_sent();
                 Ok(outgoing)
             }
         }
     }
 
     /// Processes the incoming data and returns a packet once the data is complete.
+    ///
+    /// Received batches are split into the messages they contain.
     pub fn process_incoming(
         &mut self,
         received_data: &[u8],
         time: Instant,
     ) -> Result<IncomingPackets> {
-        self.last_heard = time;
+        let incoming = self.read_incoming(received_data, time);
+        // acknowledgments are processed before the rest of the packet is read, which may still fail.
+        let spurious = self.acknowledge_handler.take_spurious_retransmits();
+        self.stats.record_spurious_retransmits(spurious);
+        incoming?.unbatch()
+    }
 
-        let mut packet_reader = PacketReader::new(received_data);
+    fn read_incoming(&mut self, received_data: &[u8], time: Instant) -> Result<IncomingPackets> {
+        // packets of other protocol versions are rejected before any connection state is touched.
+        let info = HeaderInfo::read(received_data)?;
+        let header = info.standard_header();
+        let sent_at = info.sent_at();
+
+        if header.ack_field_width() != self.config.ack_field_width {
+            return Err(ErrorKind::AckFieldWidthMismatch {
+                expected: self.config.ack_field_width,
+                got: header.ack_field_width(),
+            });
+        }
 
-        let header = packet_reader.read_standard_header()?;
+        // epochs wrap around, those in the half before the current one are earlier.
+        let epoch_distance = header.epoch().wrapping_sub(self.epoch) & MAX_EPOCH;
+        if epoch_distance > MAX_EPOCH / 2 {
+            debug!(
+                "Dropping packet of the earlier epoch {} from {}",
+                header.epoch(),
+                self.remote_address
+            );
+            return Ok(IncomingPackets::zero());
+        } else if epoch_distance > 0 {
+            info!("Connection reset by {}", self.remote_address);
+            self.epoch = header.epoch();
+            self.reset_protocol_state();
+        }
 
-        if !header.is_current_protocol() {
-            return Err(ErrorKind::ProtocolVersionMismatch);
+        self.last_heard = time;
+        self.stats.record_received();
+
+        if header.packet_type().piggybacks_acknowledgments() {
+            // these packets are unreliable and unordered, but carry the acknowledgments of the remote host.
+            let acked_header = expect_header(info.acked_header(), "acknowledgment")?;
+
+            self.congestion_handler
+                .process_incoming(acked_header.ack_seq(), time);
+            self.acknowledge_handler.process_incoming(
+                acked_header.sequence(),
+                acked_header.ack_seq(),
+                acked_header.ack_field(),
+            );
+            if let Some(selective_acks) = info.selective_ack_header() {
+                self.acknowledge_handler
+                    .process_selective_acks(selective_acks.ranges());
+            }
         }
 
         if header.is_heartbeat() {
-            // heartbeat packets are unreliable, unordered and empty packets.
-            // we already updated our `self.last_heard` time, nothing else to be done.
+            // heartbeat packets are empty, we already updated our `self.last_heard` time and the acknowledgments.
+            return Ok(IncomingPackets::zero());
+        }
+
+        if header.is_handshake() {
+            self.process_handshake(header.packet_type(), info.payload())?;
             return Ok(IncomingPackets::zero());
         }
 
+        if header.is_disconnect() {
+            // the remote host closed the connection, there is nothing left to process.
+            self.state = ConnectionState::Disconnecting;
+            return Ok(IncomingPackets::zero());
+        }
+
+        if self.state == ConnectionState::Connecting {
+            return Err(ErrorKind::HandshakeNotCompleted);
+        }
+
+        match header.packet_type() {
+            PacketType::Ping => {
+                self.pong_reply = Some(read_token(info.payload())?);
+                return Ok(IncomingPackets::zero());
+            }
+            PacketType::Nack => {
+                let sequences = read_sequences(info.payload())?;
+                self.nacked_sequences.extend(sequences);
+                return Ok(IncomingPackets::zero());
+            }
+            PacketType::Pong => {
+                let (timestamp, remote_timestamp) = read_pong(info.payload())?;
+                let sent = self.created + Duration::from_micros(timestamp);
+                // a timestamp from the future was not sent by this connection.
+                if sent <= time {
+                    self.congestion_handler
+                        .process_rtt(time.duration_since(sent), time);
+                    self.record_clock_offset(timestamp, remote_timestamp, time);
+                }
+                return Ok(IncomingPackets::zero());
+            }
+            _ => {}
+        }
+
+        // compressed payloads are decompressed before they are arranged.
+        let compression = header.compression();
+        let max_packet_size = self.config.max_packet_size;
+        let decompress = |payload| compression.decompress(payload, max_packet_size);
+
         match header.delivery_guarantee() {
             DeliveryGuarantee::Unreliable => {
                 if let OrderingGuarantee::Sequenced(_id) = header.ordering_guarantee() {
-                    let arranging_header =
-                        packet_reader.read_arranging_header(u16::from(STANDARD_HEADER_SIZE))?;
+                    let arranging_header = expect_header(info.arranging_header(), "arranging")?;
 
-                    let payload = packet_reader.read_payload();
+                    let payload = decompress(info.payload().into())?;
 
                     let stream = self
                         .sequencing_system
@@ -258,50 +746,58 @@ impl VirtualConnection {
                                 packet,
                                 header.delivery_guarantee(),
                                 OrderingGuarantee::Sequenced(Some(arranging_header.stream_id())),
-                            ),
+                            )
+                            .with_sent_at(sent_at),
                             header.packet_type(),
                         ));
                     }
 
+                    debug!(
+                        "Dropping stale sequenced packet {} from {}",
+                        arranging_header.arranging_id(),
+                        self.remote_address
+                    );
                     return Ok(IncomingPackets::zero());
                 }
 
                 return Ok(IncomingPackets::one(
                     Packet::new(
                         self.remote_address,
-                        packet_reader.read_payload(),
+                        decompress(info.payload().into())?,
                         header.delivery_guarantee(),
                         header.ordering_guarantee(),
-                    ),
+                    )
+                    .with_sent_at(sent_at),
                     header.packet_type(),
                 ));
             }
             DeliveryGuarantee::Reliable => {
                 if header.is_fragment() {
-                    if let Ok((fragment_header, acked_header)) = packet_reader.read_fragment() {
-                        let payload = packet_reader.read_payload();
-
+                    if let Some(fragment_header) = info.fragment_header() {
                         match self.fragmentation.handle_fragment(
                             fragment_header,
-                            &payload,
-                            acked_header,
+                            info.payload(),
+                            info.acked_header(),
+                            time,
                         ) {
                             Ok(Some((payload, acked_header))) => {
                                 self.congestion_handler
-                                    .process_incoming(acked_header.sequence());
+                                    .process_incoming(acked_header.ack_seq(), time);
                                 self.acknowledge_handler.process_incoming(
                                     acked_header.sequence(),
                                     acked_header.ack_seq(),
                                     acked_header.ack_field(),
                                 );
+                                self.acks_pending = true;
 
                                 return Ok(IncomingPackets::one(
                                     Packet::new(
                                         self.remote_address,
-                                        payload.into_boxed_slice(),
+                                        decompress(payload.into_boxed_slice())?,
                                         header.delivery_guarantee(),
                                         header.ordering_guarantee(),
-                                    ),
+                                    )
+                                    .with_sent_at(sent_at),
                                     PacketType::Packet, // change from Fragment to Packet type, it only matters when assembling/dissasembling packet header.
                                 ));
                             }
@@ -310,22 +806,41 @@ impl VirtualConnection {
                         };
                     }
                 } else {
-                    let acked_header = packet_reader.read_acknowledge_header()?;
+                    let acked_header = expect_header(info.acked_header(), "acknowledgment")?;
+                    let duplicate = self
+                        .acknowledge_handler
+                        .already_received(acked_header.sequence());
+                    if self
+                        .acknowledge_handler
+                        .arrived_out_of_order(acked_header.sequence())
+                    {
+                        self.stats.record_reordered();
+                    }
+                    trace!(
+                        "Received sequence {} from {}, acknowledging {}",
+                        acked_header.sequence(),
+                        self.remote_address,
+                        acked_header.ack_seq()
+                    );
 
                     self.congestion_handler
-                        .process_incoming(acked_header.sequence());
+                        .process_incoming(acked_header.ack_seq(), time);
                     self.acknowledge_handler.process_incoming(
                         acked_header.sequence(),
                         acked_header.ack_seq(),
                         acked_header.ack_field(),
                     );
+                    self.acks_pending = true;
+
+                    if let Some(selective_acks) = info.selective_ack_header() {
+                        self.acknowledge_handler
+                            .process_selective_acks(selective_acks.ranges());
+                    }
 
                     if let OrderingGuarantee::Sequenced(_) = header.ordering_guarantee() {
-                        let arranging_header = packet_reader.read_arranging_header(u16::from(
-                            STANDARD_HEADER_SIZE + ACKED_PACKET_HEADER,
-                        ))?;
+                        let arranging_header = expect_header(info.arranging_header(), "arranging")?;
 
-                        let payload = packet_reader.read_payload();
+                        let payload = decompress(info.payload().into())?;
 
                         let stream = self
                             .sequencing_system
@@ -342,30 +857,57 @@ impl VirtualConnection {
                                     OrderingGuarantee::Sequenced(Some(
                                         arranging_header.stream_id(),
                                     )),
-                                ),
+                                )
+                                .with_sent_at(sent_at),
                                 header.packet_type(),
                             ));
                         }
+                        debug!(
+                            "Dropping stale sequenced packet {} from {}",
+                            arranging_header.arranging_id(),
+                            self.remote_address
+                        );
                     } else if let OrderingGuarantee::Ordered(_id) = header.ordering_guarantee() {
-                        let arranging_header = packet_reader.read_arranging_header(u16::from(
-                            STANDARD_HEADER_SIZE + ACKED_PACKET_HEADER,
-                        ))?;
+                        let arranging_header = expect_header(info.arranging_header(), "arranging")?;
 
-                        let payload = packet_reader.read_payload();
+                        let payload = decompress(info.payload().into())?;
 
                         let stream = self
                             .ordering_system
                             .get_or_create_stream(arranging_header.stream_id());
                         let address = self.remote_address;
+                        let arranged = stream.arrange(
+                            arranging_header.arranging_id(),
+                            (payload, header.packet_type(), sent_at),
+                        );
+                        if arranged.is_none() {
+                            debug!(
+                                "Ordered packet {} from {} is not the next one of its stream",
+                                arranging_header.arranging_id(),
+                                address
+                            );
+                        }
+                        let mut packets: Vec<_> =
+                            arranged.into_iter().chain(stream.iter_mut()).collect();
+                        // a lost packet does not hold back the stream forever.
+                        if let Some(window) = self.config.max_reorder_window {
+                            let skipped = stream.skip_stalled(usize::from(window));
+                            if skipped > 0 {
+                                debug!(
+                                    "Skipping {} missing ordered packets from {}",
+                                    skipped, address
+                                );
+                                self.skipped_sequences.extend(std::iter::repeat_n(
+                                    arranging_header.stream_id(),
+                                    usize::from(skipped),
+                                ));
+                                packets.extend(stream.iter_mut());
+                            }
+                        }
                         return Ok(IncomingPackets::many(
-                            stream
-                                .arrange(
-                                    arranging_header.arranging_id(),
-                                    (payload, header.packet_type()),
-                                )
+                            packets
                                 .into_iter()
-                                .chain(stream.iter_mut())
-                                .map(|(packet, packet_type)| {
+                                .map(|(packet, packet_type, sent_at)| {
                                     (
                                         Packet::new(
                                             address,
@@ -374,21 +916,34 @@ impl VirtualConnection {
                                             OrderingGuarantee::Ordered(Some(
                                                 arranging_header.stream_id(),
                                             )),
-                                        ),
+                                        )
+                                        .with_sent_at(sent_at),
                                         packet_type,
                                     )
                                 })
                                 .collect(),
                         ));
                     } else {
-                        let payload = packet_reader.read_payload();
+                        // arranged packets are deduplicated by their stream, unordered ones by their sequence number.
+                        // the acknowledgments of a duplicate are still processed, but it is delivered only once.
+                        if duplicate {
+                            debug!(
+                                "Dropping duplicate packet {} from {}",
+                                acked_header.sequence(),
+                                self.remote_address
+                            );
+                            return Ok(IncomingPackets::zero());
+                        }
+
+                        let payload = decompress(info.payload().into())?;
                         return Ok(IncomingPackets::one(
                             Packet::new(
                                 self.remote_address,
                                 payload,
                                 header.delivery_guarantee(),
                                 header.ordering_guarantee(),
-                            ),
+                            )
+                            .with_sent_at(sent_at),
                             header.packet_type(),
                         ));
                     }
@@ -398,36 +953,359 @@ impl VirtualConnection {
         Ok(IncomingPackets::zero())
     }
 
+    // Moves the handshake forward: a request is challenged, the challenge is answered and a correct answer is accepted.
+    // Peers that do not require a handshake accept requests directly.
+    fn process_handshake(&mut self, packet_type: PacketType, payload: &[u8]) -> Result<()> {
+        let connecting = self.state == ConnectionState::Connecting;
+        match packet_type {
+            PacketType::Connect if connecting => {
+                let token = *self.challenge.get_or_insert_with(rand::random);
+                self.handshake_reply = Some(HandshakeReply::Challenge(token));
+            }
+            PacketType::Challenge if connecting => {
+                let token = read_token(payload)?;
+                self.session = Some(token);
+                self.handshake_reply = Some(HandshakeReply::Response(token));
+            }
+            PacketType::Response if connecting => {
+                // a wrong token is ignored, the remote host keeps asking until it answers correctly.
+                let answered = self.challenge == Some(read_token(payload)?);
+                if answered {
+                    self.session = self.challenge;
+                    self.state = ConnectionState::Connected;
+                    self.handshake_reply = Some(HandshakeReply::Accept);
+                }
+            }
+            PacketType::Accept if connecting => {
+                self.state = ConnectionState::Connected;
+            }
+            // the accept got lost if the remote host still asks for the connection.
+            PacketType::Connect | PacketType::Response => {
+                self.handshake_reply = Some(HandshakeReply::Accept);
+            }
+            // the remote host challenges the address this side resumes the connection from.
+            PacketType::Challenge if self.resuming => {
+                self.handshake_reply = Some(HandshakeReply::ResumeResponse(read_token(payload)?));
+            }
+            // the remote host moved the connection, the token it sends replaces the one that was used.
+            PacketType::Accept if self.resuming && !payload.is_empty() => {
+                self.session = Some(read_token(payload)?);
+                self.resuming = false;
+            }
+            // the connection was resumed from this address already, the accept got lost if the remote host asks again.
+            PacketType::Resume if !connecting => {
+                let (session, _) = read_resume(payload)?;
+                if self.session == Some(session) || self.previous_session == Some(session) {
+                    self.handshake_reply = self.session.map(HandshakeReply::ResumeAccept);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Discards fragmented packets that can not be completed anymore and counts them as dropped,
+    /// see `Config::fragment_timeout`.
+    pub fn discard_expired_fragments(&mut self, time: Instant) {
+        let discarded = self.fragmentation.discard_expired(time);
+        if discarded > 0 {
+            debug!(
+                "Discarding {} incomplete packets from {}",
+                discarded, self.remote_address
+            );
+            self.stats.record_dropped(discarded);
+        }
+    }
+
+    /// Reads the standard header of a datagram that was sent from an address without a connection,
+    /// so that junk is rejected before a connection is created for it.
+    ///
+    /// Also returns the request to resume a connection from the address, if it is a resume packet,
+    /// see `Socket::resume`. The datagram is decrypted once for both.
+    pub fn read_new_datagram(
+        config: &Config,
+        datagram: &[u8],
+    ) -> Result<(StandardHeader, Option<ResumeRequest>)> {
+        let datagram = open_datagram(config, datagram)?;
+        let mut packet_reader = PacketReader::new(&datagram);
+        let header = packet_reader.read_standard_header()?;
+
+        // only connections that completed a handshake have a token.
+        let request = if config.require_handshake
+            && header.is_current_protocol()
+            && header.packet_type() == PacketType::Resume
+        {
+            read_resume(packet_reader.payload())
+                .ok()
+                .map(|(session, challenge)| ResumeRequest { session, challenge })
+        } else {
+            None
+        };
+        Ok((header, request))
+    }
+
+    /// Returns true if the remote host asked for a connection and was challenged, but did not answer yet.
+    pub fn is_challenged(&self) -> bool {
+        self.state == ConnectionState::Connecting && self.challenge.is_some()
+    }
+
+    /// Records how often the reliable packet that was processed last has been resent already.
+    pub fn record_resends(&mut self, resends: u16) {
+        if let Some(sent_packet) = self.acknowledge_handler.last_sent_mut() {
+            sent_packet.resends = resends;
+        }
+    }
+
+    /// Records the user supplied id of the reliable packet that was processed last.
+    pub fn record_id(&mut self, id: Option<u64>) {
+        if let Some(sent_packet) = self.acknowledge_handler.last_sent_mut() {
+            sent_packet.id = id;
+        }
+    }
+
+    /// Returns the ids of the packets that were acknowledged by the remote host since this was called last.
+    pub fn take_acknowledged(&mut self) -> Vec<u64> {
+        self.acknowledge_handler.take_acknowledged()
+    }
+
+    /// Returns the ordering streams on which packets were skipped since this was called last, see `Config::max_reorder_window`.
+    pub fn take_skipped_sequences(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.skipped_sequences)
+    }
+
+    /// Starts the next epoch of the connection, see `Socket::reset`.
+    ///
+    /// The sequence numbers, acknowledgments and arranging streams start over, unacknowledged packets are not
+    /// resent anymore. Packets of the previous epoch are dropped, the remote host resets its side as well
+    /// once it receives a packet of the new epoch.
+    pub fn start_new_epoch(&mut self) {
+        self.epoch = self.epoch.wrapping_add(1) & MAX_EPOCH;
+        self.reset_protocol_state();
+    }
+
+    /// Starts a new connection in the epoch after the given one, which a removed connection with the same
+    /// address was in. Late packets of that connection are then ignored, like those from before a reset.
+    pub fn continue_after_epoch(&mut self, epoch: u8) {
+        self.epoch = epoch.wrapping_add(1) & MAX_EPOCH;
+    }
+
+    /// Returns the epoch of the connection, it is increased with every reset.
+    pub fn epoch(&self) -> u8 {
+        self.epoch
+    }
+
+    // Appends the send time to packets with user data, see `Config::packet_timestamps`.
+    fn with_timestamp<'a>(
+        &self,
+        builder: OutgoingPacketBuilder<'a>,
+        packet_type: PacketType,
+        time: Instant,
+    ) -> OutgoingPacketBuilder<'a> {
+        match packet_type {
+            PacketType::Packet | PacketType::Fragment | PacketType::Batch
+                if self.config.packet_timestamps =>
+            {
+                // the milliseconds wrap around after about 49 days.
+                let timestamp = time.saturating_duration_since(self.created).as_millis() as u32;
+                builder.with_timestamp(timestamp)
+            }
+            _ => builder,
+        }
+    }
+
+    // Adds the ranges of late packets after the acknowledgment header, see `Config::selective_acks`.
+    fn with_selective_acks<'a>(
+        &self,
+        builder: OutgoingPacketBuilder<'a>,
+    ) -> OutgoingPacketBuilder<'a> {
+        if !self.config.selective_acks {
+            return builder;
+        }
+        let ranges = self.acknowledge_handler.selective_acks();
+        if ranges.is_empty() {
+            builder
+        } else {
+            builder.with_selective_ack_header(SelectiveAckHeader::new(ranges))
+        }
+    }
+
+    fn reset_protocol_state(&mut self) {
+        self.acknowledge_handler = AcknowledgmentHandler::new(
+            self.config.ack_field_width,
+            rand::random::<u16>() >> 1,
+            self.config.max_packets_in_flight,
+        );
+        self.ordering_system = OrderingSystem::new();
+        self.sequencing_system = SequencingSystem::new();
+        self.fragmentation = Fragmentation::new(&self.config);
+        self.acks_pending = false;
+        self.nacked_sequences.clear();
+    }
+
     /// Gathers dropped packets from the acknowledgment handler.
     ///
     /// Note that after requesting dropped packets the dropped packets will be removed from this client.
     pub fn gather_dropped_packets(&mut self) -> Vec<SentPacket> {
-        self.acknowledge_handler.dropped_packets()
+        let dropped = self.acknowledge_handler.dropped_packets();
+        if !dropped.is_empty() {
+            debug!(
+                "{} packets to {} were not acknowledged in time",
+                dropped.len(),
+                self.remote_address
+            );
+        }
+        self.stats.record_dropped(dropped.len());
+        dropped
+    }
+
+    /// Returns the payload of a negative acknowledgment for the packets that were found missing since this was
+    /// called last, if there are any and `Config::negative_acks` is set.
+    pub fn negative_ack_payload(&mut self) -> Option<Vec<u8>> {
+        let missing = self.acknowledge_handler.take_missing();
+        if !self.config.negative_acks || missing.is_empty() {
+            return None;
+        }
+        Some(
+            missing
+                .iter()
+                .flat_map(|sequence| sequence.to_be_bytes())
+                .collect(),
+        )
+    }
+
+    /// Gathers the packets the remote endpoint reported missing since this was called last, they count as dropped.
+    pub fn gather_nacked_packets(&mut self) -> Vec<SentPacket> {
+        let sequences = std::mem::take(&mut self.nacked_sequences);
+        let nacked = self.acknowledge_handler.take_missing_packets(&sequences);
+        if !nacked.is_empty() {
+            debug!(
+                "{} packets to {} were reported missing",
+                nacked.len(),
+                self.remote_address
+            );
+        }
+        self.stats.record_dropped(nacked.len());
+        nacked
     }
 }
 
-impl fmt::Debug for VirtualConnection {
+impl<P> fmt::Debug for VirtualConnection<P> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{}:{}",
-            self.remote_address.ip(),
-            self.remote_address.port()
-        )
+        f.debug_struct("VirtualConnection")
+            .field("remote_address", &self.remote_address)
+            .field("state", &self.state)
+            .field(
+                "local_sequence",
+                &self.acknowledge_handler.local_sequence_num(),
+            )
+            .field(
+                "remote_sequence",
+                &self.acknowledge_handler.remote_sequence_num(),
+            )
+            .field(
+                "ack_field",
+                &format_args!("{:#b}", self.acknowledge_handler.ack_bitfield()),
+            )
+            .field("packets_in_flight", &self.packets_in_flight())
+            .field("queued_packets", &self.queued_packets.len())
+            .field("rate_limited_packets", &self.rate_limited_packets.len())
+            .field("rtt", &self.rtt())
+            .field("last_heard", &self.last_heard)
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+// Returns a header that `HeaderInfo::read` reads for the type and guarantees of the packet.
+fn expect_header<T>(header: Option<T>, name: &str) -> Result<T> {
+    header.ok_or_else(|| ErrorKind::CouldNotReadHeader(String::from(name)))
+}
+
+// Verifies the checksum of a datagram that is not read by a connection, and decrypts it with the key of the config.
+#[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+fn open_datagram<'a>(config: &Config, datagram: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+    if !is_encrypted(config) {
+        ProtocolVersion::verify_datagram(datagram)?;
+    }
+    let datagram = Checksum::verify(datagram)?;
+    #[cfg(feature = "encryption")]
+    {
+        if let Some(key) = config.encryption_key.as_ref() {
+            return Encryption::new(key).decrypt(datagram).map(Cow::Owned);
+        }
+    }
+    Ok(Cow::Borrowed(datagram))
+}
+
+// Whether the datagrams of connections with this config are encrypted, their protocol version can only be read after decrypting.
+#[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+fn is_encrypted(config: &Config) -> bool {
+    #[cfg(feature = "encryption")]
+    {
+        if config.encryption_key.is_some() {
+            return true;
+        }
     }
+    false
+}
+
+// Reads the token of a resume packet, followed by the token of the challenge it answers if it is the answer to one.
+fn read_resume(payload: &[u8]) -> Result<(u64, Option<u64>)> {
+    if payload.len() == 16 {
+        let (session, challenge) = payload.split_at(8);
+        Ok((read_token(session)?, Some(read_token(challenge)?)))
+    } else {
+        Ok((read_token(payload)?, None))
+    }
+}
+
+// Reads the timestamp of the ping a pong echoes, and the timestamp of the remote endpoint when it answered.
+fn read_pong(payload: &[u8]) -> Result<(u64, u64)> {
+    if payload.len() != 16 {
+        return Err(ErrorKind::ReceivedDataToShort);
+    }
+    let (timestamp, remote_timestamp) = payload.split_at(8);
+    Ok((read_token(timestamp)?, read_token(remote_timestamp)?))
+}
+
+// Reads the sequence numbers of a negative acknowledgment, a remote host never reports more than `MAX_NEGATIVE_ACKS`.
+fn read_sequences(payload: &[u8]) -> Result<Vec<SequenceNumber>> {
+    if !payload.len().is_multiple_of(2) || payload.len() > usize::from(MAX_NEGATIVE_ACKS) * 2 {
+        return Err(ErrorKind::DecodingError(DecodingErrorKind::NegativeAck));
+    }
+    Ok(payload
+        .chunks(2)
+        .map(|sequence| u16::from_be_bytes([sequence[0], sequence[1]]))
+        .collect())
+}
+
+// Reads the token of a challenge or response packet, or the timestamp of a ping or pong packet.
+fn read_token(payload: &[u8]) -> Result<u64> {
+    let mut token = [0; 8];
+    if payload.len() != token.len() {
+        return Err(ErrorKind::ReceivedDataToShort);
+    }
+    token.copy_from_slice(payload);
+    Ok(u64::from_be_bytes(token))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::cmp::Reverse;
+    use std::collections::HashSet;
     use std::io::Write;
     use std::time::{Duration, Instant};
 
     use byteorder::{BigEndian, WriteBytesExt};
 
     use crate::config::Config;
-    use crate::net::constants;
+    use crate::error::ErrorKind;
+    use crate::net::{constants, ConnectionStats};
     use crate::packet::header::{AckedPacketHeader, ArrangingHeader, HeaderWriter, StandardHeader};
-    use crate::packet::{DeliveryGuarantee, OrderingGuarantee, Packet, PacketInfo, PacketType};
+    use crate::packet::{
+        AckFieldWidth, Compression, DeliveryGuarantee, HeaderInfo, OrderingGuarantee,
+        OutgoingPacketBuilder, Packet, PacketInfo, PacketType,
+    };
     use crate::protocol_version::ProtocolVersion;
 
     use super::VirtualConnection;
@@ -464,7 +1342,7 @@ mod tests {
             connection.last_heard.duration_since(curr_heard),
             Duration::from_secs(2)
         );
-        assert_eq!(in_packet.is_none(), true);
+        assert!(in_packet.is_none());
     }
 
     #[test]
@@ -481,12 +1359,22 @@ mod tests {
         let second_fragment = vec![0, 0, 2, 4];
         let third_fragment = vec![0, 0, 3, 4];
 
-        let mut connection = create_virtual_connection();
+        // every fragment but the last one has to be full.
+        let config = Config {
+            fragment_size: PAYLOAD.len() as u16,
+            ..Default::default()
+        };
+        let mut connection: VirtualConnection =
+            VirtualConnection::new(get_fake_addr(), &config, Instant::now());
         let packet = connection
             .process_incoming(
-                [standard_header.as_slice(), acked_header.as_slice()]
-                    .concat()
-                    .as_slice(),
+                [
+                    standard_header.as_slice(),
+                    acked_header.as_slice(),
+                    &PAYLOAD,
+                ]
+                .concat()
+                .as_slice(),
                 Instant::now(),
             )
             .unwrap()
@@ -540,7 +1428,9 @@ mod tests {
             .unwrap();
         assert_eq!(
             packets.payload(),
-            &*[PAYLOAD, PAYLOAD, PAYLOAD].concat().into_boxed_slice()
+            &*[PAYLOAD, PAYLOAD, PAYLOAD, PAYLOAD]
+                .concat()
+                .into_boxed_slice()
         );
     }
 
@@ -683,20 +1573,95 @@ mod tests {
     }
 
     #[test]
-    fn assure_right_ordering() {
+    fn stale_sequenced_packet_is_dropped_but_acknowledged() {
         let mut connection = create_virtual_connection();
 
-        assert_incoming_with_order(
-            DeliveryGuarantee::Reliable,
-            OrderingGuarantee::Ordered(Some(1)),
-            &mut connection,
-            Some(Packet::reliable_ordered(
-                get_fake_addr(),
-                PAYLOAD.to_vec(),
-                Some(1),
-            )),
-            0,
-        );
+        let mut receive = |seq: u16, arranging_id: u16| {
+            let packet = OutgoingPacketBuilder::new(&PAYLOAD)
+                .with_default_header(
+                    PacketType::Packet,
+                    DeliveryGuarantee::Reliable,
+                    OrderingGuarantee::Sequenced(Some(1)),
+                    Compression::None,
+                    AckFieldWidth::Bits32,
+                    0,
+                )
+                .with_acknowledgment_header(seq, 0, 0, AckFieldWidth::Bits32)
+                .with_sequencing_header(arranging_id, Some(1))
+                .build();
+
+            connection
+                .process_incoming(&packet.contents(), Instant::now())
+                .unwrap()
+                .into_iter()
+                .next()
+                .is_some()
+        };
+
+        assert!(receive(0, 5));
+        assert!(!receive(1, 3));
+        assert!(receive(2, 6));
+
+        // all three packets are acknowledged, including the stale one.
+        assert_eq!(connection.acknowledge_handler.remote_sequence_num(), 2);
+        assert_eq!(connection.acknowledge_handler.ack_bitfield() & 0b11, 0b11);
+    }
+
+    #[test]
+    fn stalled_ordered_stream_is_released_past_a_lost_packet() {
+        let config = Config::builder().max_reorder_window(2).build().unwrap();
+        let mut connection: VirtualConnection =
+            VirtualConnection::new(get_fake_addr(), &config, Instant::now());
+
+        let mut receive = |arranging_id: u16| {
+            let payload = [arranging_id as u8];
+            let packet = OutgoingPacketBuilder::new(&payload)
+                .with_default_header(
+                    PacketType::Packet,
+                    DeliveryGuarantee::Reliable,
+                    OrderingGuarantee::Ordered(Some(1)),
+                    Compression::None,
+                    AckFieldWidth::Bits32,
+                    0,
+                )
+                .with_acknowledgment_header(arranging_id, 0, 0, AckFieldWidth::Bits32)
+                .with_ordering_header(arranging_id, Some(1))
+                .build();
+
+            connection
+                .process_incoming(&packet.contents(), Instant::now())
+                .unwrap()
+                .into_iter()
+                .map(|(packet, _)| packet.payload()[0])
+                .collect::<Vec<_>>()
+        };
+
+        // packet 0 is lost, the window holds two packets that wait for it.
+        assert!(receive(1).is_empty());
+        assert!(receive(2).is_empty());
+        assert_eq!(receive(3), vec![1, 2, 3]);
+        assert_eq!(receive(4), vec![4]);
+        // the skipped packet is dropped when it is resent after all.
+        assert!(receive(0).is_empty());
+
+        assert_eq!(connection.take_skipped_sequences(), vec![1]);
+    }
+
+    #[test]
+    fn assure_right_ordering() {
+        let mut connection = create_virtual_connection();
+
+        assert_incoming_with_order(
+            DeliveryGuarantee::Reliable,
+            OrderingGuarantee::Ordered(Some(1)),
+            &mut connection,
+            Some(Packet::reliable_ordered(
+                get_fake_addr(),
+                PAYLOAD.to_vec(),
+                Some(1),
+            )),
+            0,
+        );
 
         assert_incoming_with_order(
             DeliveryGuarantee::Reliable,
@@ -794,6 +1759,599 @@ mod tests {
         );
     }
 
+    #[test]
+    fn heartbeats_piggyback_acknowledgments() {
+        let mut client = create_virtual_connection();
+        let mut server = create_virtual_connection();
+        let time = Instant::now();
+
+        let reliable = client
+            .process_outgoing(
+                PacketInfo::user_packet(
+                    &PAYLOAD,
+                    DeliveryGuarantee::Reliable,
+                    OrderingGuarantee::None,
+                ),
+                None,
+                time,
+            )
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap();
+        server.process_incoming(&reliable.contents(), time).unwrap();
+        assert_eq!(client.packets_in_flight(), 1);
+
+        // the server has nothing to send, so it only sends a heartbeat.
+        let heartbeat = server
+            .process_outgoing(PacketInfo::heartbeat_packet(&[]), None, time)
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap();
+        assert_eq!(server.packets_in_flight(), 0);
+
+        let packets = client
+            .process_incoming(&heartbeat.contents(), time + Duration::from_millis(10))
+            .unwrap();
+
+        assert!(packets.into_iter().next().is_none());
+        assert_eq!(client.packets_in_flight(), 0);
+        assert_eq!(client.rtt(), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn stats_count_sent_received_and_dropped_packets() {
+        let mut connection = create_virtual_connection();
+        let time = Instant::now();
+        let first = connection.acknowledge_handler.local_sequence_num();
+
+        for _ in 0..34 {
+            connection
+                .process_outgoing(
+                    PacketInfo::user_packet(
+                        &PAYLOAD,
+                        DeliveryGuarantee::Reliable,
+                        OrderingGuarantee::None,
+                    ),
+                    None,
+                    time,
+                )
+                .unwrap();
+        }
+
+        // the remote endpoint only acknowledges our last packet, our first packet is out of the ack window.
+        let mut packet = Vec::new();
+        StandardHeader::new(
+            DeliveryGuarantee::Reliable,
+            OrderingGuarantee::None,
+            PacketType::Packet,
+        )
+        .parse(&mut packet)
+        .unwrap();
+        AckedPacketHeader::new(0, first.wrapping_add(33), 0)
+            .parse(&mut packet)
+            .unwrap();
+        packet.write_all(&PAYLOAD).unwrap();
+        connection.process_incoming(&packet, time).unwrap();
+
+        assert_eq!(connection.gather_dropped_packets().len(), 1);
+        assert_eq!(
+            connection.stats(),
+            ConnectionStats {
+                packets_sent: 34,
+                packets_received: 1,
+                packets_dropped: 1,
+                packets_reordered: 0,
+                spurious_retransmits: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn duplicate_packet_is_delivered_once() {
+        let mut connection = create_virtual_connection();
+
+        let packet = OutgoingPacketBuilder::new(&PAYLOAD)
+            .with_default_header(
+                PacketType::Packet,
+                DeliveryGuarantee::Reliable,
+                OrderingGuarantee::None,
+                Compression::None,
+                AckFieldWidth::Bits32,
+                0,
+            )
+            .with_acknowledgment_header(5, 0, 0, AckFieldWidth::Bits32)
+            .build()
+            .contents();
+
+        let received = |connection: &mut VirtualConnection| {
+            connection
+                .process_incoming(&packet, Instant::now())
+                .unwrap()
+                .into_iter()
+                .count()
+        };
+
+        assert_eq!(received(&mut connection), 1);
+        assert_eq!(received(&mut connection), 0);
+        // the duplicate is still acknowledged
+        assert_eq!(connection.acknowledge_handler.remote_sequence_num(), 5);
+    }
+
+    #[test]
+    fn reliable_unordered_packets_are_delivered_on_arrival_and_resent() {
+        let time = Instant::now();
+        let send = |connection: &mut VirtualConnection, payload: &[u8]| {
+            connection
+                .process_outgoing(
+                    PacketInfo::user_packet(
+                        payload,
+                        DeliveryGuarantee::Reliable,
+                        OrderingGuarantee::None,
+                    ),
+                    None,
+                    time,
+                )
+                .unwrap()
+                .into_iter()
+                .next()
+                .unwrap()
+                .contents()
+                .into_vec()
+        };
+        let received = |connection: &mut VirtualConnection, packet: &[u8]| -> Vec<Vec<u8>> {
+            connection
+                .process_incoming(packet, time)
+                .unwrap()
+                .into_iter()
+                .map(|(packet, _)| packet.payload().to_vec())
+                .collect()
+        };
+
+        let mut sender = create_virtual_connection();
+        let mut receiver = create_virtual_connection();
+        // just enough packets that the first one is older than the acknowledgment bitfield.
+        let packets: Vec<_> = (0..34).map(|i| send(&mut sender, &[i])).collect();
+
+        // the first packet is lost, of the others every pair arrives swapped and is delivered right away.
+        for pair in packets[1..].chunks(2) {
+            for packet in pair.iter().rev() {
+                assert_eq!(received(&mut receiver, packet).len(), 1);
+            }
+        }
+
+        // the acknowledgments of the receiver show that the first packet is missing.
+        let reply = send(&mut receiver, &[]);
+        received(&mut sender, &reply);
+        let dropped = sender.gather_dropped_packets();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(&dropped[0].payload[..], &[0]);
+
+        let resent = send(&mut sender, &dropped[0].payload);
+        assert_eq!(received(&mut receiver, &resent), vec![vec![0]]);
+        assert!(received(&mut receiver, &resent).is_empty());
+    }
+
+    #[test]
+    fn packets_arriving_after_newer_ones_are_counted_as_reordered() {
+        let time = Instant::now();
+        let mut sender = create_virtual_connection();
+        let mut receiver = create_virtual_connection();
+        let packets: Vec<_> = (0..6)
+            .map(|i| {
+                sender
+                    .process_outgoing(
+                        PacketInfo::user_packet(
+                            &[i],
+                            DeliveryGuarantee::Reliable,
+                            OrderingGuarantee::None,
+                        ),
+                        None,
+                        time,
+                    )
+                    .unwrap()
+                    .into_iter()
+                    .next()
+                    .unwrap()
+                    .contents()
+                    .into_vec()
+            })
+            .collect();
+
+        // 1 and 4 arrive late, the duplicates of 4 and 2 are not counted.
+        for &i in &[0, 2, 1, 3, 5, 4, 4, 2] {
+            receiver.process_incoming(&packets[i], time).unwrap();
+        }
+
+        assert_eq!(receiver.stats().packets_received, 8);
+        assert_eq!(receiver.stats().packets_reordered, 2);
+    }
+
+    #[test]
+    fn ack_field_width_must_match() {
+        let wide = Config {
+            ack_field_width: AckFieldWidth::Bits64,
+            ..Default::default()
+        };
+        let time = Instant::now();
+        let mut client: VirtualConnection = VirtualConnection::new(get_fake_addr(), &wide, time);
+        let mut server: VirtualConnection = VirtualConnection::new(get_fake_addr(), &wide, time);
+        let mut narrow_server = create_virtual_connection();
+
+        let packet = client
+            .process_outgoing(
+                PacketInfo::user_packet(
+                    &PAYLOAD,
+                    DeliveryGuarantee::Reliable,
+                    OrderingGuarantee::Ordered(None),
+                ),
+                None,
+                time,
+            )
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap()
+            .contents();
+
+        let received: Vec<_> = server
+            .process_incoming(&packet, time)
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].0.payload(), PAYLOAD);
+
+        match narrow_server.process_incoming(&packet, time) {
+            Err(ErrorKind::AckFieldWidthMismatch { expected, got }) => {
+                assert_eq!(expected, AckFieldWidth::Bits32);
+                assert_eq!(got, AckFieldWidth::Bits64);
+            }
+            _ => panic!["Supposed to get an ack field width mismatch"],
+        }
+    }
+
+    #[test]
+    fn timestamps_round_trip_with_user_packets() {
+        let timestamps = Config {
+            packet_timestamps: true,
+            ..Default::default()
+        };
+        let time = Instant::now();
+        let mut client: VirtualConnection =
+            VirtualConnection::new(get_fake_addr(), &timestamps, time);
+        let mut plain_client = create_virtual_connection();
+        let mut server = create_virtual_connection();
+        let sent_at = time + Duration::from_millis(1234);
+
+        for (delivery, ordering) in [
+            (DeliveryGuarantee::Unreliable, OrderingGuarantee::None),
+            (
+                DeliveryGuarantee::Reliable,
+                OrderingGuarantee::Ordered(None),
+            ),
+        ] {
+            let packet = client
+                .process_outgoing(
+                    PacketInfo::user_packet(&PAYLOAD, delivery, ordering),
+                    None,
+                    sent_at,
+                )
+                .unwrap()
+                .into_iter()
+                .next()
+                .unwrap()
+                .contents();
+            let plain_packet = plain_client
+                .process_outgoing(
+                    PacketInfo::user_packet(&PAYLOAD, delivery, ordering),
+                    None,
+                    sent_at,
+                )
+                .unwrap()
+                .into_iter()
+                .next()
+                .unwrap()
+                .contents();
+            assert_eq!(
+                packet.len(),
+                plain_packet.len() + usize::from(constants::TIMESTAMP_SIZE)
+            );
+
+            // the receiver does not need the option to read the timestamp.
+            let received: Vec<_> = server
+                .process_incoming(&packet, time)
+                .unwrap()
+                .into_iter()
+                .collect();
+            assert_eq!(received.len(), 1);
+            assert_eq!(received[0].0.payload(), PAYLOAD);
+            assert_eq!(received[0].0.sent_at(), Some(1234));
+        }
+
+        // heartbeats carry no user data, so they are not timestamped.
+        let heartbeat = client
+            .process_outgoing(PacketInfo::heartbeat_packet(&[]), None, sent_at)
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap()
+            .contents();
+        assert_eq!(heartbeat[4] & 0x40, 0);
+    }
+
+    #[test]
+    fn new_connections_start_at_random_sequence_numbers() {
+        let sequences: HashSet<u16> = (0..8)
+            .map(|_| {
+                create_virtual_connection()
+                    .acknowledge_handler
+                    .local_sequence_num()
+            })
+            .collect();
+
+        // eight connections starting at the same sequence would happen once in 2^105 tries.
+        assert!(sequences.len() > 1);
+        assert!(sequences
+            .iter()
+            .all(|sequence| *sequence <= i16::MAX as u16));
+    }
+
+    #[test]
+    fn debug_output_shows_the_connection_state() {
+        let connection = create_virtual_connection();
+        let debug = format!("{:?}", connection);
+
+        assert!(
+            debug.starts_with("VirtualConnection { remote_address: 127.0.0.1:0, state: Connected")
+        );
+        assert!(debug.contains(&format!(
+            "local_sequence: {}",
+            connection.acknowledge_handler.local_sequence_num()
+        )));
+        assert!(debug.contains("packets_in_flight: 0"));
+    }
+
+    #[test]
+    fn packets_of_an_earlier_epoch_are_dropped_after_a_reset() {
+        let mut sender = create_virtual_connection();
+        let mut receiver = create_virtual_connection();
+        let time = Instant::now();
+
+        let send = |connection: &mut VirtualConnection, payload: &[u8]| {
+            connection
+                .process_outgoing(
+                    PacketInfo::user_packet(
+                        payload,
+                        DeliveryGuarantee::Reliable,
+                        OrderingGuarantee::None,
+                    ),
+                    None,
+                    time,
+                )
+                .unwrap()
+                .into_iter()
+                .next()
+                .unwrap()
+                .contents()
+                .into_vec()
+        };
+
+        let before_reset = send(&mut sender, &[1]);
+        sender.start_new_epoch();
+        let after_reset = send(&mut sender, &[2]);
+        assert_eq!(sender.packets_in_flight(), 1);
+
+        // the receiver starts the new epoch as well, once it gets a packet of it.
+        let received = receiver.process_incoming(&after_reset, time).unwrap();
+        assert_eq!(received.into_iter().count(), 1);
+        assert_eq!(receiver.epoch(), 1);
+
+        let received = receiver.process_incoming(&before_reset, time).unwrap();
+        assert_eq!(received.into_iter().count(), 0);
+        assert_eq!(receiver.epoch(), 1);
+    }
+
+    #[test]
+    fn late_packet_is_not_resent_with_selective_acks() {
+        let time = Instant::now();
+        let send = |connection: &mut VirtualConnection| {
+            connection
+                .process_outgoing(
+                    PacketInfo::user_packet(
+                        &PAYLOAD,
+                        DeliveryGuarantee::Reliable,
+                        OrderingGuarantee::Ordered(None),
+                    ),
+                    None,
+                    time,
+                )
+                .unwrap()
+                .into_iter()
+                .next()
+                .unwrap()
+                .contents()
+                .into_vec()
+        };
+
+        for selective_acks in [false, true].iter() {
+            let config = Config::builder()
+                .selective_acks(*selective_acks)
+                .build()
+                .unwrap();
+            let mut sender = create_virtual_connection();
+            let mut receiver: VirtualConnection =
+                VirtualConnection::new(get_fake_addr(), &config, time);
+
+            let packets: Vec<_> = (0..41).map(|_| send(&mut sender)).collect();
+            // the first packet arrives after all others, too late for the acknowledgment bitfield.
+            let mut delivered = 0;
+            for packet in packets[1..].iter().chain(packets[..1].iter()) {
+                delivered += receiver
+                    .process_incoming(packet, time)
+                    .unwrap()
+                    .into_iter()
+                    .count();
+            }
+            assert_eq!(delivered, 41);
+
+            // the arranging header of the reply follows its selective acknowledgments.
+            let reply = send(&mut receiver);
+            let received = sender.process_incoming(&reply, time).unwrap();
+            assert_eq!(received.into_iter().count(), 1);
+
+            let dropped = sender.gather_dropped_packets().len();
+            if *selective_acks {
+                assert_eq!(dropped, 0);
+                assert_eq!(sender.packets_in_flight(), 0);
+            } else {
+                // the packets before the bitfield are resent, although they arrived.
+                assert_eq!(dropped, 8);
+            }
+        }
+    }
+
+    #[test]
+    fn lost_acknowledgment_is_repeated_by_a_ping() {
+        let time = Instant::now();
+        let send = |connection: &mut VirtualConnection, packet: PacketInfo| {
+            connection
+                .process_outgoing(packet, None, time)
+                .unwrap()
+                .into_iter()
+                .next()
+                .unwrap()
+                .contents()
+                .into_vec()
+        };
+        let reliable = || {
+            PacketInfo::user_packet(
+                &PAYLOAD,
+                DeliveryGuarantee::Reliable,
+                OrderingGuarantee::None,
+            )
+        };
+
+        let mut sender = create_virtual_connection();
+        let mut receiver = create_virtual_connection();
+
+        let packet = send(&mut sender, reliable());
+        receiver.process_incoming(&packet, time).unwrap();
+
+        // the reply that acknowledges the packet gets lost, the next ping carries the acknowledgment again.
+        let _lost = send(&mut receiver, reliable());
+        let timestamp = receiver.ping_timestamp(time).to_be_bytes();
+        let ping = send(&mut receiver, PacketInfo::ping_packet(&timestamp));
+
+        assert_eq!(sender.packets_in_flight(), 1);
+        sender.process_incoming(&ping, time).unwrap();
+        assert_eq!(sender.packets_in_flight(), 0);
+        // the timestamp follows the acknowledgments.
+        assert_eq!(sender.pong_reply, Some(receiver.ping_timestamp(time)));
+    }
+
+    #[test]
+    fn rtt_is_measured_on_first_acknowledgment() {
+        let mut connection = create_virtual_connection();
+        let time = Instant::now();
+        let first = connection.acknowledge_handler.local_sequence_num();
+
+        connection
+            .process_outgoing(
+                PacketInfo::user_packet(
+                    &PAYLOAD,
+                    DeliveryGuarantee::Reliable,
+                    OrderingGuarantee::None,
+                ),
+                None,
+                time,
+            )
+            .unwrap();
+
+        // the remote endpoint acknowledges our first packet.
+        let mut packet = Vec::new();
+        StandardHeader::new(
+            DeliveryGuarantee::Reliable,
+            OrderingGuarantee::None,
+            PacketType::Packet,
+        )
+        .parse(&mut packet)
+        .unwrap();
+        AckedPacketHeader::new(0, first, 0)
+            .parse(&mut packet)
+            .unwrap();
+        packet.write_all(&PAYLOAD).unwrap();
+
+        connection
+            .process_incoming(&packet, time + Duration::from_millis(50))
+            .unwrap();
+        assert_eq!(connection.rtt(), Duration::from_millis(50));
+
+        // the same acknowledgment arrives again, this should not count as a new sample.
+        connection
+            .process_incoming(&packet, time + Duration::from_millis(500))
+            .unwrap();
+        assert_eq!(connection.rtt(), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn reject_other_protocol_version_without_touching_state() {
+        let mut connection = create_virtual_connection();
+        let curr_heard = connection.last_heard;
+        let remote_sequence_num = connection.acknowledge_handler.remote_sequence_num();
+        let ack_bitfield = connection.acknowledge_handler.ack_bitfield();
+
+        let other_version = ProtocolVersion::get_crc16().wrapping_add(1);
+        let mut packet = Vec::new();
+        packet.write_u16::<BigEndian>(other_version).unwrap();
+        packet.write_all(&[0, 1, 0]).unwrap();
+        AckedPacketHeader::new(5, 0, 0).parse(&mut packet).unwrap();
+        packet.write_all(&PAYLOAD).unwrap();
+
+        let result =
+            connection.process_incoming(packet.as_slice(), curr_heard + Duration::from_secs(1));
+
+        match result {
+            Err(ErrorKind::ProtocolVersionMismatch { expected, got }) => {
+                assert_eq!(expected, ProtocolVersion::get_crc16());
+                assert_eq!(got, other_version);
+            }
+            _ => panic!["Supposed to get a protocol version mismatch"],
+        }
+        assert_eq!(connection.last_heard, curr_heard);
+        assert_eq!(
+            connection.acknowledge_handler.remote_sequence_num(),
+            remote_sequence_num
+        );
+        assert_eq!(connection.acknowledge_handler.ack_bitfield(), ack_bitfield);
+    }
+
+    #[test]
+    fn reject_datagrams_of_the_previous_protocol_version() {
+        let mut connection = create_virtual_connection();
+
+        // peers of the previous version send the crc16 of its version string, and no checksum.
+        let previous_version = crc::crc16::checksum_x25(b"laminar-0.1.0");
+        let mut packet = Vec::new();
+        packet.write_u16::<BigEndian>(previous_version).unwrap();
+        packet.write_all(&[0, 1, 0]).unwrap();
+        AckedPacketHeader::new(5, 0, 0).parse(&mut packet).unwrap();
+        packet.write_all(&PAYLOAD).unwrap();
+
+        match connection.open(&packet) {
+            Err(ErrorKind::ProtocolVersionMismatch { expected, got }) => {
+                assert_eq!(expected, ProtocolVersion::get_crc16());
+                assert_eq!(got, previous_version);
+            }
+            _ => panic!["Supposed to get a protocol version mismatch"],
+        }
+        match HeaderInfo::parse(&packet) {
+            Err(ErrorKind::ProtocolVersionMismatch { .. }) => {}
+            _ => panic!["Supposed to get a protocol version mismatch"],
+        }
+    }
+
     #[test]
     fn ensure_input_header_data_does_not_access_out_of_bounds() {
         let mut protocol_version = Vec::new();
@@ -825,6 +2383,52 @@ mod tests {
         }
     }
 
+    #[test]
+    fn pending_packets_age_with_the_clock() {
+        let time = Instant::now();
+        let mut connection: VirtualConnection =
+            VirtualConnection::new(get_fake_addr(), &Config::default(), time);
+        let packet = || {
+            PacketInfo::user_packet(
+                &PAYLOAD,
+                DeliveryGuarantee::Reliable,
+                OrderingGuarantee::None,
+            )
+        };
+
+        let first = connection.local_sequence();
+        connection.process_outgoing(packet(), None, time).unwrap();
+        connection
+            .process_outgoing(packet(), None, time + Duration::from_millis(30))
+            .unwrap();
+        connection
+            .process_outgoing(packet(), None, time + Duration::from_millis(50))
+            .unwrap();
+
+        let mut pending: Vec<_> = connection
+            .pending_packets(time + Duration::from_millis(100))
+            .collect();
+        pending.sort_by_key(|(_, age)| Reverse(*age));
+        assert_eq!(
+            pending,
+            vec![
+                (first, Duration::from_millis(100)),
+                (first.wrapping_add(1), Duration::from_millis(70)),
+                (first.wrapping_add(2), Duration::from_millis(50)),
+            ]
+        );
+
+        let mut later: Vec<_> = connection
+            .pending_packets(time + Duration::from_millis(150))
+            .collect();
+        later.sort_by_key(|(_, age)| Reverse(*age));
+        assert!(later
+            .iter()
+            .zip(pending.iter())
+            .all(|(later, pending)| later.0 == pending.0
+                && later.1 == pending.1 + Duration::from_millis(50)));
+    }
+
     /// ======= helper functions =========
     fn create_virtual_connection() -> VirtualConnection {
         VirtualConnection::new(get_fake_addr(), &Config::default(), Instant::now())
@@ -956,7 +2560,7 @@ mod tests {
             Instant::now(),
         );
 
-        assert_eq!(res.is_err(), true);
+        assert!(res.is_err());
     }
 
     #[test]
@@ -982,7 +2586,7 @@ mod tests {
             packet.contents().len(),
             1024 + constants::STANDARD_HEADER_SIZE as usize
         );
-        assert_eq!(packets.next().is_none(), true);
+        assert!(packets.next().is_none());
     }
 
     #[test]
@@ -1,14 +1,27 @@
 //! This module provides all the logic around the packet, such as reading, parsing, and constructing headers.
 
-pub use self::enums::{DeliveryGuarantee, OrderingGuarantee, PacketType};
+pub use self::batch::MessageBatch;
+pub use self::checksum::Checksum;
+pub use self::compression::Compression;
+#[cfg(feature = "encryption")]
+pub use self::encryption::{Encryption, EncryptionKey};
+pub use self::enums::{DeliveryGuarantee, OrderingGuarantee, PacketType, Priority};
+pub use self::header::AckFieldWidth;
+pub use self::header_info::HeaderInfo;
 pub use self::outgoing::{OutgoingPacket, OutgoingPacketBuilder};
 pub use self::packet_reader::PacketReader;
 pub use self::packet_structure::{Packet, PacketInfo};
 pub use self::process_result::{IncomingPackets, OutgoingPackets};
 
+pub mod batch;
 pub mod header;
 
+mod checksum;
+mod compression;
+#[cfg(feature = "encryption")]
+mod encryption;
 mod enums;
+mod header_info;
 mod outgoing;
 mod packet_reader;
 mod packet_structure;
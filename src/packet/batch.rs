@@ -0,0 +1,160 @@
+use crate::{
+    error::{DecodingErrorKind, ErrorKind, Result},
+    net::constants::BATCH_MESSAGE_HEADER_SIZE,
+    packet::{DeliveryGuarantee, OrderingGuarantee},
+};
+
+/// Small messages with the same guarantees that are packed into one datagram, see `Config::batch_messages`.
+///
+/// Every message is prefixed with its length, messages are never split over multiple batches.
+#[derive(Debug)]
+pub struct MessageBatch {
+    delivery: DeliveryGuarantee,
+    ordering: OrderingGuarantee,
+    payload: Vec<u8>,
+    messages: usize,
+}
+
+impl MessageBatch {
+    /// Creates an empty batch for messages with the given guarantees.
+    pub fn new(delivery: DeliveryGuarantee, ordering: OrderingGuarantee) -> MessageBatch {
+        MessageBatch {
+            delivery,
+            ordering,
+            payload: Vec::new(),
+            messages: 0,
+        }
+    }
+
+    /// Returns true if a message with the given guarantees and size can be added,
+    /// without the batch growing bigger than `max_size`.
+    pub fn accepts(
+        &self,
+        delivery: DeliveryGuarantee,
+        ordering: OrderingGuarantee,
+        size: usize,
+        max_size: usize,
+    ) -> bool {
+        self.delivery == delivery
+            && self.ordering == ordering
+            && self.payload.len() + framed_size(size) <= max_size
+    }
+
+    /// Appends a message to the batch.
+    pub fn push(&mut self, message: &[u8]) {
+        self.payload
+            .extend_from_slice(&(message.len() as u16).to_be_bytes());
+        self.payload.extend_from_slice(message);
+        self.messages += 1;
+    }
+
+    /// Returns the delivery guarantee of the messages in this batch.
+    pub fn delivery_guarantee(&self) -> DeliveryGuarantee {
+        self.delivery
+    }
+
+    /// Returns the ordering guarantee of the messages in this batch.
+    pub fn order_guarantee(&self) -> OrderingGuarantee {
+        self.ordering
+    }
+
+    /// Returns the framed messages.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Returns the message if it is the only one in the batch,
+    /// it is sent as a normal packet then, without the overhead of the framing.
+    pub fn single_message(&self) -> Option<&[u8]> {
+        if self.messages == 1 {
+            Some(&self.payload[BATCH_MESSAGE_HEADER_SIZE as usize..])
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns the size a message of the given size takes up in a batch.
+pub fn framed_size(size: usize) -> usize {
+    BATCH_MESSAGE_HEADER_SIZE as usize + size
+}
+
+/// Reads the messages of a received batch.
+pub fn read_messages(mut payload: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut messages = Vec::new();
+    while !payload.is_empty() {
+        if payload.len() < BATCH_MESSAGE_HEADER_SIZE as usize {
+            return Err(ErrorKind::DecodingError(DecodingErrorKind::Batch));
+        }
+        let (length, rest) = payload.split_at(BATCH_MESSAGE_HEADER_SIZE as usize);
+        let length = usize::from(u16::from_be_bytes([length[0], length[1]]));
+        if rest.len() < length {
+            return Err(ErrorKind::DecodingError(DecodingErrorKind::Batch));
+        }
+        let (message, rest) = rest.split_at(length);
+        messages.push(message);
+        payload = rest;
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_messages, MessageBatch};
+    use crate::error::{DecodingErrorKind, ErrorKind};
+    use crate::packet::{DeliveryGuarantee, OrderingGuarantee};
+
+    #[test]
+    fn messages_are_read_back_in_order() {
+        let mut batch = MessageBatch::new(DeliveryGuarantee::Reliable, OrderingGuarantee::None);
+        batch.push(&[1, 2, 3]);
+        batch.push(&[]);
+        batch.push(&[4]);
+
+        assert_eq!(batch.single_message(), None);
+        assert_eq!(
+            read_messages(batch.payload()).unwrap(),
+            vec![&[1, 2, 3][..], &[][..], &[4][..]]
+        );
+    }
+
+    #[test]
+    fn single_message_is_not_framed() {
+        let mut batch = MessageBatch::new(DeliveryGuarantee::Unreliable, OrderingGuarantee::None);
+        batch.push(&[1, 2, 3]);
+
+        assert_eq!(batch.single_message(), Some(&[1, 2, 3][..]));
+    }
+
+    #[test]
+    fn accepts_only_messages_with_the_same_guarantees_that_fit() {
+        let mut batch = MessageBatch::new(DeliveryGuarantee::Reliable, OrderingGuarantee::None);
+        batch.push(&[0; 6]);
+
+        // the batch holds 8 bytes, another message of 2 bytes needs 4 more.
+        assert!(batch.accepts(DeliveryGuarantee::Reliable, OrderingGuarantee::None, 2, 12));
+        assert!(!batch.accepts(DeliveryGuarantee::Reliable, OrderingGuarantee::None, 3, 12));
+        assert!(!batch.accepts(
+            DeliveryGuarantee::Unreliable,
+            OrderingGuarantee::None,
+            2,
+            12
+        ));
+        assert!(!batch.accepts(
+            DeliveryGuarantee::Reliable,
+            OrderingGuarantee::Ordered(None),
+            2,
+            12
+        ));
+    }
+
+    #[test]
+    fn truncated_batches_are_rejected() {
+        for payload in [&[0][..], &[0, 2, 1][..]].iter() {
+            match read_messages(payload) {
+                Err(ErrorKind::DecodingError(DecodingErrorKind::Batch)) => {}
+                _ => panic!["Supposed to get a batch decoding error"],
+            }
+        }
+    }
+}
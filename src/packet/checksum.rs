@@ -0,0 +1,82 @@
+use std::convert::TryInto;
+
+use crc::crc32;
+
+use crate::{
+    error::{ErrorKind, Result},
+    net::constants::CHECKSUM_SIZE,
+};
+
+/// Wrapper to provide some functions to guard packets against corruption.
+///
+/// The checksum is a crc32 over the header and payload and is appended to the end of the packet.
+pub struct Checksum;
+
+impl Checksum {
+    /// Calculates the checksum of the given packet data and appends it to it.
+    pub fn append(packet: &mut Vec<u8>) {
+        let checksum = crc32::checksum_ieee(packet);
+        packet.extend_from_slice(&checksum.to_be_bytes());
+    }
+
+    /// Validates the checksum at the end of the given data and returns the data without the checksum.
+    pub fn verify(data: &[u8]) -> Result<&[u8]> {
+        if data.len() < CHECKSUM_SIZE as usize {
+            return Err(ErrorKind::ReceivedDataToShort);
+        }
+
+        let (packet, checksum) = data.split_at(data.len() - CHECKSUM_SIZE as usize);
+        let checksum = u32::from_be_bytes(checksum.try_into().expect("Checksum has 4 bytes"));
+
+        if crc32::checksum_ieee(packet) == checksum {
+            Ok(packet)
+        } else {
+            Err(ErrorKind::CorruptPacket)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Checksum;
+    use crate::error::ErrorKind;
+
+    fn packet_with_checksum() -> Vec<u8> {
+        let mut packet = vec![0, 1, 0, 1, 2, 3, 4];
+        Checksum::append(&mut packet);
+        packet
+    }
+
+    #[test]
+    fn verify_returns_packet_without_checksum() {
+        let packet = packet_with_checksum();
+
+        assert_eq!(packet.len(), 11);
+        assert_eq!(Checksum::verify(&packet).unwrap(), &[0, 1, 0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn verify_rejects_flipped_byte() {
+        let mut packet = packet_with_checksum();
+        packet[3] ^= 0b0000_0100;
+
+        match Checksum::verify(&packet) {
+            Err(ErrorKind::CorruptPacket) => {}
+            _ => panic!["Supposed to get a corrupt packet error"],
+        }
+    }
+
+    #[test]
+    fn verify_rejects_truncated_packet() {
+        let packet = packet_with_checksum();
+
+        match Checksum::verify(&packet[..packet.len() - 1]) {
+            Err(ErrorKind::CorruptPacket) => {}
+            _ => panic!["Supposed to get a corrupt packet error"],
+        }
+        match Checksum::verify(&packet[..3]) {
+            Err(ErrorKind::ReceivedDataToShort) => {}
+            _ => panic!["Supposed to get a data to short error"],
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use std::convert::TryFrom;
+
+use crate::error::{DecodingErrorKind, ErrorKind, Result};
+use crate::packet::EnumConverter;
+
+/// Codec that is used to compress the payload of outgoing packets.
+///
+/// Payloads that would not get smaller are sent uncompressed.
+/// Received packets are decompressed with the codec they were sent with, regardless of this setting.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Compression {
+    /// Payloads are sent as they are.
+    #[default]
+    None = 0,
+    /// Payloads are compressed with lz4, requires the `lz4` feature.
+    #[cfg(feature = "lz4")]
+    Lz4 = 1,
+    /// Payloads are compressed with deflate, requires the `deflate` feature.
+    #[cfg(feature = "deflate")]
+    Deflate = 2,
+}
+
+impl Compression {
+    /// Compresses the given payload.
+    /// Returns `None` if compression is disabled or if the payload would not get smaller.
+    #[cfg_attr(
+        not(any(feature = "lz4", feature = "deflate")),
+        allow(unused_variables)
+    )]
+    pub fn compress(self, payload: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Compression::None => None,
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => only_if_smaller(payload, lz4_flex::compress_prepend_size(payload)),
+            #[cfg(feature = "deflate")]
+            Compression::Deflate => {
+                use std::io::Write;
+
+                let mut encoder =
+                    flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(payload).ok()?;
+                only_if_smaller(payload, encoder.finish().ok()?)
+            }
+        }
+    }
+
+    /// Decompresses the given payload, which may not get bigger than `max_size` bytes.
+    #[cfg_attr(
+        not(any(feature = "lz4", feature = "deflate")),
+        allow(unused_variables)
+    )]
+    pub fn decompress(self, payload: Box<[u8]>, max_size: usize) -> Result<Box<[u8]>> {
+        match self {
+            Compression::None => Ok(payload),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => {
+                // the uncompressed size is prepended as a little endian u32.
+                if payload.len() < 4 {
+                    return Err(ErrorKind::DecompressionFailed);
+                }
+                let (size, data) = payload.split_at(4);
+                let size = u32::from_le_bytes([size[0], size[1], size[2], size[3]]) as usize;
+                if size > max_size {
+                    return Err(ErrorKind::DecompressionFailed);
+                }
+
+                lz4_flex::decompress(data, size)
+                    .map(Vec::into_boxed_slice)
+                    .map_err(|_| ErrorKind::DecompressionFailed)
+            }
+            #[cfg(feature = "deflate")]
+            Compression::Deflate => {
+                use std::io::Read;
+
+                let mut decompressed = Vec::new();
+                flate2::read::DeflateDecoder::new(&payload[..])
+                    .take(max_size as u64 + 1)
+                    .read_to_end(&mut decompressed)
+                    .map_err(|_| ErrorKind::DecompressionFailed)?;
+                if decompressed.len() > max_size {
+                    return Err(ErrorKind::DecompressionFailed);
+                }
+
+                Ok(decompressed.into_boxed_slice())
+            }
+        }
+    }
+}
+
+#[cfg(any(feature = "lz4", feature = "deflate"))]
+fn only_if_smaller(payload: &[u8], compressed: Vec<u8>) -> Option<Vec<u8>> {
+    if compressed.len() < payload.len() {
+        Some(compressed)
+    } else {
+        None
+    }
+}
+
+impl EnumConverter for Compression {
+    type Enum = Compression;
+
+    fn to_u8(&self) -> u8 {
+        *self as u8
+    }
+}
+
+impl TryFrom<u8> for Compression {
+    type Error = ErrorKind;
+    fn try_from(value: u8) -> std::result::Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Compression::None),
+            #[cfg(feature = "lz4")]
+            1 => Ok(Compression::Lz4),
+            #[cfg(feature = "deflate")]
+            2 => Ok(Compression::Deflate),
+            _ => Err(ErrorKind::DecodingError(DecodingErrorKind::Compression)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::Compression;
+    use crate::packet::EnumConverter;
+
+    #[test]
+    fn no_compression_leaves_payload_untouched() {
+        assert_eq!(Compression::None.compress(&[0; 100]), None);
+        assert_eq!(
+            &*Compression::None
+                .decompress(vec![1, 2, 3].into_boxed_slice(), 0)
+                .unwrap(),
+            &[1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn unknown_compression_is_rejected() {
+        assert!(Compression::try_from(15).is_err());
+        assert_eq!(
+            Compression::try_from(Compression::None.to_u8()).unwrap(),
+            Compression::None
+        );
+    }
+
+    #[cfg(any(feature = "lz4", feature = "deflate"))]
+    fn enabled_codecs() -> Vec<Compression> {
+        vec![
+            #[cfg(feature = "lz4")]
+            Compression::Lz4,
+            #[cfg(feature = "deflate")]
+            Compression::Deflate,
+        ]
+    }
+
+    #[cfg(any(feature = "lz4", feature = "deflate"))]
+    #[test]
+    fn compressed_payload_round_trips() {
+        let payload = b"state state state state state state state state state".to_vec();
+
+        for codec in enabled_codecs() {
+            let compressed = codec.compress(&payload).unwrap();
+            assert!(compressed.len() < payload.len());
+            assert_eq!(
+                &*codec
+                    .decompress(compressed.into_boxed_slice(), payload.len())
+                    .unwrap(),
+                payload.as_slice()
+            );
+            assert_eq!(Compression::try_from(codec.to_u8()).unwrap(), codec);
+        }
+    }
+
+    #[cfg(any(feature = "lz4", feature = "deflate"))]
+    #[test]
+    fn payload_that_does_not_shrink_is_not_compressed() {
+        for codec in enabled_codecs() {
+            assert_eq!(codec.compress(&[1, 2, 3]), None);
+        }
+    }
+
+    #[cfg(any(feature = "lz4", feature = "deflate"))]
+    #[test]
+    fn decompression_is_limited_to_max_size() {
+        let payload = vec![0; 1000];
+
+        for codec in enabled_codecs() {
+            let compressed = codec.compress(&payload).unwrap();
+            assert!(codec
+                .decompress(compressed.into_boxed_slice(), payload.len() - 1)
+                .is_err());
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use std::{collections::VecDeque, fmt};
+
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    Key, XChaCha20Poly1305, XNonce,
+};
+
+use crate::{
+    error::{ErrorKind, Result},
+    net::constants::ENCRYPTION_HEADER_SIZE,
+};
+
+/// Shared key used to encrypt packets with XChaCha20-Poly1305.
+///
+/// Both endpoints have to be configured with the same key.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EncryptionKey([u8; 32]);
+
+impl From<[u8; 32]> for EncryptionKey {
+    fn from(key: [u8; 32]) -> Self {
+        EncryptionKey(key)
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // the key should never end up in logs.
+        write!(f, "EncryptionKey(..)")
+    }
+}
+
+// The size of the random salt at the start of each nonce, the counter of the packet follows it.
+const SALT_SIZE: usize = 16;
+// The number of packets before the newest one that are still accepted when they arrive late.
+const REPLAY_WINDOW: u64 = 128;
+// The number of salts the remote host used before that are remembered, see `ReplayWindow`.
+const MAX_RETIRED_SALTS: usize = 8;
+
+type Salt = [u8; SALT_SIZE];
+
+/// Encrypts and authenticates the packets of a single connection.
+///
+/// The 192 bit nonce is a random 128 bit salt per connection followed by a counter that is incremented for each packet,
+/// so that connections which share the key do not reuse a nonce.
+/// The packet sequence number can not be used for this, because unreliable packets do not have one and fragments share it.
+///
+/// Received packets whose nonce was received before, or which are more than 128 packets older than the newest one,
+/// are rejected as replays.
+pub struct Encryption {
+    cipher: XChaCha20Poly1305,
+    salt: Salt,
+    counter: u64,
+    replay_window: ReplayWindow,
+}
+
+impl Encryption {
+    /// Creates the encryption state for a new connection.
+    pub fn new(key: &EncryptionKey) -> Encryption {
+        Encryption {
+            cipher: XChaCha20Poly1305::new(Key::from_slice(&key.0)),
+            salt: rand::random(),
+            counter: 0,
+            replay_window: ReplayWindow::default(),
+        }
+    }
+
+    /// Encrypts the given packet and prepends the nonce it was encrypted with.
+    pub fn encrypt(&mut self, packet: &[u8]) -> Vec<u8> {
+        let mut nonce = [0; ENCRYPTION_HEADER_SIZE as usize];
+        nonce[..SALT_SIZE].copy_from_slice(&self.salt);
+        nonce[SALT_SIZE..].copy_from_slice(&self.counter.to_be_bytes());
+        self.counter = self.counter.wrapping_add(1);
+
+        let ciphertext = self
+            .cipher
+            .encrypt(XNonce::from_slice(&nonce), packet)
+            .expect("Encrypting an in-memory buffer can not fail");
+
+        [&nonce[..], &ciphertext].concat()
+    }
+
+    /// Authenticates and decrypts the given data, the nonce is expected in front of it.
+    ///
+    /// Returns `ErrorKind::ReplayedPacket` if the nonce was received before or is too old.
+    pub fn decrypt(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        if data.len() < ENCRYPTION_HEADER_SIZE as usize {
+            return Err(ErrorKind::DecryptionFailed);
+        }
+
+        let (nonce, ciphertext) = data.split_at(ENCRYPTION_HEADER_SIZE as usize);
+        let mut salt = Salt::default();
+        salt.copy_from_slice(&nonce[..SALT_SIZE]);
+        let mut counter = [0; 8];
+        counter.copy_from_slice(&nonce[SALT_SIZE..]);
+        let counter = u64::from_be_bytes(counter);
+
+        if !self.replay_window.accepts(&salt, counter) {
+            return Err(ErrorKind::ReplayedPacket);
+        }
+        let packet = self
+            .cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| ErrorKind::DecryptionFailed)?;
+        // only authenticated nonces move the window, forged ones could push it ahead otherwise.
+        self.replay_window.record(salt, counter);
+        Ok(packet)
+    }
+}
+
+// Tracks the nonces that were received from the remote host.
+//
+// The counters of the current salt are tracked in a window behind the highest one.
+// The remote host uses a new salt after it restarted, the salts it used before are rejected from then on,
+// so that their packets can not be replayed to reset the window.
+#[derive(Default)]
+struct ReplayWindow {
+    salt: Option<Salt>,
+    highest_counter: u64,
+    // bit `n` is set if the counter `n` below the highest one was received.
+    received: u128,
+    retired_salts: VecDeque<Salt>,
+}
+
+impl ReplayWindow {
+    fn accepts(&self, salt: &Salt, counter: u64) -> bool {
+        match &self.salt {
+            None => true,
+            Some(current) if current == salt => {
+                if counter > self.highest_counter {
+                    return true;
+                }
+                let age = self.highest_counter - counter;
+                age < REPLAY_WINDOW && self.received & (1 << age) == 0
+            }
+            Some(_) => !self.retired_salts.contains(salt),
+        }
+    }
+
+    fn record(&mut self, salt: Salt, counter: u64) {
+        if self.salt != Some(salt) {
+            if let Some(previous) = self.salt.replace(salt) {
+                if self.retired_salts.len() == MAX_RETIRED_SALTS {
+                    self.retired_salts.pop_front();
+                }
+                self.retired_salts.push_back(previous);
+            }
+            self.highest_counter = counter;
+            self.received = 1;
+        } else if counter > self.highest_counter {
+            let shift = counter - self.highest_counter;
+            self.received = if shift < REPLAY_WINDOW {
+                (self.received << shift) | 1
+            } else {
+                1
+            };
+            self.highest_counter = counter;
+        } else {
+            self.received |= 1 << (self.highest_counter - counter);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Encryption, EncryptionKey};
+    use crate::error::ErrorKind;
+
+    fn key(byte: u8) -> EncryptionKey {
+        EncryptionKey::from([byte; 32])
+    }
+
+    #[test]
+    fn decrypts_what_was_encrypted() {
+        let mut sender = Encryption::new(&key(1));
+        let mut receiver = Encryption::new(&key(1));
+
+        let encrypted = sender.encrypt(&[1, 2, 3]);
+
+        assert_ne!(&encrypted[encrypted.len() - 3..], &[1, 2, 3]);
+        assert_eq!(receiver.decrypt(&encrypted).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn nonce_is_not_reused() {
+        let mut sender = Encryption::new(&key(1));
+
+        assert_ne!(sender.encrypt(&[1, 2, 3]), sender.encrypt(&[1, 2, 3]));
+    }
+
+    #[test]
+    fn rejects_tampered_data_and_other_keys() {
+        let mut sender = Encryption::new(&key(1));
+        let mut encrypted = sender.encrypt(&[1, 2, 3]);
+
+        match Encryption::new(&key(2)).decrypt(&encrypted) {
+            Err(ErrorKind::DecryptionFailed) => {}
+            _ => panic!["Supposed to get a decryption error"],
+        }
+
+        let last = encrypted.len() - 1;
+        encrypted[last] ^= 1;
+        match Encryption::new(&key(1)).decrypt(&encrypted) {
+            Err(ErrorKind::DecryptionFailed) => {}
+            _ => panic!["Supposed to get a decryption error"],
+        }
+        match Encryption::new(&key(1)).decrypt(&encrypted[..4]) {
+            Err(ErrorKind::DecryptionFailed) => {}
+            _ => panic!["Supposed to get a decryption error"],
+        }
+    }
+
+    #[test]
+    fn replayed_packets_are_rejected() {
+        let mut sender = Encryption::new(&key(1));
+        let mut receiver = Encryption::new(&key(1));
+        let encrypted = sender.encrypt(&[1, 2, 3]);
+
+        assert!(receiver.decrypt(&encrypted).is_ok());
+        match receiver.decrypt(&encrypted) {
+            Err(ErrorKind::ReplayedPacket) => {}
+            _ => panic!["Supposed to get a replay error"],
+        }
+    }
+
+    #[test]
+    fn late_packets_are_accepted_within_the_window() {
+        let mut sender = Encryption::new(&key(1));
+        let mut receiver = Encryption::new(&key(1));
+        let packets: Vec<_> = (0..=128).map(|_| sender.encrypt(&[1, 2, 3])).collect();
+
+        // the packet 128 packets before the newest one is too old, the one after it is not.
+        assert!(receiver.decrypt(&packets[128]).is_ok());
+        assert!(receiver.decrypt(&packets[1]).is_ok());
+        match receiver.decrypt(&packets[0]) {
+            Err(ErrorKind::ReplayedPacket) => {}
+            _ => panic!["Supposed to get a replay error"],
+        }
+        match receiver.decrypt(&packets[1]) {
+            Err(ErrorKind::ReplayedPacket) => {}
+            _ => panic!["Supposed to get a replay error"],
+        }
+    }
+
+    #[test]
+    fn forged_packets_do_not_move_the_window() {
+        let mut sender = Encryption::new(&key(1));
+        let mut receiver = Encryption::new(&key(1));
+        let first = sender.encrypt(&[1, 2, 3]);
+        let mut forged = first.clone();
+        // a nonce far ahead of the counter, with a tag that does not match.
+        forged[super::SALT_SIZE] = 0xFF;
+
+        match receiver.decrypt(&forged) {
+            Err(ErrorKind::DecryptionFailed) => {}
+            _ => panic!["Supposed to get a decryption error"],
+        }
+        assert!(receiver.decrypt(&first).is_ok());
+    }
+
+    #[test]
+    fn packets_with_a_previous_salt_are_rejected() {
+        let mut sender = Encryption::new(&key(1));
+        let mut receiver = Encryption::new(&key(1));
+        let first = sender.encrypt(&[1, 2, 3]);
+        let unseen = sender.encrypt(&[1, 2, 3]);
+        assert!(receiver.decrypt(&first).is_ok());
+
+        // the remote host restarted and uses a new salt.
+        let mut restarted = Encryption::new(&key(1));
+        assert!(receiver.decrypt(&restarted.encrypt(&[1, 2, 3])).is_ok());
+
+        match receiver.decrypt(&unseen) {
+            Err(ErrorKind::ReplayedPacket) => {}
+            _ => panic!["Supposed to get a replay error"],
+        }
+    }
+
+    #[test]
+    fn key_is_not_printed() {
+        assert_eq!(format!("{:?}", key(1)), "EncryptionKey(..)");
+    }
+}
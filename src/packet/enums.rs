@@ -6,9 +6,10 @@ use crate::{
 };
 
 /// Enum to specify how a packet should be delivered.
-#[derive(Copy, Clone, Debug, PartialOrd, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, Default, PartialOrd, PartialEq, Eq)]
 pub enum DeliveryGuarantee {
     /// Packet may or may not be delivered
+    #[default]
     Unreliable,
     /// Packet will be delivered
     Reliable,
@@ -38,9 +39,10 @@ impl TryFrom<u8> for DeliveryGuarantee {
 }
 
 /// Enum to specify how a packet should be arranged.
-#[derive(Copy, Clone, Debug, PartialOrd, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, Default, PartialOrd, PartialEq, Eq)]
 pub enum OrderingGuarantee {
     /// No arranging will be done.
+    #[default]
     None,
     /// Packets will be arranged in sequence.
     Sequenced(Option<u8>),
@@ -48,12 +50,6 @@ pub enum OrderingGuarantee {
     Ordered(Option<u8>),
 }
 
-impl Default for OrderingGuarantee {
-    fn default() -> Self {
-        OrderingGuarantee::None
-    }
-}
-
 impl EnumConverter for OrderingGuarantee {
     type Enum = OrderingGuarantee;
 
@@ -82,6 +78,20 @@ impl TryFrom<u8> for OrderingGuarantee {
     }
 }
 
+/// Enum to specify which user packets are sent first, when packets are held back by `Config::max_bytes_per_second`.
+///
+/// The priority is not sent, it only decides the order in which the packets to one address leave.
+#[derive(Copy, Clone, Debug, Default, PartialOrd, Ord, PartialEq, Eq, Hash)]
+pub enum Priority {
+    /// Packet is sent after all others that are waiting
+    Low,
+    /// Packet is sent before those of a low priority
+    #[default]
+    Normal,
+    /// Packet is sent before all others that are waiting, and is not refused by `Config::max_pending_packets`
+    High,
+}
+
 #[derive(Copy, Clone, Debug, PartialOrd, PartialEq)]
 /// Id to identify a certain packet type.
 pub enum PacketType {
@@ -91,6 +101,38 @@ pub enum PacketType {
     Fragment = 1,
     /// Heartbeat packet
     Heartbeat = 2,
+    /// Disconnect packet
+    Disconnect = 3,
+    /// Handshake packet that asks the remote host to accept the connection
+    Connect = 4,
+    /// Handshake packet that accepts the connection
+    Accept = 5,
+    /// Handshake packet that carries a token, which has to be sent back before the connection is accepted
+    Challenge = 6,
+    /// Handshake packet that sends the token of a challenge back
+    Response = 7,
+    /// Packet that carries a timestamp, which the remote host echoes back
+    Ping = 8,
+    /// Packet that echoes the timestamp of a ping back
+    Pong = 9,
+    /// Packet that carries multiple small messages
+    Batch = 10,
+    /// Handshake packet that carries the token of an established connection,
+    /// it moves the connection to the address the packet was sent from
+    Resume = 11,
+    /// Packet that carries the sequence numbers of reliable packets which the remote host found missing
+    Nack = 12,
+}
+
+impl PacketType {
+    /// Returns true if unreliable packets of this type carry the current acknowledgments,
+    /// so that an acknowledgment which got lost is repeated even when no reliable packets are sent.
+    pub fn piggybacks_acknowledgments(self) -> bool {
+        matches!(
+            self,
+            PacketType::Heartbeat | PacketType::Ping | PacketType::Pong
+        )
+    }
 }
 
 impl EnumConverter for PacketType {
@@ -108,6 +150,16 @@ impl TryFrom<u8> for PacketType {
             0 => Ok(PacketType::Packet),
             1 => Ok(PacketType::Fragment),
             2 => Ok(PacketType::Heartbeat),
+            3 => Ok(PacketType::Disconnect),
+            4 => Ok(PacketType::Connect),
+            5 => Ok(PacketType::Accept),
+            6 => Ok(PacketType::Challenge),
+            7 => Ok(PacketType::Response),
+            8 => Ok(PacketType::Ping),
+            9 => Ok(PacketType::Pong),
+            10 => Ok(PacketType::Batch),
+            11 => Ok(PacketType::Resume),
+            12 => Ok(PacketType::Nack),
             _ => Err(ErrorKind::DecodingError(DecodingErrorKind::PacketType)),
         }
     }
@@ -142,6 +194,12 @@ mod tests {
         )
     }
 
+    #[test]
+    fn assure_default_guarantees() {
+        assert_eq!(DeliveryGuarantee::default(), DeliveryGuarantee::Unreliable);
+        assert_eq!(OrderingGuarantee::default(), OrderingGuarantee::None);
+    }
+
     #[test]
     fn assure_parsing_delivery_guarantee() {
         let unreliable = DeliveryGuarantee::Unreliable;
@@ -161,6 +219,7 @@ mod tests {
         let packet = PacketType::Packet;
         let fragment = PacketType::Fragment;
         let heartbeat = PacketType::Heartbeat;
+        let disconnect = PacketType::Disconnect;
         assert_eq!(
             PacketType::Packet,
             PacketType::try_from(packet.to_u8()).unwrap()
@@ -173,5 +232,45 @@ mod tests {
             PacketType::Heartbeat,
             PacketType::try_from(heartbeat.to_u8()).unwrap()
         );
+        assert_eq!(
+            PacketType::Disconnect,
+            PacketType::try_from(disconnect.to_u8()).unwrap()
+        );
+        assert_eq!(
+            PacketType::Connect,
+            PacketType::try_from(PacketType::Connect.to_u8()).unwrap()
+        );
+        assert_eq!(
+            PacketType::Accept,
+            PacketType::try_from(PacketType::Accept.to_u8()).unwrap()
+        );
+        assert_eq!(
+            PacketType::Challenge,
+            PacketType::try_from(PacketType::Challenge.to_u8()).unwrap()
+        );
+        assert_eq!(
+            PacketType::Response,
+            PacketType::try_from(PacketType::Response.to_u8()).unwrap()
+        );
+        assert_eq!(
+            PacketType::Ping,
+            PacketType::try_from(PacketType::Ping.to_u8()).unwrap()
+        );
+        assert_eq!(
+            PacketType::Pong,
+            PacketType::try_from(PacketType::Pong.to_u8()).unwrap()
+        );
+        assert_eq!(
+            PacketType::Batch,
+            PacketType::try_from(PacketType::Batch.to_u8()).unwrap()
+        );
+        assert_eq!(
+            PacketType::Resume,
+            PacketType::try_from(PacketType::Resume.to_u8()).unwrap()
+        );
+        assert_eq!(
+            PacketType::Nack,
+            PacketType::try_from(PacketType::Nack.to_u8()).unwrap()
+        );
     }
 }
@@ -1,11 +1,15 @@
 //! This module provides parses and readers for the headers that could be appended to any packet.
 //! We use headers to control reliability, fragmentation, and ordering.
+//!
+//! Multi-byte fields are always written in big-endian (network) byte order,
+//! so that peers on platforms with a different endianness understand each other.
 
-pub use self::acked_packet_header::AckedPacketHeader;
+pub use self::acked_packet_header::{AckFieldWidth, AckedPacketHeader};
 pub use self::arranging_header::ArrangingHeader;
 pub use self::fragment_header::FragmentHeader;
 pub use self::header_reader::HeaderReader;
 pub use self::header_writer::HeaderWriter;
+pub use self::selective_ack_header::SelectiveAckHeader;
 pub use self::standard_header::StandardHeader;
 
 mod acked_packet_header;
@@ -13,4 +17,5 @@ mod arranging_header;
 mod fragment_header;
 mod header_reader;
 mod header_writer;
+mod selective_ack_header;
 mod standard_header;
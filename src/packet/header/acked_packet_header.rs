@@ -7,6 +7,39 @@ use crate::net::constants::ACKED_PACKET_HEADER;
 
 use super::{HeaderReader, HeaderWriter};
 
+/// Number of previous packets that are acknowledged by the bitfield of each acknowledgment header.
+///
+/// A wider bitfield keeps lost packets from being resent that were received after all,
+/// which matters on connections that have many packets in flight.
+/// Both endpoints have to use the same width.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AckFieldWidth {
+    /// The past 32 packets are acknowledged.
+    #[default]
+    Bits32,
+    /// The past 64 packets are acknowledged, this costs four more bytes per header.
+    Bits64,
+}
+
+impl AckFieldWidth {
+    /// Returns the number of packets that are covered by the bitfield.
+    pub fn bits(self) -> u16 {
+        match self {
+            AckFieldWidth::Bits32 => 32,
+            AckFieldWidth::Bits64 => 64,
+        }
+    }
+
+    /// Returns the size of an `AckedPacketHeader` with a bitfield of this width.
+    pub fn header_size(self) -> u8 {
+        match self {
+            AckFieldWidth::Bits32 => ACKED_PACKET_HEADER,
+            AckFieldWidth::Bits64 => ACKED_PACKET_HEADER + 4,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 /// This header provides reliability information.
 pub struct AckedPacketHeader {
@@ -14,30 +47,58 @@ pub struct AckedPacketHeader {
     pub seq: u16,
     // This is the last acknowledged sequence number.
     ack_seq: u16,
-    // This is an bitfield of all last 32 acknowledged packages
-    ack_field: u32,
+    // This is an bitfield of all last 32 or 64 acknowledged packages
+    ack_field: u64,
+    width: AckFieldWidth,
 }
 
 impl AckedPacketHeader {
     /// When we compose packet headers, the local sequence becomes the sequence number of the packet, and the remote sequence becomes the ack.
     /// The ack bitfield is calculated by looking into a queue of up to 33 packets, containing sequence numbers in the range [remote sequence - 32, remote sequence].
     /// We set bit n (in [1,32]) in ack bits to 1 if the sequence number remote sequence - n is in the received queue.
-    pub fn new(seq_num: u16, last_seq: u16, bit_field: u32) -> AckedPacketHeader {
+    pub fn new(seq_num: u16, last_seq: u16, bit_field: u64) -> AckedPacketHeader {
         AckedPacketHeader {
             seq: seq_num,
             ack_seq: last_seq,
             ack_field: bit_field,
+            width: AckFieldWidth::Bits32,
         }
     }
 
+    /// Sets the width of the bitfield, which covers up to 64 packets with `AckFieldWidth::Bits64`.
+    pub fn with_width(mut self, width: AckFieldWidth) -> Self {
+        self.width = width;
+        self
+    }
+
+    /// Reads an `AckedPacketHeader` whose bitfield has the given width.
+    pub fn read_with_width(
+        rdr: &mut Cursor<&[u8]>,
+        width: AckFieldWidth,
+    ) -> Result<AckedPacketHeader> {
+        let seq = rdr.read_u16::<BigEndian>()?;
+        let ack_seq = rdr.read_u16::<BigEndian>()?;
+        let ack_field = match width {
+            AckFieldWidth::Bits32 => u64::from(rdr.read_u32::<BigEndian>()?),
+            AckFieldWidth::Bits64 => rdr.read_u64::<BigEndian>()?,
+        };
+
+        Ok(AckedPacketHeader {
+            seq,
+            ack_seq,
+            ack_field,
+            width,
+        })
+    }
+
     /// Returns the sequence number from this packet.
     #[allow(dead_code)]
     pub fn sequence(&self) -> u16 {
         self.seq
     }
 
-    /// Returns bit field of all last 32 acknowledged packages.
-    pub fn ack_field(&self) -> u32 {
+    /// Returns bit field of all last 32 or 64 acknowledged packages.
+    pub fn ack_field(&self) -> u64 {
         self.ack_field
     }
 
@@ -53,7 +114,10 @@ impl HeaderWriter for AckedPacketHeader {
     fn parse(&self, buffer: &mut Vec<u8>) -> Self::Output {
         buffer.write_u16::<BigEndian>(self.seq)?;
         buffer.write_u16::<BigEndian>(self.ack_seq)?;
-        buffer.write_u32::<BigEndian>(self.ack_field)?;
+        match self.width {
+            AckFieldWidth::Bits32 => buffer.write_u32::<BigEndian>(self.ack_field as u32)?,
+            AckFieldWidth::Bits64 => buffer.write_u64::<BigEndian>(self.ack_field)?,
+        }
         Ok(())
     }
 }
@@ -62,17 +126,10 @@ impl HeaderReader for AckedPacketHeader {
     type Header = Result<AckedPacketHeader>;
 
     fn read(rdr: &mut Cursor<&[u8]>) -> Self::Header {
-        let seq = rdr.read_u16::<BigEndian>()?;
-        let ack_seq = rdr.read_u16::<BigEndian>()?;
-        let ack_field = rdr.read_u32::<BigEndian>()?;
-
-        Ok(AckedPacketHeader {
-            seq,
-            ack_seq,
-            ack_field,
-        })
+        AckedPacketHeader::read_with_width(rdr, AckFieldWidth::Bits32)
     }
 
+    /// Returns the size of this header with a 32 bit wide bitfield.
     fn size() -> u8 {
         ACKED_PACKET_HEADER
     }
@@ -83,7 +140,7 @@ mod tests {
     use std::io::Cursor;
 
     use crate::net::constants::ACKED_PACKET_HEADER;
-    use crate::packet::header::{AckedPacketHeader, HeaderReader, HeaderWriter};
+    use crate::packet::header::{AckFieldWidth, AckedPacketHeader, HeaderReader, HeaderWriter};
 
     #[test]
     fn serialize() {
@@ -110,6 +167,53 @@ mod tests {
         assert_eq!(header.ack_field(), 3);
     }
 
+    #[test]
+    fn wide_bitfield_round_trips() {
+        let mut buffer = Vec::new();
+        let header = AckedPacketHeader::new(1, 2, 1 << 63).with_width(AckFieldWidth::Bits64);
+        header.parse(&mut buffer).unwrap();
+
+        assert_eq!(buffer.len() as u8, AckFieldWidth::Bits64.header_size());
+
+        let mut cursor = Cursor::new(buffer.as_slice());
+        let header =
+            AckedPacketHeader::read_with_width(&mut cursor, AckFieldWidth::Bits64).unwrap();
+
+        assert_eq!(header.sequence(), 1);
+        assert_eq!(header.ack_seq(), 2);
+        assert_eq!(header.ack_field(), 1 << 63);
+    }
+
+    #[test]
+    fn fields_are_written_in_big_endian_byte_order() {
+        let expected = [
+            (AckFieldWidth::Bits32, vec![1, 2, 3, 4, 5, 6, 7, 8]),
+            (
+                AckFieldWidth::Bits64,
+                vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12],
+            ),
+        ];
+
+        for (width, bytes) in expected.iter() {
+            let ack_field = match width {
+                AckFieldWidth::Bits32 => 0x0506_0708,
+                AckFieldWidth::Bits64 => 0x0506_0708_090a_0b0c,
+            };
+            let mut buffer = Vec::new();
+            AckedPacketHeader::new(0x0102, 0x0304, ack_field)
+                .with_width(*width)
+                .parse(&mut buffer)
+                .unwrap();
+            assert_eq!(&buffer, bytes);
+
+            let mut cursor = Cursor::new(bytes.as_slice());
+            let header = AckedPacketHeader::read_with_width(&mut cursor, *width).unwrap();
+            assert_eq!(header.sequence(), 0x0102);
+            assert_eq!(header.ack_seq(), 0x0304);
+            assert_eq!(header.ack_field(), ack_field);
+        }
+    }
+
     #[test]
     fn size() {
         assert_eq!(AckedPacketHeader::size(), ACKED_PACKET_HEADER);
@@ -107,6 +107,21 @@ mod tests {
         assert_eq!(header.fragment_count(), 3);
     }
 
+    #[test]
+    fn sequence_is_written_in_big_endian_byte_order() {
+        let mut buffer = Vec::new();
+        FragmentHeader::new(0x0102, 3, 4)
+            .parse(&mut buffer)
+            .unwrap();
+        assert_eq!(buffer, vec![1, 2, 3, 4]);
+
+        let mut cursor = Cursor::new(buffer.as_slice());
+        assert_eq!(
+            FragmentHeader::read(&mut cursor).unwrap().sequence(),
+            0x0102
+        );
+    }
+
     #[test]
     fn size() {
         assert_eq!(FragmentHeader::size(), FRAGMENT_HEADER_SIZE);
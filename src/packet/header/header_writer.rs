@@ -3,6 +3,6 @@ pub trait HeaderWriter {
     /// Associated type since we parse the header into an Output
     type Output;
 
-    /// Writes the header to the given buffer.
+    /// Writes the header to the given buffer, multi-byte fields are written in big-endian byte order.
     fn parse(&self, buffer: &mut Vec<u8>) -> Self::Output;
 }
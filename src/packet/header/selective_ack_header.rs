@@ -0,0 +1,98 @@
+use std::io::Cursor;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::error::Result;
+use crate::net::constants::{MAX_SELECTIVE_ACK_RANGES, SELECTIVE_ACK_RANGE_SIZE};
+use crate::packet::SequenceNumber;
+
+use super::{HeaderReader, HeaderWriter};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// This header acknowledges ranges of packets that are too old for the bitfield of the `AckedPacketHeader`.
+///
+/// It follows the acknowledgment header when `StandardHeader::has_selective_acks` is set.
+pub struct SelectiveAckHeader {
+    // inclusive ranges of received sequence numbers, the newest first.
+    ranges: Vec<(SequenceNumber, SequenceNumber)>,
+}
+
+impl SelectiveAckHeader {
+    /// Creates a header with the given inclusive ranges, only the first `MAX_SELECTIVE_ACK_RANGES` are kept.
+    pub fn new(mut ranges: Vec<(SequenceNumber, SequenceNumber)>) -> Self {
+        ranges.truncate(usize::from(MAX_SELECTIVE_ACK_RANGES));
+        SelectiveAckHeader { ranges }
+    }
+
+    /// Returns the inclusive ranges of sequence numbers that were received.
+    pub fn ranges(&self) -> &[(SequenceNumber, SequenceNumber)] {
+        &self.ranges
+    }
+
+    /// Returns the size of this header with its ranges.
+    pub fn encoded_size(&self) -> u8 {
+        Self::size() + self.ranges.len() as u8 * SELECTIVE_ACK_RANGE_SIZE
+    }
+}
+
+impl HeaderWriter for SelectiveAckHeader {
+    type Output = Result<()>;
+
+    fn parse(&self, buffer: &mut Vec<u8>) -> Self::Output {
+        buffer.write_u8(self.ranges.len() as u8)?;
+        for (start, end) in &self.ranges {
+            buffer.write_u16::<BigEndian>(*start)?;
+            buffer.write_u16::<BigEndian>(*end)?;
+        }
+        Ok(())
+    }
+}
+
+impl HeaderReader for SelectiveAckHeader {
+    type Header = Result<SelectiveAckHeader>;
+
+    fn read(rdr: &mut Cursor<&[u8]>) -> Self::Header {
+        let count = rdr.read_u8()?;
+        let mut ranges = Vec::with_capacity(usize::from(count));
+        for _ in 0..count {
+            let start = rdr.read_u16::<BigEndian>()?;
+            let end = rdr.read_u16::<BigEndian>()?;
+            ranges.push((start, end));
+        }
+
+        // all ranges are kept, so that `encoded_size` finds the end of the header.
+        Ok(SelectiveAckHeader { ranges })
+    }
+
+    /// Returns the size of this header without any ranges.
+    fn size() -> u8 {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::packet::header::{HeaderReader, HeaderWriter, SelectiveAckHeader};
+
+    #[test]
+    fn serialize_and_deserialize() {
+        let header = SelectiveAckHeader::new(vec![(10, 20), (65_530, 2)]);
+        let mut buffer = Vec::new();
+        header.parse(&mut buffer).unwrap();
+
+        assert_eq!(buffer.len(), usize::from(header.encoded_size()));
+        assert_eq!(buffer, vec![2, 0, 10, 0, 20, 255, 250, 0, 2]);
+
+        let mut cursor = Cursor::new(buffer.as_slice());
+        assert_eq!(SelectiveAckHeader::read(&mut cursor).unwrap(), header);
+    }
+
+    #[test]
+    fn truncated_ranges_are_rejected() {
+        let buffer = vec![2, 0, 10, 0, 20, 255];
+        let mut cursor = Cursor::new(buffer.as_slice());
+        assert!(SelectiveAckHeader::read(&mut cursor).is_err());
+    }
+}
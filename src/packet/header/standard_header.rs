@@ -4,8 +4,10 @@ use std::io::Cursor;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
 use crate::error::Result;
-use crate::net::constants::STANDARD_HEADER_SIZE;
-use crate::packet::{DeliveryGuarantee, EnumConverter, OrderingGuarantee, PacketType};
+use crate::net::constants::{MAX_EPOCH, STANDARD_HEADER_SIZE};
+use crate::packet::{
+    AckFieldWidth, Compression, DeliveryGuarantee, EnumConverter, OrderingGuarantee, PacketType,
+};
 use crate::protocol_version::ProtocolVersion;
 
 use super::{HeaderReader, HeaderWriter};
@@ -17,6 +19,11 @@ pub struct StandardHeader {
     packet_type: PacketType,
     delivery_guarantee: DeliveryGuarantee,
     ordering_guarantee: OrderingGuarantee,
+    compression: Compression,
+    ack_field_width: AckFieldWidth,
+    epoch: u8,
+    selective_acks: bool,
+    timestamp: bool,
 }
 
 impl StandardHeader {
@@ -31,11 +38,33 @@ impl StandardHeader {
             delivery_guarantee,
             ordering_guarantee,
             packet_type,
+            compression: Compression::None,
+            ack_field_width: AckFieldWidth::Bits32,
+            epoch: 0,
+            selective_acks: false,
+            timestamp: false,
         }
     }
 
+    /// Marks the payload of the packet as compressed with the given codec.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Marks the acknowledgment header of the packet as having a bitfield of the given width.
+    pub fn with_ack_field_width(mut self, ack_field_width: AckFieldWidth) -> Self {
+        self.ack_field_width = ack_field_width;
+        self
+    }
+
+    /// Marks the packet as sent in the given epoch of the connection, only the lower seven bits are kept.
+    pub fn with_epoch(mut self, epoch: u8) -> Self {
+        self.epoch = epoch & MAX_EPOCH;
+        self
+    }
+
     /// Returns the protocol version
-    #[cfg(test)]
     pub fn protocol_version(&self) -> u16 {
         self.protocol_version
     }
@@ -55,11 +84,60 @@ impl StandardHeader {
         self.packet_type
     }
 
+    /// Returns the codec the payload is compressed with
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Returns the width of the acknowledgment bitfield the sender uses
+    pub fn ack_field_width(&self) -> AckFieldWidth {
+        self.ack_field_width
+    }
+
+    /// Returns the epoch of the connection the packet was sent in
+    pub fn epoch(&self) -> u8 {
+        self.epoch
+    }
+
+    /// Returns true if a `SelectiveAckHeader` follows the acknowledgment header
+    pub fn has_selective_acks(&self) -> bool {
+        self.selective_acks
+    }
+
+    /// Returns true if the send time of the packet is appended to its payload
+    pub fn has_timestamp(&self) -> bool {
+        self.timestamp
+    }
+
     /// Returns true if the packet is a heartbeat packet, false otherwise
     pub fn is_heartbeat(&self) -> bool {
         self.packet_type == PacketType::Heartbeat
     }
 
+    /// Returns true if the packet is a disconnect packet, false otherwise
+    pub fn is_disconnect(&self) -> bool {
+        self.packet_type == PacketType::Disconnect
+    }
+
+    /// Returns true if the packet is a handshake packet, false otherwise
+    pub fn is_handshake(&self) -> bool {
+        match self.packet_type {
+            PacketType::Connect
+            | PacketType::Accept
+            | PacketType::Challenge
+            | PacketType::Response
+            | PacketType::Resume => true,
+            PacketType::Packet
+            | PacketType::Fragment
+            | PacketType::Heartbeat
+            | PacketType::Disconnect
+            | PacketType::Ping
+            | PacketType::Pong
+            | PacketType::Batch
+            | PacketType::Nack => false,
+        }
+    }
+
     /// Returns true if the packet is a fragment, false if not
     pub fn is_fragment(&self) -> bool {
         self.packet_type == PacketType::Fragment
@@ -86,9 +164,20 @@ impl HeaderWriter for StandardHeader {
 
     fn parse(&self, buffer: &mut Vec<u8>) -> Self::Output {
         buffer.write_u16::<BigEndian>(self.protocol_version)?;
-        buffer.write_u8(self.packet_type.to_u8())?;
-        buffer.write_u8(self.delivery_guarantee.to_u8())?;
-        buffer.write_u8(self.ordering_guarantee.to_u8())?;
+        // the codec is stored in the upper bits of the packet type, the highest bit marks a 64 bit ack field.
+        let wide_ack_field = match self.ack_field_width {
+            AckFieldWidth::Bits32 => 0,
+            AckFieldWidth::Bits64 => 0x80,
+        };
+        buffer.write_u8(
+            self.packet_type.to_u8() | (self.compression.to_u8() << 4) | wide_ack_field,
+        )?;
+        // the epoch is stored in the upper bits of the delivery guarantee.
+        buffer.write_u8(self.delivery_guarantee.to_u8() | (self.epoch << 1))?;
+        // the highest bits of the ordering guarantee announce selective acknowledgments and the timestamp.
+        let selective_acks = if self.selective_acks { 0x80 } else { 0 };
+        let timestamp = if self.timestamp { 0x40 } else { 0 };
+        buffer.write_u8(self.ordering_guarantee.to_u8() | selective_acks | timestamp)?;
         Ok(())
     }
 }
@@ -104,9 +193,18 @@ impl HeaderReader for StandardHeader {
 
         let header = StandardHeader {
             protocol_version,
-            packet_type: PacketType::try_from(packet_id)?,
-            delivery_guarantee: DeliveryGuarantee::try_from(delivery_guarantee_id)?,
-            ordering_guarantee: OrderingGuarantee::try_from(order_guarantee_id)?,
+            packet_type: PacketType::try_from(packet_id & 0x0F)?,
+            delivery_guarantee: DeliveryGuarantee::try_from(delivery_guarantee_id & 0x01)?,
+            ordering_guarantee: OrderingGuarantee::try_from(order_guarantee_id & 0x3F)?,
+            compression: Compression::try_from((packet_id >> 4) & 0x07)?,
+            ack_field_width: if packet_id & 0x80 == 0 {
+                AckFieldWidth::Bits32
+            } else {
+                AckFieldWidth::Bits64
+            },
+            epoch: delivery_guarantee_id >> 1,
+            selective_acks: order_guarantee_id & 0x80 != 0,
+            timestamp: order_guarantee_id & 0x40 != 0,
         };
 
         Ok(header)
@@ -124,7 +222,9 @@ mod tests {
 
     use crate::net::constants::STANDARD_HEADER_SIZE;
     use crate::packet::header::{HeaderReader, HeaderWriter, StandardHeader};
-    use crate::packet::{DeliveryGuarantee, EnumConverter, OrderingGuarantee, PacketType};
+    use crate::packet::{
+        AckFieldWidth, DeliveryGuarantee, EnumConverter, OrderingGuarantee, PacketType,
+    };
 
     #[test]
     fn serialize() {
@@ -159,6 +259,85 @@ mod tests {
         );
     }
 
+    #[test]
+    fn compression_is_stored_next_to_packet_type() {
+        let mut buffer = Vec::new();
+        StandardHeader::default().parse(&mut buffer).unwrap();
+        assert_eq!(buffer[2], PacketType::Packet.to_u8());
+
+        // bits four to six of the packet type hold the codec, which is not known.
+        buffer[2] |= 0x70;
+        let mut cursor = Cursor::new(buffer.as_slice());
+        assert!(StandardHeader::read(&mut cursor).is_err());
+    }
+
+    #[test]
+    fn ack_field_width_is_stored_in_highest_bit() {
+        let mut buffer = Vec::new();
+        StandardHeader::default()
+            .with_ack_field_width(AckFieldWidth::Bits64)
+            .parse(&mut buffer)
+            .unwrap();
+        assert_eq!(buffer[2], PacketType::Packet.to_u8() | 0x80);
+
+        let mut cursor = Cursor::new(buffer.as_slice());
+        let header = StandardHeader::read(&mut cursor).unwrap();
+        assert_eq!(header.packet_type(), PacketType::Packet);
+        assert_eq!(header.ack_field_width(), AckFieldWidth::Bits64);
+    }
+
+    #[test]
+    fn epoch_is_stored_next_to_delivery_guarantee() {
+        let mut buffer = Vec::new();
+        StandardHeader::new(
+            DeliveryGuarantee::Reliable,
+            OrderingGuarantee::None,
+            PacketType::Packet,
+        )
+        .with_epoch(0xFF)
+        .parse(&mut buffer)
+        .unwrap();
+        assert_eq!(buffer[3], 0xFF);
+
+        let mut cursor = Cursor::new(buffer.as_slice());
+        let header = StandardHeader::read(&mut cursor).unwrap();
+        assert_eq!(header.delivery_guarantee(), DeliveryGuarantee::Reliable);
+        assert_eq!(header.epoch(), 0x7F);
+    }
+
+    #[test]
+    fn selective_acks_are_announced_in_highest_bit_of_ordering_guarantee() {
+        let mut buffer = Vec::new();
+        StandardHeader::new(
+            DeliveryGuarantee::Reliable,
+            OrderingGuarantee::Ordered(None),
+            PacketType::Packet,
+        )
+        .parse(&mut buffer)
+        .unwrap();
+        // `OutgoingPacketBuilder::with_selective_ack_header` sets the bit.
+        buffer[4] |= 0x80;
+
+        let mut cursor = Cursor::new(buffer.as_slice());
+        let header = StandardHeader::read(&mut cursor).unwrap();
+        assert_eq!(
+            header.ordering_guarantee(),
+            OrderingGuarantee::Ordered(None)
+        );
+        assert!(header.has_selective_acks());
+        assert!(!header.has_timestamp());
+
+        // `OutgoingPacketBuilder::with_timestamp` sets the bit below it.
+        buffer[4] |= 0x40;
+        let mut cursor = Cursor::new(buffer.as_slice());
+        let header = StandardHeader::read(&mut cursor).unwrap();
+        assert_eq!(
+            header.ordering_guarantee(),
+            OrderingGuarantee::Ordered(None)
+        );
+        assert!(header.has_timestamp());
+    }
+
     #[test]
     fn size() {
         assert_eq!(StandardHeader::size(), STANDARD_HEADER_SIZE);
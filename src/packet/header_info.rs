@@ -0,0 +1,490 @@
+use crate::{
+    error::{ErrorKind, Result},
+    net::constants::{STANDARD_HEADER_SIZE, TIMESTAMP_SIZE},
+    packet::{
+        header::{
+            AckedPacketHeader, ArrangingHeader, FragmentHeader, SelectiveAckHeader, StandardHeader,
+        },
+        AckFieldWidth, Checksum, Compression, DeliveryGuarantee, OrderingGuarantee, PacketReader,
+        PacketType, SequenceNumber,
+    },
+    protocol_version::ProtocolVersion,
+};
+
+/// The protocol fields of a received datagram, which can be used to peek at it before it is processed.
+///
+/// Parsing only reads the given buffer, it does not touch the state of any connection.
+/// The datagram is not acknowledged, arranged or reassembled by this, it still has to be processed by a socket.
+///
+/// # Remarks
+/// - Encrypted datagrams can not be parsed, because the headers are encrypted as well.
+/// - The payload is returned as it was sent, so it may be compressed or only be a fragment of a packet.
+///   The send time that `Config::packet_timestamps` appends is split off it, see `sent_at`.
+#[derive(Clone, Debug)]
+pub struct HeaderInfo<'a> {
+    standard: StandardHeader,
+    acknowledgment: Option<AckedPacketHeader>,
+    selective_acks: Option<SelectiveAckHeader>,
+    fragment: Option<FragmentHeader>,
+    arranging: Option<ArrangingHeader>,
+    sent_at: Option<u32>,
+    payload: &'a [u8],
+}
+
+impl<'a> HeaderInfo<'a> {
+    /// Parses the headers of the given datagram, as it was received from the socket.
+    ///
+    /// Returns an error if the checksum does not match, the protocol version is not ours,
+    /// or if the datagram is too short for the headers it announces.
+    pub fn parse(datagram: &'a [u8]) -> Result<HeaderInfo<'a>> {
+        ProtocolVersion::verify_datagram(datagram)?;
+        HeaderInfo::read(Checksum::verify(datagram)?)
+    }
+
+    /// Reads the headers of a packet whose checksum was already verified and which is decrypted.
+    ///
+    /// This is how connections read the packets they receive, so it reads the same headers in the same order.
+    pub(crate) fn read(packet: &'a [u8]) -> Result<HeaderInfo<'a>> {
+        let mut reader = PacketReader::new(packet);
+
+        let mut standard = reader.read_standard_header()?;
+
+        // the send time is appended to the payload, the packet is read without it.
+        let sent_at = if standard.has_timestamp() {
+            let (contents, timestamp) =
+                split_timestamp(packet).ok_or(ErrorKind::ReceivedDataToShort)?;
+            reader = PacketReader::new(contents);
+            standard = reader.read_standard_header()?;
+            Some(timestamp)
+        } else {
+            None
+        };
+
+        if !standard.is_current_protocol() {
+            return Err(ErrorKind::ProtocolVersionMismatch {
+                expected: ProtocolVersion::get_crc16(),
+                got: standard.protocol_version(),
+            });
+        }
+
+        let mut acknowledgment = None;
+        let mut selective_acks = None;
+        let mut fragment = None;
+        let mut arranging = None;
+
+        match standard.packet_type() {
+            // these packets are unreliable and unordered, but carry the acknowledgments of the remote host.
+            PacketType::Heartbeat | PacketType::Ping | PacketType::Pong => {
+                acknowledgment = Some(reader.read_acknowledge_header()?);
+                selective_acks = reader.read_selective_ack_header()?;
+            }
+            PacketType::Fragment => {
+                let (fragment_header, acked_header) = reader.read_fragment()?;
+                fragment = Some(fragment_header);
+                acknowledgment = acked_header;
+            }
+            PacketType::Packet | PacketType::Batch => {
+                let mut arranging_offset = STANDARD_HEADER_SIZE;
+                if standard.delivery_guarantee() == DeliveryGuarantee::Reliable {
+                    acknowledgment = Some(reader.read_acknowledge_header()?);
+                    // the arranging header follows the selective acknowledgments, if there are any.
+                    selective_acks = reader.read_selective_ack_header()?;
+                    arranging_offset += standard.ack_field_width().header_size()
+                        + selective_acks
+                            .as_ref()
+                            .map_or(0, SelectiveAckHeader::encoded_size);
+                }
+                // only reliable packets can be ordered, the ordering guarantee is ignored for unreliable ones.
+                let arranged = match standard.ordering_guarantee() {
+                    OrderingGuarantee::None => false,
+                    OrderingGuarantee::Sequenced(_) => true,
+                    OrderingGuarantee::Ordered(_) => {
+                        standard.delivery_guarantee() == DeliveryGuarantee::Reliable
+                    }
+                };
+                if arranged {
+                    arranging = Some(reader.read_arranging_header(u16::from(arranging_offset))?);
+                }
+            }
+            // handshakes, disconnects and negative acknowledgments only carry a payload.
+            PacketType::Disconnect
+            | PacketType::Connect
+            | PacketType::Accept
+            | PacketType::Challenge
+            | PacketType::Response
+            | PacketType::Resume
+            | PacketType::Nack => {}
+        }
+
+        Ok(HeaderInfo {
+            standard,
+            acknowledgment,
+            selective_acks,
+            fragment,
+            arranging,
+            sent_at,
+            payload: reader.payload(),
+        })
+    }
+
+    /// Returns the type of the packet.
+    pub fn packet_type(&self) -> PacketType {
+        self.standard.packet_type()
+    }
+
+    /// Returns how the packet is delivered.
+    pub fn delivery_guarantee(&self) -> DeliveryGuarantee {
+        self.standard.delivery_guarantee()
+    }
+
+    /// Returns how the packet is arranged, including the stream it is arranged on.
+    pub fn ordering_guarantee(&self) -> OrderingGuarantee {
+        let stream_id = self.arranging.map(|arranging| arranging.stream_id());
+        match self.standard.ordering_guarantee() {
+            OrderingGuarantee::None => OrderingGuarantee::None,
+            OrderingGuarantee::Sequenced(_) => OrderingGuarantee::Sequenced(stream_id),
+            OrderingGuarantee::Ordered(_) => OrderingGuarantee::Ordered(stream_id),
+        }
+    }
+
+    /// Returns the codec the payload is compressed with.
+    pub fn compression(&self) -> Compression {
+        self.standard.compression()
+    }
+
+    /// Returns the width of the acknowledgment bitfield the sender uses.
+    pub fn ack_field_width(&self) -> AckFieldWidth {
+        self.standard.ack_field_width()
+    }
+
+    /// Returns the sequence number of the packet, if it carries acknowledgment information.
+    pub fn sequence(&self) -> Option<u16> {
+        self.acknowledgment
+            .map(|acked| acked.sequence())
+            .or_else(|| self.fragment.map(|fragment| fragment.sequence()))
+    }
+
+    /// Returns the last sequence number the sender received from us, if the packet carries acknowledgment information.
+    pub fn ack_seq(&self) -> Option<u16> {
+        self.acknowledgment.map(|acked| acked.ack_seq())
+    }
+
+    /// Returns the bitfield of the packets the sender received before `ack_seq`, if the packet carries acknowledgment information.
+    pub fn ack_field(&self) -> Option<u64> {
+        self.acknowledgment.map(|acked| acked.ack_field())
+    }
+
+    /// Returns the inclusive ranges of sequence numbers the sender received from us,
+    /// if the packet carries selective acknowledgments, see `Config::selective_acks`.
+    pub fn selective_acks(&self) -> Option<&[(SequenceNumber, SequenceNumber)]> {
+        self.selective_acks.as_ref().map(SelectiveAckHeader::ranges)
+    }
+
+    /// Returns the id of this fragment and the number of fragments of the packet, if the packet is a fragment.
+    pub fn fragment(&self) -> Option<(u8, u8)> {
+        self.fragment
+            .map(|fragment| (fragment.id(), fragment.fragment_count()))
+    }
+
+    /// Returns the identifier the packet is arranged with, if it is ordered or sequenced.
+    pub fn arranging_id(&self) -> Option<u16> {
+        self.arranging.map(|arranging| arranging.arranging_id())
+    }
+
+    /// Returns the time the remote host sent this packet at, if it was sent with `Config::packet_timestamps`.
+    ///
+    /// See `Packet::sent_at` for how the time is counted.
+    pub fn sent_at(&self) -> Option<u32> {
+        self.sent_at
+    }
+
+    /// Returns the payload that follows the headers, without the send time.
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    pub(crate) fn standard_header(&self) -> &StandardHeader {
+        &self.standard
+    }
+
+    pub(crate) fn acked_header(&self) -> Option<AckedPacketHeader> {
+        self.acknowledgment
+    }
+
+    pub(crate) fn selective_ack_header(&self) -> Option<&SelectiveAckHeader> {
+        self.selective_acks.as_ref()
+    }
+
+    pub(crate) fn fragment_header(&self) -> Option<FragmentHeader> {
+        self.fragment
+    }
+
+    pub(crate) fn arranging_header(&self) -> Option<ArrangingHeader> {
+        self.arranging
+    }
+}
+
+// Splits the send time off the end of a packet, see `Config::packet_timestamps`.
+fn split_timestamp(contents: &[u8]) -> Option<(&[u8], u32)> {
+    let split = contents
+        .len()
+        .checked_sub(usize::from(TIMESTAMP_SIZE))
+        .filter(|split| *split >= usize::from(STANDARD_HEADER_SIZE))?;
+    let (contents, timestamp) = contents.split_at(split);
+    let mut bytes = [0; TIMESTAMP_SIZE as usize];
+    bytes.copy_from_slice(timestamp);
+    Some((contents, u32::from_be_bytes(bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::HeaderInfo;
+    use crate::config::Config;
+    use crate::error::ErrorKind;
+    use crate::net::VirtualConnection;
+    use crate::packet::header::SelectiveAckHeader;
+    use crate::packet::{
+        AckFieldWidth, Checksum, Compression, DeliveryGuarantee, OrderingGuarantee,
+        OutgoingPacketBuilder, PacketInfo, PacketType,
+    };
+
+    fn datagrams(connection: &mut VirtualConnection, packet: PacketInfo<'_>) -> Vec<Vec<u8>> {
+        connection
+            .process_outgoing(packet, None, Instant::now())
+            .unwrap()
+            .into_iter()
+            .map(|packet| {
+                let mut datagram = packet.contents().to_vec();
+                Checksum::append(&mut datagram);
+                datagram
+            })
+            .collect()
+    }
+
+    fn datagram(connection: &mut VirtualConnection, packet: PacketInfo<'_>) -> Vec<u8> {
+        datagrams(connection, packet).remove(0)
+    }
+
+    fn built(builder: OutgoingPacketBuilder<'_>) -> Vec<u8> {
+        let mut datagram = builder.build().contents().to_vec();
+        Checksum::append(&mut datagram);
+        datagram
+    }
+
+    fn connection() -> VirtualConnection {
+        connection_with(Config::default())
+    }
+
+    fn connection_with(config: Config) -> VirtualConnection {
+        VirtualConnection::new("127.0.0.1:0".parse().unwrap(), &config, Instant::now())
+    }
+
+    #[test]
+    fn parses_reliable_ordered_packet() {
+        let mut connection = connection();
+        let first = datagram(
+            &mut connection,
+            PacketInfo::user_packet(&[0], DeliveryGuarantee::Reliable, OrderingGuarantee::None),
+        );
+        let first = HeaderInfo::parse(&first).unwrap().sequence().unwrap();
+        let datagram = datagram(
+            &mut connection,
+            PacketInfo::user_packet(
+                &[1, 2, 3],
+                DeliveryGuarantee::Reliable,
+                OrderingGuarantee::Ordered(Some(2)),
+            ),
+        );
+
+        let info = HeaderInfo::parse(&datagram).unwrap();
+
+        assert_eq!(info.packet_type(), PacketType::Packet);
+        assert_eq!(info.delivery_guarantee(), DeliveryGuarantee::Reliable);
+        assert_eq!(
+            info.ordering_guarantee(),
+            OrderingGuarantee::Ordered(Some(2))
+        );
+        assert_eq!(info.sequence(), Some(first.wrapping_add(1)));
+        assert_eq!(info.ack_seq(), Some(u16::MAX));
+        assert_eq!(info.arranging_id(), Some(0));
+        assert_eq!(info.fragment(), None);
+        assert_eq!(info.payload(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn parses_unreliable_packet() {
+        let datagram = datagram(
+            &mut connection(),
+            PacketInfo::user_packet(
+                &[1, 2, 3],
+                DeliveryGuarantee::Unreliable,
+                OrderingGuarantee::None,
+            ),
+        );
+
+        let info = HeaderInfo::parse(&datagram).unwrap();
+
+        assert_eq!(info.delivery_guarantee(), DeliveryGuarantee::Unreliable);
+        assert_eq!(info.sequence(), None);
+        assert_eq!(info.arranging_id(), None);
+        assert_eq!(info.payload(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn parses_heartbeat() {
+        let datagram = datagram(&mut connection(), PacketInfo::heartbeat_packet(&[]));
+
+        let info = HeaderInfo::parse(&datagram).unwrap();
+
+        assert_eq!(info.packet_type(), PacketType::Heartbeat);
+        assert!(info.sequence().is_some());
+        assert_eq!(info.ack_seq(), Some(u16::MAX));
+        assert_eq!(info.selective_acks(), None);
+        assert_eq!(info.payload(), &[] as &[u8]);
+    }
+
+    #[test]
+    fn parses_ping_and_pong() {
+        let mut connection = connection();
+        let ping = datagram(&mut connection, PacketInfo::ping_packet(&[1, 2]));
+        let pong = datagram(&mut connection, PacketInfo::pong_packet(&[3, 4]));
+
+        let ping = HeaderInfo::parse(&ping).unwrap();
+        let pong = HeaderInfo::parse(&pong).unwrap();
+
+        assert_eq!(ping.packet_type(), PacketType::Ping);
+        assert!(ping.sequence().is_some());
+        assert_eq!(ping.ack_seq(), Some(u16::MAX));
+        assert_eq!(ping.payload(), &[1, 2]);
+        assert_eq!(pong.packet_type(), PacketType::Pong);
+        assert!(pong.sequence().is_some());
+        assert_eq!(pong.ack_seq(), Some(u16::MAX));
+        assert_eq!(pong.payload(), &[3, 4]);
+    }
+
+    #[test]
+    fn parses_fragments() {
+        let config = Config::default();
+        let payload = vec![7; config.fragment_size as usize + 1];
+        let fragments = datagrams(
+            &mut connection_with(config),
+            PacketInfo::user_packet(
+                &payload,
+                DeliveryGuarantee::Reliable,
+                OrderingGuarantee::None,
+            ),
+        );
+        assert_eq!(fragments.len(), 2);
+
+        let first = HeaderInfo::parse(&fragments[0]).unwrap();
+        let second = HeaderInfo::parse(&fragments[1]).unwrap();
+
+        assert_eq!(first.packet_type(), PacketType::Fragment);
+        assert_eq!(first.fragment(), Some((0, 2)));
+        assert_eq!(first.ack_seq(), Some(u16::MAX));
+        assert_eq!(second.fragment(), Some((1, 2)));
+        // only the first fragment carries the acknowledgments, all of them carry the sequence number.
+        assert_eq!(second.ack_seq(), None);
+        assert_eq!(second.sequence(), first.sequence());
+        assert_eq!(
+            first.payload().len() + second.payload().len(),
+            payload.len()
+        );
+    }
+
+    #[test]
+    fn parses_selective_acks() {
+        let datagram = built(
+            OutgoingPacketBuilder::new(&[1, 2, 3][..])
+                .with_default_header(
+                    PacketType::Packet,
+                    DeliveryGuarantee::Reliable,
+                    OrderingGuarantee::Ordered(Some(5)),
+                    Compression::None,
+                    AckFieldWidth::Bits32,
+                    0,
+                )
+                .with_acknowledgment_header(1, 2, 7, AckFieldWidth::Bits32)
+                .with_selective_ack_header(SelectiveAckHeader::new(vec![(10, 12)]))
+                .with_ordering_header(4, Some(5)),
+        );
+
+        let info = HeaderInfo::parse(&datagram).unwrap();
+
+        assert_eq!(info.sequence(), Some(1));
+        assert_eq!(info.ack_seq(), Some(2));
+        assert_eq!(info.selective_acks(), Some(&[(10, 12)][..]));
+        assert_eq!(
+            info.ordering_guarantee(),
+            OrderingGuarantee::Ordered(Some(5))
+        );
+        assert_eq!(info.arranging_id(), Some(4));
+        assert_eq!(info.payload(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn splits_off_the_send_time() {
+        let datagram = built(
+            OutgoingPacketBuilder::new(&[1, 2, 3][..])
+                .with_default_header(
+                    PacketType::Packet,
+                    DeliveryGuarantee::Reliable,
+                    OrderingGuarantee::Sequenced(Some(1)),
+                    Compression::None,
+                    AckFieldWidth::Bits32,
+                    0,
+                )
+                .with_acknowledgment_header(1, 2, 7, AckFieldWidth::Bits32)
+                .with_sequencing_header(4, Some(1))
+                .with_timestamp(42),
+        );
+
+        let info = HeaderInfo::parse(&datagram).unwrap();
+
+        assert_eq!(info.sent_at(), Some(42));
+        assert_eq!(info.arranging_id(), Some(4));
+        assert_eq!(info.payload(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn parses_disconnect() {
+        let datagram = datagram(&mut connection(), PacketInfo::disconnect_packet());
+
+        let info = HeaderInfo::parse(&datagram).unwrap();
+
+        assert_eq!(info.packet_type(), PacketType::Disconnect);
+        assert_eq!(info.sequence(), None);
+        assert_eq!(info.sent_at(), None);
+        assert_eq!(info.payload(), &[] as &[u8]);
+    }
+
+    #[test]
+    fn rejects_corrupt_and_truncated_datagrams() {
+        let mut datagram = datagram(
+            &mut connection(),
+            PacketInfo::user_packet(
+                &[1, 2, 3],
+                DeliveryGuarantee::Reliable,
+                OrderingGuarantee::None,
+            ),
+        );
+
+        // a flipped byte in the protocol version would be read as another version.
+        datagram[2] ^= 1;
+        match HeaderInfo::parse(&datagram) {
+            Err(ErrorKind::CorruptPacket) => {}
+            _ => panic!["Supposed to get a corrupt packet error"],
+        }
+
+        // a reliable standard header without the acknowledgment header that has to follow it.
+        let mut truncated = datagram[..5].to_vec();
+        truncated[2] ^= 1;
+        Checksum::append(&mut truncated);
+        match HeaderInfo::parse(&truncated) {
+            Err(ErrorKind::CouldNotReadHeader(_)) => {}
+            _ => panic!["Supposed to get a header error"],
+        }
+    }
+}
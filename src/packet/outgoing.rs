@@ -1,25 +1,33 @@
+use std::borrow::Cow;
+
 use crate::{
-    net::constants::{DEFAULT_ORDERING_STREAM, DEFAULT_SEQUENCING_STREAM},
+    net::constants::{
+        DEFAULT_ORDERING_STREAM, DEFAULT_SEQUENCING_STREAM, STANDARD_HEADER_SIZE, TIMESTAMP_SIZE,
+    },
     packet::{
         header::{
-            AckedPacketHeader, ArrangingHeader, FragmentHeader, HeaderWriter, StandardHeader,
+            AckedPacketHeader, ArrangingHeader, FragmentHeader, HeaderWriter, SelectiveAckHeader,
+            StandardHeader,
         },
-        DeliveryGuarantee, OrderingGuarantee, PacketType,
+        AckFieldWidth, Compression, DeliveryGuarantee, OrderingGuarantee, PacketType,
     },
 };
 
 /// Builder that could be used to construct an outgoing laminar packet.
 pub struct OutgoingPacketBuilder<'p> {
     header: Vec<u8>,
-    payload: &'p [u8],
+    payload: Cow<'p, [u8]>,
+    trailer: Option<[u8; TIMESTAMP_SIZE as usize]>,
 }
 
 impl<'p> OutgoingPacketBuilder<'p> {
     /// Construct a new builder from the given `payload`.
-    pub fn new(payload: &'p [u8]) -> OutgoingPacketBuilder<'p> {
+    /// The payload is either borrowed or, if it was compressed, owned by the builder.
+    pub fn new(payload: impl Into<Cow<'p, [u8]>>) -> OutgoingPacketBuilder<'p> {
         OutgoingPacketBuilder {
             header: Vec::new(),
-            payload,
+            payload: payload.into(),
+            trailer: None,
         }
     }
 
@@ -40,8 +48,14 @@ impl<'p> OutgoingPacketBuilder<'p> {
         packet_type: PacketType,
         delivery_guarantee: DeliveryGuarantee,
         ordering_guarantee: OrderingGuarantee,
+        compression: Compression,
+        ack_field_width: AckFieldWidth,
+        epoch: u8,
     ) -> Self {
-        let header = StandardHeader::new(delivery_guarantee, ordering_guarantee, packet_type);
+        let header = StandardHeader::new(delivery_guarantee, ordering_guarantee, packet_type)
+            .with_compression(compression)
+            .with_ack_field_width(ack_field_width)
+            .with_epoch(epoch);
         header
             .parse(&mut self.header)
             .expect("Could not write default header to buffer");
@@ -54,9 +68,11 @@ impl<'p> OutgoingPacketBuilder<'p> {
         mut self,
         seq_num: u16,
         last_seq: u16,
-        bit_field: u32,
+        bit_field: u64,
+        ack_field_width: AckFieldWidth,
     ) -> Self {
-        let header = AckedPacketHeader::new(seq_num, last_seq, bit_field);
+        let header =
+            AckedPacketHeader::new(seq_num, last_seq, bit_field).with_width(ack_field_width);
         header
             .parse(&mut self.header)
             .expect("Could not write acknowledgment header to buffer");
@@ -64,6 +80,27 @@ impl<'p> OutgoingPacketBuilder<'p> {
         self
     }
 
+    /// Adds the [`SelectiveAckHeader`](./headers/selective_ack_header) after the acknowledgment header.
+    ///
+    /// The standard header, which has to be added first, is marked to announce it.
+    pub fn with_selective_ack_header(mut self, header: SelectiveAckHeader) -> Self {
+        self.header[usize::from(STANDARD_HEADER_SIZE) - 1] |= 0x80;
+        header
+            .parse(&mut self.header)
+            .expect("Could not write selective acknowledgment header to buffer");
+
+        self
+    }
+
+    /// Appends the given send time to the payload, see `Config::packet_timestamps`.
+    ///
+    /// The standard header, which has to be added first, is marked to announce it.
+    pub fn with_timestamp(mut self, timestamp: u32) -> Self {
+        self.header[usize::from(STANDARD_HEADER_SIZE) - 1] |= 0x40;
+        self.trailer = Some(timestamp.to_be_bytes());
+        self
+    }
+
     /// Adds the [`ArrangingHeader`](./headers/arranging_header) if needed.
     ///
     /// - `arranging_id` = identifier for this packet that needs to be sequenced.
@@ -99,6 +136,7 @@ impl<'p> OutgoingPacketBuilder<'p> {
         OutgoingPacket {
             header: self.header,
             payload: self.payload,
+            trailer: self.trailer,
         }
     }
 }
@@ -107,7 +145,8 @@ impl<'p> OutgoingPacketBuilder<'p> {
 #[derive(Debug)]
 pub struct OutgoingPacket<'p> {
     header: Vec<u8>,
-    payload: &'p [u8],
+    payload: Cow<'p, [u8]>,
+    trailer: Option<[u8; TIMESTAMP_SIZE as usize]>,
 }
 
 impl<'p> OutgoingPacket<'p> {
@@ -115,18 +154,38 @@ impl<'p> OutgoingPacket<'p> {
     ///
     /// # Remark
     /// - Until here we could use a reference to the outgoing data but here we need to do a hard copy.
-    /// Because the header could vary in size but should be in front of the payload provided by the user.
+    ///   Because the header could vary in size but should be in front of the payload provided by the user.
+    /// - Packets are sent with `write_into`, this is kept for the tests.
+    #[cfg(test)]
     pub fn contents(&self) -> Box<[u8]> {
-        [self.header.as_slice(), &self.payload]
-            .concat()
-            .into_boxed_slice()
+        let mut contents = Vec::with_capacity(self.header.len() + self.payload.len());
+        self.write_into(&mut contents);
+        contents.into_boxed_slice()
+    }
+
+    /// Replaces the contents of the given buffer with the contents of this packet, see `contents`.
+    ///
+    /// Reusing the same buffer for every packet that is sent saves an allocation per packet.
+    pub fn write_into(&self, buffer: &mut Vec<u8>) {
+        buffer.clear();
+        buffer.extend_from_slice(&self.header);
+        buffer.extend_from_slice(&self.payload);
+        if let Some(trailer) = &self.trailer {
+            buffer.extend_from_slice(trailer);
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::net::constants::{ACKED_PACKET_HEADER, STANDARD_HEADER_SIZE};
+    use crate::packet::header::SelectiveAckHeader;
     use crate::packet::PacketType;
-    use crate::packet::{DeliveryGuarantee, OrderingGuarantee, OutgoingPacketBuilder};
+    use crate::packet::{
+        AckFieldWidth, Compression, DeliveryGuarantee, OrderingGuarantee, OutgoingPacketBuilder,
+        PacketReader,
+    };
+    use crate::protocol_version::ProtocolVersion;
 
     fn test_payload() -> Vec<u8> {
         b"test".to_vec()
@@ -145,6 +204,20 @@ mod tests {
         assert_eq!(outgoing.contents().to_vec(), expected);
     }
 
+    #[test]
+    fn write_into_replaces_the_contents_of_the_buffer() {
+        let payload = test_payload();
+
+        let outgoing = OutgoingPacketBuilder::new(&payload)
+            .with_sequencing_header(1, Some(2))
+            .build();
+
+        let mut buffer = vec![9; 20];
+        outgoing.write_into(&mut buffer);
+
+        assert_eq!(buffer, outgoing.contents().to_vec());
+    }
+
     #[test]
     fn assure_creation_arranging_header() {
         let payload = test_payload();
@@ -163,7 +236,7 @@ mod tests {
         let payload = test_payload();
 
         let outgoing = OutgoingPacketBuilder::new(&payload)
-            .with_acknowledgment_header(1, 2, 3)
+            .with_acknowledgment_header(1, 2, 3, AckFieldWidth::Bits32)
             .build();
 
         let expected: Vec<u8> = [vec![0, 1, 0, 2, 0, 0, 0, 3], test_payload()]
@@ -182,6 +255,9 @@ mod tests {
                 PacketType::Packet,
                 DeliveryGuarantee::Reliable,
                 OrderingGuarantee::Sequenced(None),
+                Compression::None,
+                AckFieldWidth::Bits32,
+                0,
             )
             .build();
 
@@ -192,4 +268,58 @@ mod tests {
             expected
         );
     }
+
+    #[test]
+    fn reliable_ordered_packet_has_the_documented_layout() {
+        let payload = test_payload();
+
+        let outgoing = OutgoingPacketBuilder::new(&payload)
+            .with_default_header(
+                PacketType::Packet,
+                DeliveryGuarantee::Reliable,
+                OrderingGuarantee::Ordered(Some(5)),
+                Compression::None,
+                AckFieldWidth::Bits32,
+                3,
+            )
+            .with_acknowledgment_header(1, 2, 7, AckFieldWidth::Bits32)
+            .with_selective_ack_header(SelectiveAckHeader::new(vec![(10, 12)]))
+            .with_ordering_header(4, Some(5))
+            .build();
+        let contents = outgoing.contents().to_vec();
+
+        // see `docs/md_book/src/packet_header.md`.
+        let version = ProtocolVersion::get_crc16().to_be_bytes();
+        let expected: Vec<u8> = [
+            vec![version[0], version[1], 0x00, 0x07, 0x82],
+            vec![0, 1, 0, 2, 0, 0, 0, 7],
+            vec![1, 0, 10, 0, 12],
+            vec![0, 4, 5],
+            test_payload(),
+        ]
+        .concat();
+        assert_eq!(contents, expected);
+
+        let mut reader = PacketReader::new(&contents);
+        let header = reader.read_standard_header().unwrap();
+        assert_eq!(header.epoch(), 3);
+        assert!(header.has_selective_acks());
+        assert_eq!(
+            header.ordering_guarantee(),
+            OrderingGuarantee::Ordered(None)
+        );
+
+        let acked = reader.read_acknowledge_header().unwrap();
+        assert_eq!(
+            (acked.sequence(), acked.ack_seq(), acked.ack_field()),
+            (1, 2, 7)
+        );
+        let selective_acks = reader.read_selective_ack_header().unwrap().unwrap();
+        assert_eq!(selective_acks.ranges(), &[(10, 12)]);
+
+        let offset = STANDARD_HEADER_SIZE + ACKED_PACKET_HEADER + selective_acks.encoded_size();
+        let arranging = reader.read_arranging_header(u16::from(offset)).unwrap();
+        assert_eq!((arranging.arranging_id(), arranging.stream_id()), (4, 5));
+        assert_eq!(reader.payload(), &test_payload()[..]);
+    }
 }
@@ -2,7 +2,8 @@ use std::io::Cursor;
 
 use crate::net::constants::STANDARD_HEADER_SIZE;
 use crate::packet::header::{
-    AckedPacketHeader, ArrangingHeader, FragmentHeader, HeaderReader, StandardHeader,
+    AckFieldWidth, AckedPacketHeader, ArrangingHeader, FragmentHeader, HeaderReader,
+    SelectiveAckHeader, StandardHeader,
 };
 use crate::{ErrorKind, Result};
 
@@ -11,9 +12,12 @@ use crate::{ErrorKind, Result};
 /// # Remarks
 /// - `PacketReader` is using an underlying `Cursor` to manage the reading of the bytes.
 /// - `PacketReader` can interpret where some data is located in the buffer, that's why you don't have to worry about the position of the `Cursor`.
+/// - The width of the acknowledgment bitfield is taken from the last read `StandardHeader`, as is whether selective acknowledgments follow it.
 pub struct PacketReader<'s> {
     buffer: &'s [u8],
     cursor: Cursor<&'s [u8]>,
+    ack_field_width: AckFieldWidth,
+    selective_acks: bool,
 }
 
 impl<'s> PacketReader<'s> {
@@ -22,6 +26,8 @@ impl<'s> PacketReader<'s> {
         PacketReader {
             buffer,
             cursor: Cursor::new(buffer),
+            ack_field_width: AckFieldWidth::default(),
+            selective_acks: false,
         }
     }
 
@@ -33,7 +39,10 @@ impl<'s> PacketReader<'s> {
         self.cursor.set_position(0);
 
         if self.can_read(StandardHeader::size()) {
-            StandardHeader::read(&mut self.cursor)
+            let header = StandardHeader::read(&mut self.cursor)?;
+            self.ack_field_width = header.ack_field_width();
+            self.selective_acks = header.has_selective_acks();
+            Ok(header)
         } else {
             Err(ErrorKind::CouldNotReadHeader(String::from("standard")))
         }
@@ -61,8 +70,8 @@ impl<'s> PacketReader<'s> {
         // acknowledge header comes after standard header.
         self.cursor.set_position(u64::from(STANDARD_HEADER_SIZE));
 
-        if self.can_read(AckedPacketHeader::size()) {
-            AckedPacketHeader::read(&mut self.cursor)
+        if self.can_read(self.ack_field_width.header_size()) {
+            AckedPacketHeader::read_with_width(&mut self.cursor, self.ack_field_width)
         } else {
             Err(ErrorKind::CouldNotReadHeader(String::from(
                 "acknowledgment",
@@ -70,19 +79,40 @@ impl<'s> PacketReader<'s> {
         }
     }
 
+    /// Reads the `SelectiveAckHeader` if the last read `StandardHeader` announced one.
+    ///
+    /// # Remark
+    /// - Notice that this will continue on the position of last read header, which has to be the `AckedPacketHeader`.
+    pub fn read_selective_ack_header(&mut self) -> Result<Option<SelectiveAckHeader>> {
+        if !self.selective_acks {
+            return Ok(None);
+        }
+
+        if self.can_read(SelectiveAckHeader::size()) {
+            SelectiveAckHeader::read(&mut self.cursor).map(Some)
+        } else {
+            Err(ErrorKind::CouldNotReadHeader(String::from(
+                "selective acknowledgment",
+            )))
+        }
+    }
+
     /// Reads the `FragmentHeader` and optionally the `AckedPacketHeader` from the underlying buffer.
     ///
     /// # Remark
     /// - Notice that this will continue on the position of last read header;
-    /// e.g. when reading `StandardHeader` the position of the underlying `Cursor` will be at the end where it left of,
-    /// when calling this function afterward it will read the `FragmentHeader` from there on.
+    ///   e.g. when reading `StandardHeader` the position of the underlying `Cursor` will be at the end where it left of,
+    ///   when calling this function afterward it will read the `FragmentHeader` from there on.
     /// - Note that only the first fragment of a sequence contains acknowledgment information that's why `AckedPacketHeader` is optional.
     pub fn read_fragment(&mut self) -> Result<(FragmentHeader, Option<AckedPacketHeader>)> {
         if self.can_read(FragmentHeader::size()) {
             let fragment_header = FragmentHeader::read(&mut self.cursor)?;
 
             let acked_header = if fragment_header.id() == 0 {
-                Some(AckedPacketHeader::read(&mut self.cursor)?)
+                Some(AckedPacketHeader::read_with_width(
+                    &mut self.cursor,
+                    self.ack_field_width,
+                )?)
             } else {
                 None
             };
@@ -93,16 +123,14 @@ impl<'s> PacketReader<'s> {
         }
     }
 
-    /// Reads the payload` from the underlying buffer.
+    /// Returns the payload from the underlying buffer, without copying it.
     ///
     /// # Remark
     /// - Notice that this will continue on the position of last read header;
-    /// e.g. when reading `StandardHeader` the position of the underlying `Cursor` will be at the end where it left of,
-    /// when calling this function afterward it will read all the bytes from there on.
-    pub fn read_payload(&self) -> Box<[u8]> {
-        self.buffer[self.cursor.position() as usize..self.buffer.len()]
-            .to_vec()
-            .into_boxed_slice()
+    ///   e.g. when reading `StandardHeader` the position of the underlying `Cursor` will be at the end where it left of,
+    ///   when calling this function afterward it will return all the bytes from there on.
+    pub fn payload(&self) -> &'s [u8] {
+        &self.buffer[self.cursor.position() as usize..]
     }
 
     // Checks if a given length of bytes could be read with the buffer.
@@ -121,14 +149,14 @@ mod tests {
         let buffer = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
 
         let reader = PacketReader::new(buffer.as_slice());
-        assert_eq!(reader.can_read(buffer.len() as u8), true);
-        assert_eq!(reader.can_read((buffer.len() + 1) as u8), false);
+        assert!(reader.can_read(buffer.len() as u8));
+        assert!(!reader.can_read((buffer.len() + 1) as u8));
     }
 
     #[test]
     fn assure_read_standard_header() {
         // standard header
-        let reliable_ordered_payload: Vec<u8> = vec![vec![0, 1, 0, 1, 2]].concat();
+        let reliable_ordered_payload: Vec<u8> = [vec![0, 1, 0, 1, 2]].concat();
 
         let mut reader = PacketReader::new(reliable_ordered_payload.as_slice());
 
@@ -150,7 +178,7 @@ mod tests {
     fn assure_read_acknowledgment_header() {
         // standard header, acked header
         let reliable_ordered_payload: Vec<u8> =
-            vec![vec![0, 1, 0, 1, 2], vec![0, 1, 0, 2, 0, 0, 0, 3]].concat();
+            [vec![0, 1, 0, 1, 2], vec![0, 1, 0, 2, 0, 0, 0, 3]].concat();
 
         let mut reader = PacketReader::new(reliable_ordered_payload.as_slice());
 
@@ -164,7 +192,7 @@ mod tests {
     #[test]
     fn assure_read_fragment_header() {
         // standard header, acked header, arranging header
-        let reliable_ordered_payload: Vec<u8> = vec![
+        let reliable_ordered_payload: Vec<u8> = [
             vec![0, 1, 0, 1, 2],
             vec![0, 1, 0, 3],
             vec![0, 1, 0, 2, 0, 0, 0, 3],
@@ -199,7 +227,7 @@ mod tests {
     #[test]
     fn assure_read_unreliable_sequenced_header() {
         // standard header, arranging header
-        let reliable_ordered_payload: Vec<u8> = vec![vec![0, 1, 0, 1, 2], vec![0, 1, 2]].concat();
+        let reliable_ordered_payload: Vec<u8> = [vec![0, 1, 0, 1, 2], vec![0, 1, 2]].concat();
 
         let mut reader = PacketReader::new(reliable_ordered_payload.as_slice());
 
@@ -214,7 +242,7 @@ mod tests {
     #[test]
     fn assure_read_reliable_ordered_header() {
         // standard header, acked header, arranging header
-        let reliable_ordered_payload: Vec<u8> = vec![
+        let reliable_ordered_payload: Vec<u8> = [
             vec![0, 1, 0, 1, 2],
             vec![0, 1, 0, 2, 0, 0, 0, 3],
             vec![0, 1, 2],
@@ -253,7 +281,7 @@ mod tests {
     fn assure_read_reliable_unordered_header() {
         // standard header, acked header, arranging header
         let reliable_ordered_payload: Vec<u8> =
-            vec![vec![0, 1, 0, 1, 2], vec![0, 1, 0, 2, 0, 0, 0, 3]].concat();
+            [vec![0, 1, 0, 1, 2], vec![0, 1, 0, 2, 0, 0, 0, 3]].concat();
         let mut reader = PacketReader::new(reliable_ordered_payload.as_slice());
 
         let standard_header = reader.read_standard_header().unwrap();
@@ -278,7 +306,7 @@ mod tests {
     #[test]
     fn expect_read_error() {
         // standard header (with one corrupt byte)
-        let reliable_ordered_payload: Vec<u8> = vec![vec![0, 1, 0, 1]].concat();
+        let reliable_ordered_payload: Vec<u8> = [vec![0, 1, 0, 1]].concat();
 
         let mut reader = PacketReader::new(reliable_ordered_payload.as_slice());
 
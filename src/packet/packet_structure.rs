@@ -1,6 +1,6 @@
 use std::net::SocketAddr;
 
-use crate::packet::{DeliveryGuarantee, OrderingGuarantee, PacketType};
+use crate::packet::{DeliveryGuarantee, OrderingGuarantee, PacketType, Priority};
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 /// This is a user friendly packet containing the payload, endpoint, and reliability guarantees.
@@ -15,15 +15,24 @@ use crate::packet::{DeliveryGuarantee, OrderingGuarantee, PacketType};
 /// |   **Reliable Sequenced**     |    Only old     |      No            |     Sequenced    |      Yes             |   Only newest
 ///
 /// You are able to send packets with the above reliability types.
-pub struct Packet {
+///
+/// The payload can be any `AsRef<[u8]>` type, e.g. a shared or pooled buffer, so that it does
+/// not have to be copied into a `Vec<u8>` before it is sent. Received packets always own a `Vec<u8>`.
+pub struct Packet<P = Vec<u8>> {
     /// The endpoint from where it came.
     addr: SocketAddr,
     /// The raw payload of the packet.
-    payload: Box<[u8]>,
+    payload: P,
     /// Defines on how the packet will be delivered.
     delivery: DeliveryGuarantee,
     /// Defines on how the packet will be ordered.
     ordering: OrderingGuarantee,
+    /// Identifies the packet in the `PacketAcked` event, once it is acknowledged.
+    id: Option<u64>,
+    /// The time the remote host sent the packet at, see `Config::packet_timestamps`.
+    sent_at: Option<u32>,
+    /// Decides which of the packets that are held back is sent first.
+    priority: Priority,
 }
 
 impl Packet {
@@ -36,12 +45,17 @@ impl Packet {
     ) -> Packet {
         Packet {
             addr,
-            payload,
+            payload: payload.into_vec(),
             delivery,
             ordering,
+            id: None,
+            sent_at: None,
+            priority: Priority::Normal,
         }
     }
+}
 
+impl<P: AsRef<[u8]>> Packet<P> {
     /// Creates a new unreliable packet by passing the receiver, data.
     ///
     /// Unreliable: Packets can be dropped, duplicated or arrive without order.
@@ -53,12 +67,15 @@ impl Packet {
     /// |       Any       |        Yes         |      No          |      No              |       No        |
     ///
     /// Basically just bare UDP. The packet may or may not be delivered.
-    pub fn unreliable(addr: SocketAddr, payload: Vec<u8>) -> Packet {
+    pub fn unreliable(addr: SocketAddr, payload: P) -> Packet<P> {
         Packet {
             addr,
-            payload: payload.into_boxed_slice(),
+            payload,
             delivery: DeliveryGuarantee::Unreliable,
             ordering: OrderingGuarantee::None,
+            id: None,
+            sent_at: None,
+            priority: Priority::Normal,
         }
     }
 
@@ -73,16 +90,15 @@ impl Packet {
     /// |    Any + old    |        No          |      Sequenced   |      No              |       No        |
     ///
     /// Basically just bare UDP, free to be dropped, but has some sequencing to it so that only the newest packets are kept.
-    pub fn unreliable_sequenced(
-        addr: SocketAddr,
-        payload: Vec<u8>,
-        stream_id: Option<u8>,
-    ) -> Packet {
+    pub fn unreliable_sequenced(addr: SocketAddr, payload: P, stream_id: Option<u8>) -> Packet<P> {
         Packet {
             addr,
-            payload: payload.into_boxed_slice(),
+            payload,
             delivery: DeliveryGuarantee::Unreliable,
             ordering: OrderingGuarantee::Sequenced(stream_id),
+            id: None,
+            sent_at: None,
+            priority: Priority::Normal,
         }
     }
 
@@ -96,12 +112,15 @@ impl Packet {
     /// |       No        |      No            |      No          |      Yes             |       Yes       |
     ///
     /// Basically this is almost TCP without ordering of packets.
-    pub fn reliable_unordered(addr: SocketAddr, payload: Vec<u8>) -> Packet {
+    pub fn reliable_unordered(addr: SocketAddr, payload: P) -> Packet<P> {
         Packet {
             addr,
-            payload: payload.into_boxed_slice(),
+            payload,
             delivery: DeliveryGuarantee::Reliable,
             ordering: OrderingGuarantee::None,
+            id: None,
+            sent_at: None,
+            priority: Priority::Normal,
         }
     }
 
@@ -119,12 +138,15 @@ impl Packet {
     ///
     /// # Remark
     /// - When `stream_id` is specified as `None` the default stream will be used; if you are not sure what this is you can leave it at `None`.
-    pub fn reliable_ordered(addr: SocketAddr, payload: Vec<u8>, stream_id: Option<u8>) -> Packet {
+    pub fn reliable_ordered(addr: SocketAddr, payload: P, stream_id: Option<u8>) -> Packet<P> {
         Packet {
             addr,
-            payload: payload.into_boxed_slice(),
+            payload,
             delivery: DeliveryGuarantee::Reliable,
             ordering: OrderingGuarantee::Ordered(stream_id),
+            id: None,
+            sent_at: None,
+            priority: Priority::Normal,
         }
     }
 
@@ -143,18 +165,21 @@ impl Packet {
     ///
     /// # Remark
     /// - When `stream_id` is specified as `None` the default stream will be used; if you are not sure what this is you can leave it at `None`.
-    pub fn reliable_sequenced(addr: SocketAddr, payload: Vec<u8>, stream_id: Option<u8>) -> Packet {
+    pub fn reliable_sequenced(addr: SocketAddr, payload: P, stream_id: Option<u8>) -> Packet<P> {
         Packet {
             addr,
-            payload: payload.into_boxed_slice(),
+            payload,
             delivery: DeliveryGuarantee::Reliable,
             ordering: OrderingGuarantee::Sequenced(stream_id),
+            id: None,
+            sent_at: None,
+            priority: Priority::Normal,
         }
     }
 
     /// Returns the payload of this packet.
     pub fn payload(&self) -> &[u8] {
-        &self.payload
+        self.payload.as_ref()
     }
 
     /// Returns the address of this packet.
@@ -175,6 +200,61 @@ impl Packet {
     pub fn order_guarantee(&self) -> OrderingGuarantee {
         self.ordering
     }
+
+    /// Sets an id which is reported with `SocketEvent::PacketAcked` once the packet is acknowledged.
+    ///
+    /// Only reliable packets are acknowledged, the id of other packets is ignored.
+    pub fn with_id(mut self, id: u64) -> Packet<P> {
+        self.id = Some(id);
+        self
+    }
+
+    /// Returns the id that was set with [`with_id`](#method.with_id).
+    pub fn id(&self) -> Option<u64> {
+        self.id
+    }
+
+    /// Sets the priority of the packet, see `Priority`. Packets are sent with `Priority::Normal` by default.
+    ///
+    /// Packets of different priorities may leave in another order than they were passed to `Socket::send`,
+    /// so packets on one ordered or sequenced stream should share a priority.
+    pub fn with_priority(mut self, priority: Priority) -> Packet<P> {
+        self.priority = priority;
+        self
+    }
+
+    /// Returns the priority that was set with [`with_priority`](#method.with_priority).
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// Returns the time the remote host sent this packet at, if it was received with `Config::packet_timestamps`.
+    ///
+    /// It counts the milliseconds since the connection was created on the side of the remote host, with the
+    /// clock that `Socket::clock_offset` compares to ours. The transit time of the packet can be computed with
+    /// that offset, e.g. to compensate the latency of player input. Resent packets carry the time of the resend.
+    pub fn sent_at(&self) -> Option<u32> {
+        self.sent_at
+    }
+
+    /// Sets the time the remote host sent this packet at, see `sent_at`.
+    pub(crate) fn with_sent_at(mut self, sent_at: Option<u32>) -> Packet<P> {
+        self.sent_at = sent_at;
+        self
+    }
+
+    /// Copies the payload into a `Vec<u8>`, this is how packets are handed back in events.
+    pub(crate) fn into_vec_payload(self) -> Packet {
+        Packet {
+            addr: self.addr,
+            payload: self.payload.as_ref().to_vec(),
+            delivery: self.delivery,
+            ordering: self.ordering,
+            id: self.id,
+            sent_at: self.sent_at,
+            priority: self.priority,
+        }
+    }
 }
 
 /// This packet type has similar properties to `Packet` except that it doesn't own anything, and additionally has `PacketType`.
@@ -205,6 +285,20 @@ impl<'a> PacketInfo<'a> {
         }
     }
 
+    /// Creates a packet that carries the framed messages of a `MessageBatch`.
+    pub fn batch_packet(
+        payload: &'a [u8],
+        delivery: DeliveryGuarantee,
+        ordering: OrderingGuarantee,
+    ) -> Self {
+        PacketInfo {
+            packet_type: PacketType::Batch,
+            payload,
+            delivery,
+            ordering,
+        }
+    }
+
     /// Creates a heartbeat packet that is expected to be sent over the network.
     pub fn heartbeat_packet(payload: &'a [u8]) -> Self {
         PacketInfo {
@@ -214,6 +308,108 @@ impl<'a> PacketInfo<'a> {
             ordering: OrderingGuarantee::None,
         }
     }
+
+    /// Creates a handshake packet that asks the remote host to accept the connection.
+    pub fn connect_packet() -> Self {
+        PacketInfo {
+            packet_type: PacketType::Connect,
+            payload: &[],
+            delivery: DeliveryGuarantee::Unreliable,
+            ordering: OrderingGuarantee::None,
+        }
+    }
+
+    /// Creates a handshake packet that accepts the connection the remote host asked for.
+    pub fn accept_packet() -> Self {
+        PacketInfo {
+            packet_type: PacketType::Accept,
+            payload: &[],
+            delivery: DeliveryGuarantee::Unreliable,
+            ordering: OrderingGuarantee::None,
+        }
+    }
+
+    /// Creates a handshake packet that challenges the remote host to send the given token back.
+    pub fn challenge_packet(token: &'a [u8]) -> Self {
+        PacketInfo {
+            packet_type: PacketType::Challenge,
+            payload: token,
+            delivery: DeliveryGuarantee::Unreliable,
+            ordering: OrderingGuarantee::None,
+        }
+    }
+
+    /// Creates a handshake packet that sends the token of a challenge back.
+    pub fn response_packet(token: &'a [u8]) -> Self {
+        PacketInfo {
+            packet_type: PacketType::Response,
+            payload: token,
+            delivery: DeliveryGuarantee::Unreliable,
+            ordering: OrderingGuarantee::None,
+        }
+    }
+
+    /// Creates a handshake packet that asks the remote host to move the connection with the given token
+    /// to the address it is sent from.
+    pub fn resume_packet(token: &'a [u8]) -> Self {
+        PacketInfo {
+            packet_type: PacketType::Resume,
+            payload: token,
+            delivery: DeliveryGuarantee::Unreliable,
+            ordering: OrderingGuarantee::None,
+        }
+    }
+
+    /// Creates a handshake packet that accepts a resume and carries the token the connection
+    /// can be resumed with from now on.
+    pub fn resume_accept_packet(token: &'a [u8]) -> Self {
+        PacketInfo {
+            packet_type: PacketType::Accept,
+            payload: token,
+            delivery: DeliveryGuarantee::Unreliable,
+            ordering: OrderingGuarantee::None,
+        }
+    }
+
+    /// Creates a ping packet that carries the given timestamp.
+    pub fn ping_packet(timestamp: &'a [u8]) -> Self {
+        PacketInfo {
+            packet_type: PacketType::Ping,
+            payload: timestamp,
+            delivery: DeliveryGuarantee::Unreliable,
+            ordering: OrderingGuarantee::None,
+        }
+    }
+
+    /// Creates a negative acknowledgment that carries the sequence numbers of missing packets.
+    pub fn nack_packet(sequences: &'a [u8]) -> Self {
+        PacketInfo {
+            packet_type: PacketType::Nack,
+            payload: sequences,
+            delivery: DeliveryGuarantee::Unreliable,
+            ordering: OrderingGuarantee::None,
+        }
+    }
+
+    /// Creates a pong packet that echoes the timestamp of a received ping back, followed by its own timestamp.
+    pub fn pong_packet(timestamps: &'a [u8]) -> Self {
+        PacketInfo {
+            packet_type: PacketType::Pong,
+            payload: timestamps,
+            delivery: DeliveryGuarantee::Unreliable,
+            ordering: OrderingGuarantee::None,
+        }
+    }
+
+    /// Creates a disconnect packet that tells the remote host that the connection is closed.
+    pub fn disconnect_packet() -> Self {
+        PacketInfo {
+            packet_type: PacketType::Disconnect,
+            payload: &[],
+            delivery: DeliveryGuarantee::Unreliable,
+            ordering: OrderingGuarantee::None,
+        }
+    }
 }
 
 #[cfg(test)]
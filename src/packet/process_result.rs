@@ -1,7 +1,8 @@
 use std::collections::VecDeque;
 
 use crate::either::Either;
-use crate::packet::{OutgoingPacket, Packet, PacketType};
+use crate::error::Result;
+use crate::packet::{batch, OutgoingPacket, Packet, PacketType};
 
 /// Used to return incoming (from bytes to packets) or outgoing (from packet to bytes) packets.
 /// It is used as optimization in cases, where most of the time there is only one element to iterate, and we don't want to create a vector for it.
@@ -99,6 +100,48 @@ impl IncomingPackets {
             data: ZeroOrMore::many(vec),
         }
     }
+
+    /// Replaces received batches with the messages they carry, each message becomes a packet of its own.
+    pub fn unbatch(self) -> Result<Self> {
+        match self.data.data {
+            Either::Left(Some((packet, PacketType::Batch))) => {
+                let mut packets = VecDeque::new();
+                push_messages(&mut packets, packet)?;
+                Ok(Self::many(packets))
+            }
+            Either::Right(vec) if vec.iter().any(|(_, kind)| *kind == PacketType::Batch) => {
+                let mut packets = VecDeque::with_capacity(vec.len());
+                for (packet, packet_type) in vec {
+                    if packet_type == PacketType::Batch {
+                        push_messages(&mut packets, packet)?;
+                    } else {
+                        packets.push_back((packet, packet_type));
+                    }
+                }
+                Ok(Self::many(packets))
+            }
+            data => Ok(Self {
+                data: ZeroOrMore { data },
+            }),
+        }
+    }
+}
+
+// Adds a packet for every message of the given batch.
+fn push_messages(packets: &mut VecDeque<(Packet, PacketType)>, batch: Packet) -> Result<()> {
+    for message in batch::read_messages(batch.payload())? {
+        packets.push_back((
+            Packet::new(
+                batch.addr(),
+                message.into(),
+                batch.delivery_guarantee(),
+                batch.order_guarantee(),
+            )
+            .with_sent_at(batch.sent_at()),
+            PacketType::Packet,
+        ));
+    }
+    Ok(())
 }
 
 impl IntoIterator for IncomingPackets {
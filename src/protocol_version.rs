@@ -2,6 +2,7 @@ use crc::crc16;
 
 use lazy_static::lazy_static;
 
+use crate::error::{ErrorKind, Result};
 pub use crate::net::constants::PROTOCOL_VERSION;
 
 lazy_static! {
@@ -31,6 +32,27 @@ impl ProtocolVersion {
     pub fn valid_version(protocol_version_crc16: u16) -> bool {
         protocol_version_crc16 == ProtocolVersion::get_crc16()
     }
+
+    /// Validate the protocol version in front of a datagram that is not encrypted.
+    ///
+    /// This is done before the checksum is verified, because peers of other versions may lay out
+    /// their datagrams differently and would only be seen as corrupt otherwise.
+    pub fn verify_datagram(datagram: &[u8]) -> Result<()> {
+        if datagram.len() < 2 {
+            // too short for any header, which the checksum reports.
+            return Ok(());
+        }
+
+        let got = u16::from_be_bytes([datagram[0], datagram[1]]);
+        if ProtocolVersion::valid_version(got) {
+            Ok(())
+        } else {
+            Err(ErrorKind::ProtocolVersionMismatch {
+                expected: ProtocolVersion::get_crc16(),
+                got,
+            })
+        }
+    }
 }
 
 #[cfg(test)]
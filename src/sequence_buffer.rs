@@ -12,8 +12,12 @@ mod reassembly_data;
 #[derive(Debug)]
 pub struct SequenceBuffer<T: Clone + Default> {
     sequence_num: SequenceNumber,
+    // Whether anything was inserted yet, until then the sequence does not have a start.
+    started: bool,
     entry_sequences: Box<[Option<SequenceNumber>]>,
     entries: Box<[T]>,
+    // the number of stored entries.
+    len: usize,
 }
 
 impl<T: Clone + Default> SequenceBuffer<T> {
@@ -21,11 +25,60 @@ impl<T: Clone + Default> SequenceBuffer<T> {
     pub fn with_capacity(size: u16) -> Self {
         Self {
             sequence_num: 0,
+            started: false,
             entry_sequences: vec![None; size as usize].into_boxed_slice(),
             entries: vec![T::default(); size as usize].into_boxed_slice(),
+            len: 0,
         }
     }
 
+    /// Returns the number of stored entries.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the number of entries that can be stored at once.
+    pub fn capacity(&self) -> usize {
+        self.entry_sequences.len()
+    }
+
+    /// Returns true if no entries are stored.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns a reference to the entry with the given sequence number.
+    #[allow(dead_code)]
+    pub fn get(&self, sequence_num: SequenceNumber) -> Option<&T> {
+        if self.exists(sequence_num) {
+            return Some(&self.entries[self.index(sequence_num)]);
+        }
+        None
+    }
+
+    /// Returns the stored entries with their sequence numbers, in the order of their slots.
+    pub fn iter(&self) -> impl Iterator<Item = (SequenceNumber, &T)> {
+        self.entry_sequences
+            .iter()
+            .zip(self.entries.iter())
+            .filter_map(|(sequence, entry)| sequence.map(|sequence| (sequence, entry)))
+    }
+
+    /// Returns the sequence number of the entry that an insert of the given one overwrites,
+    /// because they share a slot. The capacity does not reach back that far.
+    pub fn replaced_by(&self, sequence_num: SequenceNumber) -> Option<SequenceNumber> {
+        self.entry_sequences[self.index(sequence_num)].filter(|stored| *stored != sequence_num)
+    }
+
+    /// Returns a bitfield of which of the `bits` entries before the most recent one are stored,
+    /// the least significant bit stands for the entry right before it.
+    pub fn generate_ack_field(&self, bits: u16) -> u64 {
+        let most_recent = self.sequence_num.wrapping_sub(1);
+        (1..=bits.min(64))
+            .filter(|i| self.exists(most_recent.wrapping_sub(*i)))
+            .fold(0, |field, i| field | 1 << (i - 1))
+    }
+
     /// Returns the most recently stored sequence number.
     pub fn sequence_num(&self) -> SequenceNumber {
         self.sequence_num
@@ -42,7 +95,14 @@ impl<T: Clone + Default> SequenceBuffer<T> {
 
     /// Inserts the entry data into the sequence buffer. If the requested sequence number is "too
     /// old", the entry will not be inserted and no reference will be returned.
+    ///
+    /// The first entry that is inserted can have any sequence number, the sequence starts there.
     pub fn insert(&mut self, sequence_num: SequenceNumber, entry: T) -> Option<&mut T> {
+        if !self.started {
+            self.started = true;
+            self.sequence_num = sequence_num;
+        }
+
         // sequence number is too old to insert into the buffer
         if sequence_less_than(
             sequence_num,
@@ -55,6 +115,9 @@ impl<T: Clone + Default> SequenceBuffer<T> {
         self.advance_sequence(sequence_num);
 
         let index = self.index(sequence_num);
+        if self.entry_sequences[index].is_none() {
+            self.len += 1;
+        }
         self.entry_sequences[index] = Some(sequence_num);
         self.entries[index] = entry;
         Some(&mut self.entries[index])
@@ -73,13 +136,28 @@ impl<T: Clone + Default> SequenceBuffer<T> {
     pub fn remove(&mut self, sequence_num: SequenceNumber) -> Option<T> {
         if self.exists(sequence_num) {
             let index = self.index(sequence_num);
-            let value = std::mem::replace(&mut self.entries[index], T::default());
+            let value = std::mem::take(&mut self.entries[index]);
             self.entry_sequences[index] = None;
+            self.len -= 1;
             return Some(value);
         }
         None
     }
 
+    /// Removes all entries the given predicate returns true for, and returns how many were removed.
+    pub fn remove_where(&mut self, mut predicate: impl FnMut(&T) -> bool) -> usize {
+        let mut removed = 0;
+        for index in 0..self.entry_sequences.len() {
+            if self.entry_sequences[index].is_some() && predicate(&self.entries[index]) {
+                self.entries[index] = T::default();
+                self.entry_sequences[index] = None;
+                removed += 1;
+            }
+        }
+        self.len -= removed;
+        removed
+    }
+
     // Advances the sequence number while removing older entries.
     fn advance_sequence(&mut self, sequence_num: SequenceNumber) {
         if sequence_greater_than(sequence_num.wrapping_add(1), self.sequence_num) {
@@ -103,6 +181,7 @@ impl<T: Clone + Default> SequenceBuffer<T> {
                 self.entries[index] = T::default();
                 self.entry_sequences[index] = None;
             }
+            self.len = 0;
         }
     }
 
@@ -141,13 +220,13 @@ mod tests {
         assert!(sequence_less_than(32769, 0));
 
         // in this case, 0 is greater than u16 max because we're likely at the wrapping case
-        assert!(sequence_greater_than(0, u16::max_value()));
+        assert!(sequence_greater_than(0, u16::MAX));
     }
 
     #[test]
     fn max_sequence_number_should_not_exist_by_default() {
         let buffer: SequenceBuffer<DataStub> = SequenceBuffer::with_capacity(2);
-        assert!(!buffer.exists(u16::max_value()));
+        assert!(!buffer.exists(u16::MAX));
     }
 
     #[test]
@@ -222,14 +301,116 @@ mod tests {
         assert!(!buffer.exists(2));
 
         // insert respects boundary wrap. Both of these would be earlier than 11
-        buffer.insert(u16::max_value(), DataStub);
+        buffer.insert(u16::MAX, DataStub);
         buffer.insert(0, DataStub);
-        assert!(!buffer.exists(u16::max_value()));
+        assert!(!buffer.exists(u16::MAX));
         assert!(!buffer.exists(0));
 
         assert_eq!(count_entries(&buffer), 1);
     }
 
+    #[test]
+    fn sequence_starts_at_first_insert() {
+        let mut buffer = SequenceBuffer::with_capacity(8);
+        buffer.insert(40000, DataStub);
+        assert!(buffer.exists(40000));
+        assert_eq!(buffer.sequence_num(), 40001);
+
+        buffer.insert(39999, DataStub);
+        assert!(buffer.exists(39999));
+        assert_eq!(buffer.sequence_num(), 40001);
+    }
+
+    #[test]
+    fn entries_are_looked_up_by_sequence_number() {
+        let mut buffer = SequenceBuffer::with_capacity(4);
+        buffer.insert(5, 50);
+        buffer.insert(6, 60);
+
+        assert_eq!(buffer.get(5), Some(&50));
+        assert_eq!(buffer.get(6), Some(&60));
+        // 9 shares the slot of 5.
+        assert_eq!(buffer.get(9), None);
+
+        *buffer.get_mut(6).unwrap() += 1;
+        let mut entries: Vec<_> = buffer.iter().map(|(s, entry)| (s, *entry)).collect();
+        entries.sort();
+        assert_eq!(entries, vec![(5, 50), (6, 61)]);
+    }
+
+    #[test]
+    fn len_counts_stored_entries() {
+        let mut buffer = SequenceBuffer::with_capacity(4);
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.capacity(), 4);
+
+        for i in 0..4 {
+            buffer.insert(i, i);
+        }
+        // inserting a sequence number again replaces its entry.
+        buffer.insert(3, 30);
+        assert_eq!(buffer.len(), 4);
+
+        buffer.remove(0);
+        buffer.remove(0);
+        assert_eq!(buffer.len(), 3);
+
+        assert_eq!(buffer.remove_where(|entry| *entry < 3), 2);
+        assert_eq!(buffer.len(), 1);
+
+        // jumping further ahead than the capacity clears every slot.
+        buffer.insert(100, 100);
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.iter().count(), 1);
+    }
+
+    #[test]
+    fn len_counts_entries_evicted_by_newer_ones() {
+        let mut buffer = SequenceBuffer::with_capacity(4);
+        for i in 0..6 {
+            buffer.insert(i, DataStub);
+        }
+        assert_eq!(buffer.len(), 4);
+        assert_eq!(count_entries(&buffer), 4);
+    }
+
+    #[test]
+    fn replaced_by_returns_the_entry_that_shares_the_slot() {
+        let mut buffer = SequenceBuffer::with_capacity(4);
+        buffer.insert(u16::MAX - 1, DataStub);
+        buffer.insert(u16::MAX, DataStub);
+
+        assert_eq!(buffer.replaced_by(u16::MAX), None);
+        assert_eq!(buffer.replaced_by(0), None);
+        // 65534 and 2 share a slot, the capacity divides the sequence numbers evenly.
+        assert_eq!(buffer.replaced_by(2), Some(u16::MAX - 1));
+        assert_eq!(buffer.replaced_by(3), Some(u16::MAX));
+    }
+
+    #[test]
+    fn ack_field_marks_the_entries_before_the_most_recent_one() {
+        let mut buffer = SequenceBuffer::with_capacity(33);
+        for i in &[10, 12, 13, 14] {
+            buffer.insert(*i, DataStub);
+        }
+        // 14 is the most recent, 13 is the first bit.
+        assert_eq!(buffer.generate_ack_field(32), 0b1011);
+        assert_eq!(buffer.generate_ack_field(2), 0b11);
+    }
+
+    #[test]
+    fn ack_field_wraps_around_the_sequence_numbers() {
+        let mut buffer = SequenceBuffer::with_capacity(65);
+        for i in &[u16::MAX - 1, u16::MAX, 1] {
+            buffer.insert(*i, DataStub);
+        }
+        assert_eq!(buffer.generate_ack_field(64), 0b110);
+
+        // bits that do not fit into the field are left out.
+        buffer.insert(1 + 64, DataStub);
+        assert_eq!(buffer.generate_ack_field(100), 1 << 63);
+    }
+
     fn count_entries(buffer: &SequenceBuffer<DataStub>) -> usize {
         let nums: Vec<&SequenceNumber> = buffer.entry_sequences.iter().flatten().collect();
         nums.len()
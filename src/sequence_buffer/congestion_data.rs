@@ -5,6 +5,7 @@ use crate::packet::SequenceNumber;
 #[derive(Clone)]
 /// This contains the information required to reassemble fragments.
 pub struct CongestionData {
+    #[allow(dead_code)]
     pub sequence: SequenceNumber,
     pub sending_time: Instant,
 }
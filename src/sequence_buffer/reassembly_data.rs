@@ -1,3 +1,5 @@
+use std::time::Instant;
+
 use crate::net::constants::MAX_FRAGMENTS_DEFAULT;
 use crate::packet::header::AckedPacketHeader;
 use crate::packet::SequenceNumber;
@@ -11,10 +13,16 @@ pub struct ReassemblyData {
     pub buffer: Vec<u8>,
     pub fragments_received: [bool; MAX_FRAGMENTS_DEFAULT as usize],
     pub acked_header: Option<AckedPacketHeader>,
+    pub first_received: Instant,
 }
 
 impl ReassemblyData {
-    pub fn new(sequence: SequenceNumber, num_fragments_total: u8, prealloc: usize) -> Self {
+    pub fn new(
+        sequence: SequenceNumber,
+        num_fragments_total: u8,
+        prealloc: usize,
+        first_received: Instant,
+    ) -> Self {
         Self {
             sequence,
             num_fragments_received: 0,
@@ -22,6 +30,7 @@ impl ReassemblyData {
             buffer: Vec::with_capacity(prealloc),
             fragments_received: [false; MAX_FRAGMENTS_DEFAULT as usize],
             acked_header: None,
+            first_received,
         }
     }
 }
@@ -35,6 +44,7 @@ impl Default for ReassemblyData {
             buffer: Vec::with_capacity(1024),
             fragments_received: [false; MAX_FRAGMENTS_DEFAULT as usize],
             acked_header: None,
+            first_received: Instant::now(),
         }
     }
 }
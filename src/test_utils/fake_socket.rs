@@ -1,66 +1,277 @@
-use std::{net::SocketAddr, time::Instant};
-
-use crossbeam_channel::{Receiver, Sender};
-
-use crate::net::{ConnectionManager, LinkConditioner, VirtualConnection};
-use crate::test_utils::*;
-use crate::{error::Result, Config, Packet, SocketEvent};
-
-/// Provides a similar to the real a `Socket`, but with emulated socket implementation.
-pub struct FakeSocket {
-    handler: ConnectionManager<EmulatedSocket, VirtualConnection>,
-}
-
-impl FakeSocket {
-    /// Binds to the socket.
-    pub fn bind(network: &NetworkEmulator, addr: SocketAddr, config: Config) -> Result<Self> {
-        Ok(Self {
-            handler: ConnectionManager::new(network.new_socket(addr)?, config),
-        })
-    }
-
-    /// Returns a handle to the packet sender which provides a thread-safe way to enqueue packets
-    /// to be processed. This should be used when the socket is busy running its polling loop in a
-    /// separate thread.
-    pub fn get_packet_sender(&self) -> Sender<Packet> {
-        self.handler.event_sender().clone()
-    }
-
-    /// Returns a handle to the event receiver which provides a thread-safe way to retrieve events
-    /// from the socket. This should be used when the socket is busy running its polling loop in
-    /// a separate thread.
-    pub fn get_event_receiver(&self) -> Receiver<SocketEvent> {
-        self.handler.event_receiver().clone()
-    }
-
-    /// Sends a packet.
-    pub fn send(&mut self, packet: Packet) -> Result<()> {
-        // we can savely unwrap, because receiver will always exist
-        self.handler.event_sender().send(packet).unwrap();
-        Ok(())
-    }
-
-    /// Receives a packet.
-    pub fn recv(&mut self) -> Option<SocketEvent> {
-        if let Ok(event) = self.handler.event_receiver().try_recv() {
-            Some(event)
-        } else {
-            None
-        }
-    }
-
-    /// Processes any inbound/outbound packets and handle idle clients.
-    pub fn manual_poll(&mut self, time: Instant) {
-        self.handler.manual_poll(time);
-    }
-
-    /// Returns a number of active connections.
-    pub fn connection_count(&self) -> usize {
-        self.handler.connections_count()
-    }
-
-    /// Sets the link conditioner for this socket. See [LinkConditioner] for further details.
-    pub fn set_link_conditioner(&mut self, conditioner: Option<LinkConditioner>) {
-        self.handler.socket_mut().set_link_conditioner(conditioner);
-    }
-}
+use std::{
+    any::Any,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::net::{
+    AddressFilter, Bandwidth, Connection, ConnectionManager, ConnectionQuality, ConnectionSnapshot,
+    ConnectionState, ConnectionStats, LinkConditioner, NetworkStats, VirtualConnection,
+};
+use crate::packet::{DeliveryGuarantee, OrderingGuarantee};
+use crate::test_utils::*;
+use crate::{
+    error::{ErrorKind, Result},
+    Config, Packet, SocketEvent,
+};
+
+/// Provides a similar to the real a `Socket`, but with emulated socket implementation.
+pub struct FakeSocket {
+    handler: ConnectionManager<EmulatedSocket, VirtualConnection>,
+}
+
+impl FakeSocket {
+    /// Binds to the socket.
+    pub fn bind(network: &NetworkEmulator, addr: SocketAddr, config: Config) -> Result<Self> {
+        config.validate()?;
+        Ok(Self {
+            handler: ConnectionManager::new(network.new_socket(addr)?, config),
+        })
+    }
+
+    /// Returns a handle to the packet sender which provides a thread-safe way to enqueue packets
+    /// to be processed. This should be used when the socket is busy running its polling loop in a
+    /// separate thread.
+    pub fn get_packet_sender(&self) -> Sender<Packet> {
+        self.handler.event_sender().clone()
+    }
+
+    /// Returns a handle to the event receiver which provides a thread-safe way to retrieve events
+    /// from the socket. This should be used when the socket is busy running its polling loop in
+    /// a separate thread.
+    pub fn get_event_receiver(&self) -> Receiver<SocketEvent> {
+        self.handler.event_receiver().clone()
+    }
+
+    /// Sends a packet, see `Socket::send`.
+    pub fn send(&mut self, packet: Packet) -> Result<()> {
+        if packet.delivery_guarantee() == DeliveryGuarantee::Reliable
+            && self.handler.dropped_reports_full()
+        {
+            return Err(ErrorKind::DroppedBufferFull);
+        }
+        if let Some(connection) = self.handler.connection(&packet.addr()) {
+            connection.check_send_queue(packet.delivery_guarantee(), packet.priority())?;
+        }
+        // we can savely unwrap, because receiver will always exist
+        self.handler.event_sender().send(packet).unwrap();
+        Ok(())
+    }
+
+    /// Sends the same payload to every connected address, see `Socket::broadcast`.
+    pub fn broadcast(
+        &mut self,
+        payload: &[u8],
+        delivery: DeliveryGuarantee,
+        ordering: OrderingGuarantee,
+    ) -> Result<Vec<SocketAddr>> {
+        let addresses: Vec<SocketAddr> = self
+            .handler
+            .connected_addresses()
+            .into_iter()
+            .filter(|address| self.connection_state(address) == Some(ConnectionState::Connected))
+            .collect();
+        for address in &addresses {
+            self.send(Packet::new(*address, payload.into(), delivery, ordering))?;
+        }
+        Ok(addresses)
+    }
+
+    /// Receives a packet.
+    pub fn recv(&mut self) -> Option<SocketEvent> {
+        self.handler.event_receiver().try_recv().ok()
+    }
+
+    /// Returns the packets of all connections that are ready, see `Socket::drain_received`.
+    pub fn drain_received(&mut self) -> Vec<Packet> {
+        self.handler.take_events(|event| match event {
+            SocketEvent::Packet(packet) => Ok(packet),
+            event => Err(event),
+        })
+    }
+
+    /// Processes any inbound/outbound packets and handle idle clients.
+    pub fn manual_poll(&mut self, time: Instant) {
+        self.handler.manual_poll(time);
+    }
+
+    /// Closes the connection with the given address, see `Socket::disconnect`.
+    pub fn disconnect(&mut self, addr: &SocketAddr, time: Instant) {
+        self.handler.disconnect(addr, time);
+    }
+
+    /// Sends a ping to the given address, see `Socket::ping`.
+    pub fn ping(&mut self, addr: &SocketAddr, time: Instant) {
+        self.handler.ping(addr, time);
+    }
+
+    /// Sends the pending acknowledgments to the given address, see `Socket::flush_acks`.
+    pub fn flush_acks(&mut self, addr: &SocketAddr, time: Instant) {
+        self.handler.flush_acks(addr, time);
+    }
+
+    /// Resets the connection with the given address, see `Socket::reset`.
+    pub fn reset(&mut self, addr: &SocketAddr, time: Instant) {
+        self.handler.reset(addr, time);
+    }
+
+    /// Asks the remote host to move the connection to the current address, see `Socket::resume`.
+    pub fn resume(&mut self, addr: &SocketAddr, time: Instant) {
+        self.handler.resume(addr, time);
+    }
+
+    /// Moves the socket to another address, like a NAT that changed its mapping.
+    /// Datagrams that were not received yet are moved as well.
+    pub fn rebind(&mut self, addr: SocketAddr) {
+        self.handler.socket_mut().rebind(addr);
+    }
+
+    /// Overrides the idle timeout of the connection with the given address, see `Socket::set_timeout`.
+    pub fn set_timeout(&mut self, addr: &SocketAddr, timeout: Duration) {
+        if let Some(connection) = self.handler.connection_mut(addr) {
+            connection.timeout = Some(timeout);
+        }
+    }
+
+    /// Sets a closure that is called for every connection that times out, see `Socket::set_timeout_hook`.
+    pub fn set_timeout_hook(&mut self, hook: impl FnMut(SocketAddr) + Send + 'static) {
+        self.handler.set_timeout_hook(hook);
+    }
+
+    /// Sets which remote addresses packets are accepted from, see `Socket::set_address_filter`.
+    pub fn set_address_filter(&mut self, filter: AddressFilter) {
+        self.handler.set_address_filter(filter);
+    }
+
+    /// Stores application data with the connection of the given address, see `Socket::set_user_data`.
+    pub fn set_user_data<T: Any + Send + Sync>(&mut self, addr: &SocketAddr, data: T) {
+        if let Some(connection) = self.handler.connection_mut(addr) {
+            connection.user_data = Some(Box::new(data));
+        }
+    }
+
+    /// Returns the application data stored for the given address, see `Socket::user_data`.
+    pub fn user_data<T: Any>(&self, addr: &SocketAddr) -> Option<&T> {
+        self.handler
+            .connection(addr)
+            .and_then(|connection| connection.user_data.as_ref())
+            .and_then(|data| data.downcast_ref())
+    }
+
+    /// Returns a number of active connections.
+    pub fn connection_count(&self) -> usize {
+        self.handler.connections_count()
+    }
+
+    /// Returns the number of reliable packets to the given address that are not acknowledged yet.
+    pub fn packets_in_flight(&self, addr: &SocketAddr) -> Option<u16> {
+        self.handler
+            .connection(addr)
+            .map(VirtualConnection::packets_in_flight)
+    }
+
+    /// Returns the reliable packets to the given address that are not acknowledged yet,
+    /// with how long they have been waiting at the given time, see `Socket::pending_packets`.
+    pub fn pending_packets(
+        &self,
+        addr: &SocketAddr,
+        time: Instant,
+    ) -> Option<Vec<(u16, Duration)>> {
+        self.handler
+            .connection(addr)
+            .map(|connection| connection.pending_packets(time).collect())
+    }
+
+    /// Returns the round trip time to the given address, see `Socket::rtt`.
+    pub fn rtt(&self, addr: &SocketAddr) -> Option<Duration> {
+        self.handler.connection(addr).map(VirtualConnection::rtt)
+    }
+
+    /// Returns the sequence number of the next reliable packet to the given address, see `Socket::local_sequence`.
+    pub fn local_sequence(&self, addr: &SocketAddr) -> Option<u16> {
+        self.handler
+            .connection(addr)
+            .map(VirtualConnection::local_sequence)
+    }
+
+    /// Returns the newest sequence number the given address acknowledged, see `Socket::remote_ack_sequence`.
+    pub fn remote_ack_sequence(&self, addr: &SocketAddr) -> Option<u16> {
+        self.handler
+            .connection(addr)
+            .map(VirtualConnection::remote_ack_sequence)
+    }
+
+    /// Returns how long the remote host at the given address has been silent, see `Socket::idle_for`.
+    pub fn idle_for(&self, addr: &SocketAddr, time: Instant) -> Option<Duration> {
+        self.handler
+            .connection(addr)
+            .map(|connection| connection.idle_for(time))
+    }
+
+    /// Returns the estimated clock offset of the given address, see `Socket::clock_offset`.
+    pub fn clock_offset(&self, addr: &SocketAddr) -> Option<i64> {
+        self.handler
+            .connection(addr)
+            .and_then(VirtualConnection::clock_offset)
+    }
+
+    /// Returns the jitter of the round trip time to the given address, see `Socket::jitter`.
+    pub fn jitter(&self, addr: &SocketAddr) -> Option<Duration> {
+        self.handler.connection(addr).map(VirtualConnection::jitter)
+    }
+
+    /// Returns the packet counters of the connection with the given address, see `Socket::stats`.
+    pub fn stats(&self, addr: &SocketAddr) -> Option<ConnectionStats> {
+        self.handler.connection(addr).map(VirtualConnection::stats)
+    }
+
+    /// Returns the bytes per second sent to and received from the given address, see `Socket::bandwidth`.
+    pub fn bandwidth(&self, addr: &SocketAddr) -> Option<Bandwidth> {
+        self.handler
+            .connection(addr)
+            .map(VirtualConnection::bandwidth)
+    }
+
+    /// Returns the rating of the connection with the given address, see `Socket::quality`.
+    pub fn quality(&self, addr: &SocketAddr) -> Option<ConnectionQuality> {
+        self.handler
+            .connection(addr)
+            .map(VirtualConnection::quality)
+    }
+
+    /// Returns the state of the connection with the given address, see `Socket::connection_state`.
+    pub fn connection_state(&self, addr: &SocketAddr) -> Option<ConnectionState> {
+        self.handler.connection(addr).map(Connection::state)
+    }
+
+    /// Returns a copy of the state of every active connection, see `Socket::snapshot`.
+    pub fn snapshot(&self) -> Vec<ConnectionSnapshot> {
+        self.handler
+            .connections()
+            .map(VirtualConnection::snapshot)
+            .collect()
+    }
+
+    /// Returns the totals over all connections of this socket.
+    pub fn aggregate_stats(&self) -> NetworkStats {
+        self.handler.aggregate_stats()
+    }
+
+    /// Returns the stats of this socket and its connections as JSON, see `Socket::stats_json`.
+    #[cfg(feature = "json")]
+    pub fn stats_json(&self) -> String {
+        crate::net::stats_json(self.handler.aggregate_stats(), self.handler.connections())
+    }
+
+    /// Resets the totals returned by `aggregate_stats`.
+    pub fn reset_stats(&mut self) {
+        self.handler.reset_stats();
+    }
+
+    /// Sets the link conditioner for this socket. See [LinkConditioner] for further details.
+    pub fn set_link_conditioner(&mut self, conditioner: Option<LinkConditioner>) {
+        self.handler.socket_mut().set_link_conditioner(conditioner);
+    }
+}
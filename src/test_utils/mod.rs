@@ -1,5 +1,5 @@
-pub use fake_socket::FakeSocket;
-pub use network_emulator::{EmulatedSocket, NetworkEmulator};
-
-mod fake_socket;
-mod network_emulator;
+pub use fake_socket::FakeSocket;
+pub use network_emulator::{EmulatedSocket, NetworkEmulator};
+
+mod fake_socket;
+mod network_emulator;
@@ -1,106 +1,153 @@
-use std::{
-    cell::RefCell,
-    collections::hash_map::Entry,
-    collections::{HashMap, VecDeque},
-    io::Result,
-    net::SocketAddr,
-    rc::Rc,
-};
-
-use crate::net::{DatagramSocket, LinkConditioner};
-
-/// This type allows to share global state between all sockets, created from the same instance of `NetworkEmulator`.
-type GlobalBindings = Rc<RefCell<HashMap<SocketAddr, VecDeque<(SocketAddr, Vec<u8>)>>>>;
-
-/// Enables to create the emulated socket, that share global state stored by this network emulator.
-#[derive(Debug, Default)]
-pub struct NetworkEmulator {
-    network: GlobalBindings,
-}
-
-impl NetworkEmulator {
-    /// Creates an emulated socket by binding to an address.
-    /// If other socket already was bound to this address, error will be returned instead.
-    pub fn new_socket(&self, address: SocketAddr) -> Result<EmulatedSocket> {
-        match self.network.borrow_mut().entry(address) {
-            Entry::Occupied(_) => Err(std::io::Error::new(
-                std::io::ErrorKind::AddrInUse,
-                "Cannot bind to address",
-            )),
-            Entry::Vacant(entry) => {
-                entry.insert(Default::default());
-                Ok(EmulatedSocket {
-                    network: self.network.clone(),
-                    address,
-                    conditioner: Default::default(),
-                })
-            }
-        }
-    }
-
-    /// Clear all packets from a socket that is bound to provided address.
-    pub fn clear_packets(&self, addr: SocketAddr) {
-        if let Some(packets) = self.network.borrow_mut().get_mut(&addr) {
-            packets.clear();
-        }
-    }
-}
-
-/// Implementation of a socket, that is created by `NetworkEmulator`.
-#[derive(Debug, Clone)]
-pub struct EmulatedSocket {
-    network: GlobalBindings,
-    address: SocketAddr,
-    conditioner: Option<LinkConditioner>,
-}
-
-impl EmulatedSocket {
-    pub fn set_link_conditioner(&mut self, conditioner: Option<LinkConditioner>) {
-        self.conditioner = conditioner;
-    }
-}
-
-impl DatagramSocket for EmulatedSocket {
-    /// Sends a packet to and address if there is a socket bound to it. Otherwise it will simply be ignored.
-    fn send_packet(&mut self, addr: &SocketAddr, payload: &[u8]) -> Result<usize> {
-        let send = if let Some(ref mut conditioner) = self.conditioner {
-            conditioner.should_send()
-        } else {
-            true
-        };
-        if send {
-            if let Some(binded) = self.network.borrow_mut().get_mut(addr) {
-                binded.push_back((self.address, payload.to_vec()));
-            }
-            Ok(payload.len())
-        } else {
-            Ok(0)
-        }
-    }
-
-    /// Receives a packet from this socket.
-    fn receive_packet<'a>(&mut self, buffer: &'a mut [u8]) -> Result<(&'a [u8], SocketAddr)> {
-        if let Some((addr, payload)) = self
-            .network
-            .borrow_mut()
-            .get_mut(&self.address)
-            .unwrap()
-            .pop_front()
-        {
-            let slice = &mut buffer[..payload.len()];
-            slice.copy_from_slice(payload.as_ref());
-            Ok((slice, addr))
-        } else {
-            Err(std::io::ErrorKind::WouldBlock.into())
-        }
-    }
-
-    /// Returns the socket address that this socket was created from.
-    fn local_addr(&self) -> Result<SocketAddr> {
-        Ok(self.address)
-    }
-
-    fn is_blocking_mode(&self) -> bool {
-        false
-    }
-}
+use std::{
+    cell::RefCell,
+    collections::hash_map::Entry,
+    collections::{HashMap, VecDeque},
+    io::Result,
+    net::SocketAddr,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use crate::net::{Clock, DatagramSocket, LinkConditioner, ManualClock};
+
+/// This type allows to share global state between all sockets, created from the same instance of `NetworkEmulator`.
+type GlobalBindings = Rc<RefCell<HashMap<SocketAddr, VecDeque<Datagram>>>>;
+
+/// A datagram on its way to a socket, it can be received once the network clock reached `deliver_at`.
+#[derive(Debug, Clone)]
+struct Datagram {
+    from: SocketAddr,
+    payload: Vec<u8>,
+    deliver_at: Instant,
+}
+
+/// Enables to create the emulated socket, that share global state stored by this network emulator.
+#[derive(Debug, Default)]
+pub struct NetworkEmulator {
+    network: GlobalBindings,
+    clock: ManualClock,
+}
+
+impl NetworkEmulator {
+    /// Creates an emulated socket by binding to an address.
+    /// If other socket already was bound to this address, error will be returned instead.
+    pub fn new_socket(&self, address: SocketAddr) -> Result<EmulatedSocket> {
+        match self.network.borrow_mut().entry(address) {
+            Entry::Occupied(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                "Cannot bind to address",
+            )),
+            Entry::Vacant(entry) => {
+                entry.insert(Default::default());
+                Ok(EmulatedSocket {
+                    network: self.network.clone(),
+                    address,
+                    conditioner: Default::default(),
+                    clock: self.clock.clone(),
+                })
+            }
+        }
+    }
+
+    /// Returns the clock that decides when datagrams, which are delayed by a link conditioner, arrive.
+    /// Datagrams without latency arrive right away.
+    pub fn clock(&self) -> ManualClock {
+        self.clock.clone()
+    }
+
+    /// Clear all packets from a socket that is bound to provided address.
+    pub fn clear_packets(&self, addr: SocketAddr) {
+        if let Some(packets) = self.network.borrow_mut().get_mut(&addr) {
+            packets.clear();
+        }
+    }
+}
+
+/// Implementation of a socket, that is created by `NetworkEmulator`.
+#[derive(Debug, Clone)]
+pub struct EmulatedSocket {
+    network: GlobalBindings,
+    address: SocketAddr,
+    conditioner: Option<LinkConditioner>,
+    clock: ManualClock,
+}
+
+impl EmulatedSocket {
+    pub fn set_link_conditioner(&mut self, conditioner: Option<LinkConditioner>) {
+        self.conditioner = conditioner;
+    }
+
+    /// Moves the binding of this socket to another address, with the datagrams it did not receive yet.
+    pub fn rebind(&mut self, address: SocketAddr) {
+        let mut network = self.network.borrow_mut();
+        let datagrams = network.remove(&self.address).unwrap_or_default();
+        network.insert(address, datagrams);
+        self.address = address;
+    }
+}
+
+impl DatagramSocket for EmulatedSocket {
+    /// Sends a packet to and address if there is a socket bound to it. Otherwise it will simply be ignored.
+    /// The link conditioner of this socket may drop, delay, duplicate or reorder the packet.
+    fn send_packet(&mut self, addr: &SocketAddr, payload: &[u8]) -> Result<usize> {
+        let (send, duplicate, reorder, delay) = if let Some(ref mut conditioner) = self.conditioner
+        {
+            (
+                conditioner.should_send(),
+                conditioner.should_duplicate(),
+                conditioner.should_reorder(),
+                conditioner.delay(),
+            )
+        } else {
+            (true, false, false, Duration::default())
+        };
+        if send {
+            if let Some(binded) = self.network.borrow_mut().get_mut(addr) {
+                let datagram = Datagram {
+                    from: self.address,
+                    payload: payload.to_vec(),
+                    deliver_at: self.clock.now() + delay,
+                };
+                for _ in 0..if duplicate { 2 } else { 1 } {
+                    // a reordered datagram overtakes the one that was sent before it.
+                    let index = if reorder {
+                        binded.len().saturating_sub(1)
+                    } else {
+                        binded.len()
+                    };
+                    binded.insert(index, datagram.clone());
+                }
+            }
+            Ok(payload.len())
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Receives a packet from this socket, packets that are still delayed are skipped.
+    fn receive_packet<'a>(&mut self, buffer: &'a mut [u8]) -> Result<(&'a [u8], SocketAddr)> {
+        let now = self.clock.now();
+        let mut network = self.network.borrow_mut();
+        let received = network.get_mut(&self.address).unwrap();
+        if let Some(datagram) = received
+            .iter()
+            .position(|datagram| datagram.deliver_at <= now)
+            .and_then(|index| received.remove(index))
+        {
+            let slice = &mut buffer[..datagram.payload.len()];
+            slice.copy_from_slice(datagram.payload.as_ref());
+            Ok((slice, datagram.from))
+        } else {
+            Err(std::io::ErrorKind::WouldBlock.into())
+        }
+    }
+
+    /// Returns the socket address that this socket was created from.
+    fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.address)
+    }
+
+    fn is_blocking_mode(&self) -> bool {
+        false
+    }
+}
@@ -1,146 +1,523 @@
-use std::{collections::HashSet, net::SocketAddr, time::Instant};
-
-#[cfg(feature = "tester")]
-use laminar::LinkConditioner;
-use laminar::{Config, Packet, Socket, SocketEvent};
-
-#[test]
-fn binding_to_any() {
-    // bind to 10 different addresses
-    let sock_without_config = (0..5).map(|_| Socket::bind_any());
-    let sock_with_config = (0..5).map(|_| Socket::bind_any_with_config(Config::default()));
-
-    let valid_socks: Vec<_> = sock_without_config
-        .chain(sock_with_config)
-        .filter_map(|sock| sock.ok())
-        .collect();
-    assert_eq!(valid_socks.len(), 10);
-
-    let unique_addresses: HashSet<_> = valid_socks
-        .into_iter()
-        .map(|sock| sock.local_addr().unwrap())
-        .collect();
-    assert_eq!(unique_addresses.len(), 10);
-}
-
-#[test]
-fn blocking_sender_and_receiver() {
-    let cfg = Config::default();
-
-    let mut client = Socket::bind_any_with_config(cfg.clone()).unwrap();
-    let mut server = Socket::bind_any_with_config(Config {
-        blocking_mode: true,
-        ..cfg
-    })
-    .unwrap();
-
-    let server_addr = server.local_addr().unwrap();
-    let client_addr = client.local_addr().unwrap();
-
-    let time = Instant::now();
-
-    client
-        .send(Packet::unreliable(server_addr, b"Hello world!".to_vec()))
-        .unwrap();
-
-    client.manual_poll(time);
-    server.manual_poll(time);
-
-    assert_eq![SocketEvent::Connect(client_addr), server.recv().unwrap()];
-    if let SocketEvent::Packet(packet) = server.recv().unwrap() {
-        assert_eq![b"Hello world!", packet.payload()];
-    } else {
-        panic!["Did not receive a packet when it should"];
-    }
-}
-
-#[test]
-fn local_addr() {
-    let port = 40000;
-    let socket =
-        Socket::bind(format!("127.0.0.1:{}", port).parse::<SocketAddr>().unwrap()).unwrap();
-    assert_eq!(port, socket.local_addr().unwrap().port());
-}
-
-#[test]
-#[cfg(feature = "tester")]
-fn use_link_conditioner() {
-    let mut client = Socket::bind_any().unwrap();
-    let mut server = Socket::bind_any().unwrap();
-
-    let server_addr = server.local_addr().unwrap();
-
-    let link_conditioner = {
-        let mut lc = LinkConditioner::new();
-        lc.set_packet_loss(1.0);
-        Some(lc)
-    };
-
-    client.set_link_conditioner(link_conditioner);
-    client
-        .send(Packet::unreliable(server_addr, b"Hello world!".to_vec()))
-        .unwrap();
-
-    let time = Instant::now();
-    client.manual_poll(time);
-    server.manual_poll(time);
-
-    assert_eq!(server.recv().is_none(), true);
-}
-
-#[test]
-#[cfg(feature = "tester")]
-fn poll_in_thread() {
-    use std::thread;
-    let mut server = Socket::bind_any().unwrap();
-    let mut client = Socket::bind_any().unwrap();
-    let server_addr = server.local_addr().unwrap();
-
-    // get sender and receiver from server, and start polling in separate thread
-    let (sender, receiver) = (server.get_packet_sender(), server.get_event_receiver());
-    let _thread = thread::spawn(move || server.start_polling());
-
-    // server will responde to this
-    client
-        .send(Packet::reliable_unordered(server_addr, b"Hello!".to_vec()))
-        .expect("This should send");
-    // this will break the loop
-    client
-        .send(Packet::reliable_unordered(server_addr, b"Bye!".to_vec()))
-        .expect("This should send");
-    client.manual_poll(Instant::now());
-
-    // listen for received server messages, and break when "Bye!" is received.
-    loop {
-        if let Ok(event) = receiver.recv() {
-            if let SocketEvent::Packet(packet) = event {
-                let msg = packet.payload();
-
-                if msg == b"Bye!" {
-                    break;
-                }
-
-                sender
-                    .send(Packet::reliable_unordered(
-                        packet.addr(),
-                        b"Hi, there!".to_vec(),
-                    ))
-                    .expect("This should send");
-            }
-        }
-    }
-    // loop until we get response from server.
-    loop {
-        client.manual_poll(Instant::now());
-        if let Some(packet) = client.recv() {
-            assert_eq!(
-                packet,
-                SocketEvent::Packet(Packet::reliable_unordered(
-                    server_addr,
-                    b"Hi, there!".to_vec()
-                ))
-            );
-            break;
-        }
-    }
-}
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "tester")]
+use laminar::LinkConditioner;
+use laminar::{Config, ErrorKind, ManualClock, Packet, PayloadSocket, Socket, SocketEvent};
+
+#[test]
+fn binding_to_any() {
+    // bind to 10 different addresses
+    let sock_without_config = (0..5).map(|_| Socket::bind_any());
+    let sock_with_config = (0..5).map(|_| Socket::bind_any_with_config(Config::default()));
+
+    let valid_socks: Vec<_> = sock_without_config
+        .chain(sock_with_config)
+        .filter_map(|sock| sock.ok())
+        .collect();
+    assert_eq!(valid_socks.len(), 10);
+
+    let unique_addresses: HashSet<_> = valid_socks
+        .into_iter()
+        .map(|sock| sock.local_addr().unwrap())
+        .collect();
+    assert_eq!(unique_addresses.len(), 10);
+}
+
+#[test]
+fn blocking_sender_and_receiver() {
+    let cfg = Config::default();
+
+    let mut client = Socket::bind_any_with_config(cfg.clone()).unwrap();
+    let mut server = Socket::bind_any_with_config(Config {
+        blocking_mode: true,
+        ..cfg
+    })
+    .unwrap();
+
+    let server_addr = server.local_addr().unwrap();
+    let client_addr = client.local_addr().unwrap();
+
+    let time = Instant::now();
+
+    client
+        .send(Packet::unreliable(server_addr, b"Hello world!".to_vec()))
+        .unwrap();
+
+    client.manual_poll(time);
+    server.manual_poll(time);
+
+    assert_eq![SocketEvent::Connect(client_addr), server.recv().unwrap()];
+    if let SocketEvent::Packet(packet) = server.recv().unwrap() {
+        assert_eq![b"Hello world!", packet.payload()];
+    } else {
+        panic!["Did not receive a packet when it should"];
+    }
+}
+
+#[test]
+fn non_blocking_sockets_exchange_packets_over_loopback() {
+    let mut server = Socket::bind_any().unwrap();
+    let mut client = Socket::bind_any().unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let client_addr = client.local_addr().unwrap();
+
+    // nothing was sent yet, polling must not block.
+    server.manual_poll(Instant::now());
+    assert_eq!(server.recv(), None);
+
+    client
+        .send(Packet::reliable_unordered(server_addr, b"Hello".to_vec()))
+        .unwrap();
+    client.manual_poll(Instant::now());
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut events = Vec::new();
+    while events.len() < 2 && Instant::now() < deadline {
+        server.manual_poll(Instant::now());
+        events.extend(std::iter::from_fn(|| server.recv()));
+    }
+
+    assert_eq!(
+        events,
+        vec![
+            SocketEvent::Connect(client_addr),
+            SocketEvent::Packet(Packet::reliable_unordered(client_addr, b"Hello".to_vec())),
+        ]
+    );
+}
+
+#[test]
+fn shared_payloads_are_sent_without_copying_them_first() {
+    let mut server = Socket::bind_any().unwrap();
+    let mut client = PayloadSocket::<Arc<[u8]>>::bind_any().unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let client_addr = client.local_addr().unwrap();
+
+    // the same buffer is handed to both packets.
+    let payload: Arc<[u8]> = Arc::from(&b"Hello"[..]);
+    client
+        .send(Packet::unreliable(server_addr, payload.clone()))
+        .unwrap();
+    client
+        .send(Packet::reliable_unordered(server_addr, payload.clone()))
+        .unwrap();
+    client.manual_poll(Instant::now());
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut packets = Vec::new();
+    while packets.len() < 2 && Instant::now() < deadline {
+        server.manual_poll(Instant::now());
+        // every packet from the unknown client comes with a connect event.
+        packets.extend(server.events().filter_map(|event| match event {
+            SocketEvent::Packet(packet) => Some(packet),
+            _ => None,
+        }));
+    }
+
+    assert_eq!(
+        packets,
+        vec![
+            Packet::unreliable(client_addr, b"Hello".to_vec()),
+            Packet::reliable_unordered(client_addr, b"Hello".to_vec()),
+        ]
+    );
+}
+
+#[test]
+fn events_are_taken_after_each_poll() {
+    let mut server = Socket::bind_any().unwrap();
+    let mut client = Socket::bind_any().unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let client_addr = client.local_addr().unwrap();
+
+    for id in 0..3 {
+        client
+            .send(Packet::unreliable(server_addr, vec![id]))
+            .unwrap();
+    }
+    client.poll();
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut payloads = HashSet::new();
+    while payloads.len() < 3 && Instant::now() < deadline {
+        server.poll();
+        for event in server.events() {
+            match event {
+                SocketEvent::Packet(packet) => {
+                    assert_eq!(packet.addr(), client_addr);
+                    payloads.insert(packet.payload().to_vec());
+                }
+                SocketEvent::Connect(addr) => assert_eq!(addr, client_addr),
+                event => panic!["Unexpected event {:?}", event],
+            }
+        }
+    }
+
+    assert_eq!(payloads, (0..3).map(|id| vec![id]).collect::<HashSet<_>>());
+    assert_eq!(server.events().next(), None);
+}
+
+#[test]
+fn local_addr() {
+    let port = 40000;
+    let socket =
+        Socket::bind(format!("127.0.0.1:{}", port).parse::<SocketAddr>().unwrap()).unwrap();
+    assert_eq!(port, socket.local_addr().unwrap().port());
+}
+
+#[test]
+#[cfg(feature = "tester")]
+fn use_link_conditioner() {
+    let mut client = Socket::bind_any().unwrap();
+    let mut server = Socket::bind_any().unwrap();
+
+    let server_addr = server.local_addr().unwrap();
+
+    let link_conditioner = {
+        let mut lc = LinkConditioner::new();
+        lc.set_packet_loss(1.0);
+        Some(lc)
+    };
+
+    client.set_link_conditioner(link_conditioner);
+    client
+        .send(Packet::unreliable(server_addr, b"Hello world!".to_vec()))
+        .unwrap();
+
+    let time = Instant::now();
+    client.manual_poll(time);
+    server.manual_poll(time);
+
+    assert!(server.recv().is_none());
+}
+
+#[test]
+#[cfg(all(feature = "tester", feature = "std-threads"))]
+fn poll_in_thread() {
+    use std::thread;
+    let mut server = Socket::bind_any().unwrap();
+    let mut client = Socket::bind_any().unwrap();
+    let server_addr = server.local_addr().unwrap();
+
+    // get sender and receiver from server, and start polling in separate thread
+    let (sender, receiver) = (server.get_packet_sender(), server.get_event_receiver());
+    let _thread = thread::spawn(move || server.start_polling());
+
+    // server will responde to this
+    client
+        .send(Packet::reliable_unordered(server_addr, b"Hello!".to_vec()))
+        .expect("This should send");
+    // this will break the loop
+    client
+        .send(Packet::reliable_unordered(server_addr, b"Bye!".to_vec()))
+        .expect("This should send");
+    client.manual_poll(Instant::now());
+
+    // listen for received server messages, and break when "Bye!" is received.
+    loop {
+        if let Ok(SocketEvent::Packet(packet)) = receiver.recv() {
+            let msg = packet.payload();
+
+            if msg == b"Bye!" {
+                break;
+            }
+
+            sender
+                .send(Packet::reliable_unordered(
+                    packet.addr(),
+                    b"Hi, there!".to_vec(),
+                ))
+                .expect("This should send");
+        }
+    }
+    // loop until we get response from server.
+    loop {
+        client.manual_poll(Instant::now());
+        if let Some(packet) = client.recv() {
+            assert_eq!(
+                packet,
+                SocketEvent::Packet(Packet::reliable_unordered(
+                    server_addr,
+                    b"Hi, there!".to_vec()
+                ))
+            );
+            break;
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "std-threads")]
+fn polling_thread_can_be_shut_down() {
+    let server = Socket::bind_any().unwrap();
+    let mut client = Socket::bind_any().unwrap();
+    let server_addr = server.local_addr().unwrap();
+
+    let receiver = server.get_event_receiver();
+    let handle = server.start_polling_thread(Some(Duration::from_millis(1)));
+
+    client
+        .send(Packet::unreliable(server_addr, b"Hello!".to_vec()))
+        .unwrap();
+    client.manual_poll(Instant::now());
+
+    assert!(receiver.recv_timeout(Duration::from_secs(1)).is_ok());
+
+    // the polling loop stops and the socket is handed back
+    let server = handle.shutdown();
+    assert_eq!(server.local_addr().unwrap(), server_addr);
+
+    // dropping a handle stops its polling loop as well
+    drop(server.start_polling_thread(None));
+}
+
+#[test]
+fn rtt_is_known_after_round_trip() {
+    let mut server = Socket::bind_any().unwrap();
+    let mut client = Socket::bind_any().unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let client_addr = client.local_addr().unwrap();
+
+    assert_eq!(client.rtt(&server_addr), None);
+
+    client
+        .send(Packet::reliable_unordered(server_addr, b"Ping".to_vec()))
+        .unwrap();
+    client.manual_poll(Instant::now());
+
+    // wait for the ping, and answer it so that the client receives an acknowledgment.
+    loop {
+        server.manual_poll(Instant::now());
+        if let Some(SocketEvent::Packet(_)) = server.recv() {
+            break;
+        }
+    }
+    server
+        .send(Packet::reliable_unordered(client_addr, b"Pong".to_vec()))
+        .unwrap();
+    server.manual_poll(Instant::now());
+
+    loop {
+        client.manual_poll(Instant::now());
+        if let Some(SocketEvent::Packet(_)) = client.recv() {
+            break;
+        }
+    }
+
+    assert!(client.rtt(&server_addr).is_some());
+    assert_eq!(
+        client.send_rate(&server_addr),
+        Some(Config::default().good_network_send_rate)
+    );
+}
+
+#[test]
+fn connected_addresses_are_listed() {
+    let mut socket = Socket::bind_any().unwrap();
+    let peers: Vec<_> = (0..3).map(|_| Socket::bind_any().unwrap()).collect();
+    let peer_addresses: HashSet<SocketAddr> = peers
+        .iter()
+        .map(|peer| peer.local_addr().unwrap())
+        .collect();
+
+    assert_eq!(socket.connection_count(), 0);
+    assert!(socket.connected_addresses().is_empty());
+
+    for addr in peer_addresses.iter() {
+        socket
+            .send(Packet::unreliable(*addr, b"Hello!".to_vec()))
+            .unwrap();
+    }
+    socket.manual_poll(Instant::now());
+
+    assert_eq!(socket.connection_count(), 3);
+    assert_eq!(
+        socket
+            .connected_addresses()
+            .into_iter()
+            .collect::<HashSet<_>>(),
+        peer_addresses
+    );
+}
+
+#[test]
+fn connection_times_out_on_manual_clock() {
+    let clock = ManualClock::new();
+    let mut socket = Socket::bind_any().unwrap();
+    let peer_addr = Socket::bind_any().unwrap().local_addr().unwrap();
+    socket.set_clock(clock.clone());
+
+    socket
+        .send(Packet::unreliable(peer_addr, b"Hello!".to_vec()))
+        .unwrap();
+    socket.poll();
+    assert_eq!(socket.connection_count(), 1);
+
+    clock.advance(Config::default().idle_connection_timeout - Duration::from_millis(1));
+    socket.poll();
+    assert_eq!(socket.connection_count(), 1);
+
+    // no time has passed for real, but the connection timed out for the socket.
+    clock.advance(Duration::from_millis(1));
+    socket.poll();
+    assert_eq!(socket.recv(), Some(SocketEvent::Timeout(peer_addr)));
+    assert_eq!(socket.connection_count(), 0);
+}
+
+#[test]
+fn idle_time_follows_the_manual_clock() {
+    let clock = ManualClock::new();
+    let mut socket = Socket::bind_any().unwrap();
+    let peer_addr = Socket::bind_any().unwrap().local_addr().unwrap();
+    socket.set_clock(clock.clone());
+    assert_eq!(socket.idle_for(&peer_addr), None);
+
+    socket
+        .send(Packet::unreliable(peer_addr, b"Hello!".to_vec()))
+        .unwrap();
+    socket.poll();
+    assert_eq!(socket.idle_for(&peer_addr), Some(Duration::from_secs(0)));
+
+    // sending does not count, only hearing from the peer does.
+    clock.advance(Duration::from_millis(300));
+    socket
+        .send(Packet::unreliable(peer_addr, b"Hello?".to_vec()))
+        .unwrap();
+    socket.poll();
+    assert_eq!(
+        socket.idle_for(&peer_addr),
+        Some(Duration::from_millis(300))
+    );
+}
+
+#[test]
+#[cfg(feature = "tester")]
+fn reliable_packets_arrive_despite_packet_loss() {
+    let mut server = Socket::bind_any().unwrap();
+    let mut client = Socket::bind_any().unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let client_addr = client.local_addr().unwrap();
+
+    let link_conditioner = |seed| {
+        let mut lc = LinkConditioner::with_seed(seed);
+        lc.set_packet_loss(0.2);
+        Some(lc)
+    };
+    client.set_link_conditioner(link_conditioner(1));
+    server.set_link_conditioner(link_conditioner(2));
+
+    // payloads from 100 on are only sent to keep the acknowledgments flowing.
+    let mut received = HashSet::new();
+    for i in 0..300 {
+        client
+            .send(Packet::reliable_unordered(
+                server_addr,
+                vec![i.min(255) as u8],
+            ))
+            .unwrap();
+        server
+            .send(Packet::reliable_unordered(client_addr, vec![]))
+            .unwrap();
+
+        let time = Instant::now();
+        client.manual_poll(time);
+        server.manual_poll(time);
+
+        while client.recv().is_some() {}
+        while let Some(event) = server.recv() {
+            if let SocketEvent::Packet(packet) = event {
+                received.insert(packet.payload()[0]);
+            }
+        }
+    }
+
+    assert!(client.aggregate_stats().packets_dropped > 0);
+    assert!((0..100).all(|payload| received.contains(&payload)));
+}
+
+#[test]
+fn payloads_that_can_not_be_sent_are_refused() {
+    let config = Config::builder()
+        .receive_buffer_max_size(1000)
+        .fragment_size(500)
+        .max_fragments(4)
+        .build()
+        .unwrap();
+    let mut socket = Socket::bind_any_with_config(config).unwrap();
+    let addr = "127.0.0.1:12345".parse::<SocketAddr>().unwrap();
+
+    // unreliable packets are not fragmented, they have to fit into a single datagram with their headers.
+    socket.send(Packet::unreliable(addr, vec![0; 979])).unwrap();
+    match socket.send(Packet::unreliable(addr, vec![0; 980])) {
+        Err(ErrorKind::PayloadTooLarge { size, max }) => assert_eq!((size, max), (980, 979)),
+        _ => panic!["Supposed to get a payload too large error"],
+    }
+
+    // reliable packets can use all fragments.
+    socket
+        .send(Packet::reliable_unordered(addr, vec![0; 2000]))
+        .unwrap();
+    match socket.send(Packet::reliable_unordered(addr, vec![0; 2001])) {
+        Err(ErrorKind::PayloadTooLarge { size, max }) => assert_eq!((size, max), (2001, 2000)),
+        _ => panic!["Supposed to get a payload too large error"],
+    }
+}
+
+#[test]
+#[cfg(feature = "std-threads")]
+fn blocking_send_returns_once_the_packet_is_acknowledged() {
+    // with a handshake the server keeps the connection before the first packet arrives.
+    let config = Config::builder().require_handshake(true).build().unwrap();
+    let server = Socket::bind_any_with_config(config.clone()).unwrap();
+    let mut client = Socket::bind_any_with_config(config).unwrap();
+    let server_addr = server.local_addr().unwrap();
+
+    // the server answers the packet, which acknowledges it.
+    let (sender, receiver) = (server.get_packet_sender(), server.get_event_receiver());
+    let _handle = server.start_polling_thread(Some(Duration::from_millis(1)));
+    let answer = std::thread::spawn(move || {
+        while let Ok(event) = receiver.recv_timeout(Duration::from_secs(5)) {
+            if let SocketEvent::Packet(packet) = event {
+                sender
+                    .send(Packet::reliable_unordered(
+                        packet.addr(),
+                        b"Welcome".to_vec(),
+                    ))
+                    .unwrap();
+                break;
+            }
+        }
+    });
+
+    client
+        .send_reliable_blocking(server_addr, b"Login".to_vec(), Duration::from_secs(5))
+        .unwrap();
+    answer.join().unwrap();
+
+    // the answer is kept for the application.
+    assert!(client.events().any(|event| event
+        == SocketEvent::Packet(Packet::reliable_unordered(server_addr, b"Welcome".to_vec()))));
+}
+
+#[test]
+#[cfg(feature = "std-threads")]
+fn blocking_send_times_out_when_the_peer_is_silent() {
+    let silent = Socket::bind_any().unwrap();
+    let mut client = Socket::bind_any().unwrap();
+
+    let started = Instant::now();
+    match client.send_reliable_blocking(
+        silent.local_addr().unwrap(),
+        b"Login".to_vec(),
+        Duration::from_millis(100),
+    ) {
+        Err(ErrorKind::AcknowledgmentTimeout) => {}
+        result => panic!("Supposed to time out, got {:?}", result),
+    }
+    assert!(started.elapsed() >= Duration::from_millis(100));
+}
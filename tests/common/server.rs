@@ -144,7 +144,7 @@ impl ServerHandle {
     }
 
     /// Iterate over the events that have happened on the server.
-    pub fn iter_events(&self) -> TryIter<ServerEvent> {
+    pub fn iter_events(&self) -> TryIter<'_, ServerEvent> {
         self.events_rx.try_iter()
     }
 